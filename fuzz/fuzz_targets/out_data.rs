@@ -0,0 +1,26 @@
+//! Feeds arbitrary OUT endpoint data to the command parser.
+//!
+//! Run with `cargo +nightly fuzz run out_data` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use xinput_device::host;
+use xinput_device::protocol::{OutData, OUT_REPORT_LEN};
+
+fuzz_target!(|data: &[u8]| {
+    match OutData::from_raw(data) {
+        OutData::Led(led) => {
+            assert!(led <= 0x0F);
+            // Wireless LED commands are re-encoded by the host module.
+            if data.len() == OUT_REPORT_LEN && data[3] & 0xF0 == 0x40 {
+                assert_eq!(host::led_command(led)[..4], data[..4]);
+            }
+        }
+        OutData::Rumble(strong, weak) => {
+            assert!(data.contains(&strong) && data.contains(&weak));
+        }
+        OutData::Unknown(unknown) => assert_eq!(unknown, data),
+        OutData::ConnectionStatus | OutData::Ack | OutData::PowerOff => {}
+    }
+});
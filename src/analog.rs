@@ -0,0 +1,776 @@
+//! Analog stick calibration: range calibration, axis orientation, deadzones
+//! and response curves applied to the `thumb_*` values of an
+//! [`XboxGamepad`], and automatic drift compensation.
+//!
+//! All math is integer only, stick magnitudes use the range `0..=i16::MAX`.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant};
+
+use crate::controller::{Trigger, XboxGamepad};
+use crate::remap::Transform;
+
+const FULL_SCALE: u32 = i16::MAX as u32;
+
+/// How the deadzones are measured.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeadzoneShape {
+    /// Each axis on its own. Snaps to the axes near the center.
+    Axial,
+    /// Distance of the stick from the center, keeping the direction intact.
+    Radial,
+}
+
+/// Mapping from deflection to reported value after the deadzones are removed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Curve {
+    Linear,
+    /// Finer control near the center.
+    Cubic,
+    /// Lookup table, see [`CurveTable`].
+    Lut(CurveTable),
+}
+
+/// Reason a lookup table was rejected by [`CurveTable::new`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CurveError {
+    /// Fewer than two entries.
+    TooShort,
+}
+
+/// Outputs for evenly spaced inputs from center (first entry) to full
+/// deflection (last entry), linearly interpolated in between. Outputs above
+/// `i16::MAX` are clamped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CurveTable(&'static [u16]);
+
+impl CurveTable {
+    /// Checks that `table` has at least two entries.
+    pub const fn new(table: &'static [u16]) -> Result<Self, CurveError> {
+        if table.len() < 2 {
+            return Err(CurveError::TooShort);
+        }
+        Ok(Self(table))
+    }
+
+    pub const fn entries(&self) -> &'static [u16] {
+        self.0
+    }
+}
+
+impl Curve {
+    /// Lookup table curve, see [`CurveTable::new`].
+    pub const fn lut(table: &'static [u16]) -> Result<Self, CurveError> {
+        match CurveTable::new(table) {
+            Ok(table) => Ok(Curve::Lut(table)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Applies the curve to a magnitude in `0..=i16::MAX`.
+    pub fn apply(&self, magnitude: u16) -> u16 {
+        let m = u32::from(magnitude).min(FULL_SCALE);
+        let out = match self {
+            Curve::Linear => m,
+            Curve::Cubic => (u64::from(m).pow(3) / u64::from(FULL_SCALE).pow(2)) as u32,
+            Curve::Lut(CurveTable(table)) => {
+                // CurveTable::new checked for two entries.
+                let segments = table.len() as u32 - 1;
+                let pos = m * segments;
+                let idx = (pos / FULL_SCALE).min(segments - 1) as usize;
+                let frac = pos - idx as u32 * FULL_SCALE;
+                let (a, b) = (i64::from(table[idx]), i64::from(table[idx + 1]));
+                (a + (b - a) * i64::from(frac) / i64::from(FULL_SCALE)) as u32
+            }
+        };
+        out.min(FULL_SCALE) as u16
+    }
+}
+
+/// Calibration of a single stick.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StickConfig {
+    /// Deflection around the center that is reported as centered.
+    pub inner_deadzone: u16,
+    /// Deflection before the edge that is already reported as full deflection.
+    pub outer_deadzone: u16,
+    pub shape: DeadzoneShape,
+    pub curve: Curve,
+    /// Report the X axis mirrored, right becomes left.
+    pub invert_x: bool,
+    /// Report the Y axis mirrored, up becomes down.
+    pub invert_y: bool,
+    /// Exchange the X and Y axes, applied before inverting.
+    pub swap_axes: bool,
+}
+
+impl Default for StickConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StickConfig {
+    /// No deadzones, a linear response and axes as wired.
+    pub const fn new() -> Self {
+        Self {
+            inner_deadzone: 0,
+            outer_deadzone: 0,
+            shape: DeadzoneShape::Radial,
+            curve: Curve::Linear,
+            invert_x: false,
+            invert_y: false,
+            swap_axes: false,
+        }
+    }
+
+    /// Applies the axis swap and inversion to a raw stick position.
+    pub fn orient(&self, x: i16, y: i16) -> (i16, i16) {
+        let (x, y) = if self.swap_axes { (y, x) } else { (x, y) };
+        let invert = |value: i16, invert: bool| {
+            if invert {
+                value.saturating_neg()
+            } else {
+                value
+            }
+        };
+        (invert(x, self.invert_x), invert(y, self.invert_y))
+    }
+
+    // Removes the deadzones and applies the curve to a magnitude.
+    fn scale(&self, magnitude: u32) -> u32 {
+        let inner = u32::from(self.inner_deadzone).min(FULL_SCALE);
+        let outer = FULL_SCALE
+            .saturating_sub(u32::from(self.outer_deadzone))
+            .max(inner + 1);
+        if magnitude <= inner {
+            return 0;
+        }
+        let scaled = ((magnitude - inner) * FULL_SCALE / (outer - inner)).min(FULL_SCALE);
+        u32::from(self.curve.apply(scaled as u16))
+    }
+
+    /// Applies orientation, deadzones and curve to a raw stick position.
+    pub fn apply(&self, x: i16, y: i16) -> (i16, i16) {
+        let (x, y) = self.orient(x, y);
+        match self.shape {
+            DeadzoneShape::Axial => (self.apply_axis(x), self.apply_axis(y)),
+            DeadzoneShape::Radial => {
+                let (x, y) = (i64::from(x), i64::from(y));
+                let magnitude = ((x * x + y * y) as u64).isqrt() as u32;
+                if magnitude == 0 {
+                    return (0, 0);
+                }
+                let out = i64::from(self.scale(magnitude));
+                let clamp = |v: i64| v.clamp(-(FULL_SCALE as i64), FULL_SCALE as i64) as i16;
+                (
+                    clamp(x * out / i64::from(magnitude)),
+                    clamp(y * out / i64::from(magnitude)),
+                )
+            }
+        }
+    }
+
+    fn apply_axis(&self, value: i16) -> i16 {
+        let out = self.scale(u32::from(value.unsigned_abs())) as i16;
+        if value < 0 {
+            -out
+        } else {
+            out
+        }
+    }
+}
+
+/// Measured range of a single axis.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AxisCalibration {
+    pub min: i16,
+    pub center: i16,
+    pub max: i16,
+}
+
+impl Default for AxisCalibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl AxisCalibration {
+    /// Calibration that reports raw values unchanged.
+    pub const IDENTITY: Self = Self {
+        min: -i16::MAX,
+        center: 0,
+        max: i16::MAX,
+    };
+
+    /// Maps `min..=center..=max` to the full `-i16::MAX..=i16::MAX` range.
+    pub fn apply(&self, raw: i16) -> i16 {
+        let (raw, center) = (i32::from(raw), i32::from(self.center));
+        let offset = raw - center;
+        let span = if offset < 0 {
+            center - i32::from(self.min)
+        } else {
+            i32::from(self.max) - center
+        };
+        if span <= 0 {
+            return 0;
+        }
+        let full = FULL_SCALE as i32;
+        (offset * full / span).clamp(-full, full) as i16
+    }
+}
+
+/// Measured ranges of all stick axes.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Calibration {
+    pub left_x: AxisCalibration,
+    pub left_y: AxisCalibration,
+    pub right_x: AxisCalibration,
+    pub right_y: AxisCalibration,
+}
+
+impl Calibration {
+    /// Length of the serialized calibration.
+    pub const SERIALIZED_LEN: usize = 26;
+    const VERSION: u8 = 1;
+
+    fn axes(&self) -> [&AxisCalibration; 4] {
+        [&self.left_x, &self.left_y, &self.right_x, &self.right_y]
+    }
+
+    /// Serializes the calibration to store it in flash.
+    pub fn to_bytes(&self) -> [u8; Self::SERIALIZED_LEN] {
+        let mut bytes = [0_u8; Self::SERIALIZED_LEN];
+        bytes[0] = Self::VERSION;
+        for (axis, chunk) in self.axes().iter().zip(bytes[1..25].chunks_exact_mut(6)) {
+            chunk[0..2].copy_from_slice(&axis.min.to_le_bytes());
+            chunk[2..4].copy_from_slice(&axis.center.to_le_bytes());
+            chunk[4..6].copy_from_slice(&axis.max.to_le_bytes());
+        }
+        bytes[25] = checksum(&bytes[..25]);
+        bytes
+    }
+
+    /// Restores a calibration written by [`Calibration::to_bytes`].
+    ///
+    /// Returns `None` for erased flash, corrupted data or an unknown version.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::SERIALIZED_LEN
+            || bytes[0] != Self::VERSION
+            || bytes[25] != checksum(&bytes[..25])
+        {
+            return None;
+        }
+        let axis = |chunk: &[u8]| AxisCalibration {
+            min: i16::from_le_bytes([chunk[0], chunk[1]]),
+            center: i16::from_le_bytes([chunk[2], chunk[3]]),
+            max: i16::from_le_bytes([chunk[4], chunk[5]]),
+        };
+        Some(Self {
+            left_x: axis(&bytes[1..7]),
+            left_y: axis(&bytes[7..13]),
+            right_x: axis(&bytes[13..19]),
+            right_y: axis(&bytes[19..25]),
+        })
+    }
+}
+
+impl Transform for Calibration {
+    fn transform(&self, mut pad: XboxGamepad) -> XboxGamepad {
+        pad.thumb_left_x = self.left_x.apply(pad.thumb_left_x);
+        pad.thumb_left_y = self.left_y.apply(pad.thumb_left_y);
+        pad.thumb_right_x = self.right_x.apply(pad.thumb_right_x);
+        pad.thumb_right_y = self.right_y.apply(pad.thumb_right_y);
+        pad
+    }
+}
+
+// Sum of all bytes, inverted so erased (all 0xFF) flash does not validate.
+fn checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b))
+}
+
+/// Calibration capture session.
+///
+/// Feed samples with the sticks at rest to [`CalibrationCapture::sample_center`],
+/// then samples while the user rotates both sticks along their edges to
+/// [`CalibrationCapture::sample_range`], and collect the result with
+/// [`CalibrationCapture::finish`].
+pub struct CalibrationCapture {
+    center_sum: [i64; 4],
+    center_samples: u32,
+    min: [i16; 4],
+    max: [i16; 4],
+}
+
+impl Default for CalibrationCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CalibrationCapture {
+    pub const fn new() -> Self {
+        Self {
+            center_sum: [0; 4],
+            center_samples: 0,
+            min: [i16::MAX; 4],
+            max: [i16::MIN; 4],
+        }
+    }
+
+    fn axes(pad: &XboxGamepad) -> [i16; 4] {
+        [
+            pad.thumb_left_x,
+            pad.thumb_left_y,
+            pad.thumb_right_x,
+            pad.thumb_right_y,
+        ]
+    }
+
+    /// Records raw stick values with both sticks at rest.
+    pub fn sample_center(&mut self, pad: &XboxGamepad) {
+        for (sum, value) in self.center_sum.iter_mut().zip(Self::axes(pad)) {
+            *sum += i64::from(value);
+        }
+        self.center_samples += 1;
+    }
+
+    /// Records raw stick values while the sticks are rotated.
+    pub fn sample_range(&mut self, pad: &XboxGamepad) {
+        for (i, value) in Self::axes(pad).into_iter().enumerate() {
+            self.min[i] = self.min[i].min(value);
+            self.max[i] = self.max[i].max(value);
+        }
+    }
+
+    /// Calibration from the recorded samples. Axes without samples keep the
+    /// identity calibration.
+    pub fn finish(&self) -> Calibration {
+        let axis = |i: usize| {
+            let mut axis = AxisCalibration::IDENTITY;
+            if self.center_samples > 0 {
+                axis.center = (self.center_sum[i] / i64::from(self.center_samples)) as i16;
+            }
+            if self.min[i] < axis.center && self.max[i] > axis.center {
+                axis.min = self.min[i];
+                axis.max = self.max[i];
+            }
+            axis
+        };
+        Calibration {
+            left_x: axis(0),
+            left_y: axis(1),
+            right_x: axis(2),
+            right_y: axis(3),
+        }
+    }
+}
+
+/// Calibration of both sticks, usable as a pipeline [`Transform`].
+///
+/// Raw values are first mapped to the full range using `calibration`, then
+/// the axes are swapped and inverted and the deadzones and curves are
+/// applied. Swap the sticks with
+/// [`ButtonMap::swap_sticks`](crate::remap::ButtonMap::swap_sticks).
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AnalogConfig {
+    pub calibration: Calibration,
+    pub left: StickConfig,
+    pub right: StickConfig,
+}
+
+impl Transform for AnalogConfig {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        let mut pad = self.calibration.transform(pad);
+        (pad.thumb_left_x, pad.thumb_left_y) = self.left.apply(pad.thumb_left_x, pad.thumb_left_y);
+        (pad.thumb_right_x, pad.thumb_right_y) =
+            self.right.apply(pad.thumb_right_x, pad.thumb_right_y);
+        pad
+    }
+}
+
+/// Settings of a [`DriftCompensator`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DriftConfig {
+    /// Raw deflection on both axes up to which a stick counts as resting.
+    /// Must be larger than `max_stick_correction`, or a drifted stick is
+    /// never seen at rest.
+    pub rest_radius: i16,
+    /// Raw value up to which a trigger counts as released.
+    pub trigger_rest: u8,
+    /// Time an input must rest before its center is tracked.
+    pub settle: Duration,
+    /// Largest offset removed from a stick axis.
+    pub max_stick_correction: i16,
+    /// Largest offset removed from a trigger.
+    pub max_trigger_correction: u8,
+    /// Each state moves the tracked center by `1 / 2^rate_shift` of the
+    /// distance to the resting value. From 32 on the center stays put.
+    pub rate_shift: u8,
+}
+
+impl Default for DriftConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DriftConfig {
+    /// Tracks up to about 10% of stick and trigger travel after three
+    /// seconds at rest.
+    pub const fn new() -> Self {
+        Self {
+            rest_radius: 6000,
+            trigger_rest: 40,
+            settle: Duration::from_secs(3),
+            max_stick_correction: 3300,
+            max_trigger_correction: 25,
+            rate_shift: 6,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct DriftTracker {
+    // start of the current rest, if resting
+    since: Option<Instant>,
+    // tracked center of each axis, 8 fractional bits
+    offsets: [i32; 2],
+}
+
+impl DriftTracker {
+    const NEW: Self = Self {
+        since: None,
+        offsets: [0; 2],
+    };
+
+    fn update(
+        &mut self,
+        raw: [i32; 2],
+        resting: bool,
+        limit: i32,
+        config: &DriftConfig,
+        now: Instant,
+    ) {
+        if !resting {
+            self.since = None;
+            return;
+        }
+        let since = *self.since.get_or_insert(now);
+        if now - since < config.settle {
+            return;
+        }
+        let limit = limit << 8;
+        for (offset, raw) in self.offsets.iter_mut().zip(raw) {
+            let step = ((raw << 8) - *offset).checked_shr(u32::from(config.rate_shift));
+            *offset += step.unwrap_or(0);
+            *offset = (*offset).clamp(-limit, limit);
+        }
+    }
+
+    fn offset(&self, axis: usize) -> i32 {
+        self.offsets[axis] >> 8
+    }
+}
+
+/// Removes slow drift of the resting position of sticks and triggers, as
+/// seen on worn potentiometers of retro pads, without a manual
+/// recalibration.
+///
+/// While an input stays within the rest thresholds of its [`DriftConfig`]
+/// for the settle time, its tracked center slowly follows the raw value;
+/// the tracked offset, bounded by the max correction, is subtracted from
+/// every state. Put it before [`AnalogConfig`] in the pipeline, so the
+/// deadzones apply to the compensated values. The state only changes while
+/// an input rests, so deliberate small deflections held briefly are kept.
+pub struct DriftCompensator {
+    pub config: DriftConfig,
+    // left stick, right stick, left trigger, right trigger
+    trackers: Mutex<CriticalSectionRawMutex, Cell<[DriftTracker; 4]>>,
+}
+
+impl DriftCompensator {
+    pub const fn new(config: DriftConfig) -> Self {
+        Self {
+            config,
+            trackers: Mutex::new(Cell::new([DriftTracker::NEW; 4])),
+        }
+    }
+
+    /// Tracked offsets of the left and right stick as `(x, y)`.
+    pub fn stick_offsets(&self) -> [(i16, i16); 2] {
+        let trackers = self.trackers.lock(Cell::get);
+        let offsets = |tracker: &DriftTracker| (tracker.offset(0) as i16, tracker.offset(1) as i16);
+        [offsets(&trackers[0]), offsets(&trackers[1])]
+    }
+
+    /// Tracked offsets of the left and right trigger.
+    pub fn trigger_offsets(&self) -> [u8; 2] {
+        let trackers = self.trackers.lock(Cell::get);
+        [trackers[2].offset(0) as u8, trackers[3].offset(0) as u8]
+    }
+
+    /// Forgets the tracked offsets, e.g. after a manual calibration.
+    pub fn reset(&self) {
+        self.trackers
+            .lock(|trackers| trackers.set([DriftTracker::NEW; 4]));
+    }
+
+    /// Processes `pad` as sampled at `now`, see [`Transform::transform`].
+    pub fn apply_at(&self, mut pad: XboxGamepad, now: Instant) -> XboxGamepad {
+        self.trackers.lock(|cell| {
+            let config = &self.config;
+            let mut trackers = cell.get();
+            let sticks = [
+                (&mut pad.thumb_left_x, &mut pad.thumb_left_y),
+                (&mut pad.thumb_right_x, &mut pad.thumb_right_y),
+            ];
+            for ((x, y), tracker) in sticks.into_iter().zip(&mut trackers[..2]) {
+                let raw = [i32::from(*x), i32::from(*y)];
+                let rest = i32::from(config.rest_radius);
+                let resting = raw.iter().all(|value| value.abs() <= rest);
+                let limit = i32::from(config.max_stick_correction.max(0));
+                tracker.update(raw, resting, limit, config, now);
+                let full = i32::from(i16::MAX);
+                *x = (raw[0] - tracker.offset(0)).clamp(-full, full) as i16;
+                *y = (raw[1] - tracker.offset(1)).clamp(-full, full) as i16;
+            }
+
+            for (trigger, tracker) in [Trigger::Left, Trigger::Right]
+                .into_iter()
+                .zip(&mut trackers[2..])
+            {
+                let raw = pad.trigger(trigger);
+                let resting = raw <= config.trigger_rest;
+                let limit = i32::from(config.max_trigger_correction);
+                tracker.update([i32::from(raw), 0], resting, limit, config, now);
+                // Only positive offsets make sense for triggers.
+                let offset = tracker.offset(0).max(0) as u8;
+                pad.set_trigger(trigger, raw.saturating_sub(offset));
+            }
+
+            cell.set(trackers);
+        });
+        pad
+    }
+}
+
+impl Transform for DriftCompensator {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        self.apply_at(pad, Instant::now())
+    }
+}
+
+/// Bitmask of digital stick directions, see [`DigitalStick`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Directions(pub u8);
+
+impl Directions {
+    pub const UP: Self = Self(1 << 0);
+    pub const DOWN: Self = Self(1 << 1);
+    pub const LEFT: Self = Self(1 << 2);
+    pub const RIGHT: Self = Self(1 << 3);
+
+    pub fn from_buttons(up: bool, down: bool, left: bool, right: bool) -> Self {
+        let bit = |pressed: bool, dir: Self| if pressed { dir.0 } else { 0 };
+        Self(
+            bit(up, Self::UP)
+                | bit(down, Self::DOWN)
+                | bit(left, Self::LEFT)
+                | bit(right, Self::RIGHT),
+        )
+    }
+
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // -1, 0 or 1 along an axis, opposing directions cancel out.
+    fn axis(&self, negative: Self, positive: Self) -> i8 {
+        match (self.contains(negative), self.contains(positive)) {
+            (true, false) => -1,
+            (false, true) => 1,
+            _ => 0,
+        }
+    }
+
+    /// HID hat switch value: 0 is up, increasing clockwise in 45 degree
+    /// steps to 7. `None` when centered; opposing directions cancel out.
+    pub fn hat(&self) -> Option<u8> {
+        let x = self.axis(Self::LEFT, Self::RIGHT);
+        let y = self.axis(Self::DOWN, Self::UP);
+        match (x, y) {
+            (0, 1) => Some(0),
+            (1, 1) => Some(1),
+            (1, 0) => Some(2),
+            (1, -1) => Some(3),
+            (0, -1) => Some(4),
+            (-1, -1) => Some(5),
+            (-1, 0) => Some(6),
+            (-1, 1) => Some(7),
+            _ => None,
+        }
+    }
+
+    /// Directions of a hat switch value, centered for values above 7.
+    pub fn from_hat(hat: u8) -> Self {
+        Self::from_buttons(
+            matches!(hat, 0 | 1 | 7),
+            matches!(hat, 3..=5),
+            matches!(hat, 5..=7),
+            matches!(hat, 1..=3),
+        )
+    }
+}
+
+impl core::ops::BitOr for Directions {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// Emulates an analog stick with four digital direction buttons.
+///
+/// Opposing directions cancel out, use [`Socd`](crate::socd::Socd) before
+/// this for other resolutions.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DigitalStick {
+    /// Deflection while a tilt (walk speed) modifier is held, in
+    /// `0..=i16::MAX`. The first held modifier wins.
+    pub tilt: [u16; 2],
+}
+
+impl Default for DigitalStick {
+    fn default() -> Self {
+        Self {
+            tilt: [FULL_SCALE as u16 / 2, FULL_SCALE as u16 / 4],
+        }
+    }
+}
+
+impl DigitalStick {
+    /// Stick position `(x, y)` for the held directions. Diagonals have the
+    /// same deflection as cardinal directions.
+    pub fn position(&self, directions: Directions, tilt: [bool; 2]) -> (i16, i16) {
+        let magnitude = match tilt {
+            [true, _] => self.tilt[0],
+            [false, true] => self.tilt[1],
+            _ => FULL_SCALE as u16,
+        };
+        let magnitude = i32::from(magnitude).min(FULL_SCALE as i32);
+
+        let x = i32::from(directions.axis(Directions::LEFT, Directions::RIGHT));
+        let y = i32::from(directions.axis(Directions::DOWN, Directions::UP));
+
+        // 1/sqrt(2) in 0.15 fixed point
+        const DIAGONAL: i32 = 23170;
+        let magnitude = if x != 0 && y != 0 {
+            (magnitude * DIAGONAL) >> 15
+        } else {
+            magnitude
+        };
+        ((x * magnitude) as i16, (y * magnitude) as i16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: u16 = i16::MAX as u16;
+
+    #[test]
+    fn lookup_table_needs_two_entries() {
+        assert_eq!(CurveTable::new(&[]), Err(CurveError::TooShort));
+        assert_eq!(Curve::lut(&[FULL]), Err(CurveError::TooShort));
+        assert!(Curve::lut(&[0, FULL]).is_ok());
+    }
+
+    #[test]
+    fn lookup_table_interpolates() {
+        let Ok(curve) = Curve::lut(&[0, 1000, 40_000]) else {
+            panic!("valid table rejected");
+        };
+        assert_eq!(curve.apply(0), 0);
+        assert_eq!(curve.apply(FULL / 4), 499);
+        assert_eq!(curve.apply(FULL / 2), 999);
+        // The last entry is above full scale.
+        assert_eq!(curve.apply(FULL), FULL);
+    }
+
+    fn drifted(x: i16) -> XboxGamepad {
+        XboxGamepad {
+            thumb_left_x: x,
+            ..XboxGamepad::new()
+        }
+    }
+
+    #[test]
+    fn drift_is_tracked_after_settling() {
+        // Shared between tasks, so it has to be Sync.
+        static DRIFT: DriftCompensator = DriftCompensator::new(DriftConfig::new());
+        let at = Instant::from_millis;
+        assert_eq!(DRIFT.apply_at(drifted(1000), at(0)).thumb_left_x, 1000);
+        assert_eq!(DRIFT.apply_at(drifted(1000), at(2999)).thumb_left_x, 1000);
+        // 1/64 of the distance per state.
+        assert_eq!(DRIFT.apply_at(drifted(1000), at(3000)).thumb_left_x, 985);
+        for ms in 3001..4000 {
+            DRIFT.apply_at(drifted(1000), at(ms));
+        }
+        assert_eq!(DRIFT.stick_offsets(), [(999, 0), (0, 0)]);
+        // A deflection ends the rest, the offset stays.
+        assert_eq!(
+            DRIFT.apply_at(drifted(20_000), at(4000)).thumb_left_x,
+            19_001
+        );
+        DRIFT.reset();
+        assert_eq!(DRIFT.stick_offsets(), [(0, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn drift_rate_shift_out_of_range() {
+        let config = DriftConfig {
+            settle: Duration::from_ticks(0),
+            ..DriftConfig::new()
+        };
+        let frozen = DriftCompensator::new(DriftConfig {
+            rate_shift: 40,
+            ..config
+        });
+        assert_eq!(
+            frozen
+                .apply_at(drifted(-1000), Instant::from_millis(0))
+                .thumb_left_x,
+            -1000
+        );
+        assert_eq!(frozen.stick_offsets(), [(0, 0), (0, 0)]);
+
+        let immediate = DriftCompensator::new(DriftConfig {
+            rate_shift: 0,
+            ..config
+        });
+        assert_eq!(
+            immediate
+                .apply_at(drifted(-1000), Instant::from_millis(0))
+                .thumb_left_x,
+            0
+        );
+    }
+}
@@ -0,0 +1,270 @@
+//! BLE HID-over-GATT gamepad backend.
+//!
+//! This module is independent of the BLE stack: it provides the HID report
+//! map, the input report encoding and a [`State`] shared with the
+//! application's GATT server task (TrouBLE, nrf-softdevice, ...), which
+//! forwards reports as notifications of the HID service and exposes
+//! [`State::battery_level`] through the battery service.
+//!
+//! Reports are paced to the connection interval set with
+//! [`State::set_connection_interval`]: at most one report is handed out per
+//! connection event, with the states in between merged by a [`Coalescer`].
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::analog::Directions;
+use crate::controller::XboxGamepad;
+use crate::hid_descriptor::{page, usage, Collection, ReportDescriptor};
+use crate::transport::{Backend, Capabilities, Coalescer, ReportSink};
+
+/// Length of the input report described by [`REPORT_MAP`].
+pub const REPORT_LEN: usize = 13;
+
+/// HID report map for the HID service's Report Map characteristic.
+pub const REPORT_MAP: &[u8] = &DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>();
+
+const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new()
+    .usage_page(page::GENERIC_DESKTOP)
+    .usage(usage::GAME_PAD)
+    .collection(Collection::Application)
+    .buttons(16)
+    // dpad as hat switch
+    .hat_switch()
+    .padding(4)
+    // sticks
+    .axes(
+        &[usage::X, usage::Y, usage::RX, usage::RY],
+        16,
+        -32767,
+        32767,
+    )
+    // triggers
+    .axes(&[usage::Z, usage::RZ], 8, 0, 255)
+    .end_collection();
+
+/// Hat switch value for the dpad, 0 is up and values increase clockwise.
+/// Returns the null state 8 when centered or for contradicting directions.
+pub fn hat_switch(up: bool, down: bool, left: bool, right: bool) -> u8 {
+    Directions::from_buttons(up, down, left, right)
+        .hat()
+        .unwrap_or(8)
+}
+
+/// Encodes the input report described by [`REPORT_MAP`].
+pub fn input_report(pad: &XboxGamepad) -> [u8; REPORT_LEN] {
+    let mut report = [0_u8; REPORT_LEN];
+
+    let buttons = [
+        pad.btn_a,
+        pad.btn_b,
+        pad.btn_x,
+        pad.btn_y,
+        pad.btn_left_shoulder,
+        pad.btn_right_shoulder,
+        pad.btn_back,
+        pad.btn_start,
+        pad.btn_guide,
+        pad.btn_left_thumb,
+        pad.btn_right_thumb,
+    ];
+    let bits = buttons
+        .iter()
+        .enumerate()
+        .fold(0_u16, |bits, (i, &pressed)| {
+            bits | (u16::from(pressed) << i)
+        });
+    [report[0], report[1]] = bits.to_le_bytes();
+
+    report[2] = hat_switch(pad.dpad_up, pad.dpad_down, pad.dpad_left, pad.dpad_right);
+
+    // HID Y axes point down, xinput Y axes point up.
+    let axis = |value: i16| value.max(-i16::MAX).to_le_bytes();
+    [report[3], report[4]] = axis(pad.thumb_left_x);
+    [report[5], report[6]] = axis(pad.thumb_left_y.saturating_neg());
+    [report[7], report[8]] = axis(pad.thumb_right_x);
+    [report[9], report[10]] = axis(pad.thumb_right_y.saturating_neg());
+
+    [report[11]] = pad.trigger_left.to_le_bytes();
+    [report[12]] = pad.trigger_right.to_le_bytes();
+
+    report
+}
+
+struct Pacing {
+    coalescer: Coalescer,
+    last_report: Instant,
+}
+
+/// Shared state between the application and the GATT server task.
+pub struct State {
+    pacing: Mutex<CriticalSectionRawMutex, RefCell<Pacing>>,
+    changed: Signal<CriticalSectionRawMutex, ()>,
+    connection_interval_us: AtomicU32,
+    connected: AtomicBool,
+    battery_level: AtomicU8,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            pacing: Mutex::new(RefCell::new(Pacing {
+                coalescer: Coalescer::new(),
+                last_report: Instant::from_ticks(0),
+            })),
+            changed: Signal::new(),
+            connection_interval_us: AtomicU32::new(0),
+            connected: AtomicBool::new(false),
+            battery_level: AtomicU8::new(100),
+        }
+    }
+
+    /// Publishes new gamepad state.
+    pub fn send(&self, pad: &XboxGamepad) {
+        self.pacing
+            .lock(|pacing| pacing.borrow_mut().coalescer.push(*pad));
+        self.changed.signal(());
+    }
+
+    /// Waits for the next input report to notify.
+    ///
+    /// Returns no earlier than one connection interval after the previous
+    /// report.
+    pub async fn report(&self) -> [u8; REPORT_LEN] {
+        loop {
+            self.changed.wait().await;
+
+            let interval = self.connection_interval();
+            let next = self.pacing.lock(|pacing| pacing.borrow().last_report) + interval;
+            Timer::at(next).await;
+
+            let report = self.pacing.lock(|pacing| {
+                let mut pacing = pacing.borrow_mut();
+                let pad = pacing.coalescer.take()?;
+                pacing.last_report = Instant::now();
+                if pacing.coalescer.is_pending() {
+                    self.changed.signal(());
+                }
+                Some(pad)
+            });
+            if let Some(pad) = report {
+                return input_report(&pad);
+            }
+        }
+    }
+
+    /// Called by the GATT server task with the negotiated connection interval.
+    pub fn set_connection_interval(&self, interval: Duration) {
+        let us = interval.as_micros().min(u64::from(u32::MAX)) as u32;
+        self.connection_interval_us.store(us, Ordering::Relaxed);
+    }
+
+    pub fn connection_interval(&self) -> Duration {
+        Duration::from_micros(self.connection_interval_us.load(Ordering::Relaxed).into())
+    }
+
+    /// Called by the GATT server task when a central subscribed to input
+    /// reports or disconnected.
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::Relaxed);
+    }
+
+    /// Sets the battery level in percent reported by the battery service.
+    pub fn set_battery_level(&self, percent: u8) {
+        self.battery_level
+            .store(percent.min(100), Ordering::Relaxed);
+    }
+
+    pub fn battery_level(&self) -> u8 {
+        self.battery_level.load(Ordering::Relaxed)
+    }
+}
+
+impl ReportSink for State {
+    fn send(&self, pad: &XboxGamepad) {
+        State::send(self, pad);
+    }
+
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+}
+
+impl Backend for State {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            analog_triggers: true,
+            ..Capabilities::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const GOLDEN: &[u8] = &[
+        0x05, 0x01,       // Usage Page (Generic Desktop)
+        0x09, 0x05,       // Usage (Game Pad)
+        0xA1, 0x01,       // Collection (Application)
+        // 16 buttons
+        0x05, 0x09,       //   Usage Page (Button)
+        0x19, 0x01,       //   Usage Minimum (1)
+        0x29, 0x10,       //   Usage Maximum (16)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x25, 0x01,       //   Logical Maximum (1)
+        0x75, 0x01,       //   Report Size (1)
+        0x95, 0x10,       //   Report Count (16)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        // dpad as hat switch
+        0x05, 0x01,       //   Usage Page (Generic Desktop)
+        0x09, 0x39,       //   Usage (Hat switch)
+        // Logical Minimum (0) carries over from the buttons
+        0x25, 0x07,       //   Logical Maximum (7)
+        0x35, 0x00,       //   Physical Minimum (0)
+        0x46, 0x3B, 0x01, //   Physical Maximum (315)
+        0x65, 0x14,       //   Unit (Degrees)
+        0x75, 0x04,       //   Report Size (4)
+        0x95, 0x01,       //   Report Count (1)
+        0x81, 0x42,       //   Input (Data, Var, Abs, Null State)
+        0x65, 0x00,       //   Unit (None)
+        0x45, 0x00,       //   Physical Maximum (0)
+        0x81, 0x03,       //   Input (Const) 4 bit padding
+        // sticks
+        0x09, 0x30,       //   Usage (X)
+        0x09, 0x31,       //   Usage (Y)
+        0x09, 0x33,       //   Usage (Rx)
+        0x09, 0x34,       //   Usage (Ry)
+        0x16, 0x01, 0x80, //   Logical Minimum (-32767)
+        0x26, 0xFF, 0x7F, //   Logical Maximum (32767)
+        0x75, 0x10,       //   Report Size (16)
+        0x95, 0x04,       //   Report Count (4)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        // triggers
+        0x09, 0x32,       //   Usage (Z)
+        0x09, 0x35,       //   Usage (Rz)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x26, 0xFF, 0x00, //   Logical Maximum (255)
+        0x75, 0x08,       //   Report Size (8)
+        0x95, 0x02,       //   Report Count (2)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        0xC0,             // End Collection
+    ];
+
+    #[test]
+    fn report_descriptor_bytes() {
+        assert_eq!(REPORT_MAP, GOLDEN);
+    }
+}
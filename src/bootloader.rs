@@ -0,0 +1,38 @@
+//! Entering the bootloader on request of a configuration tool, so firmware
+//! can be updated without pressing a boot button.
+//!
+//! The configuration channels only call the hook when the request carries
+//! [`MAGIC`], which guards against stray commands.
+
+/// Guard value that must accompany a bootloader request.
+pub const MAGIC: [u8; 4] = *b"BOOT";
+
+/// Resets the device into its bootloader.
+pub type EnterBootloader = fn() -> !;
+
+/// Reboots an RP2040 into the USB mass storage bootloader through the boot
+/// ROM's `reset_to_usb_boot` function.
+#[cfg(feature = "rp2040")]
+pub fn rp2040_reset_to_usb_boot() -> ! {
+    type RomTableLookup = unsafe extern "C" fn(*const u16, u32) -> usize;
+    type ResetToUsbBoot = unsafe extern "C" fn(u32, u32) -> !;
+
+    // Pointers to the lookup function and the function table are stored as
+    // 16 bit values in the ROM header.
+    const ROM_FUNC_TABLE: *const u16 = 0x14 as _;
+    const ROM_TABLE_LOOKUP: *const u16 = 0x18 as _;
+    const RESET_TO_USB_BOOT: u32 = u32::from_le_bytes([b'U', b'B', 0, 0]);
+
+    info!("entering bootloader");
+    // SAFETY: the boot ROM is always mapped at address 0 on the RP2040 and
+    // the looked up function does not return.
+    unsafe {
+        let lookup = core::mem::transmute::<usize, RomTableLookup>(usize::from(
+            ROM_TABLE_LOOKUP.read_volatile(),
+        ));
+        let table = usize::from(ROM_FUNC_TABLE.read_volatile()) as *const u16;
+        let reset = core::mem::transmute::<usize, ResetToUsbBoot>(lookup(table, RESET_TO_USB_BOOT));
+        // No activity LED, both the mass storage and the PICOBOOT interface.
+        reset(0, 0)
+    }
+}
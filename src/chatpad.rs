@@ -0,0 +1,54 @@
+//! Chatpad keystrokes on the xbox 360 wireless receiver protocol.
+//!
+//! Like [`protocol`](crate::protocol) this is plain data encoding. The
+//! [`XInput`](crate::xinput::XInput) task announces the chatpad and sends
+//! the key reports for keys passed to
+//! [`State::send_chatpad_keys`](crate::xinput::State::send_chatpad_keys).
+
+use crate::protocol::IN_REPORT_LEN;
+
+/// Chatpad modifier keys, combined as a bit mask.
+pub mod modifier {
+    pub const SHIFT: u8 = 0x01;
+    pub const GREEN: u8 = 0x02;
+    pub const ORANGE: u8 = 0x04;
+    pub const MESSENGER: u8 = 0x08;
+}
+
+/// Keys currently held on the chatpad.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChatpadKeys {
+    /// Bit mask of [`modifier`] keys.
+    pub modifiers: u8,
+    /// Chatpad scan codes of up to two held keys, 0 for none. The scan code
+    /// is `row << 4 | column` of the chatpad's key matrix, starting at 1.
+    pub keys: [u8; 2],
+}
+
+impl ChatpadKeys {
+    pub const NONE: Self = Self {
+        modifiers: 0,
+        keys: [0; 2],
+    };
+}
+
+/// Report announcing that a chatpad is attached to the controller.
+pub fn announcement_report() -> [u8; IN_REPORT_LEN] {
+    let mut report = [0_u8; IN_REPORT_LEN];
+    report[1] = 0x02; // Message contains chatpad data
+    report[3] = 0xF0; // Unused
+    report[4] = 0x02; // Chatpad attached?
+    report
+}
+
+/// Report carrying the held chatpad keys.
+pub fn key_report(keys: &ChatpadKeys) -> [u8; IN_REPORT_LEN] {
+    let mut report = [0_u8; IN_REPORT_LEN];
+    report[1] = 0x02; // Message contains chatpad data
+    report[3] = 0xF0; // Unused
+    report[4] = 0x00; // Key data
+    report[24] = keys.modifiers;
+    report[25..27].copy_from_slice(&keys.keys);
+    report
+}
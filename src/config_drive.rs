@@ -0,0 +1,575 @@
+//! USB mass storage "config drive" holding the settings as `CONFIG.TXT`.
+//!
+//! The drive shows up on any OS without a driver or tool. It contains a
+//! FAT12 volume generated from the current [`Settings`] in their text form
+//! (see [`settings`](crate::settings)). The user edits the file and ejects
+//! the drive, which parses the file and applies it to the [`Pipeline`]. A
+//! file with errors is ignored as a whole. After ejecting, the drive reports
+//! no medium until the device is reconnected, which regenerates the file.
+//!
+//! The volume lives in a RAM buffer provided by the caller, at least
+//! [`MIN_SECTORS`] sectors of [`SECTOR_SIZE`] bytes. Only the first
+//! [`MAX_SECTORS`] sectors are used.
+//!
+//! The interface implements the Bulk-Only Transport with the SCSI commands
+//! hosts use for flash drives. There is a single LUN; the `GET MAX LUN`
+//! request is stalled, which hosts treat as one LUN.
+
+use core::fmt;
+
+use embassy_usb::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use embassy_usb::Builder;
+
+use crate::settings::{Pipeline, Settings};
+
+/// Size of a sector of the drive.
+pub const SECTOR_SIZE: usize = 512;
+/// Smallest disk the volume can be generated on.
+pub const MIN_SECTORS: usize = 16;
+/// Largest disk the file allocation table can describe.
+pub const MAX_SECTORS: usize = DATA_START + (SECTOR_SIZE * 2 / 3 - 2);
+/// Longest `CONFIG.TXT` that is read back.
+pub const MAX_FILE_LEN: usize = 2048;
+
+const MAX_PACKET_SIZE: u16 = 64;
+
+const CLASS_MASS_STORAGE: u8 = 0x08;
+const SUBCLASS_SCSI: u8 = 0x06;
+const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+// Volume layout: boot sector, a single FAT sector, the root directory and
+// one sector per cluster from cluster 2 on.
+const FAT_START: usize = 1;
+const ROOT_START: usize = 2;
+const ROOT_ENTRIES: usize = 64;
+const DATA_START: usize = ROOT_START + ROOT_ENTRIES * DIR_ENTRY_LEN / SECTOR_SIZE;
+const DIR_ENTRY_LEN: usize = 32;
+
+const VOLUME_LABEL: &[u8; 11] = b"XINPUT     ";
+const FILE_NAME: &[u8; 11] = b"CONFIG  TXT";
+const FILE_HEADER: &str =
+    "# Edit and eject the drive to apply. Lines starting with # are ignored.\n";
+
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ENTRY_DELETED: u8 = 0xE5;
+const FAT_END_OF_CHAIN: u16 = 0xFFF;
+
+const CBW_SIGNATURE: [u8; 4] = *b"USBC";
+const CSW_SIGNATURE: [u8; 4] = *b"USBS";
+const CBW_LEN: usize = 31;
+
+/// Reason `CONFIG.TXT` could not be read back.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum ReadError {
+    NotFound,
+    TooLarge,
+    /// Broken cluster chain or invalid UTF-8.
+    Corrupt,
+}
+
+fn sectors(disk: &[u8]) -> usize {
+    (disk.len() / SECTOR_SIZE).min(MAX_SECTORS)
+}
+
+fn fat_entry(fat: &[u8], cluster: usize) -> u16 {
+    let offset = cluster * 3 / 2;
+    let value = u16::from_le_bytes([fat[offset], fat[offset + 1]]);
+    if cluster % 2 == 0 {
+        value & 0xFFF
+    } else {
+        value >> 4
+    }
+}
+
+fn set_fat_entry(fat: &mut [u8], cluster: usize, value: u16) {
+    let offset = cluster * 3 / 2;
+    let old = u16::from_le_bytes([fat[offset], fat[offset + 1]]);
+    let new = if cluster % 2 == 0 {
+        (old & 0xF000) | value
+    } else {
+        (old & 0x000F) | value << 4
+    };
+    fat[offset..offset + 2].copy_from_slice(&new.to_le_bytes());
+}
+
+/// `fmt::Write` into a byte slice, failing once it is full.
+struct SliceWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl fmt::Write for SliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Generates a volume containing `settings` as `CONFIG.TXT`.
+fn format(disk: &mut [u8], settings: &Settings) {
+    let sectors = sectors(disk);
+    let disk = &mut disk[..sectors * SECTOR_SIZE];
+    disk.fill(0);
+
+    let boot = &mut disk[..SECTOR_SIZE];
+    boot[..11].copy_from_slice(b"\xEB\x3C\x90MSWIN4.1");
+    boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    boot[13] = 1; // sectors per cluster
+    boot[14..16].copy_from_slice(&(FAT_START as u16).to_le_bytes());
+    boot[16] = 1; // number of FATs
+    boot[17..19].copy_from_slice(&(ROOT_ENTRIES as u16).to_le_bytes());
+    boot[19..21].copy_from_slice(&(sectors as u16).to_le_bytes());
+    boot[21] = 0xF8; // fixed media
+    boot[22..24].copy_from_slice(&1_u16.to_le_bytes()); // sectors per FAT
+    boot[24..26].copy_from_slice(&1_u16.to_le_bytes()); // sectors per track
+    boot[26..28].copy_from_slice(&1_u16.to_le_bytes()); // heads
+    boot[36] = 0x80; // drive number
+    boot[38] = 0x29; // extended boot signature
+    boot[39..43].copy_from_slice(b"XID1"); // volume serial number
+    boot[43..54].copy_from_slice(VOLUME_LABEL);
+    boot[54..62].copy_from_slice(b"FAT12   ");
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+    let (head, data) = disk.split_at_mut(DATA_START * SECTOR_SIZE);
+    let mut file = SliceWriter { buf: data, len: 0 };
+    let written =
+        fmt::Write::write_str(&mut file, FILE_HEADER).and_then(|()| settings.write_text(&mut file));
+    if written.is_err() {
+        warn!("config drive: disk too small for the settings");
+    }
+    let len = file.len;
+
+    let fat = &mut head[FAT_START * SECTOR_SIZE..ROOT_START * SECTOR_SIZE];
+    set_fat_entry(fat, 0, 0xFF8);
+    set_fat_entry(fat, 1, FAT_END_OF_CHAIN);
+    let clusters = len.div_ceil(SECTOR_SIZE);
+    for cluster in 2..2 + clusters {
+        let next = if cluster == clusters + 1 {
+            FAT_END_OF_CHAIN
+        } else {
+            cluster as u16 + 1
+        };
+        set_fat_entry(fat, cluster, next);
+    }
+
+    let root = &mut head[ROOT_START * SECTOR_SIZE..];
+    root[..11].copy_from_slice(VOLUME_LABEL);
+    root[11] = ATTR_VOLUME_ID;
+    let entry = &mut root[DIR_ENTRY_LEN..2 * DIR_ENTRY_LEN];
+    entry[..11].copy_from_slice(FILE_NAME);
+    entry[11] = ATTR_ARCHIVE;
+    let first_cluster: u16 = if clusters > 0 { 2 } else { 0 };
+    entry[26..28].copy_from_slice(&first_cluster.to_le_bytes());
+    entry[28..32].copy_from_slice(&(len as u32).to_le_bytes());
+}
+
+/// Copies `CONFIG.TXT` from the volume into `buf`.
+fn read_file<'b>(disk: &[u8], buf: &'b mut [u8]) -> Result<&'b str, ReadError> {
+    let sectors = sectors(disk);
+    let root = &disk[ROOT_START * SECTOR_SIZE..DATA_START * SECTOR_SIZE];
+    let entry = root
+        .chunks_exact(DIR_ENTRY_LEN)
+        .take_while(|entry| entry[0] != 0)
+        .find(|entry| {
+            entry[0] != ENTRY_DELETED
+                && entry[11] & (ATTR_VOLUME_ID | ATTR_DIRECTORY) == 0
+                && entry[..11] == FILE_NAME[..]
+        })
+        .ok_or(ReadError::NotFound)?;
+    let len = u32::from_le_bytes([entry[28], entry[29], entry[30], entry[31]]) as usize;
+    let buf = buf.get_mut(..len).ok_or(ReadError::TooLarge)?;
+
+    let fat = &disk[FAT_START * SECTOR_SIZE..ROOT_START * SECTOR_SIZE];
+    let mut cluster = usize::from(u16::from_le_bytes([entry[26], entry[27]]));
+    for chunk in buf.chunks_mut(SECTOR_SIZE) {
+        let sector = DATA_START + cluster.checked_sub(2).ok_or(ReadError::Corrupt)?;
+        if sector >= sectors {
+            return Err(ReadError::Corrupt);
+        }
+        let start = sector * SECTOR_SIZE;
+        chunk.copy_from_slice(&disk[start..start + chunk.len()]);
+        // A chain longer than the file is fine, the length limits the loop.
+        cluster = usize::from(fat_entry(fat, cluster));
+    }
+    core::str::from_utf8(buf).map_err(|_| ReadError::Corrupt)
+}
+
+/// Parses `CONFIG.TXT` and applies it to `pipeline`.
+fn apply_file(disk: &[u8], pipeline: &Pipeline<'_>) {
+    let mut buf = [0_u8; MAX_FILE_LEN];
+    let text = match read_file(disk, &mut buf) {
+        Ok(text) => text,
+        Err(e) => {
+            warn!("config drive: can not read CONFIG.TXT: {:?}", e);
+            return;
+        }
+    };
+    let mut settings = pipeline.settings();
+    match settings.parse_text(text) {
+        Ok(()) => {
+            info!("config drive: applying {:?}", settings);
+            pipeline.apply(&settings);
+        }
+        Err(e) => warn!("config drive: error in line {} of CONFIG.TXT", e.line),
+    }
+}
+
+/// SCSI sense key and additional sense code of the last failed command.
+#[derive(Clone, Copy)]
+struct Sense {
+    key: u8,
+    asc: u8,
+}
+
+impl Sense {
+    const NONE: Sense = Sense {
+        key: 0x00,
+        asc: 0x00,
+    };
+    const MEDIUM_NOT_PRESENT: Sense = Sense {
+        key: 0x02,
+        asc: 0x3A,
+    };
+    const INVALID_COMMAND: Sense = Sense {
+        key: 0x05,
+        asc: 0x20,
+    };
+    const LBA_OUT_OF_RANGE: Sense = Sense {
+        key: 0x05,
+        asc: 0x21,
+    };
+}
+
+/// Command block wrapper received on the OUT endpoint.
+struct Cbw {
+    tag: [u8; 4],
+    data_len: usize,
+    data_in: bool,
+    cb: [u8; 16],
+}
+
+impl Cbw {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() != CBW_LEN || data[..4] != CBW_SIGNATURE {
+            return None;
+        }
+        let mut cb = [0_u8; 16];
+        cb.copy_from_slice(&data[15..31]);
+        Some(Self {
+            tag: [data[4], data[5], data[6], data[7]],
+            data_len: u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize,
+            data_in: data[12] & 0x80 != 0,
+            cb,
+        })
+    }
+
+    /// Sector range of a READ(10), WRITE(10) or VERIFY(10) command.
+    fn blocks(&self) -> (usize, usize) {
+        let lba = u32::from_be_bytes([self.cb[2], self.cb[3], self.cb[4], self.cb[5]]);
+        let count = u16::from_be_bytes([self.cb[7], self.cb[8]]);
+        (lba as usize, usize::from(count))
+    }
+}
+
+/// Outcome of a command, reported in the command status wrapper.
+enum Status {
+    /// Command passed after transferring this many data bytes.
+    Passed(usize),
+    Failed(Sense),
+}
+
+/// Sends up to `expected` bytes of `data`, returning the number sent.
+async fn send<E: EndpointIn>(
+    ep: &mut E,
+    expected: usize,
+    data: &[u8],
+) -> Result<usize, EndpointError> {
+    let data = &data[..data.len().min(expected)];
+    for packet in data.chunks(usize::from(MAX_PACKET_SIZE)) {
+        ep.write(packet).await?;
+    }
+    // A short packet tells the host that less data than expected follows.
+    if data.len() < expected && data.len() % usize::from(MAX_PACKET_SIZE) == 0 {
+        ep.write(&[]).await?;
+    }
+    Ok(data.len())
+}
+
+/// Receives `expected` bytes into `dest`, dropping what does not fit.
+async fn receive<E: EndpointOut>(
+    ep: &mut E,
+    expected: usize,
+    dest: &mut [u8],
+) -> Result<usize, EndpointError> {
+    let mut packet = [0_u8; MAX_PACKET_SIZE as usize];
+    let mut received = 0;
+    while received < expected {
+        let n = ep.read(&mut packet).await?;
+        let start = received.min(dest.len());
+        let stored = (dest.len() - start).min(n);
+        dest[start..start + stored].copy_from_slice(&packet[..stored]);
+        received += n;
+        if n < packet.len() {
+            break;
+        }
+    }
+    Ok(received.min(expected))
+}
+
+/// Mass storage interface serving the config drive.
+pub struct ConfigDrive<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+    disk: &'d mut [u8],
+    present: bool,
+    sense: Sense,
+}
+
+impl<'d, D: Driver<'d>> ConfigDrive<'d, D> {
+    /// Adds the interface to `builder`. `disk` holds the volume, see the
+    /// [module documentation](self).
+    pub fn new(builder: &mut Builder<'d, D>, disk: &'d mut [u8]) -> Self {
+        assert!(
+            sectors(disk) >= MIN_SECTORS,
+            "config drive needs at least MIN_SECTORS sectors"
+        );
+        let mut function = builder.function(CLASS_MASS_STORAGE, SUBCLASS_SCSI, PROTOCOL_BULK_ONLY);
+        let mut interface = function.interface();
+        let mut alt =
+            interface.alt_setting(CLASS_MASS_STORAGE, SUBCLASS_SCSI, PROTOCOL_BULK_ONLY, None);
+        let read_ep = alt.endpoint_bulk_out(MAX_PACKET_SIZE);
+        let write_ep = alt.endpoint_bulk_in(MAX_PACKET_SIZE);
+        Self {
+            read_ep,
+            write_ep,
+            disk,
+            present: true,
+            sense: Sense::NONE,
+        }
+    }
+
+    /// Serves the drive, applying `CONFIG.TXT` to `pipeline` when the host
+    /// ejects it.
+    pub async fn run(mut self, pipeline: Pipeline<'_>) -> ! {
+        loop {
+            self.read_ep.wait_enabled().await;
+            format(self.disk, &pipeline.settings());
+            self.present = true;
+            self.sense = Sense::NONE;
+            debug!("config drive connected");
+            let _ = self.serve(&pipeline).await;
+            debug!("config drive disconnected");
+        }
+    }
+
+    async fn serve(&mut self, pipeline: &Pipeline<'_>) -> Result<(), EndpointError> {
+        let mut packet = [0_u8; MAX_PACKET_SIZE as usize];
+        loop {
+            let n = self.read_ep.read(&mut packet).await?;
+            let Some(cbw) = Cbw::parse(&packet[..n]) else {
+                warn!("config drive: invalid command block");
+                continue;
+            };
+            let status = self.command(&cbw, pipeline).await?;
+            let (residue, status) = match status {
+                Status::Passed(len) => (cbw.data_len - len, 0),
+                Status::Failed(sense) => {
+                    debug!("config drive: command {:#X} failed", cbw.cb[0]);
+                    self.sense = sense;
+                    // Complete the data stage the host expects.
+                    if cbw.data_in {
+                        send(&mut self.write_ep, cbw.data_len, &[]).await?;
+                    } else if cbw.data_len > 0 {
+                        receive(&mut self.read_ep, cbw.data_len, &mut []).await?;
+                    }
+                    (cbw.data_len, 1)
+                }
+            };
+            let mut csw = [0_u8; 13];
+            csw[..4].copy_from_slice(&CSW_SIGNATURE);
+            csw[4..8].copy_from_slice(&cbw.tag);
+            csw[8..12].copy_from_slice(&(residue as u32).to_le_bytes());
+            csw[12] = status;
+            self.write_ep.write(&csw).await?;
+        }
+    }
+
+    async fn command(
+        &mut self,
+        cbw: &Cbw,
+        pipeline: &Pipeline<'_>,
+    ) -> Result<Status, EndpointError> {
+        let sectors = sectors(self.disk);
+        let expected = cbw.data_len;
+        let opcode = cbw.cb[0];
+        // INQUIRY, REQUEST SENSE, START STOP UNIT and PREVENT ALLOW MEDIUM
+        // REMOVAL work without a medium.
+        if !self.present && !matches!(opcode, 0x12 | 0x03 | 0x1B | 0x1E) {
+            return Ok(Status::Failed(Sense::MEDIUM_NOT_PRESENT));
+        }
+
+        let sent = match opcode {
+            // TEST UNIT READY, PREVENT ALLOW MEDIUM REMOVAL, SYNCHRONIZE CACHE(10)
+            0x00 | 0x1E | 0x35 => 0,
+            // REQUEST SENSE
+            0x03 => {
+                let sense = core::mem::replace(&mut self.sense, Sense::NONE);
+                let mut data = [0_u8; 18];
+                data[0] = 0x70; // current error
+                data[2] = sense.key;
+                data[7] = 10; // additional length
+                data[12] = sense.asc;
+                send(&mut self.write_ep, expected, &data).await?
+            }
+            // INQUIRY
+            0x12 => {
+                let mut data = [0_u8; 36];
+                data[1] = 0x80; // removable
+                data[2] = 0x04; // SPC-2
+                data[3] = 0x02; // response data format
+                data[4] = (data.len() - 5) as u8;
+                data[8..16].copy_from_slice(b"xinput  ");
+                data[16..32].copy_from_slice(b"Config drive    ");
+                data[32..36].copy_from_slice(b"1.0 ");
+                send(&mut self.write_ep, expected, &data).await?
+            }
+            // MODE SENSE(6): no mode pages, not write protected
+            0x1A => send(&mut self.write_ep, expected, &[3, 0, 0, 0]).await?,
+            // MODE SENSE(10)
+            0x5A => send(&mut self.write_ep, expected, &[0, 6, 0, 0, 0, 0, 0, 0]).await?,
+            // START STOP UNIT
+            0x1B => {
+                let (start, load_eject) = (cbw.cb[4] & 0x01 != 0, cbw.cb[4] & 0x02 != 0);
+                if load_eject && !start && self.present {
+                    debug!("config drive ejected");
+                    apply_file(self.disk, pipeline);
+                    self.present = false;
+                } else if load_eject && start {
+                    self.present = true;
+                }
+                0
+            }
+            // READ FORMAT CAPACITIES
+            0x23 => {
+                let mut data = [0_u8; 12];
+                data[3] = 8; // capacity list length
+                data[4..8].copy_from_slice(&(sectors as u32).to_be_bytes());
+                data[8] = 0x02; // formatted media
+                data[9..12].copy_from_slice(&(SECTOR_SIZE as u32).to_be_bytes()[1..]);
+                send(&mut self.write_ep, expected, &data).await?
+            }
+            // READ CAPACITY(10)
+            0x25 => {
+                let mut data = [0_u8; 8];
+                data[..4].copy_from_slice(&(sectors as u32 - 1).to_be_bytes());
+                data[4..].copy_from_slice(&(SECTOR_SIZE as u32).to_be_bytes());
+                send(&mut self.write_ep, expected, &data).await?
+            }
+            // READ(10), WRITE(10), VERIFY(10)
+            0x28 | 0x2A | 0x2F => {
+                let (lba, count) = cbw.blocks();
+                if lba.saturating_add(count) > sectors {
+                    return Ok(Status::Failed(Sense::LBA_OUT_OF_RANGE));
+                }
+                let range = lba * SECTOR_SIZE..(lba + count) * SECTOR_SIZE;
+                match opcode {
+                    0x28 => send(&mut self.write_ep, expected, &self.disk[range]).await?,
+                    0x2A => receive(&mut self.read_ep, expected, &mut self.disk[range]).await?,
+                    _ => 0,
+                }
+            }
+            _ => return Ok(Status::Failed(Sense::INVALID_COMMAND)),
+        };
+        Ok(Status::Passed(sent))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embassy_futures::block_on;
+    use embassy_usb::driver::{Direction, EndpointAddress, EndpointInfo, EndpointType};
+
+    // Endpoint receiving packets of the given lengths, filled with their
+    // index.
+    struct FakeEndpoint {
+        info: EndpointInfo,
+        packets: &'static [usize],
+        next: usize,
+    }
+
+    impl FakeEndpoint {
+        fn new(packets: &'static [usize]) -> Self {
+            Self {
+                info: EndpointInfo {
+                    addr: EndpointAddress::from_parts(1, Direction::Out),
+                    ep_type: EndpointType::Bulk,
+                    max_packet_size: MAX_PACKET_SIZE,
+                    interval_ms: 0,
+                },
+                packets,
+                next: 0,
+            }
+        }
+    }
+
+    impl Endpoint for FakeEndpoint {
+        fn info(&self) -> &EndpointInfo {
+            &self.info
+        }
+
+        async fn wait_enabled(&mut self) {}
+    }
+
+    impl EndpointOut for FakeEndpoint {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+            let len = *self.packets.get(self.next).ok_or(EndpointError::Disabled)?;
+            buf[..len].fill(self.next as u8);
+            self.next += 1;
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn receive_stores_data() {
+        let mut ep = FakeEndpoint::new(&[64, 64]);
+        let mut dest = [0xFF_u8; 128];
+        assert_eq!(block_on(receive(&mut ep, 128, &mut dest)), Ok(128));
+        assert!(dest[..64].iter().all(|byte| *byte == 0));
+        assert!(dest[64..].iter().all(|byte| *byte == 1));
+    }
+
+    #[test]
+    fn receive_drops_what_does_not_fit() {
+        let mut ep = FakeEndpoint::new(&[64, 64, 64]);
+        let mut dest = [0xFF_u8; 100];
+        assert_eq!(block_on(receive(&mut ep, 192, &mut dest)), Ok(192));
+        assert!(dest[..64].iter().all(|byte| *byte == 0));
+        assert!(dest[64..].iter().all(|byte| *byte == 1));
+        assert_eq!(ep.next, 3);
+    }
+
+    #[test]
+    fn receive_drains_without_a_buffer() {
+        // A failed command still reads the data stage it announced.
+        let mut ep = FakeEndpoint::new(&[64, 64, 64, 64]);
+        assert_eq!(block_on(receive(&mut ep, 256, &mut [])), Ok(256));
+        assert_eq!(ep.next, 4);
+    }
+
+    #[test]
+    fn receive_stops_at_a_short_packet() {
+        let mut ep = FakeEndpoint::new(&[64, 10, 64]);
+        let mut dest = [0_u8; 192];
+        assert_eq!(block_on(receive(&mut ep, 192, &mut dest)), Ok(74));
+        assert_eq!(ep.next, 2);
+    }
+}
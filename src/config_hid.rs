@@ -0,0 +1,296 @@
+//! Vendor defined HID interface exchanging [`Settings`](settings::Settings)
+//! as feature reports.
+//!
+//! HID needs no driver on any OS and is reachable from browsers through
+//! WebHID, so a configurator can adjust the controller live. The single
+//! feature report [`REPORT_ID`] carries a settings blob (see
+//! [`settings`]) zero padded to [`REPORT_LEN`] bytes:
+//! GET_REPORT returns the current settings, SET_REPORT applies the records
+//! it contains.
+//!
+//! Writing [`MAGIC`](bootloader::MAGIC) to feature report
+//! [`BOOTLOADER_REPORT_ID`] calls the bootloader hook, if one is set. The
+//! device resets before the request completes, so hosts see it fail.
+//!
+//! Requests are answered from the control endpoint, so there is no task to
+//! run. The control buffer must hold `REPORT_LEN + 1` bytes.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex};
+use embassy_usb::class::hid::{self, HidWriter, ReportId, RequestHandler};
+use embassy_usb::control::OutResponse;
+use embassy_usb::driver::Driver;
+use embassy_usb::Builder;
+
+use crate::bootloader::{self, EnterBootloader};
+use crate::hid_descriptor::{flags, page, Collection, ReportDescriptor};
+use crate::settings::{self, Pipeline};
+
+/// ID of the settings feature report.
+pub const REPORT_ID: u8 = 1;
+/// Length of the settings feature report, without the report ID.
+pub const REPORT_LEN: usize = 63;
+/// ID of the feature report that enters the bootloader.
+pub const BOOTLOADER_REPORT_ID: u8 = 2;
+
+const _: () = assert!(settings::ENCODED_LEN <= REPORT_LEN);
+
+/// Report descriptor of the configuration interface.
+pub const REPORT_DESCRIPTOR: &[u8] = &DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>();
+
+const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new()
+    .usage_page(page::VENDOR)
+    .usage(0x01)
+    .collection(Collection::Application)
+    .report_id(REPORT_ID)
+    .usage(0x02)
+    .logical_minimum(0)
+    .logical_maximum(255)
+    .report_size(8)
+    .report_count(REPORT_LEN as u8)
+    .feature(flags::DATA_VAR_ABS)
+    .report_id(BOOTLOADER_REPORT_ID)
+    .usage(0x03)
+    .report_count(bootloader::MAGIC.len() as u8)
+    .feature(flags::DATA_VAR_ABS)
+    .end_collection();
+
+/// Answers the feature report requests from the settings of a [`Pipeline`].
+pub struct ConfigHandler<'a> {
+    pipeline: Pipeline<'a>,
+    rejected: AtomicUsize,
+    enter_bootloader: Option<EnterBootloader>,
+}
+
+impl<'a> ConfigHandler<'a> {
+    pub const fn new(pipeline: Pipeline<'a>) -> Self {
+        Self {
+            pipeline,
+            rejected: AtomicUsize::new(0),
+            enter_bootloader: None,
+        }
+    }
+
+    /// Enables the bootloader report, which calls `hook`.
+    pub const fn with_bootloader_hook(mut self, hook: EnterBootloader) -> Self {
+        self.enter_bootloader = Some(hook);
+        self
+    }
+
+    /// Number of SET_REPORT requests rejected because of invalid blobs.
+    pub fn rejected(&self) -> usize {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+impl RequestHandler for ConfigHandler<'_> {
+    fn get_report(&self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        if id != ReportId::Feature(REPORT_ID) || buf.len() < REPORT_LEN + 1 {
+            return None;
+        }
+        let report = &mut buf[..REPORT_LEN + 1];
+        report.fill(0);
+        report[0] = REPORT_ID;
+        self.pipeline.settings().encode(&mut report[1..]).ok()?;
+        Some(report.len())
+    }
+
+    fn set_report(&self, id: ReportId, data: &[u8]) -> OutResponse {
+        if id == ReportId::Feature(BOOTLOADER_REPORT_ID) {
+            return match (self.enter_bootloader, data) {
+                (Some(enter_bootloader), [BOOTLOADER_REPORT_ID, magic @ ..])
+                    if magic == bootloader::MAGIC =>
+                {
+                    enter_bootloader()
+                }
+                _ => OutResponse::Rejected,
+            };
+        }
+        let ReportId::Feature(REPORT_ID) = id else {
+            return OutResponse::Rejected;
+        };
+        // The data stage starts with the report ID.
+        let Some((&REPORT_ID, blob)) = data.split_first() else {
+            return OutResponse::Rejected;
+        };
+        let mut settings = self.pipeline.settings();
+        match settings.decode(blob) {
+            Ok(()) => {
+                debug!("config hid: applying {:?}", settings);
+                self.pipeline.apply(&settings);
+                OutResponse::Accepted
+            }
+            Err(e) => {
+                warn!("config hid: rejected settings: {:?}", e);
+                // There is no fetch_add on every target, the critical
+                // section keeps other requests out.
+                CriticalSectionRawMutex::new().lock(|| {
+                    let rejected = self.rejected.load(Ordering::Relaxed);
+                    self.rejected
+                        .store(rejected.wrapping_add(1), Ordering::Relaxed);
+                });
+                OutResponse::Rejected
+            }
+        }
+    }
+}
+
+/// Adds the configuration interface to `builder`.
+///
+/// The interrupt IN endpoint required by HID never sends anything.
+pub fn add_interface<'d, D: Driver<'d>>(
+    builder: &mut Builder<'d, D>,
+    state: &'d mut hid::State<'d>,
+    handler: &'d ConfigHandler<'d>,
+) {
+    let config = hid::Config {
+        report_descriptor: REPORT_DESCRIPTOR,
+        request_handler: Some(handler),
+        poll_ms: 255,
+        max_packet_size: 8,
+    };
+    let _ = HidWriter::<_, 1>::new(builder, state, config);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analog::AnalogConfig;
+    use crate::controller::Button;
+    use crate::remap::{ButtonMap, Shared};
+    use crate::settings::Settings;
+    use crate::socd::Socd;
+    use crate::turbo::Turbo;
+
+    struct Stages {
+        map: Shared<ButtonMap>,
+        analog: Shared<AnalogConfig>,
+        socd: Shared<Socd>,
+        turbo: Shared<Turbo>,
+    }
+
+    impl Stages {
+        fn new() -> Self {
+            Self {
+                map: Shared::new(ButtonMap::identity()),
+                analog: Shared::new(AnalogConfig::default()),
+                socd: Shared::new(Socd::default()),
+                turbo: Shared::new(Turbo::default()),
+            }
+        }
+
+        fn pipeline(&self) -> Pipeline<'_> {
+            Pipeline {
+                map: &self.map,
+                analog: &self.analog,
+                socd: &self.socd,
+                turbo: &self.turbo,
+            }
+        }
+    }
+
+    #[test]
+    fn get_report_returns_the_settings() {
+        let stages = Stages::new();
+        stages
+            .turbo
+            .update(|turbo| turbo.set_button(Button::A, true));
+        let handler = ConfigHandler::new(stages.pipeline());
+        let mut buf = [0xEE; 64];
+        assert_eq!(
+            handler.get_report(ReportId::Feature(REPORT_ID), &mut buf),
+            Some(REPORT_LEN + 1)
+        );
+        assert_eq!(buf[0], REPORT_ID);
+        let mut settings = Settings::default();
+        settings.decode(&buf[1..]).unwrap();
+        assert_eq!(settings, stages.pipeline().settings());
+        assert_eq!(settings.turbo_buttons, 1 << Button::A as u16);
+        assert!(buf[1 + settings::ENCODED_LEN..]
+            .iter()
+            .all(|byte| *byte == 0));
+
+        assert_eq!(
+            handler.get_report(ReportId::Feature(REPORT_ID), &mut buf[..63]),
+            None
+        );
+        assert_eq!(handler.get_report(ReportId::In(REPORT_ID), &mut buf), None);
+    }
+
+    #[test]
+    fn set_report_applies_the_settings() {
+        let stages = Stages::new();
+        let handler = ConfigHandler::new(stages.pipeline());
+        let mut settings = Settings {
+            turbo_rate: 20,
+            ..Settings::default()
+        };
+        settings.left.inner_deadzone = 3000;
+        let mut report = [0; REPORT_LEN + 1];
+        report[0] = REPORT_ID;
+        settings.encode(&mut report[1..]).unwrap();
+        assert_eq!(
+            handler.set_report(ReportId::Feature(REPORT_ID), &report),
+            OutResponse::Accepted
+        );
+        assert_eq!(stages.pipeline().settings(), settings);
+        assert_eq!(handler.rejected(), 0);
+    }
+
+    #[test]
+    fn invalid_reports_are_rejected_and_counted() {
+        let stages = Stages::new();
+        let handler = ConfigHandler::new(stages.pipeline());
+        let before = stages.pipeline().settings();
+        let bad_version = [REPORT_ID, settings::VERSION + 1];
+        assert_eq!(
+            handler.set_report(ReportId::Feature(REPORT_ID), &bad_version),
+            OutResponse::Rejected
+        );
+        let truncated = [REPORT_ID, settings::VERSION, 0x04, 2, 0];
+        assert_eq!(
+            handler.set_report(ReportId::Feature(REPORT_ID), &truncated),
+            OutResponse::Rejected
+        );
+        assert_eq!(handler.rejected(), 2);
+        assert_eq!(stages.pipeline().settings(), before);
+
+        // Not settings blobs, so not counted.
+        assert_eq!(
+            handler.set_report(ReportId::Feature(REPORT_ID), &[]),
+            OutResponse::Rejected
+        );
+        let mut magic_without_hook = [BOOTLOADER_REPORT_ID; 1 + bootloader::MAGIC.len()];
+        magic_without_hook[1..].copy_from_slice(&bootloader::MAGIC);
+        assert_eq!(
+            handler.set_report(ReportId::Feature(BOOTLOADER_REPORT_ID), &magic_without_hook),
+            OutResponse::Rejected
+        );
+        assert_eq!(handler.rejected(), 2);
+    }
+
+    #[rustfmt::skip]
+    const GOLDEN: &[u8] = &[
+        0x06, 0x00, 0xFF, // Usage Page (Vendor Defined 0xFF00)
+        0x09, 0x01,       // Usage (0x01)
+        0xA1, 0x01,       // Collection (Application)
+        0x85, REPORT_ID,  //   Report ID
+        0x09, 0x02,       //   Usage (0x02)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x26, 0xFF, 0x00, //   Logical Maximum (255)
+        0x75, 0x08,       //   Report Size (8)
+        0x95, REPORT_LEN as u8, // Report Count
+        0xB1, 0x02,       //   Feature (Data, Variable, Absolute)
+        0x85, BOOTLOADER_REPORT_ID, // Report ID
+        0x09, 0x03,       //   Usage (0x03)
+        0x95, bootloader::MAGIC.len() as u8, // Report Count
+        0xB1, 0x02,       //   Feature (Data, Variable, Absolute)
+        0xC0,             // End Collection
+    ];
+
+    #[test]
+    fn report_descriptor_bytes() {
+        assert_eq!(REPORT_DESCRIPTOR, GOLDEN);
+    }
+}
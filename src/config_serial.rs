@@ -0,0 +1,1207 @@
+//! Line based configuration and debug channel on a CDC-ACM interface.
+//!
+//! The interface is added to the same [`Builder`] as the XInput interfaces,
+//! so the device becomes a composite device. Set `composite_with_iads` in
+//! the USB config, otherwise Windows does not bind its serial driver.
+//!
+//! Every command is a single line, answered with one or more lines. Errors
+//! start with `error:`, responses longer than [`LINE_LEN`] are answered with
+//! `error: line too long`.
+//!
+//! | Command                            | Effect                                      |
+//! |------------------------------------|---------------------------------------------|
+//! | `map`                              | Prints a `logical=physical` line per remapped button |
+//! | `map <logical> <physical>`         | Reports `physical` as `logical` button      |
+//! | `swap <a> <b>`                     | Exchanges two logical buttons               |
+//! | `reset`                            | Restores the identity button map            |
+//! | `deadzone <left\|right>`           | Prints `<inner> <outer>` of a stick         |
+//! | `deadzone <left\|right> <in> <out>`| Sets the deadzones of a stick               |
+//! | `dump [n]`                         | Prints the last `n` input frames, oldest first, as [`ControllerData`] hex |
+//! | `profile`                          | Lists the stored profiles, `*` marks the active one |
+//! | `profile <n>`                      | Selects the profile in slot `n`             |
+//! | `profile save <n> <name>`          | Stores the current settings in slot `n`     |
+//! | `packets`                          | Prints `queued <n> sent <n>`, see [`PacketCounters`] |
+//! | `link`                             | Prints `link <0-255>` or `link unknown`, see [`State::set_link_quality`](crate::xinput::State::set_link_quality) |
+//! | `inject <hex> [ms]`                | Reports a [`ControllerData`] hex frame instead of the physical input, for `ms` or until the next command, see [`Injector`] |
+//! | `release`                          | Returns to the physical input after the queued frames |
+//! | `record <on\|off>`                 | Starts or stops the input recording stream, see [`Recorder`] |
+//! | `descriptor <config\|info>`        | Prints the captured configuration descriptor or the controller info report as hex, see [`DescriptorCapture`] |
+//! | `bootloader BOOT`                  | Resets into the bootloader, see [`bootloader`] |
+//! | `diag`                             | Prints the button and axis statistics of the diagnostics mode, see [`Diagnostics`] |
+//! | `diag <on\|off\|reset>`            | Turns the diagnostics mode on or off, or clears its statistics |
+//!
+//! While recording, every frame passing the [`Recorder`] is sent between
+//! the response lines as a packet holding the line written by
+//! [`InputRecord::encode`]: `rec `, the timestamp in microseconds as 8 hex
+//! digits, a space and the [`ControllerData`] hex. Decode it on the host
+//! with [`RecordDecoder`](crate::host::RecordDecoder).
+//!
+//! While the diagnostics mode is on, every frame is sent as a
+//! `raw <hex> <timestamp_us>` line with the [`ControllerData`] of the
+//! frame, and the statistics are repeated every
+//! [`DIAGNOSTICS_STATS_PERIOD`]: a `button <name> <presses> <chatter>
+//! <shortest_ms>` line for every button pressed so far and an
+//! `axis <name> <min> <max>` line for every axis.
+//!
+//! Buttons are named `up`, `down`, `left`, `right`, `start`, `back`, `ls`,
+//! `rs`, `lb`, `rb`, `guide`, `a`, `b`, `x` and `y`.
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+use core::future::pending;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant, Ticker, Timer};
+use embassy_usb::class::cdc_acm::{self, CdcAcmClass, Receiver, Sender};
+use embassy_usb::driver::{Driver, EndpointError};
+use embassy_usb::Builder;
+
+use crate::analog::{AnalogConfig, StickConfig};
+use crate::bootloader::{self, EnterBootloader};
+use crate::controller::{Button, XboxGamepad};
+use crate::diagnostics::{self, Diagnostics};
+use crate::host::InputRecord;
+use crate::input::InputSource;
+use crate::profiles::Profiles;
+use crate::protocol::ControllerData;
+use crate::remap::{ButtonMap, Shared, Transform};
+use crate::xinput::capture::{DescriptorCapture, CAPTURE_LEN};
+use crate::xinput::PacketCounters;
+
+/// Maximum length of a command or response line, without line ending.
+pub const LINE_LEN: usize = 64;
+const MAX_PACKET_SIZE: u16 = 64;
+/// Time between answering the `bootloader` command and calling the hook.
+const BOOTLOADER_DELAY: Duration = Duration::from_millis(50);
+/// Number of `inject` and `release` commands queued by an [`Injector`].
+pub const INJECT_QUEUE_LEN: usize = 16;
+/// Number of frames a [`Recorder`] buffers for the serial channel.
+pub const RECORD_QUEUE_LEN: usize = 16;
+/// Time between the statistics sent while the diagnostics mode is on.
+pub const DIAGNOSTICS_STATS_PERIOD: Duration = Duration::from_secs(1);
+
+/// Stick addressed by a `deadzone` command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+/// Data printed by a `descriptor` command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DescriptorKind {
+    Config,
+    ControllerInfo,
+}
+
+/// Parsed command line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command<'a> {
+    GetMap,
+    Map {
+        logical: Button,
+        physical: Button,
+    },
+    Swap(Button, Button),
+    ResetMap,
+    GetDeadzone(Stick),
+    SetDeadzone {
+        stick: Stick,
+        inner: u16,
+        outer: u16,
+    },
+    Dump(usize),
+    ListProfiles,
+    SelectProfile(usize),
+    SaveProfile {
+        index: usize,
+        name: &'a str,
+    },
+    Packets,
+    LinkQuality,
+    /// `hold` of `None` keeps the frame until the next command.
+    Inject {
+        data: ControllerData,
+        hold: Option<Duration>,
+    },
+    Release,
+    Record(bool),
+    Descriptor(DescriptorKind),
+    EnterBootloader,
+    GetDiagnostics,
+    SetDiagnostics(bool),
+    ResetDiagnostics,
+}
+
+/// Reason a command line was rejected.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    /// Missing, surplus or malformed arguments.
+    InvalidArguments,
+    /// The line did not fit into [`LINE_LEN`] bytes.
+    TooLong,
+}
+
+impl ParseError {
+    fn message(&self) -> &'static str {
+        match self {
+            ParseError::Empty => "empty line",
+            ParseError::UnknownCommand => "unknown command",
+            ParseError::InvalidArguments => "invalid arguments",
+            ParseError::TooLong => "line too long",
+        }
+    }
+}
+
+fn parse_frame(hex: &str) -> Option<ControllerData> {
+    let hex = hex.as_bytes();
+    if hex.len() != 24 {
+        return None;
+    }
+    let mut data = [0_u8; 12];
+    for (byte, digits) in data.iter_mut().zip(hex.chunks_exact(2)) {
+        let digits = core::str::from_utf8(digits).ok()?;
+        *byte = u8::from_str_radix(digits, 16).ok()?;
+    }
+    Some(ControllerData(data))
+}
+
+fn parse_stick(name: &str) -> Option<Stick> {
+    match name {
+        "left" => Some(Stick::Left),
+        "right" => Some(Stick::Right),
+        _ => None,
+    }
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(line: &'a str) -> Result<Self, ParseError> {
+        let mut words = line.split_ascii_whitespace();
+        let Some(command) = words.next() else {
+            return Err(ParseError::Empty);
+        };
+        let mut args = [""; 3];
+        let mut count = 0;
+        for word in words {
+            let Some(arg) = args.get_mut(count) else {
+                return Err(ParseError::InvalidArguments);
+            };
+            *arg = word;
+            count += 1;
+        }
+        let button = |name| Button::from_name(name).ok_or(ParseError::InvalidArguments);
+        let stick = |name| parse_stick(name).ok_or(ParseError::InvalidArguments);
+        let number = |arg: &str| arg.parse::<u16>().map_err(|_| ParseError::InvalidArguments);
+        let frame = |hex| parse_frame(hex).ok_or(ParseError::InvalidArguments);
+
+        match (command, &args[..count]) {
+            ("map", []) => Ok(Command::GetMap),
+            ("map", [logical, physical]) => Ok(Command::Map {
+                logical: button(logical)?,
+                physical: button(physical)?,
+            }),
+            ("swap", [a, b]) => Ok(Command::Swap(button(a)?, button(b)?)),
+            ("reset", []) => Ok(Command::ResetMap),
+            ("deadzone", [s]) => Ok(Command::GetDeadzone(stick(s)?)),
+            ("deadzone", [s, inner, outer]) => Ok(Command::SetDeadzone {
+                stick: stick(s)?,
+                inner: number(inner)?,
+                outer: number(outer)?,
+            }),
+            ("dump", []) => Ok(Command::Dump(usize::MAX)),
+            ("dump", [n]) => Ok(Command::Dump(usize::from(number(n)?))),
+            ("profile", []) => Ok(Command::ListProfiles),
+            ("profile", [n]) => Ok(Command::SelectProfile(usize::from(number(n)?))),
+            ("profile", ["save", n, name]) => Ok(Command::SaveProfile {
+                index: usize::from(number(n)?),
+                name,
+            }),
+            ("packets", []) => Ok(Command::Packets),
+            ("link", []) => Ok(Command::LinkQuality),
+            ("inject", [hex]) => Ok(Command::Inject {
+                data: frame(hex)?,
+                hold: None,
+            }),
+            ("inject", [hex, ms]) => Ok(Command::Inject {
+                data: frame(hex)?,
+                hold: Some(Duration::from_millis(number(ms)?.into())),
+            }),
+            ("release", []) => Ok(Command::Release),
+            ("record", ["on"]) => Ok(Command::Record(true)),
+            ("record", ["off"]) => Ok(Command::Record(false)),
+            ("descriptor", ["config"]) => Ok(Command::Descriptor(DescriptorKind::Config)),
+            ("descriptor", ["info"]) => Ok(Command::Descriptor(DescriptorKind::ControllerInfo)),
+            ("bootloader", [magic]) if magic.as_bytes() == bootloader::MAGIC => {
+                Ok(Command::EnterBootloader)
+            }
+            ("diag", []) => Ok(Command::GetDiagnostics),
+            ("diag", ["on"]) => Ok(Command::SetDiagnostics(true)),
+            ("diag", ["off"]) => Ok(Command::SetDiagnostics(false)),
+            ("diag", ["reset"]) => Ok(Command::ResetDiagnostics),
+            (
+                "map" | "swap" | "reset" | "deadzone" | "dump" | "profile" | "packets" | "link"
+                | "inject" | "release" | "record" | "descriptor" | "bootloader" | "diag",
+                _,
+            ) => Err(ParseError::InvalidArguments),
+            _ => Err(ParseError::UnknownCommand),
+        }
+    }
+}
+
+struct Ring<const N: usize> {
+    frames: [XboxGamepad; N],
+    // index of the next frame to write
+    next: usize,
+    len: usize,
+}
+
+/// Pass-through [`Transform`] that keeps the last `N` gamepad states for
+/// the `dump` command.
+pub struct History<const N: usize> {
+    ring: Mutex<CriticalSectionRawMutex, RefCell<Ring<N>>>,
+}
+
+impl<const N: usize> Default for History<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> History<N> {
+    pub const fn new() -> Self {
+        Self {
+            ring: Mutex::new(RefCell::new(Ring {
+                frames: [XboxGamepad::new(); N],
+                next: 0,
+                len: 0,
+            })),
+        }
+    }
+
+    /// Adds a frame, dropping the oldest one when full.
+    pub fn record(&self, pad: &XboxGamepad) {
+        if N == 0 {
+            return;
+        }
+        self.ring.lock(|ring| {
+            let mut ring = ring.borrow_mut();
+            let next = ring.next;
+            ring.frames[next] = *pad;
+            ring.next = (next + 1) % N;
+            ring.len = (ring.len + 1).min(N);
+        });
+    }
+
+    /// Number of recorded frames.
+    pub fn len(&self) -> usize {
+        self.ring.lock(|ring| ring.borrow().len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Frame recorded `age` frames before the newest one.
+    pub fn get(&self, age: usize) -> Option<XboxGamepad> {
+        self.ring.lock(|ring| {
+            let ring = ring.borrow();
+            (age < ring.len).then(|| ring.frames[(ring.next + N - 1 - age) % N])
+        })
+    }
+}
+
+impl<const N: usize> Transform for History<N> {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        self.record(&pad);
+        pad
+    }
+}
+
+/// Pass-through [`Transform`] that streams frames to the `record`
+/// command, with the time they passed it.
+///
+/// Put it last in the transform chain to record the frames as reported.
+/// When the serial channel falls behind the oldest frames are dropped.
+pub struct Recorder {
+    enabled: Mutex<CriticalSectionRawMutex, Cell<bool>>,
+    frames: Channel<CriticalSectionRawMutex, InputRecord, RECORD_QUEUE_LEN>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recorder {
+    pub const fn new() -> Self {
+        Self {
+            enabled: Mutex::new(Cell::new(false)),
+            frames: Channel::new(),
+        }
+    }
+
+    /// Adds a frame if recording is enabled.
+    pub fn record(&self, pad: &XboxGamepad) {
+        if !self.is_enabled() {
+            return;
+        }
+        let record = InputRecord {
+            timestamp_us: Instant::now().as_micros() as u32,
+            pad: *pad,
+        };
+        if self.frames.try_send(record).is_err() {
+            let _ = self.frames.try_receive();
+            let _ = self.frames.try_send(record);
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.lock(Cell::get)
+    }
+
+    /// Starts or stops recording, discarding unsent frames when stopping.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.lock(|cell| cell.set(enabled));
+        if !enabled {
+            while self.frames.try_receive().is_ok() {}
+        }
+    }
+}
+
+impl Transform for Recorder {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        self.record(&pad);
+        pad
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Injection {
+    Frame {
+        pad: XboxGamepad,
+        hold: Option<Duration>,
+    },
+    Release,
+}
+
+/// Synthetic input from the `inject` command, so test rigs can script
+/// button sequences without hardware on the frontend.
+///
+/// Wrap the physical source with [`Injector::source`]. Timed frames are
+/// played back to back; a frame without a hold time is reported until the
+/// next command. After the last timed frame or a `release` the physical
+/// input is reported again.
+pub struct Injector {
+    commands: Channel<CriticalSectionRawMutex, Injection, INJECT_QUEUE_LEN>,
+}
+
+impl Default for Injector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Injector {
+    pub const fn new() -> Self {
+        Self {
+            commands: Channel::new(),
+        }
+    }
+
+    /// Queues a frame, returns `false` if the queue is full.
+    pub fn inject(&self, pad: XboxGamepad, hold: Option<Duration>) -> bool {
+        self.commands
+            .try_send(Injection::Frame { pad, hold })
+            .is_ok()
+    }
+
+    /// Queues the return to the physical input, returns `false` if the
+    /// queue is full.
+    pub fn release(&self) -> bool {
+        self.commands.try_send(Injection::Release).is_ok()
+    }
+
+    /// `source` with injected frames taking precedence.
+    pub fn source<S: InputSource>(&self, source: S) -> Injected<'_, S> {
+        Injected {
+            injector: self,
+            source,
+            physical: XboxGamepad::new(),
+            injecting: false,
+            until: None,
+            last: None,
+        }
+    }
+}
+
+/// Input source returned by [`Injector::source`].
+pub struct Injected<'a, S> {
+    injector: &'a Injector,
+    source: S,
+    physical: XboxGamepad,
+    injecting: bool,
+    // End of the timed frame being reported.
+    until: Option<Instant>,
+    last: Option<XboxGamepad>,
+}
+
+impl<S> Injected<'_, S> {
+    /// Whether injected frames are reported.
+    pub fn is_injecting(&self) -> bool {
+        self.injecting
+    }
+}
+
+// The next command, which waits for the end of the timed frame `until`.
+// `None` if no command followed the timed frame.
+async fn next_command(injector: &Injector, until: &mut Option<Instant>) -> Option<Injection> {
+    match *until {
+        Some(deadline) => {
+            Timer::at(deadline).await;
+            *until = None;
+            injector.commands.try_receive().ok()
+        }
+        None => Some(injector.commands.receive().await),
+    }
+}
+
+impl<S: InputSource> InputSource for Injected<'_, S> {
+    async fn next(&mut self) -> XboxGamepad {
+        loop {
+            let pad = match select(
+                self.source.next(),
+                next_command(self.injector, &mut self.until),
+            )
+            .await
+            {
+                Either::First(pad) => {
+                    self.physical = pad;
+                    if self.injecting {
+                        continue;
+                    }
+                    pad
+                }
+                Either::Second(Some(Injection::Frame { pad, hold })) => {
+                    self.injecting = true;
+                    self.until = hold.map(|hold| Instant::now() + hold);
+                    pad
+                }
+                // Released, or the last timed frame ended.
+                Either::Second(Some(Injection::Release) | None) => {
+                    self.injecting = false;
+                    self.physical
+                }
+            };
+            if self.last != Some(pad) {
+                self.last = Some(pad);
+                return pad;
+            }
+        }
+    }
+}
+
+// Response line, replaced by an error if it does not fit into LINE_LEN.
+struct Line {
+    buf: [u8; LINE_LEN + 2],
+    len: usize,
+    overflow: bool,
+}
+
+impl Line {
+    fn new() -> Self {
+        Self {
+            buf: [0; LINE_LEN + 2],
+            len: 0,
+            overflow: false,
+        }
+    }
+
+    fn finish(&mut self) -> &[u8] {
+        if self.overflow {
+            *self = Self::new();
+            let _ = write!(self, "error: {}", ParseError::TooLong.message());
+        }
+        self.buf[self.len..self.len + 2].copy_from_slice(b"\r\n");
+        &self.buf[..self.len + 2]
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let Some(buf) = self.buf[..LINE_LEN].get_mut(self.len..self.len + s.len()) else {
+            self.overflow = true;
+            return Err(core::fmt::Error);
+        };
+        buf.copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+/// CDC-ACM interface serving the configuration protocol.
+pub struct ConfigSerial<'d, D: Driver<'d>> {
+    receiver: Receiver<'d, D>,
+    responder: Responder<'d, D>,
+}
+
+// Everything but the OUT endpoint, so responses and streamed data can be
+// written while a read is pending.
+struct Responder<'d, D: Driver<'d>> {
+    sender: Sender<'d, D>,
+    enter_bootloader: Option<EnterBootloader>,
+    counters: Option<fn() -> PacketCounters>,
+    link_quality: Option<fn() -> Option<u8>>,
+    injector: Option<&'d Injector>,
+    recorder: Option<&'d Recorder>,
+    capture: Option<&'d DescriptorCapture>,
+    diagnostics: Option<&'d Diagnostics>,
+}
+
+// Packet received on the OUT endpoint.
+struct Packet {
+    data: [u8; MAX_PACKET_SIZE as usize],
+    len: usize,
+}
+
+impl<'d, D: Driver<'d>> ConfigSerial<'d, D> {
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut cdc_acm::State<'d>) -> Self {
+        let (sender, receiver) = CdcAcmClass::new(builder, state, MAX_PACKET_SIZE).split();
+        Self {
+            receiver,
+            responder: Responder {
+                sender,
+                enter_bootloader: None,
+                counters: None,
+                link_quality: None,
+                injector: None,
+                recorder: None,
+                capture: None,
+                diagnostics: None,
+            },
+        }
+    }
+
+    /// Enables the `bootloader` command, which calls `hook`.
+    pub fn with_bootloader_hook(mut self, hook: EnterBootloader) -> Self {
+        self.responder.enter_bootloader = Some(hook);
+        self
+    }
+
+    /// Enables the `packets` command, which prints the counters returned by
+    /// `hook`, usually [`State::counters`](crate::xinput::State::counters)
+    /// of a static state.
+    pub fn with_packet_counters(mut self, hook: fn() -> PacketCounters) -> Self {
+        self.responder.counters = Some(hook);
+        self
+    }
+
+    /// Enables the `link` command, which prints the quality returned by
+    /// `hook`, usually
+    /// [`State::link_quality`](crate::xinput::State::link_quality) of a
+    /// static state.
+    pub fn with_link_quality(mut self, hook: fn() -> Option<u8>) -> Self {
+        self.responder.link_quality = Some(hook);
+        self
+    }
+
+    /// Enables the `inject` and `release` commands, which queue frames to
+    /// `injector`.
+    pub fn with_injector(mut self, injector: &'d Injector) -> Self {
+        self.responder.injector = Some(injector);
+        self
+    }
+
+    /// Enables the `record` command, which streams the frames of
+    /// `recorder`.
+    pub fn with_recorder(mut self, recorder: &'d Recorder) -> Self {
+        self.responder.recorder = Some(recorder);
+        self
+    }
+
+    /// Enables the `descriptor` command, which prints the data captured by
+    /// `capture`.
+    pub fn with_descriptor_capture(mut self, capture: &'d DescriptorCapture) -> Self {
+        self.responder.capture = Some(capture);
+        self
+    }
+
+    /// Enables the `diag` command, which controls `diagnostics`, and
+    /// streams its frames and statistics while the mode is on.
+    pub fn with_diagnostics(mut self, diagnostics: &'d Diagnostics) -> Self {
+        self.responder.diagnostics = Some(diagnostics);
+        self
+    }
+
+    /// Serves commands until the endpoint is disabled, e.g. because the
+    /// device was unplugged, then waits for the next connection.
+    ///
+    /// The `profile` commands answer with an error without `profiles`.
+    pub async fn run<const H: usize>(
+        mut self,
+        map: &Shared<ButtonMap>,
+        analog: &Shared<AnalogConfig>,
+        history: &History<H>,
+        profiles: Option<&Profiles<'_>>,
+    ) -> ! {
+        loop {
+            self.receiver.wait_connection().await;
+            debug!("config serial connected");
+            let packets = Channel::new();
+            // Reading in its own future keeps a read in progress when
+            // streamed data is written.
+            let _ = select(
+                read_packets(&mut self.receiver, &packets),
+                self.responder
+                    .serve(&packets, map, analog, history, profiles),
+            )
+            .await;
+            debug!("config serial disconnected");
+            if let Some(recorder) = self.responder.recorder {
+                recorder.set_enabled(false);
+            }
+        }
+    }
+}
+
+// Passes the received packets to `packets` until the endpoint fails.
+async fn read_packets<'d, D: Driver<'d>>(
+    receiver: &mut Receiver<'d, D>,
+    packets: &Channel<NoopRawMutex, Result<Packet, EndpointError>, 1>,
+) {
+    loop {
+        let mut data = [0; MAX_PACKET_SIZE as usize];
+        match receiver.read_packet(&mut data).await {
+            Ok(len) => packets.send(Ok(Packet { data, len })).await,
+            Err(error) => {
+                packets.send(Err(error)).await;
+                return;
+            }
+        }
+    }
+}
+
+impl<'d, D: Driver<'d>> Responder<'d, D> {
+    async fn write_diagnostics(&mut self, diagnostics: &Diagnostics) -> Result<(), EndpointError> {
+        for button in Button::ALL {
+            let stats = diagnostics.button(button);
+            if stats.presses == 0 {
+                continue;
+            }
+            let mut line = Line::new();
+            let _ = write!(
+                line,
+                "button {} {} {} ",
+                button.name(),
+                stats.presses,
+                stats.chatter
+            );
+            let _ = match stats.shortest {
+                Some(shortest) => write!(line, "{}", shortest.as_millis()),
+                None => line.write_str("-"),
+            };
+            self.write_line(&mut line).await?;
+        }
+        for axis in diagnostics::AXES {
+            if let Some((min, max)) = diagnostics.axis(axis).range {
+                let mut line = Line::new();
+                let _ = write!(
+                    line,
+                    "axis {} {} {}",
+                    diagnostics::axis_name(axis),
+                    min,
+                    max
+                );
+                self.write_line(&mut line).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_hex(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        for chunk in data.chunks(LINE_LEN / 2) {
+            let mut line = Line::new();
+            for byte in chunk {
+                let _ = write!(line, "{byte:02x}");
+            }
+            self.write_line(&mut line).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_line(&mut self, line: &mut Line) -> Result<(), EndpointError> {
+        let data = line.finish();
+        for chunk in data.chunks(usize::from(MAX_PACKET_SIZE)) {
+            self.sender.write_packet(chunk).await?;
+        }
+        if data.len() % usize::from(MAX_PACKET_SIZE) == 0 {
+            // Terminate the transfer.
+            self.sender.write_packet(&[]).await?;
+        }
+        Ok(())
+    }
+
+    async fn respond<const H: usize>(
+        &mut self,
+        command: Command<'_>,
+        map: &Shared<ButtonMap>,
+        analog: &Shared<AnalogConfig>,
+        history: &History<H>,
+        profiles: Option<&Profiles<'_>>,
+    ) -> Result<(), EndpointError> {
+        let mut line = Line::new();
+        match command {
+            Command::GetMap => {
+                let map = map.update(|map| *map);
+                for logical in Button::ALL {
+                    let physical = map.source(logical);
+                    if logical != physical {
+                        let mut entry = Line::new();
+                        let _ = write!(entry, "{}={}", logical.name(), physical.name());
+                        self.write_line(&mut entry).await?;
+                    }
+                }
+                let _ = line.write_str("ok");
+            }
+            Command::Map { logical, physical } => {
+                map.update(|map| map.map(physical, logical));
+                let _ = line.write_str("ok");
+            }
+            Command::Swap(a, b) => {
+                map.update(|map| map.swap(a, b));
+                let _ = line.write_str("ok");
+            }
+            Command::ResetMap => {
+                map.update(|map| *map = ButtonMap::identity());
+                let _ = line.write_str("ok");
+            }
+            Command::GetDeadzone(stick) => {
+                let config = analog.update(|analog| *stick_config(analog, stick));
+                let _ = write!(line, "{} {}", config.inner_deadzone, config.outer_deadzone);
+            }
+            Command::SetDeadzone {
+                stick,
+                inner,
+                outer,
+            } => {
+                analog.update(|analog| {
+                    let config = stick_config(analog, stick);
+                    config.inner_deadzone = inner;
+                    config.outer_deadzone = outer;
+                });
+                let _ = line.write_str("ok");
+            }
+            Command::Dump(n) => {
+                for age in (0..n.min(history.len())).rev() {
+                    let Some(pad) = history.get(age) else {
+                        continue;
+                    };
+                    let mut frame = Line::new();
+                    for byte in ControllerData::from(pad).0 {
+                        let _ = write!(frame, "{byte:02x}");
+                    }
+                    self.write_line(&mut frame).await?;
+                }
+                let _ = line.write_str("ok");
+            }
+            Command::Packets => match self.counters {
+                Some(counters) => {
+                    let counters = counters();
+                    let _ = write!(line, "queued {} sent {}", counters.queued, counters.sent);
+                }
+                None => {
+                    let _ = line.write_str("error: counters not available");
+                }
+            },
+            Command::LinkQuality => match self.link_quality.map(|hook| hook()) {
+                Some(Some(quality)) => {
+                    let _ = write!(line, "link {quality}");
+                }
+                Some(None) => {
+                    let _ = line.write_str("link unknown");
+                }
+                None => {
+                    let _ = line.write_str("error: link quality not available");
+                }
+            },
+            Command::Inject { .. } | Command::Release => {
+                let _ = line.write_str(match (self.injector, command) {
+                    (None, _) => "error: injection not available",
+                    (Some(injector), Command::Inject { data, hold }) => {
+                        match injector.inject(data.into(), hold) {
+                            true => "ok",
+                            false => "error: queue full",
+                        }
+                    }
+                    (Some(injector), _) => match injector.release() {
+                        true => "ok",
+                        false => "error: queue full",
+                    },
+                });
+            }
+            Command::Record(enabled) => match self.recorder {
+                Some(recorder) => {
+                    recorder.set_enabled(enabled);
+                    let _ = line.write_str("ok");
+                }
+                None => {
+                    let _ = line.write_str("error: recording not available");
+                }
+            },
+            Command::Descriptor(kind) => match self.capture {
+                Some(capture) => {
+                    match kind {
+                        DescriptorKind::Config => {
+                            let mut config = [0_u8; CAPTURE_LEN];
+                            let len = capture.config_descriptor(|data| {
+                                config[..data.len()].copy_from_slice(data);
+                                data.len()
+                            });
+                            self.write_hex(&config[..len]).await?;
+                        }
+                        DescriptorKind::ControllerInfo => {
+                            self.write_hex(&capture.controller_info()).await?;
+                        }
+                    }
+                    let _ = line.write_str("ok");
+                }
+                None => {
+                    let _ = line.write_str("error: descriptors not captured");
+                }
+            },
+            Command::EnterBootloader => match self.enter_bootloader {
+                Some(enter_bootloader) => {
+                    let _ = line.write_str("ok");
+                    self.write_line(&mut line).await?;
+                    // Give the host time to read the answer.
+                    Timer::after(BOOTLOADER_DELAY).await;
+                    enter_bootloader()
+                }
+                None => {
+                    let _ = line.write_str("error: bootloader not available");
+                }
+            },
+            Command::GetDiagnostics | Command::SetDiagnostics(_) | Command::ResetDiagnostics => {
+                match self.diagnostics {
+                    Some(diagnostics) => {
+                        match command {
+                            Command::SetDiagnostics(enabled) => diagnostics.set_enabled(enabled),
+                            Command::ResetDiagnostics => diagnostics.reset(),
+                            _ => self.write_diagnostics(diagnostics).await?,
+                        }
+                        let _ = line.write_str("ok");
+                    }
+                    None => {
+                        let _ = line.write_str("error: diagnostics not available");
+                    }
+                }
+            }
+            Command::ListProfiles | Command::SelectProfile(_) | Command::SaveProfile { .. } => {
+                match profiles {
+                    Some(profiles) => self.respond_profile(command, profiles, &mut line).await?,
+                    None => {
+                        let _ = line.write_str("error: profiles not available");
+                    }
+                }
+            }
+        }
+        self.write_line(&mut line).await
+    }
+
+    async fn respond_profile(
+        &mut self,
+        command: Command<'_>,
+        profiles: &Profiles<'_>,
+        line: &mut Line,
+    ) -> Result<(), EndpointError> {
+        let _ = line.write_str(match command {
+            Command::ListProfiles => {
+                for index in 0..profiles.len() {
+                    let Some(profile) = profiles.get(index) else {
+                        continue;
+                    };
+                    let mut entry = Line::new();
+                    let _ = write!(entry, "{} {}", index, profile.name());
+                    if profiles.active() == Some(index) {
+                        let _ = entry.write_str(" *");
+                    }
+                    self.write_line(&mut entry).await?;
+                }
+                "ok"
+            }
+            Command::SelectProfile(index) => match profiles.select(index) {
+                true => "ok",
+                false => "error: empty slot",
+            },
+            Command::SaveProfile { index, name } => match profiles.save(index, name) {
+                true => "ok",
+                false => "error: no such slot",
+            },
+            _ => "error: unknown command",
+        });
+        Ok(())
+    }
+
+    async fn serve<const H: usize>(
+        &mut self,
+        packets: &Channel<NoopRawMutex, Result<Packet, EndpointError>, 1>,
+        map: &Shared<ButtonMap>,
+        analog: &Shared<AnalogConfig>,
+        history: &History<H>,
+        profiles: Option<&Profiles<'_>>,
+    ) -> Result<(), EndpointError> {
+        let mut line = [0_u8; LINE_LEN];
+        let mut len = 0;
+        let mut overflow = false;
+        let mut stats = Ticker::every(DIAGNOSTICS_STATS_PERIOD);
+        loop {
+            let streamed = next_streamed(self.recorder, self.diagnostics, &mut stats);
+            let packet = match select(packets.receive(), streamed).await {
+                Either::First(packet) => packet?,
+                Either::Second(Streamed::Record(record)) => {
+                    self.sender.write_packet(&record.encode()).await?;
+                    continue;
+                }
+                Either::Second(Streamed::Raw(record)) => {
+                    let mut line = Line::new();
+                    let _ = line.write_str("raw ");
+                    for byte in ControllerData::from(record.pad).0 {
+                        let _ = write!(line, "{byte:02x}");
+                    }
+                    let _ = write!(line, " {}", record.timestamp_us);
+                    self.write_line(&mut line).await?;
+                    continue;
+                }
+                Either::Second(Streamed::Stats(diagnostics)) => {
+                    self.write_diagnostics(diagnostics).await?;
+                    continue;
+                }
+            };
+            for &byte in &packet.data[..packet.len] {
+                if byte != b'\r' && byte != b'\n' {
+                    match line.get_mut(len) {
+                        Some(slot) => *slot = byte,
+                        None => overflow = true,
+                    }
+                    len += 1;
+                    continue;
+                }
+                if len == 0 {
+                    // Second half of a CRLF line ending.
+                    continue;
+                }
+                let command = if overflow {
+                    Err(ParseError::TooLong)
+                } else {
+                    core::str::from_utf8(&line[..len])
+                        .map_err(|_| ParseError::InvalidArguments)
+                        .and_then(Command::parse)
+                };
+                (len, overflow) = (0, false);
+                match command {
+                    Ok(command) => {
+                        debug!("config serial: {:?}", command);
+                        self.respond(command, map, analog, history, profiles)
+                            .await?;
+                    }
+                    Err(e) => {
+                        let mut response = Line::new();
+                        let _ = write!(response, "error: {}", e.message());
+                        self.write_line(&mut response).await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Data sent without a command.
+enum Streamed<'a> {
+    Record(InputRecord),
+    Raw(InputRecord),
+    Stats(&'a Diagnostics),
+}
+
+async fn next_streamed<'a>(
+    recorder: Option<&Recorder>,
+    diagnostics: Option<&'a Diagnostics>,
+    stats: &mut Ticker,
+) -> Streamed<'a> {
+    let record = async {
+        match recorder {
+            Some(recorder) => recorder.frames.receive().await,
+            None => pending().await,
+        }
+    };
+    let diagnostics = async {
+        let Some(diagnostics) = diagnostics else {
+            return pending().await;
+        };
+        loop {
+            match select(diagnostics.frames.receive(), stats.next()).await {
+                Either::First(record) => return Streamed::Raw(record),
+                Either::Second(()) if diagnostics.is_enabled() => {
+                    return Streamed::Stats(diagnostics)
+                }
+                Either::Second(()) => {}
+            }
+        }
+    };
+    match select(record, diagnostics).await {
+        Either::First(record) => Streamed::Record(record),
+        Either::Second(streamed) => streamed,
+    }
+}
+
+fn stick_config(analog: &mut AnalogConfig, stick: Stick) -> &mut StickConfig {
+    match stick {
+        Stick::Left => &mut analog.left,
+        Stick::Right => &mut analog.right,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn commands_are_parsed() {
+        let cases = [
+            ("map", Command::GetMap),
+            (
+                "map a b",
+                Command::Map {
+                    logical: Button::A,
+                    physical: Button::B,
+                },
+            ),
+            (
+                "  swap lb   rb ",
+                Command::Swap(Button::LeftShoulder, Button::RightShoulder),
+            ),
+            ("reset", Command::ResetMap),
+            ("deadzone left", Command::GetDeadzone(Stick::Left)),
+            (
+                "deadzone right 100 30000",
+                Command::SetDeadzone {
+                    stick: Stick::Right,
+                    inner: 100,
+                    outer: 30000,
+                },
+            ),
+            ("dump", Command::Dump(usize::MAX)),
+            ("dump 5", Command::Dump(5)),
+            ("profile", Command::ListProfiles),
+            ("profile 2", Command::SelectProfile(2)),
+            (
+                "profile save 1 race",
+                Command::SaveProfile {
+                    index: 1,
+                    name: "race",
+                },
+            ),
+            ("packets", Command::Packets),
+            ("link", Command::LinkQuality),
+            (
+                "inject 000102030405060708090a0b 250",
+                Command::Inject {
+                    data: ControllerData([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]),
+                    hold: Some(Duration::from_millis(250)),
+                },
+            ),
+            ("release", Command::Release),
+            ("record on", Command::Record(true)),
+            ("record off", Command::Record(false)),
+            (
+                "descriptor info",
+                Command::Descriptor(DescriptorKind::ControllerInfo),
+            ),
+            ("bootloader BOOT", Command::EnterBootloader),
+            ("diag", Command::GetDiagnostics),
+            ("diag reset", Command::ResetDiagnostics),
+        ];
+        for (line, command) in cases {
+            assert_eq!(Command::parse(line), Ok(command), "{line}");
+        }
+    }
+
+    #[test]
+    fn malformed_commands_are_rejected() {
+        let cases = [
+            ("", ParseError::Empty),
+            ("   ", ParseError::Empty),
+            ("jump", ParseError::UnknownCommand),
+            ("map a", ParseError::InvalidArguments),
+            ("map a z", ParseError::InvalidArguments),
+            ("deadzone up", ParseError::InvalidArguments),
+            ("deadzone left 1 70000", ParseError::InvalidArguments),
+            ("dump 1 2 3 4", ParseError::InvalidArguments),
+            ("inject 0001", ParseError::InvalidArguments),
+            (
+                "inject 000102030405060708090a0g",
+                ParseError::InvalidArguments,
+            ),
+            ("record maybe", ParseError::InvalidArguments),
+            ("bootloader boot", ParseError::InvalidArguments),
+            ("packets now", ParseError::InvalidArguments),
+        ];
+        for (line, error) in cases {
+            assert_eq!(Command::parse(line), Err(error), "{line}");
+        }
+    }
+
+    #[test]
+    fn responses_parse_back_into_commands() {
+        // `dump` frames are accepted by `inject`.
+        let mut pad = XboxGamepad::new();
+        pad.btn_a = true;
+        pad.thumb_left_x = -12345;
+        pad.trigger_right = -1;
+        let data = ControllerData::from(pad);
+        let mut frame = Line::new();
+        let _ = frame.write_str("inject ");
+        for byte in data.0 {
+            let _ = write!(frame, "{byte:02x}");
+        }
+        let frame = core::str::from_utf8(&frame.buf[..frame.len]).unwrap();
+        assert_eq!(
+            Command::parse(frame),
+            Ok(Command::Inject { data, hold: None })
+        );
+
+        // `map` entries are accepted by `map <logical> <physical>`.
+        for (logical, physical) in Button::ALL.into_iter().zip(Button::ALL.into_iter().rev()) {
+            let mut entry = Line::new();
+            let _ = write!(entry, "{}={}", logical.name(), physical.name());
+            let entry = core::str::from_utf8(&entry.buf[..entry.len]).unwrap();
+            let (logical_name, physical_name) = entry.split_once('=').unwrap();
+            assert_eq!(
+                Command::parse(&std::format!("map {logical_name} {physical_name}")),
+                Ok(Command::Map { logical, physical })
+            );
+        }
+    }
+
+    #[test]
+    fn long_responses_are_replaced_by_an_error() {
+        let mut line = Line::new();
+        assert!(line.write_str(&"x".repeat(LINE_LEN)).is_ok());
+        assert_eq!(line.finish().len(), LINE_LEN + 2);
+
+        let mut line = Line::new();
+        let _ = line.write_str("profile ");
+        assert!(line.write_str(&"x".repeat(LINE_LEN)).is_err());
+        assert_eq!(line.finish(), b"error: line too long\r\n");
+    }
+}
@@ -0,0 +1,169 @@
+//! HID consumer control interface (volume, media keys) next to the XInput
+//! interfaces, so extra buttons of a controller can send media keys.
+//!
+//! The device becomes a composite device with an XInput and a HID function.
+//! Use [`usb_config_wireless_receiver_composite`] and add all
+//! [`XInput`](crate::xinput::XInput) slots before this interface. The slots
+//! then keep the interface numbers of a genuine receiver, which Windows
+//! matches when binding its XInput driver to the composite device's
+//! children.
+//!
+//! [`usb_config_wireless_receiver_composite`]: crate::presets::usb_config_wireless_receiver_composite
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::{Channel, TrySendError};
+use embassy_usb::class::hid::{self, HidWriter};
+use embassy_usb::driver::Driver;
+use embassy_usb::Builder;
+
+use crate::hid_descriptor::{flags, page, usage, Collection, ReportDescriptor};
+
+/// Length of the input report: one 16 bit usage.
+pub const REPORT_LEN: usize = 2;
+const QUEUE_LEN: usize = 8;
+
+/// Report descriptor of the consumer control interface.
+pub const REPORT_DESCRIPTOR: &[u8] = &DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>();
+
+const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new()
+    .usage_page(page::CONSUMER)
+    .usage(usage::CONSUMER_CONTROL)
+    .collection(Collection::Application)
+    .logical_minimum(0)
+    .logical_maximum(1023)
+    .usage_minimum(0)
+    .usage_maximum(1023)
+    .report_size(16)
+    .report_count(1)
+    .input(flags::DATA_ARRAY_ABS)
+    .end_collection();
+
+/// Usage ID of the consumer page.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Usage(pub u16);
+
+impl Usage {
+    /// No key held.
+    pub const NONE: Usage = Usage(0x000);
+    pub const PLAY_PAUSE: Usage = Usage(0x0CD);
+    pub const NEXT_TRACK: Usage = Usage(0x0B5);
+    pub const PREVIOUS_TRACK: Usage = Usage(0x0B6);
+    pub const STOP: Usage = Usage(0x0B7);
+    pub const MUTE: Usage = Usage(0x0E2);
+    pub const VOLUME_UP: Usage = Usage(0x0E9);
+    pub const VOLUME_DOWN: Usage = Usage(0x0EA);
+}
+
+/// Shared state between the application and the [`ConsumerControl`] task.
+///
+/// Like [`xinput::State`](crate::xinput::State) all methods can be called
+/// from any context. Up to 8 changes are queued, the oldest is dropped when
+/// the queue is full.
+pub struct State {
+    usages: Channel<CriticalSectionRawMutex, Usage, QUEUE_LEN>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            usages: Channel::new(),
+        }
+    }
+
+    fn send(&self, mut usage: Usage) {
+        while let Err(TrySendError::Full(rejected)) = self.usages.try_send(usage) {
+            let _ = self.usages.try_receive();
+            usage = rejected;
+        }
+    }
+
+    /// Reports `usage` as held until [`State::release`].
+    pub fn press(&self, usage: Usage) {
+        self.send(usage);
+    }
+
+    pub fn release(&self) {
+        self.send(Usage::NONE);
+    }
+
+    /// Presses and releases `usage`.
+    pub fn tap(&self, usage: Usage) {
+        self.press(usage);
+        self.release();
+    }
+}
+
+/// HID interface sending the usages of a [`State`].
+pub struct ConsumerControl<'d, D: Driver<'d>> {
+    writer: HidWriter<'d, D, REPORT_LEN>,
+    state: &'d State,
+}
+
+impl<'d, D: Driver<'d>> ConsumerControl<'d, D> {
+    /// Adds the interface to `builder`, after the XInput interfaces.
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        hid_state: &'d mut hid::State<'d>,
+        state: &'d State,
+    ) -> Self {
+        let config = hid::Config {
+            report_descriptor: REPORT_DESCRIPTOR,
+            request_handler: None,
+            poll_ms: 10,
+            max_packet_size: REPORT_LEN as u16,
+        };
+        Self {
+            writer: HidWriter::new(builder, hid_state, config),
+            state,
+        }
+    }
+
+    /// Sends the queued usages while the device is configured.
+    pub async fn run(mut self) -> ! {
+        loop {
+            self.writer.ready().await;
+            debug!("consumer control ready");
+            loop {
+                let usage = self.state.usages.receive().await;
+                trace!("consumer control usage {:#X}", usage.0);
+                if self.writer.write(&usage.0.to_le_bytes()).await.is_err() {
+                    // Disabled, the usage is dropped like a stale input update.
+                    debug!("consumer control disabled");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const GOLDEN: &[u8] = &[
+        0x05, 0x0C,       // Usage Page (Consumer)
+        0x09, 0x01,       // Usage (Consumer Control)
+        0xA1, 0x01,       // Collection (Application)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x26, 0xFF, 0x03, //   Logical Maximum (1023)
+        0x19, 0x00,       //   Usage Minimum (0)
+        0x2A, 0xFF, 0x03, //   Usage Maximum (1023)
+        0x75, 0x10,       //   Report Size (16)
+        0x95, 0x01,       //   Report Count (1)
+        0x81, 0x00,       //   Input (Data, Array, Absolute)
+        0xC0,             // End Collection
+    ];
+
+    #[test]
+    fn report_descriptor_bytes() {
+        assert_eq!(REPORT_DESCRIPTOR, GOLDEN);
+    }
+}
@@ -1,6 +1,8 @@
-use crate::xinput::ControllerData;
+use crate::protocol::ControllerData;
 
 /// xbox 360 controller inputs
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct XboxGamepad {
     pub dpad_up: bool,
     pub dpad_down: bool,
@@ -25,6 +27,185 @@ pub struct XboxGamepad {
     pub thumb_right_y: i16,
 }
 
+/// Digital buttons of an [`XboxGamepad`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Button {
+    DpadUp,
+    DpadDown,
+    DpadLeft,
+    DpadRight,
+    Start,
+    Back,
+    LeftThumb,
+    RightThumb,
+    LeftShoulder,
+    RightShoulder,
+    Guide,
+    A,
+    B,
+    X,
+    Y,
+}
+
+impl Button {
+    pub const ALL: [Button; 15] = [
+        Button::DpadUp,
+        Button::DpadDown,
+        Button::DpadLeft,
+        Button::DpadRight,
+        Button::Start,
+        Button::Back,
+        Button::LeftThumb,
+        Button::RightThumb,
+        Button::LeftShoulder,
+        Button::RightShoulder,
+        Button::Guide,
+        Button::A,
+        Button::B,
+        Button::X,
+        Button::Y,
+    ];
+
+    /// Short name used by the configuration channels.
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::DpadUp => "up",
+            Button::DpadDown => "down",
+            Button::DpadLeft => "left",
+            Button::DpadRight => "right",
+            Button::Start => "start",
+            Button::Back => "back",
+            Button::LeftThumb => "ls",
+            Button::RightThumb => "rs",
+            Button::LeftShoulder => "lb",
+            Button::RightShoulder => "rb",
+            Button::Guide => "guide",
+            Button::A => "a",
+            Button::B => "b",
+            Button::X => "x",
+            Button::Y => "y",
+        }
+    }
+
+    /// Button called `name`, see [`Button::name`].
+    pub fn from_name(name: &str) -> Option<Button> {
+        Button::ALL.into_iter().find(|button| button.name() == name)
+    }
+}
+
+/// Analog triggers of an [`XboxGamepad`]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trigger {
+    Left,
+    Right,
+}
+
+impl Trigger {
+    pub const ALL: [Trigger; 2] = [Trigger::Left, Trigger::Right];
+
+    /// Short name used by the configuration channels.
+    pub fn name(self) -> &'static str {
+        match self {
+            Trigger::Left => "left",
+            Trigger::Right => "right",
+        }
+    }
+
+    /// Trigger called `name`, see [`Trigger::name`].
+    pub fn from_name(name: &str) -> Option<Trigger> {
+        Trigger::ALL
+            .into_iter()
+            .find(|trigger| trigger.name() == name)
+    }
+}
+
+impl XboxGamepad {
+    /// Neutral state: no buttons pressed, sticks centered, triggers released.
+    pub const fn new() -> Self {
+        Self {
+            dpad_up: false,
+            dpad_down: false,
+            dpad_left: false,
+            dpad_right: false,
+            btn_start: false,
+            btn_back: false,
+            btn_left_thumb: false,
+            btn_right_thumb: false,
+            btn_left_shoulder: false,
+            btn_right_shoulder: false,
+            btn_guide: false,
+            btn_a: false,
+            btn_b: false,
+            btn_x: false,
+            btn_y: false,
+            trigger_left: 0,
+            trigger_right: 0,
+            thumb_left_x: 0,
+            thumb_left_y: 0,
+            thumb_right_x: 0,
+            thumb_right_y: 0,
+        }
+    }
+
+    pub fn button(&self, button: Button) -> bool {
+        match button {
+            Button::DpadUp => self.dpad_up,
+            Button::DpadDown => self.dpad_down,
+            Button::DpadLeft => self.dpad_left,
+            Button::DpadRight => self.dpad_right,
+            Button::Start => self.btn_start,
+            Button::Back => self.btn_back,
+            Button::LeftThumb => self.btn_left_thumb,
+            Button::RightThumb => self.btn_right_thumb,
+            Button::LeftShoulder => self.btn_left_shoulder,
+            Button::RightShoulder => self.btn_right_shoulder,
+            Button::Guide => self.btn_guide,
+            Button::A => self.btn_a,
+            Button::B => self.btn_b,
+            Button::X => self.btn_x,
+            Button::Y => self.btn_y,
+        }
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        let field = match button {
+            Button::DpadUp => &mut self.dpad_up,
+            Button::DpadDown => &mut self.dpad_down,
+            Button::DpadLeft => &mut self.dpad_left,
+            Button::DpadRight => &mut self.dpad_right,
+            Button::Start => &mut self.btn_start,
+            Button::Back => &mut self.btn_back,
+            Button::LeftThumb => &mut self.btn_left_thumb,
+            Button::RightThumb => &mut self.btn_right_thumb,
+            Button::LeftShoulder => &mut self.btn_left_shoulder,
+            Button::RightShoulder => &mut self.btn_right_shoulder,
+            Button::Guide => &mut self.btn_guide,
+            Button::A => &mut self.btn_a,
+            Button::B => &mut self.btn_b,
+            Button::X => &mut self.btn_x,
+            Button::Y => &mut self.btn_y,
+        };
+        *field = pressed;
+    }
+
+    /// Trigger value, 0 is released.
+    pub fn trigger(&self, trigger: Trigger) -> u8 {
+        match trigger {
+            Trigger::Left => self.trigger_left as u8,
+            Trigger::Right => self.trigger_right as u8,
+        }
+    }
+
+    pub fn set_trigger(&mut self, trigger: Trigger, value: u8) {
+        match trigger {
+            Trigger::Left => self.trigger_left = value as i8,
+            Trigger::Right => self.trigger_right = value as i8,
+        }
+    }
+}
+
 impl From<XboxGamepad> for ControllerData {
     fn from(joy: XboxGamepad) -> Self {
         let mut xinput_data = [0_u8; 12];
@@ -67,3 +248,53 @@ impl From<XboxGamepad> for ControllerData {
         Self(xinput_data)
     }
 }
+
+impl From<ControllerData> for XboxGamepad {
+    fn from(data: ControllerData) -> Self {
+        let [b0, b1, trigger_left, trigger_right, lx0, lx1, ly0, ly1, rx0, rx1, ry0, ry1] = data.0;
+        let bit = |byte: u8, bit: u8| byte & (1 << bit) != 0;
+
+        Self {
+            dpad_up: bit(b0, 0),
+            dpad_down: bit(b0, 1),
+            dpad_left: bit(b0, 2),
+            dpad_right: bit(b0, 3),
+            btn_start: bit(b0, 4),
+            btn_back: bit(b0, 5),
+            btn_left_thumb: bit(b0, 6),
+            btn_right_thumb: bit(b0, 7),
+            btn_left_shoulder: bit(b1, 0),
+            btn_right_shoulder: bit(b1, 1),
+            btn_guide: bit(b1, 2),
+            btn_a: bit(b1, 4),
+            btn_b: bit(b1, 5),
+            btn_x: bit(b1, 6),
+            btn_y: bit(b1, 7),
+            trigger_left: i8::from_le_bytes([trigger_left]),
+            trigger_right: i8::from_le_bytes([trigger_right]),
+            thumb_left_x: i16::from_le_bytes([lx0, lx1]),
+            thumb_left_y: i16::from_le_bytes([ly0, ly1]),
+            thumb_right_x: i16::from_le_bytes([rx0, rx1]),
+            thumb_right_y: i16::from_le_bytes([ry0, ry1]),
+        }
+    }
+}
+
+/// Motion sensor sample, reported next to [`XboxGamepad`] by controllers
+/// with an IMU.
+///
+/// XInput has no motion data, backends that do embed it in their reports.
+/// Axes are the ones of the sensor as mounted. Values span the full
+/// `i16` range for ±2000 °/s ([`ImuSample::GYRO_FULL_SCALE_DPS`]) and ±8 g
+/// ([`ImuSample::ACCEL_FULL_SCALE_G`]), whatever sensor produced them.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImuSample {
+    pub gyro: [i16; 3],
+    pub accel: [i16; 3],
+}
+
+impl ImuSample {
+    pub const GYRO_FULL_SCALE_DPS: i32 = 2000;
+    pub const ACCEL_FULL_SCALE_G: i32 = 8;
+}
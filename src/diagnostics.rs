@@ -0,0 +1,259 @@
+//! Diagnostics mode for bringing up new hardware.
+//!
+//! [`Diagnostics`] is a pass-through [`Transform`] that, while the mode is
+//! on, collects the frontend states passing through it: every frame is
+//! queued for streaming, each button counts presses and chatter, and each
+//! axis keeps the smallest and largest value seen. Put it first in the
+//! pipeline to see the raw frontend data, and toggle it with a
+//! [`Hotkey`](crate::hotkeys::Hotkey):
+//!
+//! ```ignore
+//! static DIAGNOSTICS: Diagnostics = Diagnostics::new();
+//! static HOTKEYS: [Hotkey; 1] = [
+//!     Hotkey::new(&[Button::Start, Button::Back, Button::Y], Duration::from_secs(3), || DIAGNOSTICS.toggle()),
+//! ];
+//! let transform = (&DIAGNOSTICS, Hotkeys::new(&HOTKEYS), (&BUTTON_MAP, DIAGNOSTICS.mute()));
+//! ```
+//!
+//! With the `config-serial` feature, `ConfigSerial::with_diagnostics`
+//! streams the data over the serial channel and adds the `diag` command.
+//! With the `display` feature, `StatusDisplay::with_diagnostics` shows a
+//! test pattern while the mode is on.
+
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::Channel;
+use embassy_time::{Duration, Instant};
+
+use crate::controller::{Button, Trigger, XboxGamepad};
+use crate::host::InputRecord;
+use crate::input::analog_adc::Axis;
+use crate::remap::Transform;
+
+/// Number of frames buffered for streaming; older frames are dropped.
+pub const DIAGNOSTICS_QUEUE_LEN: usize = 16;
+
+/// Axes tracked by [`Diagnostics::axis`], in the order of the `diag`
+/// output.
+pub const AXES: [Axis; 6] = [
+    Axis::LeftX,
+    Axis::LeftY,
+    Axis::RightX,
+    Axis::RightY,
+    Axis::LeftTrigger,
+    Axis::RightTrigger,
+];
+
+/// Short name of an axis used by the `diag` command.
+pub fn axis_name(axis: Axis) -> &'static str {
+    match axis {
+        Axis::LeftX => "lx",
+        Axis::LeftY => "ly",
+        Axis::RightX => "rx",
+        Axis::RightY => "ry",
+        Axis::LeftTrigger => "lt",
+        Axis::RightTrigger => "rt",
+    }
+}
+
+fn axis_value(pad: &XboxGamepad, axis: Axis) -> i16 {
+    match axis {
+        Axis::LeftX => pad.thumb_left_x,
+        Axis::LeftY => pad.thumb_left_y,
+        Axis::RightX => pad.thumb_right_x,
+        Axis::RightY => pad.thumb_right_y,
+        Axis::LeftTrigger => i16::from(pad.trigger(Trigger::Left)),
+        Axis::RightTrigger => i16::from(pad.trigger(Trigger::Right)),
+    }
+}
+
+/// Debounce statistics of a button.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ButtonStats {
+    pub presses: u32,
+    /// Presses or releases that lasted less than the chatter window, a sign
+    /// of a bouncing switch or a too short debounce time.
+    pub chatter: u32,
+    /// Shortest press seen.
+    pub shortest: Option<Duration>,
+}
+
+/// Range of values an axis reached, `None` before the first frame.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AxisStats {
+    pub range: Option<(i16, i16)>,
+}
+
+struct Stats {
+    last: Option<XboxGamepad>,
+    // Time of the last change of every button.
+    changed: [Instant; Button::ALL.len()],
+    buttons: [ButtonStats; Button::ALL.len()],
+    axes: [AxisStats; AXES.len()],
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            last: None,
+            changed: [Instant::from_ticks(0); Button::ALL.len()],
+            buttons: [ButtonStats {
+                presses: 0,
+                chatter: 0,
+                shortest: None,
+            }; Button::ALL.len()],
+            axes: [AxisStats { range: None }; AXES.len()],
+        }
+    }
+
+    fn update(&mut self, pad: &XboxGamepad, now: Instant, chatter_window: Duration) {
+        for (i, button) in Button::ALL.into_iter().enumerate() {
+            let pressed = pad.button(button);
+            let was_pressed = self.last.is_some_and(|last| last.button(button));
+            if pressed == was_pressed {
+                continue;
+            }
+            let stats = &mut self.buttons[i];
+            let held = now - self.changed[i];
+            // The first press has no previous release to measure.
+            if (!pressed || stats.presses > 0) && held < chatter_window {
+                stats.chatter += 1;
+            }
+            if pressed {
+                stats.presses += 1;
+            } else {
+                stats.shortest = Some(stats.shortest.map_or(held, |shortest| shortest.min(held)));
+            }
+            self.changed[i] = now;
+        }
+        for (stats, axis) in self.axes.iter_mut().zip(AXES) {
+            let value = axis_value(pad, axis);
+            stats.range = Some(match stats.range {
+                Some((min, max)) => (min.min(value), max.max(value)),
+                None => (value, value),
+            });
+        }
+        self.last = Some(*pad);
+    }
+}
+
+/// Diagnostics mode state, see the [module documentation](self).
+///
+/// Like [`xinput::State`](crate::xinput::State) it can be used from any
+/// context.
+pub struct Diagnostics {
+    enabled: Mutex<CriticalSectionRawMutex, Cell<bool>>,
+    stats: Mutex<CriticalSectionRawMutex, RefCell<Stats>>,
+    pub(crate) frames: Channel<CriticalSectionRawMutex, InputRecord, DIAGNOSTICS_QUEUE_LEN>,
+    chatter_window: Duration,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Diagnostics {
+    /// Counts presses and releases shorter than 20 ms as chatter.
+    pub const fn new() -> Self {
+        Self::with_chatter_window(Duration::from_millis(20))
+    }
+
+    /// Counts presses and releases shorter than `window` as chatter.
+    pub const fn with_chatter_window(window: Duration) -> Self {
+        Self {
+            enabled: Mutex::new(Cell::new(false)),
+            stats: Mutex::new(RefCell::new(Stats::new())),
+            frames: Channel::new(),
+            chatter_window: window,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.lock(Cell::get)
+    }
+
+    /// Turns the mode on or off. Turning it on starts with fresh
+    /// statistics, turning it off discards unsent frames.
+    pub fn set_enabled(&self, enabled: bool) {
+        if self.enabled.lock(|cell| cell.replace(enabled)) == enabled {
+            return;
+        }
+        debug!("diagnostics: {}", if enabled { "on" } else { "off" });
+        if enabled {
+            self.reset();
+        } else {
+            while self.frames.try_receive().is_ok() {}
+        }
+    }
+
+    /// Switches the mode, e.g. as the action of a hotkey.
+    pub fn toggle(&self) {
+        self.set_enabled(!self.is_enabled());
+    }
+
+    /// Clears the statistics.
+    pub fn reset(&self) {
+        self.stats.lock(|stats| *stats.borrow_mut() = Stats::new());
+    }
+
+    /// Statistics of `button` since the mode was turned on.
+    pub fn button(&self, button: Button) -> ButtonStats {
+        self.stats
+            .lock(|stats| stats.borrow().buttons[button as usize])
+    }
+
+    /// Range of `axis` since the mode was turned on.
+    pub fn axis(&self, axis: Axis) -> AxisStats {
+        self.stats.lock(|stats| stats.borrow().axes[axis as usize])
+    }
+
+    /// Records `pad` as sampled at `now` if the mode is on, see
+    /// [`Transform::transform`].
+    pub fn record_at(&self, pad: &XboxGamepad, now: Instant) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.stats
+            .lock(|stats| stats.borrow_mut().update(pad, now, self.chatter_window));
+        let record = InputRecord {
+            timestamp_us: now.as_micros() as u32,
+            pad: *pad,
+        };
+        if self.frames.try_send(record).is_err() {
+            let _ = self.frames.try_receive();
+            let _ = self.frames.try_send(record);
+        }
+    }
+
+    /// [`Transform`] reporting a neutral gamepad while the mode is on, so
+    /// testing the buttons does not control the host. Put it after the
+    /// hotkeys, so the chord still leaves the mode.
+    pub fn mute(&self) -> Mute<'_> {
+        Mute(self)
+    }
+}
+
+impl Transform for Diagnostics {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        self.record_at(&pad, Instant::now());
+        pad
+    }
+}
+
+/// Transform returned by [`Diagnostics::mute`].
+pub struct Mute<'a>(&'a Diagnostics);
+
+impl Transform for Mute<'_> {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        match self.0.is_enabled() {
+            true => XboxGamepad::new(),
+            false => pad,
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! HID dance pad personality: 16 buttons and no axes or hat switch, so
+//! opposite arrows can be reported at the same time.
+//!
+//! Rhythm games like StepMania read the arrows as buttons 1 to 4 in the
+//! left, down, up, right column order. [`State`] implements
+//! [`ReportSink`], so panels from
+//! [`input::dance_pad`](crate::input::dance_pad) are routed into it with
+//! [`input::route`](crate::input::route), see
+//! [`DancePadState::from_gamepad`]. Multi-player builds add one interface
+//! per pad.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_usb::class::hid::{self, HidWriter};
+use embassy_usb::driver::Driver;
+use embassy_usb::Builder;
+
+use crate::controller::XboxGamepad;
+use crate::hid_descriptor::{page, usage, Collection, ReportDescriptor};
+use crate::transport::{Backend, Capabilities, ReportSink};
+
+/// Length of the input report.
+pub const REPORT_LEN: usize = 2;
+
+/// Report descriptor of the dance pad interface.
+pub const REPORT_DESCRIPTOR: &[u8] = &DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>();
+
+const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new()
+    .usage_page(page::GENERIC_DESKTOP)
+    .usage(usage::GAME_PAD)
+    .collection(Collection::Application)
+    .buttons(16)
+    .end_collection();
+
+/// Dance pad state.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DancePadState {
+    /// Bit 0 is button 1.
+    pub buttons: u16,
+}
+
+impl DancePadState {
+    /// Maps a gamepad state: buttons 1 to 4 are the dpad left, down, up
+    /// and right, buttons 5 to 13 are A, B, X, Y, back, start, guide, LB
+    /// and RB. Opposite directions are kept.
+    pub fn from_gamepad(pad: &XboxGamepad) -> Self {
+        let buttons = [
+            pad.dpad_left,
+            pad.dpad_down,
+            pad.dpad_up,
+            pad.dpad_right,
+            pad.btn_a,
+            pad.btn_b,
+            pad.btn_x,
+            pad.btn_y,
+            pad.btn_back,
+            pad.btn_start,
+            pad.btn_guide,
+            pad.btn_left_shoulder,
+            pad.btn_right_shoulder,
+        ];
+        Self {
+            buttons: buttons
+                .iter()
+                .enumerate()
+                .fold(0, |bits, (i, &pressed)| bits | (u16::from(pressed) << i)),
+        }
+    }
+
+    /// Encodes the input report described by [`REPORT_DESCRIPTOR`].
+    pub fn input_report(&self) -> [u8; REPORT_LEN] {
+        self.buttons.to_le_bytes()
+    }
+}
+
+/// Shared state between the application and the [`HidDancePad`] task.
+///
+/// Like [`xinput::State`](crate::xinput::State) all methods can be called
+/// from any context. Only the latest state is reported.
+pub struct State {
+    pad: Mutex<CriticalSectionRawMutex, Cell<DancePadState>>,
+    changed: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            pad: Mutex::new(Cell::new(DancePadState { buttons: 0 })),
+            changed: Signal::new(),
+        }
+    }
+
+    /// Publishes new dance pad state.
+    pub fn send_dance_pad(&self, pad: DancePadState) {
+        self.pad.lock(|cell| cell.set(pad));
+        self.changed.signal(());
+    }
+
+    /// Latest published dance pad state.
+    pub fn dance_pad(&self) -> DancePadState {
+        self.pad.lock(Cell::get)
+    }
+}
+
+impl ReportSink for State {
+    fn send(&self, pad: &XboxGamepad) {
+        self.send_dance_pad(DancePadState::from_gamepad(pad));
+    }
+}
+
+impl Backend for State {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
+/// HID interface sending the dance pad state of a [`State`].
+pub struct HidDancePad<'d, D: Driver<'d>> {
+    writer: HidWriter<'d, D, REPORT_LEN>,
+    state: &'d State,
+}
+
+impl<'d, D: Driver<'d>> HidDancePad<'d, D> {
+    /// Adds the interface to `builder`, after the XInput interfaces if
+    /// there are any.
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        hid_state: &'d mut hid::State<'d>,
+        state: &'d State,
+    ) -> Self {
+        let config = hid::Config {
+            report_descriptor: REPORT_DESCRIPTOR,
+            request_handler: None,
+            poll_ms: 1,
+            max_packet_size: REPORT_LEN as u16,
+        };
+        Self {
+            writer: HidWriter::new(builder, hid_state, config),
+            state,
+        }
+    }
+
+    /// Sends the dance pad state on every change while the device is
+    /// configured.
+    pub async fn run(mut self) -> ! {
+        loop {
+            self.writer.ready().await;
+            debug!("hid dance pad ready");
+            // Report the current state to a newly configured host.
+            self.state.changed.signal(());
+            loop {
+                self.state.changed.wait().await;
+                let report = self.state.dance_pad().input_report();
+                if self.writer.write(&report).await.is_err() {
+                    debug!("hid dance pad disabled");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const GOLDEN: &[u8] = &[
+        0x05, 0x01,       // Usage Page (Generic Desktop)
+        0x09, 0x05,       // Usage (Game Pad)
+        0xA1, 0x01,       // Collection (Application)
+        // 16 buttons
+        0x05, 0x09,       //   Usage Page (Button)
+        0x19, 0x01,       //   Usage Minimum (1)
+        0x29, 0x10,       //   Usage Maximum (16)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x25, 0x01,       //   Logical Maximum (1)
+        0x75, 0x01,       //   Report Size (1)
+        0x95, 0x10,       //   Report Count (16)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        0xC0,             // End Collection
+    ];
+
+    #[test]
+    fn report_descriptor_bytes() {
+        assert_eq!(REPORT_DESCRIPTOR, GOLDEN);
+    }
+}
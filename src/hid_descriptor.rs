@@ -0,0 +1,428 @@
+//! Builder for HID report descriptors, evaluated at compile time.
+//!
+//! The HID backends describe their reports with [`ReportDescriptor`]
+//! instead of hand-written byte arrays. Items are encoded with the shortest
+//! data size, and global items are only written when their value changes,
+//! so helpers like [`ReportDescriptor::buttons`] can be chained freely:
+//!
+//! ```ignore
+//! const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new()
+//!     .usage_page(page::GENERIC_DESKTOP)
+//!     .usage(usage::GAME_PAD)
+//!     .collection(Collection::Application)
+//!     .buttons(16)
+//!     .axes(&[usage::X, usage::Y], 16, -32767, 32767)
+//!     .end_collection();
+//! pub const REPORT_DESCRIPTOR: &[u8] = &DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>();
+//! ```
+
+/// Capacity of a [`ReportDescriptor`] in bytes.
+pub const MAX_LEN: usize = 512;
+
+/// Usage pages.
+pub mod page {
+    pub const GENERIC_DESKTOP: u16 = 0x01;
+    pub const SIMULATION: u16 = 0x02;
+    pub const BUTTON: u16 = 0x09;
+    pub const CONSUMER: u16 = 0x0C;
+    pub const PID: u16 = 0x0F;
+    pub const VENDOR: u16 = 0xFF00;
+}
+
+/// Usages of the generic desktop, simulation and consumer pages.
+pub mod usage {
+    pub const POINTER: u16 = 0x01;
+    pub const MOUSE: u16 = 0x02;
+    pub const JOYSTICK: u16 = 0x04;
+    pub const GAME_PAD: u16 = 0x05;
+    pub const X: u16 = 0x30;
+    pub const Y: u16 = 0x31;
+    pub const Z: u16 = 0x32;
+    pub const RX: u16 = 0x33;
+    pub const RY: u16 = 0x34;
+    pub const RZ: u16 = 0x35;
+    pub const WHEEL: u16 = 0x38;
+    pub const HAT_SWITCH: u16 = 0x39;
+
+    pub const THROTTLE: u16 = 0xBB;
+    pub const ACCELERATOR: u16 = 0xC4;
+    pub const BRAKE: u16 = 0xC5;
+    pub const CLUTCH: u16 = 0xC6;
+    pub const STEERING: u16 = 0xC8;
+
+    pub const CONSUMER_CONTROL: u16 = 0x01;
+    pub const AC_PAN: u16 = 0x0238;
+}
+
+/// Data flags of input, output and feature items.
+pub mod flags {
+    pub const DATA_ARRAY_ABS: u8 = 0x00;
+    pub const CONSTANT: u8 = 0x03;
+    pub const DATA_VAR_ABS: u8 = 0x02;
+    pub const DATA_VAR_REL: u8 = 0x06;
+    pub const NULL_STATE: u8 = 0x40;
+}
+
+/// Collection types.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Collection {
+    Physical = 0x00,
+    Application = 0x01,
+    Logical = 0x02,
+}
+
+// Item prefixes without the size bits.
+const INPUT: u8 = 0x80;
+const OUTPUT: u8 = 0x90;
+const COLLECTION: u8 = 0xA0;
+const FEATURE: u8 = 0xB0;
+const END_COLLECTION: u8 = 0xC0;
+const USAGE_PAGE: u8 = 0x04;
+const LOGICAL_MINIMUM: u8 = 0x14;
+const LOGICAL_MAXIMUM: u8 = 0x24;
+const PHYSICAL_MINIMUM: u8 = 0x34;
+const PHYSICAL_MAXIMUM: u8 = 0x44;
+const UNIT: u8 = 0x64;
+const REPORT_SIZE: u8 = 0x74;
+const REPORT_ID: u8 = 0x84;
+const REPORT_COUNT: u8 = 0x94;
+const USAGE: u8 = 0x08;
+const USAGE_MINIMUM: u8 = 0x18;
+const USAGE_MAXIMUM: u8 = 0x28;
+
+/// Unit of degrees, for hat switches.
+const UNIT_DEGREES: u32 = 0x14;
+
+// Global items tracked to skip unchanged values, indexed by the tag.
+const GLOBALS: usize = 10;
+
+/// HID report descriptor under construction, see the
+/// [module documentation](self).
+///
+/// All methods are `const fn` taking and returning the builder, and panic
+/// at compile time when [`MAX_LEN`] is exceeded.
+#[derive(Clone, Copy)]
+pub struct ReportDescriptor {
+    bytes: [u8; MAX_LEN],
+    len: usize,
+    globals: [Option<i64>; GLOBALS],
+}
+
+impl Default for ReportDescriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReportDescriptor {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; MAX_LEN],
+            len: 0,
+            globals: [None; GLOBALS],
+        }
+    }
+
+    /// Length of the descriptor in bytes.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn as_bytes(&self) -> &[u8] {
+        self.bytes.split_at(self.len).0
+    }
+
+    /// The descriptor as an array of exactly [`ReportDescriptor::len`]
+    /// bytes, to be stored in a `const`.
+    pub const fn to_array<const N: usize>(&self) -> [u8; N] {
+        assert!(N == self.len, "array length must match the descriptor");
+        let mut array = [0; N];
+        let mut i = 0;
+        while i < N {
+            array[i] = self.bytes[i];
+            i += 1;
+        }
+        array
+    }
+
+    const fn push(mut self, byte: u8) -> Self {
+        assert!(self.len < MAX_LEN, "report descriptor too long");
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        self
+    }
+
+    /// Appends an item with unsigned data.
+    const fn item_unsigned(self, prefix: u8, value: u32) -> Self {
+        if value <= 0xFF {
+            self.push(prefix | 1).push(value as u8)
+        } else if value <= 0xFFFF {
+            self.push(prefix | 2)
+                .push(value as u8)
+                .push((value >> 8) as u8)
+        } else {
+            self.item4(prefix, value)
+        }
+    }
+
+    /// Appends an item with signed data.
+    const fn item_signed(self, prefix: u8, value: i32) -> Self {
+        if value >= i8::MIN as i32 && value <= i8::MAX as i32 {
+            self.push(prefix | 1).push(value as u8)
+        } else if value >= i16::MIN as i32 && value <= i16::MAX as i32 {
+            self.push(prefix | 2)
+                .push(value as u8)
+                .push((value >> 8) as u8)
+        } else {
+            self.item4(prefix, value as u32)
+        }
+    }
+
+    const fn item4(self, prefix: u8, value: u32) -> Self {
+        self.push(prefix | 3)
+            .push(value as u8)
+            .push((value >> 8) as u8)
+            .push((value >> 16) as u8)
+            .push((value >> 24) as u8)
+    }
+
+    /// Appends a global item unless it already has `value`.
+    const fn global(mut self, prefix: u8, value: i64, signed: bool) -> Self {
+        let index = (prefix >> 4) as usize;
+        if let Some(current) = self.globals[index] {
+            if current == value {
+                return self;
+            }
+        }
+        self.globals[index] = Some(value);
+        if signed {
+            self.item_signed(prefix, value as i32)
+        } else {
+            self.item_unsigned(prefix, value as u32)
+        }
+    }
+
+    /// Appends prebuilt items, e.g. a hand-written part. The global state
+    /// is unknown afterwards, so the next global items are always written.
+    pub const fn raw(mut self, items: &[u8]) -> Self {
+        let mut i = 0;
+        while i < items.len() {
+            self = self.push(items[i]);
+            i += 1;
+        }
+        self.globals = [None; GLOBALS];
+        self
+    }
+
+    pub const fn usage_page(self, page: u16) -> Self {
+        self.global(USAGE_PAGE, page as i64, false)
+    }
+
+    pub const fn logical_minimum(self, value: i32) -> Self {
+        self.global(LOGICAL_MINIMUM, value as i64, true)
+    }
+
+    pub const fn logical_maximum(self, value: i32) -> Self {
+        self.global(LOGICAL_MAXIMUM, value as i64, true)
+    }
+
+    pub const fn physical_minimum(self, value: i32) -> Self {
+        self.global(PHYSICAL_MINIMUM, value as i64, true)
+    }
+
+    pub const fn physical_maximum(self, value: i32) -> Self {
+        self.global(PHYSICAL_MAXIMUM, value as i64, true)
+    }
+
+    pub const fn unit(self, unit: u32) -> Self {
+        self.global(UNIT, unit as i64, false)
+    }
+
+    pub const fn report_size(self, bits: u8) -> Self {
+        self.global(REPORT_SIZE, bits as i64, false)
+    }
+
+    pub const fn report_id(self, id: u8) -> Self {
+        self.global(REPORT_ID, id as i64, false)
+    }
+
+    pub const fn report_count(self, count: u8) -> Self {
+        self.global(REPORT_COUNT, count as i64, false)
+    }
+
+    pub const fn usage(self, usage: u16) -> Self {
+        self.item_unsigned(USAGE, usage as u32)
+    }
+
+    pub const fn usage_minimum(self, usage: u16) -> Self {
+        self.item_unsigned(USAGE_MINIMUM, usage as u32)
+    }
+
+    pub const fn usage_maximum(self, usage: u16) -> Self {
+        self.item_unsigned(USAGE_MAXIMUM, usage as u32)
+    }
+
+    /// Appends several usages.
+    pub const fn usages(mut self, usages: &[u16]) -> Self {
+        let mut i = 0;
+        while i < usages.len() {
+            self = self.usage(usages[i]);
+            i += 1;
+        }
+        self
+    }
+
+    pub const fn collection(self, collection: Collection) -> Self {
+        self.item_unsigned(COLLECTION, collection as u32)
+    }
+
+    pub const fn end_collection(self) -> Self {
+        self.push(END_COLLECTION)
+    }
+
+    /// Input item with [`flags`].
+    pub const fn input(self, flags: u8) -> Self {
+        self.item_unsigned(INPUT, flags as u32)
+    }
+
+    /// Output item with [`flags`].
+    pub const fn output(self, flags: u8) -> Self {
+        self.item_unsigned(OUTPUT, flags as u32)
+    }
+
+    /// Feature item with [`flags`].
+    pub const fn feature(self, flags: u8) -> Self {
+        self.item_unsigned(FEATURE, flags as u32)
+    }
+
+    /// `bits` of constant padding, in fields of the current report size if
+    /// it divides `bits`.
+    pub const fn padding(self, bits: u8) -> Self {
+        let this = match self.globals[(REPORT_SIZE >> 4) as usize] {
+            Some(size) if size > 0 && bits as i64 % size == 0 => {
+                self.report_count((bits as i64 / size) as u8)
+            }
+            _ => self.report_size(bits).report_count(1),
+        };
+        this.input(flags::CONSTANT)
+    }
+
+    /// `count` buttons numbered from 1, one bit each, padded to a whole
+    /// byte.
+    pub const fn buttons(self, count: u8) -> Self {
+        let this = self
+            .usage_page(page::BUTTON)
+            .usage_minimum(1)
+            .usage_maximum(count as u16)
+            .logical_minimum(0)
+            .logical_maximum(1)
+            .report_size(1)
+            .report_count(count)
+            .input(flags::DATA_VAR_ABS);
+        match count % 8 {
+            0 => this,
+            used => this.padding(8 - used),
+        }
+    }
+
+    /// 4 bit hat switch on the generic desktop page, 0 to 7 clockwise from
+    /// up in steps of 45 degrees and 8 or more when centered. Not padded.
+    pub const fn hat_switch(self) -> Self {
+        self.usage_page(page::GENERIC_DESKTOP)
+            .usage(usage::HAT_SWITCH)
+            .logical_minimum(0)
+            .logical_maximum(7)
+            .physical_minimum(0)
+            .physical_maximum(315)
+            .unit(UNIT_DEGREES)
+            .report_size(4)
+            .report_count(1)
+            .input(flags::DATA_VAR_ABS | flags::NULL_STATE)
+            .unit(0)
+            .physical_maximum(0)
+    }
+
+    /// Absolute axes of `bits` each with the `usages` of the current page.
+    pub const fn axes(self, usages: &[u16], bits: u8, min: i32, max: i32) -> Self {
+        self.usages(usages)
+            .logical_minimum(min)
+            .logical_maximum(max)
+            .report_size(bits)
+            .report_count(usages.len() as u8)
+            .input(flags::DATA_VAR_ABS)
+    }
+
+    /// Relative axes like [`ReportDescriptor::axes`], e.g. mouse motion.
+    pub const fn relative_axes(self, usages: &[u16], bits: u8, min: i32, max: i32) -> Self {
+        self.usages(usages)
+            .logical_minimum(min)
+            .logical_maximum(max)
+            .report_size(bits)
+            .report_count(usages.len() as u8)
+            .input(flags::DATA_VAR_REL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_use_the_shortest_size() {
+        let descriptor = ReportDescriptor::new()
+            .logical_minimum(-1)
+            .logical_maximum(255)
+            .physical_minimum(-32767)
+            .physical_maximum(65535)
+            .usage(usage::AC_PAN)
+            .usage_page(page::VENDOR);
+        #[rustfmt::skip]
+        let expected = [
+            0x15, 0xFF,
+            0x26, 0xFF, 0x00,
+            0x36, 0x01, 0x80,
+            0x47, 0xFF, 0xFF, 0x00, 0x00,
+            0x0A, 0x38, 0x02,
+            0x06, 0x00, 0xFF,
+        ];
+        assert_eq!(descriptor.as_bytes(), expected);
+    }
+
+    #[test]
+    fn unchanged_globals_are_skipped() {
+        let descriptor = ReportDescriptor::new()
+            .report_size(8)
+            .report_count(2)
+            .input(flags::DATA_VAR_ABS)
+            .report_size(8)
+            .report_count(1)
+            .input(flags::DATA_VAR_ABS);
+        assert_eq!(
+            descriptor.as_bytes(),
+            [0x75, 0x08, 0x95, 0x02, 0x81, 0x02, 0x95, 0x01, 0x81, 0x02]
+        );
+        // Raw items may change any global.
+        let descriptor = ReportDescriptor::new()
+            .report_size(8)
+            .raw(&[0x75, 0x10])
+            .report_size(8);
+        assert_eq!(descriptor.as_bytes(), [0x75, 0x08, 0x75, 0x10, 0x75, 0x08]);
+    }
+
+    #[test]
+    fn buttons_are_padded_to_a_byte() {
+        const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new().buttons(5);
+        #[rustfmt::skip]
+        let expected = [
+            0x05, 0x09, 0x19, 0x01, 0x29, 0x05, 0x15, 0x00, 0x25, 0x01,
+            0x75, 0x01, 0x95, 0x05, 0x81, 0x02,
+            0x95, 0x03, 0x81, 0x03,
+        ];
+        assert_eq!(DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>(), expected);
+    }
+}
@@ -0,0 +1,280 @@
+//! HID flight stick personality for HOTAS builds: X, Y, Z and Rz axes, a
+//! throttle, an 8-way hat switch and 32 buttons.
+//!
+//! Sticks and throttle quadrants publish [`FlightstickState`]s with
+//! [`State::send_flightstick`]. [`State`] also implements [`ReportSink`],
+//! so a gamepad frontend routed through the usual calibration and deadzone
+//! [`Transform`](crate::remap::Transform)s drives the stick as well, see
+//! [`FlightstickState::from_gamepad`]. Hat switches made of four direction
+//! buttons are encoded with [`Directions::hat`].
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_usb::class::hid::{self, HidWriter};
+use embassy_usb::driver::Driver;
+use embassy_usb::Builder;
+
+use crate::analog::Directions;
+use crate::controller::XboxGamepad;
+use crate::hid_descriptor::{page, usage, Collection, ReportDescriptor};
+use crate::transport::{Backend, Capabilities, ReportSink};
+
+/// Length of the input report.
+pub const REPORT_LEN: usize = 15;
+
+/// Report descriptor of the flight stick interface.
+pub const REPORT_DESCRIPTOR: &[u8] = &DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>();
+
+const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new()
+    .usage_page(page::GENERIC_DESKTOP)
+    .usage(usage::JOYSTICK)
+    .collection(Collection::Application)
+    .buttons(32)
+    .hat_switch()
+    .padding(4)
+    // stick and twist
+    .axes(
+        &[usage::X, usage::Y, usage::Z, usage::RZ],
+        16,
+        -32767,
+        32767,
+    )
+    // throttle
+    .usage_page(page::SIMULATION)
+    .axes(&[usage::THROTTLE], 16, 0, 65535)
+    .end_collection();
+
+/// Flight stick state, axes in HID orientation.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlightstickState {
+    /// Negative is left.
+    pub x: i16,
+    /// Negative is pushed forward.
+    pub y: i16,
+    pub z: i16,
+    /// Twist, negative is counterclockwise.
+    pub rz: i16,
+    /// 0 is idle.
+    pub throttle: u16,
+    pub hat: Directions,
+    /// Bit 0 is button 1.
+    pub buttons: u32,
+}
+
+impl FlightstickState {
+    /// Maps a gamepad state: the left stick is the stick, the right stick
+    /// X and Y axes are twist (Rz) and Z, the right trigger is the throttle
+    /// and the dpad is the hat. Buttons 1 to 12 are A, B, X, Y, LB, RB,
+    /// back, start, guide, the stick buttons and the left trigger pulled
+    /// past half way.
+    pub fn from_gamepad(pad: &XboxGamepad) -> Self {
+        let buttons = [
+            pad.btn_a,
+            pad.btn_b,
+            pad.btn_x,
+            pad.btn_y,
+            pad.btn_left_shoulder,
+            pad.btn_right_shoulder,
+            pad.btn_back,
+            pad.btn_start,
+            pad.btn_guide,
+            pad.btn_left_thumb,
+            pad.btn_right_thumb,
+            pad.trigger_left as u8 >= 0x80,
+        ];
+        // HID Y axes point down, xinput Y axes point up.
+        Self {
+            x: pad.thumb_left_x,
+            y: pad.thumb_left_y.saturating_neg(),
+            z: pad.thumb_right_y.saturating_neg(),
+            rz: pad.thumb_right_x,
+            throttle: u16::from(pad.trigger_right as u8) * 257,
+            hat: Directions::from_buttons(
+                pad.dpad_up,
+                pad.dpad_down,
+                pad.dpad_left,
+                pad.dpad_right,
+            ),
+            buttons: buttons
+                .iter()
+                .enumerate()
+                .fold(0, |bits, (i, &pressed)| bits | (u32::from(pressed) << i)),
+        }
+    }
+
+    /// Encodes the input report described by [`REPORT_DESCRIPTOR`].
+    pub fn input_report(&self) -> [u8; REPORT_LEN] {
+        let mut report = [0_u8; REPORT_LEN];
+        report[0..4].copy_from_slice(&self.buttons.to_le_bytes());
+        report[4] = self.hat.hat().unwrap_or(8);
+        let axis = |value: i16| value.max(-i16::MAX).to_le_bytes();
+        report[5..7].copy_from_slice(&axis(self.x));
+        report[7..9].copy_from_slice(&axis(self.y));
+        report[9..11].copy_from_slice(&axis(self.z));
+        report[11..13].copy_from_slice(&axis(self.rz));
+        report[13..15].copy_from_slice(&self.throttle.to_le_bytes());
+        report
+    }
+}
+
+/// Shared state between the application and the [`HidFlightstick`] task.
+///
+/// Like [`xinput::State`](crate::xinput::State) all methods can be called
+/// from any context. Only the latest state is reported.
+pub struct State {
+    stick: Mutex<CriticalSectionRawMutex, Cell<FlightstickState>>,
+    changed: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            stick: Mutex::new(Cell::new(FlightstickState {
+                x: 0,
+                y: 0,
+                z: 0,
+                rz: 0,
+                throttle: 0,
+                hat: Directions(0),
+                buttons: 0,
+            })),
+            changed: Signal::new(),
+        }
+    }
+
+    /// Publishes new flight stick state.
+    pub fn send_flightstick(&self, stick: FlightstickState) {
+        self.stick.lock(|cell| cell.set(stick));
+        self.changed.signal(());
+    }
+
+    /// Latest published flight stick state.
+    pub fn flightstick(&self) -> FlightstickState {
+        self.stick.lock(Cell::get)
+    }
+}
+
+impl ReportSink for State {
+    fn send(&self, pad: &XboxGamepad) {
+        self.send_flightstick(FlightstickState::from_gamepad(pad));
+    }
+}
+
+impl Backend for State {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
+/// HID interface sending the flight stick state of a [`State`].
+pub struct HidFlightstick<'d, D: Driver<'d>> {
+    writer: HidWriter<'d, D, REPORT_LEN>,
+    state: &'d State,
+}
+
+impl<'d, D: Driver<'d>> HidFlightstick<'d, D> {
+    /// Adds the interface to `builder`, after the XInput interfaces if
+    /// there are any.
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        hid_state: &'d mut hid::State<'d>,
+        state: &'d State,
+    ) -> Self {
+        let config = hid::Config {
+            report_descriptor: REPORT_DESCRIPTOR,
+            request_handler: None,
+            poll_ms: 1,
+            max_packet_size: REPORT_LEN as u16,
+        };
+        Self {
+            writer: HidWriter::new(builder, hid_state, config),
+            state,
+        }
+    }
+
+    /// Sends the flight stick state on every change while the device is
+    /// configured.
+    pub async fn run(mut self) -> ! {
+        loop {
+            self.writer.ready().await;
+            debug!("hid flightstick ready");
+            // Report the current state to a newly configured host.
+            self.state.changed.signal(());
+            loop {
+                self.state.changed.wait().await;
+                let report = self.state.flightstick().input_report();
+                if self.writer.write(&report).await.is_err() {
+                    debug!("hid flightstick disabled");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const GOLDEN: &[u8] = &[
+        0x05, 0x01,       // Usage Page (Generic Desktop)
+        0x09, 0x04,       // Usage (Joystick)
+        0xA1, 0x01,       // Collection (Application)
+        // 32 buttons
+        0x05, 0x09,       //   Usage Page (Button)
+        0x19, 0x01,       //   Usage Minimum (1)
+        0x29, 0x20,       //   Usage Maximum (32)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x25, 0x01,       //   Logical Maximum (1)
+        0x75, 0x01,       //   Report Size (1)
+        0x95, 0x20,       //   Report Count (32)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        // hat switch
+        0x05, 0x01,       //   Usage Page (Generic Desktop)
+        0x09, 0x39,       //   Usage (Hat switch)
+        0x25, 0x07,       //   Logical Maximum (7)
+        0x35, 0x00,       //   Physical Minimum (0)
+        0x46, 0x3B, 0x01, //   Physical Maximum (315)
+        0x65, 0x14,       //   Unit (Degrees)
+        0x75, 0x04,       //   Report Size (4)
+        0x95, 0x01,       //   Report Count (1)
+        0x81, 0x42,       //   Input (Data, Var, Abs, Null State)
+        0x65, 0x00,       //   Unit (None)
+        0x45, 0x00,       //   Physical Maximum (0)
+        0x81, 0x03,       //   Input (Const) 4 bit padding
+        // stick and twist
+        0x09, 0x30,       //   Usage (X)
+        0x09, 0x31,       //   Usage (Y)
+        0x09, 0x32,       //   Usage (Z)
+        0x09, 0x35,       //   Usage (Rz)
+        0x16, 0x01, 0x80, //   Logical Minimum (-32767)
+        0x26, 0xFF, 0x7F, //   Logical Maximum (32767)
+        0x75, 0x10,       //   Report Size (16)
+        0x95, 0x04,       //   Report Count (4)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        // throttle
+        0x05, 0x02,       //   Usage Page (Simulation Controls)
+        0x09, 0xBB,       //   Usage (Throttle)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x27, 0xFF, 0xFF, 0x00, 0x00, // Logical Maximum (65535)
+        0x95, 0x01,       //   Report Count (1)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        0xC0,             // End Collection
+    ];
+
+    #[test]
+    fn report_descriptor_bytes() {
+        assert_eq!(REPORT_DESCRIPTOR, GOLDEN);
+    }
+}
@@ -0,0 +1,251 @@
+//! HID light gun personality: an absolute pointer with trigger and
+//! buttons, the way Sinden and GUN4IR guns present themselves.
+//!
+//! Gun adapters publish [`LightgunState`]s with [`State::send_lightgun`],
+//! e.g. from a camera tracking board over UART. [`State`] also implements
+//! [`ReportSink`], so a gamepad source like the PSX GunCon decoder
+//! ([`PsxPadReport`](crate::input::psx::PsxPadReport)) is routed into it
+//! with [`input::route`](crate::input::route), see
+//! [`LightgunState::from_gamepad`].
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_usb::class::hid::{self, HidWriter};
+use embassy_usb::driver::Driver;
+use embassy_usb::Builder;
+
+use crate::controller::XboxGamepad;
+use crate::hid_descriptor::{page, usage, Collection, ReportDescriptor};
+use crate::transport::{Backend, Capabilities, ReportSink};
+
+/// Length of the input report.
+pub const REPORT_LEN: usize = 5;
+/// Largest coordinate, at the right and bottom edge of the screen.
+pub const POSITION_MAX: u16 = 0x7FFF;
+
+/// Trigger, bit 0 of [`LightgunState::buttons`].
+pub const BTN_TRIGGER: u8 = 1 << 0;
+/// Mouse button 2.
+pub const BTN_A: u8 = 1 << 1;
+/// Mouse button 3.
+pub const BTN_B: u8 = 1 << 2;
+/// Mouse button 4.
+pub const BTN_START: u8 = 1 << 3;
+/// Mouse button 5.
+pub const BTN_SELECT: u8 = 1 << 4;
+
+/// Report descriptor of the light gun interface.
+pub const REPORT_DESCRIPTOR: &[u8] = &DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>();
+
+const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new()
+    .usage_page(page::GENERIC_DESKTOP)
+    .usage(usage::MOUSE)
+    .collection(Collection::Application)
+    .usage(usage::POINTER)
+    .collection(Collection::Physical)
+    .buttons(5)
+    // absolute position
+    .usage_page(page::GENERIC_DESKTOP)
+    .axes(&[usage::X, usage::Y], 16, 0, 32767)
+    .end_collection()
+    .end_collection();
+
+/// Light gun state.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LightgunState {
+    /// 0 is the left edge of the screen, [`POSITION_MAX`] the right edge.
+    pub x: u16,
+    /// 0 is the top edge of the screen, [`POSITION_MAX`] the bottom edge.
+    pub y: u16,
+    /// Cleared while the gun points away from the screen, which reports
+    /// the top left corner like GUN4IR does for off screen reloads.
+    pub on_screen: bool,
+    /// `BTN_*` bits.
+    pub buttons: u8,
+}
+
+impl LightgunState {
+    /// Maps a gamepad state the way the GunCon decoder fills it: the left
+    /// stick is the position, the right trigger pulled past half way fires,
+    /// A, B, start and back are the other buttons.
+    pub fn from_gamepad(pad: &XboxGamepad) -> Self {
+        // xinput Y axes point up, screen coordinates down.
+        let position = |value: i16| {
+            let value = i32::from(value.max(-i16::MAX)) + i32::from(i16::MAX);
+            (value / 2) as u16
+        };
+        let buttons = [
+            pad.trigger_right as u8 >= 0x80,
+            pad.btn_a,
+            pad.btn_b,
+            pad.btn_start,
+            pad.btn_back,
+        ];
+        Self {
+            x: position(pad.thumb_left_x),
+            y: position(pad.thumb_left_y.saturating_neg()),
+            on_screen: true,
+            buttons: buttons
+                .iter()
+                .enumerate()
+                .fold(0, |bits, (i, &pressed)| bits | (u8::from(pressed) << i)),
+        }
+    }
+
+    /// Encodes the input report described by [`REPORT_DESCRIPTOR`].
+    pub fn input_report(&self) -> [u8; REPORT_LEN] {
+        let (x, y) = if self.on_screen {
+            (self.x.min(POSITION_MAX), self.y.min(POSITION_MAX))
+        } else {
+            (0, 0)
+        };
+        let mut report = [0_u8; REPORT_LEN];
+        report[0] = self.buttons & 0x1F;
+        report[1..3].copy_from_slice(&x.to_le_bytes());
+        report[3..5].copy_from_slice(&y.to_le_bytes());
+        report
+    }
+}
+
+/// Shared state between the application and the [`HidLightgun`] task.
+///
+/// Like [`xinput::State`](crate::xinput::State) all methods can be called
+/// from any context. Only the latest state is reported.
+pub struct State {
+    gun: Mutex<CriticalSectionRawMutex, Cell<LightgunState>>,
+    changed: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            gun: Mutex::new(Cell::new(LightgunState {
+                x: 0,
+                y: 0,
+                on_screen: false,
+                buttons: 0,
+            })),
+            changed: Signal::new(),
+        }
+    }
+
+    /// Publishes new light gun state.
+    pub fn send_lightgun(&self, gun: LightgunState) {
+        self.gun.lock(|cell| cell.set(gun));
+        self.changed.signal(());
+    }
+
+    /// Latest published light gun state.
+    pub fn lightgun(&self) -> LightgunState {
+        self.gun.lock(Cell::get)
+    }
+}
+
+impl ReportSink for State {
+    fn send(&self, pad: &XboxGamepad) {
+        self.send_lightgun(LightgunState::from_gamepad(pad));
+    }
+}
+
+impl Backend for State {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
+/// HID interface sending the light gun state of a [`State`].
+pub struct HidLightgun<'d, D: Driver<'d>> {
+    writer: HidWriter<'d, D, REPORT_LEN>,
+    state: &'d State,
+}
+
+impl<'d, D: Driver<'d>> HidLightgun<'d, D> {
+    /// Adds the interface to `builder`, after the XInput interfaces if
+    /// there are any.
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        hid_state: &'d mut hid::State<'d>,
+        state: &'d State,
+    ) -> Self {
+        let config = hid::Config {
+            report_descriptor: REPORT_DESCRIPTOR,
+            request_handler: None,
+            poll_ms: 1,
+            max_packet_size: REPORT_LEN as u16,
+        };
+        Self {
+            writer: HidWriter::new(builder, hid_state, config),
+            state,
+        }
+    }
+
+    /// Sends the light gun state on every change while the device is
+    /// configured.
+    pub async fn run(mut self) -> ! {
+        loop {
+            self.writer.ready().await;
+            debug!("hid lightgun ready");
+            // Report the current state to a newly configured host.
+            self.state.changed.signal(());
+            loop {
+                self.state.changed.wait().await;
+                let report = self.state.lightgun().input_report();
+                if self.writer.write(&report).await.is_err() {
+                    debug!("hid lightgun disabled");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const GOLDEN: &[u8] = &[
+        0x05, 0x01,       // Usage Page (Generic Desktop)
+        0x09, 0x02,       // Usage (Mouse)
+        0xA1, 0x01,       // Collection (Application)
+        0x09, 0x01,       //   Usage (Pointer)
+        0xA1, 0x00,       //   Collection (Physical)
+        // 5 buttons
+        0x05, 0x09,       //     Usage Page (Button)
+        0x19, 0x01,       //     Usage Minimum (1)
+        0x29, 0x05,       //     Usage Maximum (5)
+        0x15, 0x00,       //     Logical Minimum (0)
+        0x25, 0x01,       //     Logical Maximum (1)
+        0x75, 0x01,       //     Report Size (1)
+        0x95, 0x05,       //     Report Count (5)
+        0x81, 0x02,       //     Input (Data, Var, Abs)
+        0x95, 0x03,       //     Report Count (3)
+        0x81, 0x03,       //     Input (Const) 3 bit padding
+        // absolute position
+        0x05, 0x01,       //     Usage Page (Generic Desktop)
+        0x09, 0x30,       //     Usage (X)
+        0x09, 0x31,       //     Usage (Y)
+        // Logical Minimum (0) carries over from the buttons
+        0x26, 0xFF, 0x7F, //     Logical Maximum (32767)
+        0x75, 0x10,       //     Report Size (16)
+        0x95, 0x02,       //     Report Count (2)
+        0x81, 0x02,       //     Input (Data, Var, Abs)
+        0xC0,             //   End Collection
+        0xC0,             // End Collection
+    ];
+
+    #[test]
+    fn report_descriptor_bytes() {
+        assert_eq!(REPORT_DESCRIPTOR, GOLDEN);
+    }
+}
@@ -0,0 +1,463 @@
+//! HID mouse personality driven by a gamepad, so a media PC controller
+//! doubles as pointing device.
+//!
+//! [`State`] is a [`ReportSink`] fed with the gamepad state like any other
+//! backend; while mouse mode is on, [`StickToMouse`] turns the right stick
+//! into pointer motion and the left stick or dpad into scrolling, and
+//! [`HidMouse`] sends the result. Mouse mode is toggled at runtime, e.g.
+//! from a [`Hotkey`](crate::hotkeys::Hotkey) action:
+//!
+//! ```ignore
+//! static MOUSE: hid_mouse::State = hid_mouse::State::new(MouseConfig::new());
+//! Hotkey::new(&[Button::Back, Button::RightThumb], Duration::from_secs(1), || {
+//!     MOUSE.toggle();
+//! })
+//! ```
+//!
+//! [`State`] is also a [`Transform`]: put it in the gamepad pipeline to
+//! hide the inputs driving the mouse from the gamepad while mouse mode is
+//! on.
+
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::select;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Instant, Ticker};
+use embassy_usb::class::hid::{self, HidWriter};
+use embassy_usb::driver::Driver;
+use embassy_usb::Builder;
+
+use crate::analog::Curve;
+use crate::controller::XboxGamepad;
+use crate::hid_descriptor::{page, usage, Collection, ReportDescriptor};
+use crate::remap::Transform;
+use crate::transport::{Backend, Capabilities, ReportSink};
+
+/// Length of the input report.
+pub const REPORT_LEN: usize = 5;
+/// Interval at which pointer motion is reported while a stick is deflected.
+pub const MOTION_PERIOD: Duration = Duration::from_millis(4);
+
+const FULL_SCALE: i64 = i16::MAX as i64;
+const MICROS_PER_SECOND: i64 = 1_000_000;
+
+/// Left button, bit 0 of [`MouseReport::buttons`].
+pub const BTN_LEFT: u8 = 1 << 0;
+/// Right button.
+pub const BTN_RIGHT: u8 = 1 << 1;
+/// Middle button.
+pub const BTN_MIDDLE: u8 = 1 << 2;
+/// Back button, button 4.
+pub const BTN_BACK: u8 = 1 << 3;
+/// Forward button, button 5.
+pub const BTN_FORWARD: u8 = 1 << 4;
+
+/// Report descriptor of the mouse interface.
+pub const REPORT_DESCRIPTOR: &[u8] = &DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>();
+
+const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new()
+    .usage_page(page::GENERIC_DESKTOP)
+    .usage(usage::MOUSE)
+    .collection(Collection::Application)
+    .usage(usage::POINTER)
+    .collection(Collection::Physical)
+    .buttons(5)
+    // motion and wheel
+    .usage_page(page::GENERIC_DESKTOP)
+    .relative_axes(&[usage::X, usage::Y, usage::WHEEL], 8, -127, 127)
+    // horizontal wheel
+    .usage_page(page::CONSUMER)
+    .relative_axes(&[usage::AC_PAN], 8, -127, 127)
+    .end_collection()
+    .end_collection();
+
+/// Movement and buttons of one mouse report.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MouseReport {
+    /// `BTN_*` bits.
+    pub buttons: u8,
+    /// Positive is right.
+    pub x: i8,
+    /// Positive is down.
+    pub y: i8,
+    /// Positive scrolls up.
+    pub wheel: i8,
+    /// Positive scrolls right.
+    pub pan: i8,
+}
+
+impl MouseReport {
+    /// Whether the report moves the pointer or a wheel.
+    pub fn has_motion(&self) -> bool {
+        (self.x, self.y, self.wheel, self.pan) != (0, 0, 0, 0)
+    }
+
+    /// Encodes the input report described by [`REPORT_DESCRIPTOR`].
+    pub fn input_report(&self) -> [u8; REPORT_LEN] {
+        [
+            self.buttons & 0x1F,
+            self.x as u8,
+            self.y as u8,
+            self.wheel as u8,
+            self.pan as u8,
+        ]
+    }
+}
+
+/// Gamepad inputs that scroll.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Scroll {
+    Off,
+    /// The left stick scrolls with the same curve as the pointer.
+    LeftStick,
+    /// The dpad scrolls at full speed.
+    Dpad,
+}
+
+/// Settings of [`StickToMouse`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MouseConfig {
+    /// Stick deflection that does not move the pointer.
+    pub deadzone: u16,
+    /// Acceleration: pointer speed over stick deflection.
+    pub curve: Curve,
+    /// Pointer speed at full deflection, in counts per second.
+    pub speed: u16,
+    pub scroll: Scroll,
+    /// Scroll speed at full deflection, in wheel detents per second.
+    pub scroll_speed: u16,
+}
+
+impl Default for MouseConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MouseConfig {
+    /// Cubic acceleration up to 1500 counts per second, scrolling with the
+    /// left stick.
+    pub const fn new() -> Self {
+        Self {
+            deadzone: 4000,
+            curve: Curve::Cubic,
+            speed: 1500,
+            scroll: Scroll::LeftStick,
+            scroll_speed: 20,
+        }
+    }
+}
+
+/// Turns gamepad states into mouse reports: the right stick moves the
+/// pointer, A, B and X are the left, right and middle button, LB and RB
+/// back and forward.
+///
+/// Motion is integrated over time, so the pointer speed does not depend on
+/// how often [`StickToMouse::update`] is called.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StickToMouse {
+    pub config: MouseConfig,
+    // fractional counts of x, y, wheel and pan, in counts * FULL_SCALE * 10^6
+    remainder: [i64; 4],
+}
+
+impl StickToMouse {
+    pub const fn new(config: MouseConfig) -> Self {
+        Self {
+            config,
+            remainder: [0; 4],
+        }
+    }
+
+    /// Drops fractional motion, e.g. after mouse mode was off.
+    pub fn reset(&mut self) {
+        self.remainder = [0; 4];
+    }
+
+    // Speed of an axis in counts per second times FULL_SCALE.
+    fn velocity(&self, value: i16, speed: u16) -> i64 {
+        let deadzone = i64::from(self.config.deadzone).min(FULL_SCALE - 1);
+        let magnitude = i64::from(value.unsigned_abs()).min(FULL_SCALE);
+        if magnitude <= deadzone {
+            return 0;
+        }
+        let scaled = (magnitude - deadzone) * FULL_SCALE / (FULL_SCALE - deadzone);
+        let curved = i64::from(self.config.curve.apply(scaled as u16));
+        curved * i64::from(speed) * i64::from(value.signum())
+    }
+
+    /// Computes the report for `pad` after `elapsed` time has passed since
+    /// the previous update.
+    pub fn update(&mut self, pad: &XboxGamepad, elapsed: Duration) -> MouseReport {
+        let config = self.config;
+        let full = |pressed: bool| if pressed { i16::MAX } else { 0 };
+        let (scroll_x, scroll_y) = match config.scroll {
+            Scroll::Off => (0, 0),
+            Scroll::LeftStick => (pad.thumb_left_x, pad.thumb_left_y),
+            Scroll::Dpad => (
+                full(pad.dpad_right) - full(pad.dpad_left),
+                full(pad.dpad_up) - full(pad.dpad_down),
+            ),
+        };
+        // HID Y axes point down, xinput Y axes point up.
+        let velocities = [
+            self.velocity(pad.thumb_right_x, config.speed),
+            -self.velocity(pad.thumb_right_y, config.speed),
+            self.velocity(scroll_y, config.scroll_speed),
+            self.velocity(scroll_x, config.scroll_speed),
+        ];
+        let elapsed = elapsed.as_micros().min(MICROS_PER_SECOND as u64) as i64;
+        let unit = FULL_SCALE * MICROS_PER_SECOND;
+        let mut counts = [0_i8; 4];
+        for ((velocity, remainder), count) in velocities
+            .into_iter()
+            .zip(&mut self.remainder)
+            .zip(&mut counts)
+        {
+            if velocity == 0 {
+                *remainder = 0;
+                continue;
+            }
+            *remainder += velocity * elapsed;
+            let whole = (*remainder / unit).clamp(-127, 127);
+            *remainder -= whole * unit;
+            // Motion beyond one report is dropped instead of lagging behind.
+            *remainder = (*remainder).clamp(-unit, unit);
+            *count = whole as i8;
+        }
+        let buttons = [
+            pad.btn_a,
+            pad.btn_b,
+            pad.btn_x,
+            pad.btn_left_shoulder,
+            pad.btn_right_shoulder,
+        ];
+        MouseReport {
+            buttons: buttons
+                .iter()
+                .enumerate()
+                .fold(0, |bits, (i, &pressed)| bits | (u8::from(pressed) << i)),
+            x: counts[0],
+            y: counts[1],
+            wheel: counts[2],
+            pan: counts[3],
+        }
+    }
+}
+
+/// Shared state between the application and the [`HidMouse`] task.
+///
+/// Like [`xinput::State`](crate::xinput::State) all methods can be called
+/// from any context.
+pub struct State {
+    enabled: AtomicBool,
+    pad: Mutex<CriticalSectionRawMutex, Cell<XboxGamepad>>,
+    mouse: Mutex<CriticalSectionRawMutex, RefCell<StickToMouse>>,
+    changed: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl State {
+    /// Starts with mouse mode off.
+    pub const fn new(config: MouseConfig) -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            pad: Mutex::new(Cell::new(XboxGamepad::new())),
+            mouse: Mutex::new(RefCell::new(StickToMouse::new(config))),
+            changed: Signal::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        self.changed.signal(());
+    }
+
+    /// Flips mouse mode, returning the new setting.
+    pub fn toggle(&self) -> bool {
+        let enabled = !self.is_enabled();
+        self.set_enabled(enabled);
+        enabled
+    }
+
+    /// Changes the pointer and scroll settings.
+    pub fn set_config(&self, config: MouseConfig) {
+        self.mouse.lock(|mouse| mouse.borrow_mut().config = config);
+    }
+
+    pub fn config(&self) -> MouseConfig {
+        self.mouse.lock(|mouse| mouse.borrow().config)
+    }
+
+    fn update(&self, elapsed: Duration) -> MouseReport {
+        let pad = self.pad.lock(Cell::get);
+        self.mouse
+            .lock(|mouse| mouse.borrow_mut().update(&pad, elapsed))
+    }
+}
+
+impl ReportSink for State {
+    fn send(&self, pad: &XboxGamepad) {
+        self.pad.lock(|cell| cell.set(*pad));
+        if self.is_enabled() {
+            self.changed.signal(());
+        }
+    }
+}
+
+impl Backend for State {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
+/// Hides the inputs driving the mouse while mouse mode is on.
+impl Transform for State {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        if !self.is_enabled() {
+            return pad;
+        }
+        let mut pad = XboxGamepad {
+            thumb_right_x: 0,
+            thumb_right_y: 0,
+            btn_a: false,
+            btn_b: false,
+            btn_x: false,
+            btn_left_shoulder: false,
+            btn_right_shoulder: false,
+            ..pad
+        };
+        match self.config().scroll {
+            Scroll::Off => {}
+            Scroll::LeftStick => (pad.thumb_left_x, pad.thumb_left_y) = (0, 0),
+            Scroll::Dpad => {
+                pad.dpad_up = false;
+                pad.dpad_down = false;
+                pad.dpad_left = false;
+                pad.dpad_right = false;
+            }
+        }
+        pad
+    }
+}
+
+/// HID interface sending the mouse reports of a [`State`].
+pub struct HidMouse<'d, D: Driver<'d>> {
+    writer: HidWriter<'d, D, REPORT_LEN>,
+    state: &'d State,
+}
+
+impl<'d, D: Driver<'d>> HidMouse<'d, D> {
+    /// Adds the interface to `builder`, after the XInput interfaces if
+    /// there are any.
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        hid_state: &'d mut hid::State<'d>,
+        state: &'d State,
+    ) -> Self {
+        let config = hid::Config {
+            report_descriptor: REPORT_DESCRIPTOR,
+            request_handler: None,
+            poll_ms: 1,
+            max_packet_size: REPORT_LEN as u16,
+        };
+        Self {
+            writer: HidWriter::new(builder, hid_state, config),
+            state,
+        }
+    }
+
+    /// Sends mouse reports while mouse mode is on and the device is
+    /// configured: on every button change, and every [`MOTION_PERIOD`]
+    /// while the pointer or a wheel moves.
+    pub async fn run(mut self) -> ! {
+        let mut ticker = Ticker::every(MOTION_PERIOD);
+        loop {
+            self.writer.ready().await;
+            debug!("hid mouse ready");
+            let mut last = MouseReport::default();
+            let mut updated = Instant::now();
+            loop {
+                if self.state.is_enabled() {
+                    select(self.state.changed.wait(), ticker.next()).await;
+                } else {
+                    self.state.changed.wait().await;
+                    self.state.mouse.lock(|mouse| mouse.borrow_mut().reset());
+                    updated = Instant::now();
+                }
+                let now = Instant::now();
+                let report = if self.state.is_enabled() {
+                    self.state.update(now - updated)
+                } else {
+                    MouseReport::default()
+                };
+                updated = now;
+                if report == last && !report.has_motion() {
+                    continue;
+                }
+                last = report;
+                if self.writer.write(&report.input_report()).await.is_err() {
+                    debug!("hid mouse disabled");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const GOLDEN: &[u8] = &[
+        0x05, 0x01,       // Usage Page (Generic Desktop)
+        0x09, 0x02,       // Usage (Mouse)
+        0xA1, 0x01,       // Collection (Application)
+        0x09, 0x01,       //   Usage (Pointer)
+        0xA1, 0x00,       //   Collection (Physical)
+        // 5 buttons
+        0x05, 0x09,       //     Usage Page (Button)
+        0x19, 0x01,       //     Usage Minimum (1)
+        0x29, 0x05,       //     Usage Maximum (5)
+        0x15, 0x00,       //     Logical Minimum (0)
+        0x25, 0x01,       //     Logical Maximum (1)
+        0x75, 0x01,       //     Report Size (1)
+        0x95, 0x05,       //     Report Count (5)
+        0x81, 0x02,       //     Input (Data, Var, Abs)
+        0x95, 0x03,       //     Report Count (3)
+        0x81, 0x03,       //     Input (Const) 3 bit padding
+        // motion and wheel
+        0x05, 0x01,       //     Usage Page (Generic Desktop)
+        0x09, 0x30,       //     Usage (X)
+        0x09, 0x31,       //     Usage (Y)
+        0x09, 0x38,       //     Usage (Wheel)
+        0x15, 0x81,       //     Logical Minimum (-127)
+        0x25, 0x7F,       //     Logical Maximum (127)
+        0x75, 0x08,       //     Report Size (8)
+        // Report Count (3) carries over from the padding
+        0x81, 0x06,       //     Input (Data, Var, Rel)
+        // horizontal wheel
+        0x05, 0x0C,       //     Usage Page (Consumer)
+        0x0A, 0x38, 0x02, //     Usage (AC Pan)
+        0x95, 0x01,       //     Report Count (1)
+        0x81, 0x06,       //     Input (Data, Var, Rel)
+        0xC0,             //   End Collection
+        0xC0,             // End Collection
+    ];
+
+    #[test]
+    fn report_descriptor_bytes() {
+        assert_eq!(REPORT_DESCRIPTOR, GOLDEN);
+    }
+}
@@ -0,0 +1,306 @@
+//! HID racing wheel personality with more axes than the Xbox 360 layout:
+//! 16 bit steering, throttle, brake and clutch, and 32 buttons.
+//!
+//! Wheel bases and pedal boxes with full resolution sources publish
+//! [`WheelState`]s with [`State::send_wheel`]. [`State`] also implements
+//! [`ReportSink`], so a gamepad frontend routed through the usual
+//! calibration and deadzone [`Transform`](crate::remap::Transform)s drives
+//! the wheel as well, see [`WheelState::from_gamepad`].
+//!
+//! The interface can be the only function of the device or be added after
+//! the XInput interfaces like the consumer control interface.
+//! [`HidWheel::new_ffb`] adds force feedback, see [`pid`].
+
+use core::cell::Cell;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_usb::class::hid::{self, HidReader, HidReaderWriter, HidWriter};
+use embassy_usb::driver::Driver;
+use embassy_usb::Builder;
+
+use crate::controller::XboxGamepad;
+use crate::hid_descriptor::{page, usage, Collection, ReportDescriptor};
+use crate::transport::{Backend, Capabilities, ReportSink};
+
+pub mod pid;
+
+/// Report ID of the input report.
+pub const INPUT_REPORT_ID: u8 = 0x01;
+/// Length of the input report including the report ID.
+pub const REPORT_LEN: usize = 13;
+
+/// Report descriptor of the wheel interface.
+pub const REPORT_DESCRIPTOR: &[u8] = &DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>();
+
+const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new()
+    .usage_page(page::GENERIC_DESKTOP)
+    .usage(usage::JOYSTICK)
+    .collection(Collection::Application)
+    .report_id(1)
+    .buttons(32)
+    // steering
+    .usage_page(page::SIMULATION)
+    .axes(&[usage::STEERING], 16, -32767, 32767)
+    // pedals
+    .axes(
+        &[usage::ACCELERATOR, usage::BRAKE, usage::CLUTCH],
+        16,
+        0,
+        65535,
+    )
+    .end_collection();
+
+/// Wheel and pedal state.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WheelState {
+    /// Negative is left.
+    pub steering: i16,
+    /// 0 is released.
+    pub throttle: u16,
+    pub brake: u16,
+    pub clutch: u16,
+    /// Bit 0 is button 1.
+    pub buttons: u32,
+}
+
+impl WheelState {
+    /// Maps a gamepad state: the left stick X axis steers, the right and
+    /// left triggers are throttle and brake, and pushing the right stick
+    /// down operates the clutch. Buttons 1 to 15 are A, B, X, Y, LB, RB,
+    /// back, start, guide, the stick buttons and the dpad (up, down, left,
+    /// right).
+    pub fn from_gamepad(pad: &XboxGamepad) -> Self {
+        let buttons = [
+            pad.btn_a,
+            pad.btn_b,
+            pad.btn_x,
+            pad.btn_y,
+            pad.btn_left_shoulder,
+            pad.btn_right_shoulder,
+            pad.btn_back,
+            pad.btn_start,
+            pad.btn_guide,
+            pad.btn_left_thumb,
+            pad.btn_right_thumb,
+            pad.dpad_up,
+            pad.dpad_down,
+            pad.dpad_left,
+            pad.dpad_right,
+        ];
+        let trigger = |value: i8| u16::from(value as u8) * 257;
+        Self {
+            steering: pad.thumb_left_x.max(-i16::MAX),
+            throttle: trigger(pad.trigger_right),
+            brake: trigger(pad.trigger_left),
+            clutch: pad.thumb_right_y.min(0).unsigned_abs().saturating_mul(2),
+            buttons: buttons
+                .iter()
+                .enumerate()
+                .fold(0, |bits, (i, &pressed)| bits | (u32::from(pressed) << i)),
+        }
+    }
+
+    /// Encodes the input report described by [`REPORT_DESCRIPTOR`].
+    pub fn input_report(&self) -> [u8; REPORT_LEN] {
+        let mut report = [0_u8; REPORT_LEN];
+        report[0] = INPUT_REPORT_ID;
+        report[1..5].copy_from_slice(&self.buttons.to_le_bytes());
+        report[5..7].copy_from_slice(&self.steering.max(-i16::MAX).to_le_bytes());
+        report[7..9].copy_from_slice(&self.throttle.to_le_bytes());
+        report[9..11].copy_from_slice(&self.brake.to_le_bytes());
+        report[11..13].copy_from_slice(&self.clutch.to_le_bytes());
+        report
+    }
+}
+
+/// Shared state between the application and the [`HidWheel`] task.
+///
+/// Like [`xinput::State`](crate::xinput::State) all methods can be called
+/// from any context. Only the latest state is reported.
+pub struct State {
+    wheel: Mutex<CriticalSectionRawMutex, Cell<WheelState>>,
+    changed: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            wheel: Mutex::new(Cell::new(WheelState {
+                steering: 0,
+                throttle: 0,
+                brake: 0,
+                clutch: 0,
+                buttons: 0,
+            })),
+            changed: Signal::new(),
+        }
+    }
+
+    /// Publishes new wheel state.
+    pub fn send_wheel(&self, wheel: WheelState) {
+        self.wheel.lock(|cell| cell.set(wheel));
+        self.changed.signal(());
+    }
+
+    /// Latest published wheel state.
+    pub fn wheel(&self) -> WheelState {
+        self.wheel.lock(Cell::get)
+    }
+}
+
+impl ReportSink for State {
+    fn send(&self, pad: &XboxGamepad) {
+        self.send_wheel(WheelState::from_gamepad(pad));
+    }
+}
+
+impl Backend for State {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            analog_triggers: true,
+            ..Capabilities::default()
+        }
+    }
+}
+
+/// HID interface sending the wheel state of a [`State`].
+pub struct HidWheel<'d, D: Driver<'d>> {
+    writer: HidWriter<'d, D, REPORT_LEN>,
+    reader: Option<(HidReader<'d, D, { pid::OUT_REPORT_LEN }>, &'d pid::Ffb)>,
+    state: &'d State,
+}
+
+impl<'d, D: Driver<'d>> HidWheel<'d, D> {
+    /// Adds the interface to `builder`, after the XInput interfaces if
+    /// there are any.
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        hid_state: &'d mut hid::State<'d>,
+        state: &'d State,
+    ) -> Self {
+        let config = hid::Config {
+            report_descriptor: REPORT_DESCRIPTOR,
+            request_handler: None,
+            poll_ms: 1,
+            max_packet_size: REPORT_LEN as u16,
+        };
+        Self {
+            writer: HidWriter::new(builder, hid_state, config),
+            reader: None,
+            state,
+        }
+    }
+
+    /// Like [`HidWheel::new`], with the force feedback reports of
+    /// [`pid::REPORT_DESCRIPTOR`] answered by `ffb`.
+    pub fn new_ffb(
+        builder: &mut Builder<'d, D>,
+        hid_state: &'d mut hid::State<'d>,
+        state: &'d State,
+        ffb: &'d pid::Ffb,
+    ) -> Self {
+        let config = hid::Config {
+            report_descriptor: pid::REPORT_DESCRIPTOR,
+            request_handler: Some(ffb),
+            poll_ms: 1,
+            max_packet_size: pid::OUT_REPORT_LEN as u16,
+        };
+        let (reader, writer) = HidReaderWriter::new(builder, hid_state, config).split();
+        Self {
+            writer,
+            reader: Some((reader, ffb)),
+            state,
+        }
+    }
+
+    /// Sends the wheel state on every change while the device is
+    /// configured, and passes force feedback reports to the [`pid::Ffb`].
+    pub async fn run(self) -> ! {
+        let Some((reader, ffb)) = self.reader else {
+            write_reports(self.writer, self.state).await
+        };
+        match select(
+            write_reports(self.writer, self.state),
+            reader.run(true, ffb),
+        )
+        .await
+        {
+            Either::First(never) | Either::Second(never) => never,
+        }
+    }
+}
+
+async fn write_reports<'d, D: Driver<'d>>(
+    mut writer: HidWriter<'d, D, REPORT_LEN>,
+    state: &State,
+) -> ! {
+    loop {
+        writer.ready().await;
+        debug!("hid wheel ready");
+        // Report the current state to a newly configured host.
+        state.changed.signal(());
+        loop {
+            state.changed.wait().await;
+            let report = state.wheel().input_report();
+            if writer.write(&report).await.is_err() {
+                debug!("hid wheel disabled");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[rustfmt::skip]
+    const GOLDEN: &[u8] = &[
+        0x05, 0x01,       // Usage Page (Generic Desktop)
+        0x09, 0x04,       // Usage (Joystick)
+        0xA1, 0x01,       // Collection (Application)
+        0x85, 0x01,       //   Report ID (1)
+        // 32 buttons
+        0x05, 0x09,       //   Usage Page (Button)
+        0x19, 0x01,       //   Usage Minimum (1)
+        0x29, 0x20,       //   Usage Maximum (32)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x25, 0x01,       //   Logical Maximum (1)
+        0x75, 0x01,       //   Report Size (1)
+        0x95, 0x20,       //   Report Count (32)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        // steering
+        0x05, 0x02,       //   Usage Page (Simulation Controls)
+        0x09, 0xC8,       //   Usage (Steering)
+        0x16, 0x01, 0x80, //   Logical Minimum (-32767)
+        0x26, 0xFF, 0x7F, //   Logical Maximum (32767)
+        0x75, 0x10,       //   Report Size (16)
+        0x95, 0x01,       //   Report Count (1)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        // pedals
+        0x09, 0xC4,       //   Usage (Accelerator)
+        0x09, 0xC5,       //   Usage (Brake)
+        0x09, 0xC6,       //   Usage (Clutch)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x27, 0xFF, 0xFF, 0x00, 0x00, //   Logical Maximum (65535)
+        // Report Size (16) carries over from the steering
+        0x95, 0x03,       //   Report Count (3)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        0xC0,             // End Collection
+    ];
+
+    #[test]
+    fn report_descriptor_bytes() {
+        assert_eq!(REPORT_DESCRIPTOR, GOLDEN);
+    }
+}
@@ -0,0 +1,627 @@
+//! USB HID PID (Physical Interface Device) force feedback for the wheel.
+//!
+//! [`REPORT_DESCRIPTOR`] adds the PID reports DirectInput and the Linux
+//! `hid-pidff` driver use to download effects. [`Ffb`] manages the effect
+//! blocks the host allocates and turns the reports into [`FfbEvent`]s; the
+//! application receives them and drives the motor, as the force depends on
+//! the wheel position and speed only it knows.
+//!
+//! Supported are constant force, spring and damper effects on the steering
+//! axis. Magnitudes and coefficients range from -10000 to 10000, as in
+//! DirectInput.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::{Channel, TrySendError};
+use embassy_time::Duration;
+use embassy_usb::class::hid::{ReportId, RequestHandler};
+use embassy_usb::control::OutResponse;
+
+use crate::hid_descriptor::ReportDescriptor;
+
+/// Number of effect blocks the host can allocate.
+pub const MAX_EFFECTS: u8 = 8;
+/// Longest output report including the report ID.
+pub const OUT_REPORT_LEN: usize = 16;
+const QUEUE_LEN: usize = 16;
+
+pub const SET_EFFECT_REPORT_ID: u8 = 0x11;
+pub const SET_CONDITION_REPORT_ID: u8 = 0x13;
+pub const SET_CONSTANT_FORCE_REPORT_ID: u8 = 0x15;
+pub const EFFECT_OPERATION_REPORT_ID: u8 = 0x1A;
+pub const BLOCK_FREE_REPORT_ID: u8 = 0x1B;
+pub const DEVICE_CONTROL_REPORT_ID: u8 = 0x1C;
+pub const DEVICE_GAIN_REPORT_ID: u8 = 0x1D;
+pub const CREATE_NEW_EFFECT_REPORT_ID: u8 = 0x21;
+pub const BLOCK_LOAD_REPORT_ID: u8 = 0x22;
+pub const POOL_REPORT_ID: u8 = 0x23;
+
+/// Effect duration meaning infinite.
+const INFINITE: u16 = 0xFFFF;
+
+/// PID reports, placed in the wheel's application collection.
+#[rustfmt::skip]
+const PID_ITEMS: &[u8] = &[
+    0x05, 0x0F,       //   Usage Page (PID)
+    // Set Effect
+    0x09, 0x21,       //   Usage (Set Effect Report)
+    0xA1, 0x02,       //   Collection (Logical)
+    0x85, SET_EFFECT_REPORT_ID, // Report ID
+    0x09, 0x22,       //     Usage (Effect Block Index)
+    0x15, 0x01,       //     Logical Minimum (1)
+    0x25, MAX_EFFECTS, //    Logical Maximum
+    0x75, 0x08,       //     Report Size (8)
+    0x95, 0x01,       //     Report Count (1)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0x09, 0x25,       //     Usage (Effect Type)
+    0xA1, 0x02,       //     Collection (Logical)
+    0x09, 0x26,       //       Usage (ET Constant Force)
+    0x09, 0x40,       //       Usage (ET Spring)
+    0x09, 0x41,       //       Usage (ET Damper)
+    0x25, 0x03,       //       Logical Maximum (3)
+    0x91, 0x00,       //       Output (Data, Array, Abs)
+    0xC0,             //     End Collection
+    0x09, 0x50,       //     Usage (Duration)
+    0x09, 0x54,       //     Usage (Trigger Repeat Interval)
+    0x09, 0x51,       //     Usage (Sample Period)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x27, 0xFF, 0xFF, 0x00, 0x00, // Logical Maximum (65535)
+    0x66, 0x03, 0x10, //     Unit (Seconds)
+    0x55, 0x0D,       //     Unit Exponent (-3)
+    0x75, 0x10,       //     Report Size (16)
+    0x95, 0x03,       //     Report Count (3)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0x55, 0x00,       //     Unit Exponent (0)
+    0x66, 0x00, 0x00, //     Unit (None)
+    0x09, 0x52,       //     Usage (Gain)
+    0x09, 0x53,       //     Usage (Trigger Button)
+    0x26, 0xFF, 0x00, //     Logical Maximum (255)
+    0x75, 0x08,       //     Report Size (8)
+    0x95, 0x02,       //     Report Count (2)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0x09, 0x55,       //     Usage (Axes Enable)
+    0xA1, 0x02,       //     Collection (Logical)
+    0x05, 0x01,       //       Usage Page (Generic Desktop)
+    0x09, 0x30,       //       Usage (X)
+    0x25, 0x01,       //       Logical Maximum (1)
+    0x75, 0x01,       //       Report Size (1)
+    0x95, 0x01,       //       Report Count (1)
+    0x91, 0x02,       //       Output (Data, Var, Abs)
+    0xC0,             //     End Collection
+    0x05, 0x0F,       //     Usage Page (PID)
+    0x09, 0x56,       //     Usage (Direction Enable)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0x95, 0x06,       //     Report Count (6)
+    0x91, 0x03,       //     Output (Const) 6 bit padding
+    0x09, 0x57,       //     Usage (Direction)
+    0xA1, 0x02,       //     Collection (Logical)
+    0x0B, 0x01, 0x00, 0x0A, 0x00, // Usage (Ordinal: Instance 1)
+    0x66, 0x14, 0x00, //       Unit (Degrees)
+    0x55, 0x0E,       //       Unit Exponent (-2)
+    0x26, 0xFF, 0x00, //       Logical Maximum (255)
+    0x35, 0x00,       //       Physical Minimum (0)
+    0x47, 0xA0, 0x8C, 0x00, 0x00, // Physical Maximum (36000)
+    0x75, 0x08,       //       Report Size (8)
+    0x95, 0x01,       //       Report Count (1)
+    0x91, 0x02,       //       Output (Data, Var, Abs)
+    0x45, 0x00,       //       Physical Maximum (0)
+    0x55, 0x00,       //       Unit Exponent (0)
+    0x66, 0x00, 0x00, //       Unit (None)
+    0xC0,             //     End Collection
+    0xC0,             //   End Collection
+    // Set Condition
+    0x09, 0x5F,       //   Usage (Set Condition Report)
+    0xA1, 0x02,       //   Collection (Logical)
+    0x85, SET_CONDITION_REPORT_ID, // Report ID
+    0x09, 0x22,       //     Usage (Effect Block Index)
+    0x15, 0x01,       //     Logical Minimum (1)
+    0x25, MAX_EFFECTS, //    Logical Maximum
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0x09, 0x23,       //     Usage (Parameter Block Offset)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x25, 0x01,       //     Logical Maximum (1)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0x09, 0x60,       //     Usage (CP Offset)
+    0x09, 0x61,       //     Usage (Positive Coefficient)
+    0x09, 0x62,       //     Usage (Negative Coefficient)
+    0x16, 0xF0, 0xD8, //     Logical Minimum (-10000)
+    0x26, 0x10, 0x27, //     Logical Maximum (10000)
+    0x75, 0x10,       //     Report Size (16)
+    0x95, 0x03,       //     Report Count (3)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0x09, 0x63,       //     Usage (Positive Saturation)
+    0x09, 0x64,       //     Usage (Negative Saturation)
+    0x09, 0x65,       //     Usage (Dead Band)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0xC0,             //   End Collection
+    // Set Constant Force
+    0x09, 0x73,       //   Usage (Set Constant Force Report)
+    0xA1, 0x02,       //   Collection (Logical)
+    0x85, SET_CONSTANT_FORCE_REPORT_ID, // Report ID
+    0x09, 0x22,       //     Usage (Effect Block Index)
+    0x15, 0x01,       //     Logical Minimum (1)
+    0x25, MAX_EFFECTS, //    Logical Maximum
+    0x75, 0x08,       //     Report Size (8)
+    0x95, 0x01,       //     Report Count (1)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0x09, 0x70,       //     Usage (Magnitude)
+    0x16, 0xF0, 0xD8, //     Logical Minimum (-10000)
+    0x26, 0x10, 0x27, //     Logical Maximum (10000)
+    0x75, 0x10,       //     Report Size (16)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0xC0,             //   End Collection
+    // Effect Operation
+    0x09, 0x77,       //   Usage (Effect Operation Report)
+    0xA1, 0x02,       //   Collection (Logical)
+    0x85, EFFECT_OPERATION_REPORT_ID, // Report ID
+    0x09, 0x22,       //     Usage (Effect Block Index)
+    0x15, 0x01,       //     Logical Minimum (1)
+    0x25, MAX_EFFECTS, //    Logical Maximum
+    0x75, 0x08,       //     Report Size (8)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0x09, 0x78,       //     Usage (Effect Operation)
+    0xA1, 0x02,       //     Collection (Logical)
+    0x09, 0x79,       //       Usage (Op Effect Start)
+    0x09, 0x7A,       //       Usage (Op Effect Start Solo)
+    0x09, 0x7B,       //       Usage (Op Effect Stop)
+    0x25, 0x03,       //       Logical Maximum (3)
+    0x91, 0x00,       //       Output (Data, Array, Abs)
+    0xC0,             //     End Collection
+    0x09, 0x7C,       //     Usage (Loop Count)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x26, 0xFF, 0x00, //     Logical Maximum (255)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0xC0,             //   End Collection
+    // Block Free
+    0x09, 0x90,       //   Usage (PID Block Free Report)
+    0xA1, 0x02,       //   Collection (Logical)
+    0x85, BLOCK_FREE_REPORT_ID, // Report ID
+    0x09, 0x22,       //     Usage (Effect Block Index)
+    0x15, 0x01,       //     Logical Minimum (1)
+    0x25, MAX_EFFECTS, //    Logical Maximum
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0xC0,             //   End Collection
+    // Device Control
+    0x09, 0x96,       //   Usage (PID Device Control Report)
+    0xA1, 0x02,       //   Collection (Logical)
+    0x85, DEVICE_CONTROL_REPORT_ID, // Report ID
+    0x09, 0x97,       //     Usage (DC Enable Actuators)
+    0x09, 0x98,       //     Usage (DC Disable Actuators)
+    0x09, 0x99,       //     Usage (DC Stop All Effects)
+    0x09, 0x9A,       //     Usage (DC Device Reset)
+    0x09, 0x9B,       //     Usage (DC Device Pause)
+    0x09, 0x9C,       //     Usage (DC Device Continue)
+    0x25, 0x06,       //     Logical Maximum (6)
+    0x91, 0x00,       //     Output (Data, Array, Abs)
+    0xC0,             //   End Collection
+    // Device Gain
+    0x09, 0x7D,       //   Usage (Device Gain Report)
+    0xA1, 0x02,       //   Collection (Logical)
+    0x85, DEVICE_GAIN_REPORT_ID, // Report ID
+    0x09, 0x7E,       //     Usage (Device Gain)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x26, 0xFF, 0x00, //     Logical Maximum (255)
+    0x91, 0x02,       //     Output (Data, Var, Abs)
+    0xC0,             //   End Collection
+    // Create New Effect
+    0x09, 0xAB,       //   Usage (Create New Effect Report)
+    0xA1, 0x02,       //   Collection (Logical)
+    0x85, CREATE_NEW_EFFECT_REPORT_ID, // Report ID
+    0x09, 0x25,       //     Usage (Effect Type)
+    0xA1, 0x02,       //     Collection (Logical)
+    0x09, 0x26,       //       Usage (ET Constant Force)
+    0x09, 0x40,       //       Usage (ET Spring)
+    0x09, 0x41,       //       Usage (ET Damper)
+    0x15, 0x01,       //       Logical Minimum (1)
+    0x25, 0x03,       //       Logical Maximum (3)
+    0xB1, 0x00,       //       Feature (Data, Array, Abs)
+    0xC0,             //     End Collection
+    0x05, 0x01,       //     Usage Page (Generic Desktop)
+    0x09, 0x3B,       //     Usage (Byte Count)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x27, 0xFF, 0xFF, 0x00, 0x00, // Logical Maximum (65535)
+    0x75, 0x10,       //     Report Size (16)
+    0xB1, 0x02,       //     Feature (Data, Var, Abs)
+    0x05, 0x0F,       //     Usage Page (PID)
+    0xC0,             //   End Collection
+    // Block Load
+    0x09, 0x89,       //   Usage (PID Block Load Report)
+    0xA1, 0x02,       //   Collection (Logical)
+    0x85, BLOCK_LOAD_REPORT_ID, // Report ID
+    0x09, 0x22,       //     Usage (Effect Block Index)
+    0x15, 0x01,       //     Logical Minimum (1)
+    0x25, MAX_EFFECTS, //    Logical Maximum
+    0x75, 0x08,       //     Report Size (8)
+    0xB1, 0x02,       //     Feature (Data, Var, Abs)
+    0x09, 0x8B,       //     Usage (Block Load Status)
+    0xA1, 0x02,       //     Collection (Logical)
+    0x09, 0x8C,       //       Usage (Block Load Success)
+    0x09, 0x8D,       //       Usage (Block Load Full)
+    0x09, 0x8E,       //       Usage (Block Load Error)
+    0x25, 0x03,       //       Logical Maximum (3)
+    0xB1, 0x00,       //       Feature (Data, Array, Abs)
+    0xC0,             //     End Collection
+    0x09, 0xAC,       //     Usage (RAM Pool Available)
+    0x15, 0x00,       //     Logical Minimum (0)
+    0x27, 0xFF, 0xFF, 0x00, 0x00, // Logical Maximum (65535)
+    0x75, 0x10,       //     Report Size (16)
+    0xB1, 0x02,       //     Feature (Data, Var, Abs)
+    0xC0,             //   End Collection
+    // Pool
+    0x09, 0x7F,       //   Usage (PID Pool Report)
+    0xA1, 0x02,       //   Collection (Logical)
+    0x85, POOL_REPORT_ID, // Report ID
+    0x09, 0x80,       //     Usage (RAM Pool Size)
+    0xB1, 0x02,       //     Feature (Data, Var, Abs)
+    0x09, 0x83,       //     Usage (Simultaneous Effects Max)
+    0x26, 0xFF, 0x00, //     Logical Maximum (255)
+    0x75, 0x08,       //     Report Size (8)
+    0xB1, 0x02,       //     Feature (Data, Var, Abs)
+    0x09, 0xA9,       //     Usage (Device Managed Pool)
+    0x09, 0xAA,       //     Usage (Shared Parameter Blocks)
+    0x25, 0x01,       //     Logical Maximum (1)
+    0x75, 0x01,       //     Report Size (1)
+    0x95, 0x02,       //     Report Count (2)
+    0xB1, 0x02,       //     Feature (Data, Var, Abs)
+    0x75, 0x06,       //     Report Size (6)
+    0x95, 0x01,       //     Report Count (1)
+    0xB1, 0x03,       //     Feature (Const) 6 bit padding
+    0xC0,             //   End Collection
+];
+
+/// Report descriptor of the wheel interface with force feedback: the
+/// wheel's [`REPORT_DESCRIPTOR`](super::REPORT_DESCRIPTOR) with the PID
+/// reports in its application collection.
+pub const REPORT_DESCRIPTOR: &[u8] = &DESCRIPTOR.to_array::<{ DESCRIPTOR.len() }>();
+
+// Everything but the wheel's End Collection, then the PID reports.
+const DESCRIPTOR: ReportDescriptor = ReportDescriptor::new()
+    .raw(
+        super::REPORT_DESCRIPTOR
+            .split_at(super::REPORT_DESCRIPTOR.len() - 1)
+            .0,
+    )
+    .raw(PID_ITEMS)
+    .end_collection();
+
+/// Effect types the wheel supports.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EffectType {
+    ConstantForce,
+    /// Force towards the center, proportional to the distance.
+    Spring,
+    /// Force against the movement, proportional to the speed.
+    Damper,
+}
+
+impl EffectType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            1 => Some(EffectType::ConstantForce),
+            2 => Some(EffectType::Spring),
+            3 => Some(EffectType::Damper),
+            _ => None,
+        }
+    }
+}
+
+/// General parameters of an effect.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EffectParams {
+    /// Effect block index, 1 to [`MAX_EFFECTS`].
+    pub block: u8,
+    pub effect: EffectType,
+    /// `None` plays until stopped.
+    pub duration: Option<Duration>,
+    pub trigger_repeat_interval: Duration,
+    pub sample_period: Duration,
+    /// Effect gain, 255 is full strength.
+    pub gain: u8,
+    /// Button that starts the effect, 0xFF for none.
+    pub trigger_button: u8,
+    /// Direction of constant force effects, 0 to 255 for a full turn.
+    pub direction: u8,
+}
+
+/// Parameters of a spring or damper effect.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Condition {
+    pub block: u8,
+    /// Axis the parameters apply to, 0 is steering.
+    pub axis: u8,
+    /// Center point, -10000 to 10000 across the axis range.
+    pub center: i16,
+    pub positive_coefficient: i16,
+    pub negative_coefficient: i16,
+    pub positive_saturation: u16,
+    pub negative_saturation: u16,
+    /// Range around the center without force.
+    pub dead_band: u16,
+}
+
+/// Device wide command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceControl {
+    EnableActuators,
+    DisableActuators,
+    StopAllEffects,
+    /// Stops and frees all effects.
+    Reset,
+    Pause,
+    Continue,
+}
+
+/// Force feedback command from the host.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FfbEvent {
+    /// The host allocated an effect block.
+    Created {
+        block: u8,
+        effect: EffectType,
+    },
+    SetEffect(EffectParams),
+    SetCondition(Condition),
+    SetConstantForce {
+        block: u8,
+        magnitude: i16,
+    },
+    /// Starts an effect, `solo` stops all others. A `loop_count` of 0xFF
+    /// repeats it until stopped.
+    Start {
+        block: u8,
+        solo: bool,
+        loop_count: u8,
+    },
+    Stop {
+        block: u8,
+    },
+    /// The host freed an effect block.
+    Free {
+        block: u8,
+    },
+    Control(DeviceControl),
+    /// Gain of all effects, 255 is full strength.
+    Gain(u8),
+}
+
+impl FfbEvent {
+    /// Parses a PID output report, starting with its report ID.
+    pub fn parse(report: &[u8]) -> Option<FfbEvent> {
+        let u16_at = |i: usize| u16::from_le_bytes([report[i], report[i + 1]]);
+        let i16_at = |i: usize| i16::from_le_bytes([report[i], report[i + 1]]);
+        let event = match *report {
+            [SET_EFFECT_REPORT_ID, block, effect, _, _, _, _, _, _, gain, trigger_button, _, direction, ..] =>
+            {
+                let duration = u16_at(3);
+                FfbEvent::SetEffect(EffectParams {
+                    block,
+                    effect: EffectType::from_u8(effect)?,
+                    duration: (duration != INFINITE)
+                        .then(|| Duration::from_millis(duration.into())),
+                    trigger_repeat_interval: Duration::from_millis(u16_at(5).into()),
+                    sample_period: Duration::from_millis(u16_at(7).into()),
+                    gain,
+                    trigger_button,
+                    direction,
+                })
+            }
+            [SET_CONDITION_REPORT_ID, block, axis, ..] if report.len() >= 15 => {
+                FfbEvent::SetCondition(Condition {
+                    block,
+                    axis,
+                    center: i16_at(3),
+                    positive_coefficient: i16_at(5),
+                    negative_coefficient: i16_at(7),
+                    positive_saturation: u16_at(9),
+                    negative_saturation: u16_at(11),
+                    dead_band: u16_at(13),
+                })
+            }
+            [SET_CONSTANT_FORCE_REPORT_ID, block, _, _, ..] => FfbEvent::SetConstantForce {
+                block,
+                magnitude: i16_at(2),
+            },
+            [EFFECT_OPERATION_REPORT_ID, block, operation, loop_count, ..] => match operation {
+                1 | 2 => FfbEvent::Start {
+                    block,
+                    solo: operation == 2,
+                    loop_count,
+                },
+                3 => FfbEvent::Stop { block },
+                _ => return None,
+            },
+            [BLOCK_FREE_REPORT_ID, block, ..] => FfbEvent::Free { block },
+            [DEVICE_CONTROL_REPORT_ID, control, ..] => FfbEvent::Control(match control {
+                1 => DeviceControl::EnableActuators,
+                2 => DeviceControl::DisableActuators,
+                3 => DeviceControl::StopAllEffects,
+                4 => DeviceControl::Reset,
+                5 => DeviceControl::Pause,
+                6 => DeviceControl::Continue,
+                _ => return None,
+            }),
+            [DEVICE_GAIN_REPORT_ID, gain, ..] => FfbEvent::Gain(gain),
+            _ => return None,
+        };
+        Some(event)
+    }
+}
+
+/// Result of the last effect allocation, read by the host with the Block
+/// Load report.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum BlockLoad {
+    Success(u8),
+    Full,
+    Error,
+}
+
+struct Pool {
+    blocks: [Option<EffectType>; MAX_EFFECTS as usize],
+    last_load: BlockLoad,
+}
+
+/// Effect block pool and event queue of the force feedback interface.
+///
+/// Pass it to [`HidWheel::new_ffb`](super::HidWheel::new_ffb), which
+/// answers the host's requests from it, and receive the events in the
+/// motor control task. Up to 16 events are queued; the oldest is dropped
+/// when the queue is full, so receive them promptly.
+pub struct Ffb {
+    pool: Mutex<CriticalSectionRawMutex, RefCell<Pool>>,
+    events: Channel<CriticalSectionRawMutex, FfbEvent, QUEUE_LEN>,
+}
+
+impl Default for Ffb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ffb {
+    pub const fn new() -> Self {
+        Self {
+            pool: Mutex::new(RefCell::new(Pool {
+                blocks: [None; MAX_EFFECTS as usize],
+                last_load: BlockLoad::Error,
+            })),
+            events: Channel::new(),
+        }
+    }
+
+    /// Waits for the next command from the host.
+    pub async fn receive(&self) -> FfbEvent {
+        self.events.receive().await
+    }
+
+    pub fn try_receive(&self) -> Option<FfbEvent> {
+        self.events.try_receive().ok()
+    }
+
+    /// Type of the effect in `block`, `None` if it is not allocated.
+    pub fn effect_type(&self, block: u8) -> Option<EffectType> {
+        let index = usize::from(block).checked_sub(1)?;
+        self.pool
+            .lock(|pool| pool.borrow().blocks.get(index).copied().flatten())
+    }
+
+    fn send(&self, mut event: FfbEvent) {
+        trace!("ffb: {:?}", event);
+        while let Err(TrySendError::Full(rejected)) = self.events.try_send(event) {
+            warn!("ffb: event queue full");
+            let _ = self.events.try_receive();
+            event = rejected;
+        }
+    }
+
+    fn create(&self, effect: Option<EffectType>) {
+        let load = self.pool.lock(|pool| {
+            let mut pool = pool.borrow_mut();
+            let load = match (effect, pool.blocks.iter().position(Option::is_none)) {
+                (None, _) => BlockLoad::Error,
+                (Some(_), None) => BlockLoad::Full,
+                (Some(_), Some(index)) => {
+                    pool.blocks[index] = effect;
+                    BlockLoad::Success(index as u8 + 1)
+                }
+            };
+            pool.last_load = load;
+            load
+        });
+        match (load, effect) {
+            (BlockLoad::Success(block), Some(effect)) => {
+                self.send(FfbEvent::Created { block, effect })
+            }
+            _ => warn!("ffb: effect allocation failed: {:?}", load),
+        }
+    }
+
+    fn free(&self, block: Option<u8>) {
+        self.pool.lock(|pool| {
+            let blocks = &mut pool.borrow_mut().blocks;
+            match block {
+                Some(block) => {
+                    if let Some(slot) = usize::from(block)
+                        .checked_sub(1)
+                        .and_then(|index| blocks.get_mut(index))
+                    {
+                        *slot = None;
+                    }
+                }
+                None => blocks.fill(None),
+            }
+        });
+    }
+}
+
+impl RequestHandler for Ffb {
+    fn get_report(&self, id: ReportId, buf: &mut [u8]) -> Option<usize> {
+        let report: &[u8] = match id {
+            ReportId::Feature(BLOCK_LOAD_REPORT_ID) => {
+                let (block, status) = match self.pool.lock(|pool| pool.borrow().last_load) {
+                    BlockLoad::Success(block) => (block, 1),
+                    BlockLoad::Full => (0, 2),
+                    BlockLoad::Error => (0, 3),
+                };
+                // The pool is managed by the device, its size is nominal.
+                &[BLOCK_LOAD_REPORT_ID, block, status, 0xFF, 0xFF]
+            }
+            ReportId::Feature(POOL_REPORT_ID) => &[POOL_REPORT_ID, 0xFF, 0xFF, MAX_EFFECTS, 0x01],
+            _ => return None,
+        };
+        buf.get_mut(..report.len())?.copy_from_slice(report);
+        Some(report.len())
+    }
+
+    fn set_report(&self, id: ReportId, data: &[u8]) -> OutResponse {
+        match (id, data) {
+            (
+                ReportId::Feature(CREATE_NEW_EFFECT_REPORT_ID),
+                [CREATE_NEW_EFFECT_REPORT_ID, effect, ..],
+            ) => {
+                self.create(EffectType::from_u8(*effect));
+                OutResponse::Accepted
+            }
+            (ReportId::Out(_), _) => {
+                let Some(event) = FfbEvent::parse(data) else {
+                    debug!("ffb: unsupported report {:?}", data);
+                    return OutResponse::Rejected;
+                };
+                match event {
+                    FfbEvent::Free { block } => self.free(Some(block)),
+                    FfbEvent::Control(DeviceControl::Reset) => self.free(None),
+                    _ => {}
+                }
+                self.send(event);
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_descriptor_bytes() {
+        // The wheel's descriptor, pinned by its own test, without its End
+        // Collection.
+        let wheel = super::super::REPORT_DESCRIPTOR;
+        let (head, rest) = REPORT_DESCRIPTOR.split_at(wheel.len() - 1);
+        assert_eq!(head, &wheel[..wheel.len() - 1]);
+        let (items, end) = rest.split_at(PID_ITEMS.len());
+        assert_eq!(items, PID_ITEMS);
+        assert_eq!(end, [0xC0]);
+    }
+}
@@ -0,0 +1,346 @@
+//! Host side of the wireless receiver protocol: decodes the reports the
+//! device sends and encodes the commands a driver sends.
+//!
+//! This lets PC tools and integration tests act as a fake driver against
+//! the device code. Like [`protocol`] it is plain data and needs neither
+//! embassy nor `std`.
+//!
+//! [`RecordDecoder`] decodes the input recording stream of the serial
+//! configuration channel, for measuring polling consistency.
+
+use crate::chatpad::ChatpadKeys;
+use crate::controller::XboxGamepad;
+use crate::protocol::{self, ControllerData, IN_REPORT_LEN, OUT_REPORT_LEN};
+
+/// Report received from the device on the IN endpoint.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InReport<'d> {
+    /// A controller was connected (`true`) or disconnected.
+    ConnectionStatus(bool),
+    Input(XboxGamepad),
+    /// No change in input data for a while.
+    Idle,
+    ControllerInfo,
+    ChatpadAnnouncement,
+    ChatpadKeys(ChatpadKeys),
+    Unknown(&'d [u8]),
+}
+
+impl<'d> InReport<'d> {
+    pub fn from_raw(report: &'d [u8]) -> Self {
+        if report == protocol::connection_status_report(true) {
+            return InReport::ConnectionStatus(true);
+        }
+        if report == protocol::connection_status_report(false) {
+            return InReport::ConnectionStatus(false);
+        }
+        let Ok(raw) = <&[u8; IN_REPORT_LEN]>::try_from(report) else {
+            return InReport::Unknown(report);
+        };
+
+        match raw {
+            [0x00, 0x0F, 0x00, 0xF0, ..] => InReport::ControllerInfo,
+            [0x00, 0x01, _, 0xF0, 0x00, 0x13, ..] => {
+                let mut data = [0_u8; 12];
+                data.copy_from_slice(&raw[6..18]);
+                InReport::Input(ControllerData(data).into())
+            }
+            [0x00, 0x02, _, 0xF0, 0x02, ..] => InReport::ChatpadAnnouncement,
+            [0x00, 0x02, _, 0xF0, 0x00, ..] => InReport::ChatpadKeys(ChatpadKeys {
+                modifiers: raw[24],
+                keys: [raw[25], raw[26]],
+            }),
+            _ if *raw == protocol::idle_report() => InReport::Idle,
+            _ => InReport::Unknown(report),
+        }
+    }
+}
+
+fn out_report(header: [u8; 4]) -> [u8; OUT_REPORT_LEN] {
+    let mut report = [0_u8; OUT_REPORT_LEN];
+    report[..4].copy_from_slice(&header);
+    report
+}
+
+/// Command asking for the connection status of the controller.
+pub fn connection_status_command() -> [u8; OUT_REPORT_LEN] {
+    out_report([0x08, 0x00, 0x0F, 0xC0])
+}
+
+/// Acknowledges a report from the device.
+pub fn ack_command() -> [u8; OUT_REPORT_LEN] {
+    out_report([0x00, 0x00, 0x00, 0x40])
+}
+
+/// Sets the LED pattern, see [`protocol::player_index`].
+pub fn led_command(led: u8) -> [u8; OUT_REPORT_LEN] {
+    out_report([0x00, 0x00, 0x08, 0x40 | (led & 0x0F)])
+}
+
+/// Turns the controller off.
+pub fn power_off_command() -> [u8; OUT_REPORT_LEN] {
+    out_report([0x00, 0x00, 0x08, 0xC0])
+}
+
+/// Sets the strong (left) and weak (right) rumble motor speeds.
+pub fn rumble_command(strong: u8, weak: u8) -> [u8; OUT_REPORT_LEN] {
+    let mut report = out_report([0x00, 0x01, 0x0F, 0xC0]);
+    report[5] = strong;
+    report[6] = weak;
+    report
+}
+
+/// Start of an encoded [`InputRecord`] line. Command responses never
+/// start with it.
+pub const RECORD_PREFIX: &[u8] = b"rec ";
+/// Length of an encoded [`InputRecord`] line, with the line ending.
+pub const RECORD_LEN: usize = RECORD_PREFIX.len() + 8 + 1 + 24 + 2;
+
+/// Input frame accepted by the device, with the time it was accepted.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InputRecord {
+    /// Device time in microseconds, wraps after about 71 minutes.
+    pub timestamp_us: u32,
+    pub pad: XboxGamepad,
+}
+
+impl InputRecord {
+    /// A text line of [`RECORD_PREFIX`], the timestamp as 8 hex digits and
+    /// the [`ControllerData`] of the frame in hex, so clients reading the
+    /// serial channel line by line can tell records from responses.
+    pub fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut record = [0_u8; RECORD_LEN];
+        let (prefix, rest) = record.split_at_mut(RECORD_PREFIX.len());
+        prefix.copy_from_slice(RECORD_PREFIX);
+        let (timestamp, rest) = rest.split_at_mut(8);
+        write_hex(timestamp, &self.timestamp_us.to_be_bytes());
+        rest[0] = b' ';
+        write_hex(&mut rest[1..25], &ControllerData::from(self.pad).0);
+        rest[25..].copy_from_slice(b"\r\n");
+        record
+    }
+
+    /// Decodes a line written by [`InputRecord::encode`], with or without
+    /// the line ending.
+    pub fn decode(line: &[u8]) -> Option<Self> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let rest = line.strip_prefix(RECORD_PREFIX)?;
+        let [timestamp @ .., b' '] = rest.get(..9)? else {
+            return None;
+        };
+        let mut timestamp_bytes = [0_u8; 4];
+        read_hex(&mut timestamp_bytes, timestamp)?;
+        let mut data = [0_u8; 12];
+        read_hex(&mut data, &rest[9..])?;
+        Some(Self {
+            timestamp_us: u32::from_be_bytes(timestamp_bytes),
+            pad: ControllerData(data).into(),
+        })
+    }
+
+    /// Microseconds since `previous`, correct across a timestamp wrap.
+    pub fn interval_us(&self, previous: &InputRecord) -> u32 {
+        self.timestamp_us.wrapping_sub(previous.timestamp_us)
+    }
+}
+
+/// Splits the byte stream of the serial channel into [`InputRecord`]s,
+/// skipping the response lines in between.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RecordDecoder {
+    line: [u8; RECORD_LEN],
+    len: usize,
+}
+
+impl Default for RecordDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecordDecoder {
+    pub const fn new() -> Self {
+        Self {
+            line: [0; RECORD_LEN],
+            len: 0,
+        }
+    }
+
+    /// Feeds one received byte, returning a record once its line is
+    /// complete.
+    pub fn push(&mut self, byte: u8) -> Option<InputRecord> {
+        if byte == b'\n' {
+            let record = self.line.get(..self.len).and_then(InputRecord::decode);
+            self.len = 0;
+            return record;
+        }
+        // Longer lines are responses and only counted until their end.
+        if let Some(slot) = self.line.get_mut(self.len) {
+            *slot = byte;
+        }
+        self.len = self.len.saturating_add(1);
+        None
+    }
+}
+
+fn write_hex(out: &mut [u8], bytes: &[u8]) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    for (pair, byte) in out.chunks_exact_mut(2).zip(bytes) {
+        pair[0] = DIGITS[usize::from(byte >> 4)];
+        pair[1] = DIGITS[usize::from(byte & 0x0F)];
+    }
+}
+
+// Fails unless `hex` holds exactly the digits of `out`.
+fn read_hex(out: &mut [u8], hex: &[u8]) -> Option<()> {
+    if hex.len() != 2 * out.len() {
+        return None;
+    }
+    let digit = |c: u8| char::from(c).to_digit(16).map(|d| d as u8);
+    for (byte, pair) in out.iter_mut().zip(hex.chunks_exact(2)) {
+        *byte = digit(pair[0])? << 4 | digit(pair[1])?;
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Button;
+
+    fn record() -> InputRecord {
+        let mut pad = XboxGamepad::new();
+        pad.set_button(Button::A, true);
+        pad.thumb_left_x = -1234;
+        pad.trigger_right = -1;
+        InputRecord {
+            timestamp_us: 0xDEAD_BEEF,
+            pad,
+        }
+    }
+
+    #[test]
+    fn record_is_a_text_line() {
+        let encoded = record().encode();
+        assert!(encoded.starts_with(b"rec deadbeef "));
+        assert!(encoded.ends_with(b"\r\n"));
+        assert!(encoded[..RECORD_LEN - 2]
+            .iter()
+            .all(|byte| byte.is_ascii_graphic() || *byte == b' '));
+        assert_eq!(InputRecord::decode(&encoded), Some(record()));
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        let encoded = record().encode();
+        assert_eq!(InputRecord::decode(&encoded[..RECORD_LEN - 3]), None);
+        let mut bad_digit = encoded;
+        bad_digit[20] = b'g';
+        assert_eq!(InputRecord::decode(&bad_digit), None);
+        assert_eq!(InputRecord::decode(b"ok\r\n"), None);
+    }
+
+    #[test]
+    fn decoder_skips_responses() {
+        let mut stream = [0_u8; 256];
+        let mut len = 0;
+        let mut push = |bytes: &[u8]| {
+            stream[len..len + bytes.len()].copy_from_slice(bytes);
+            len += bytes.len();
+        };
+        push(b"ok\r\n");
+        push(&record().encode());
+        push(b"error: a response longer than a record line, which is skipped\r\n");
+        push(&record().encode());
+        let mut decoder = RecordDecoder::new();
+        let mut records = stream[..len].iter().filter_map(|byte| decoder.push(*byte));
+        assert_eq!(records.next(), Some(record()));
+        assert_eq!(records.next(), Some(record()));
+        assert_eq!(records.next(), None);
+    }
+
+    // Pads from pseudo-random controller data, covering every button bit
+    // and axis byte.
+    fn pads() -> impl Iterator<Item = XboxGamepad> {
+        let mut seed = 0x2545_F491_u32;
+        (0..1000).map(move |_| {
+            let mut data = [0_u8; 12];
+            for byte in &mut data {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                *byte = seed as u8;
+            }
+            ControllerData(data).into()
+        })
+    }
+
+    #[test]
+    fn commands_round_trip() {
+        use protocol::OutData;
+
+        for led in 0..16 {
+            assert_eq!(OutData::from_raw(&led_command(led)), OutData::Led(led));
+        }
+        for strong in 0..=u8::MAX {
+            for weak in 0..=u8::MAX {
+                assert_eq!(
+                    OutData::from_raw(&rumble_command(strong, weak)),
+                    OutData::Rumble(strong, weak)
+                );
+            }
+        }
+        assert_eq!(
+            OutData::from_raw(&connection_status_command()),
+            OutData::ConnectionStatus
+        );
+        assert_eq!(OutData::from_raw(&ack_command()), OutData::Ack);
+        assert_eq!(OutData::from_raw(&power_off_command()), OutData::PowerOff);
+    }
+
+    #[test]
+    fn input_reports_round_trip() {
+        let mut reports = protocol::InputReports::new();
+        for pad in pads().chain([XboxGamepad::new()]) {
+            let data = ControllerData::from(pad);
+            assert_eq!(
+                InReport::from_raw(&protocol::input_report(&data)),
+                InReport::Input(pad)
+            );
+            assert_eq!(
+                InReport::from_raw(reports.fill(&data)),
+                InReport::Input(pad)
+            );
+        }
+    }
+
+    #[test]
+    fn status_reports_round_trip() {
+        for connected in [false, true] {
+            assert_eq!(
+                InReport::from_raw(&protocol::connection_status_report(connected)),
+                InReport::ConnectionStatus(connected)
+            );
+        }
+        assert_eq!(InReport::from_raw(&protocol::idle_report()), InReport::Idle);
+        assert_eq!(
+            InReport::from_raw(&protocol::CONTROLLER_INFO),
+            InReport::ControllerInfo
+        );
+        assert_eq!(
+            InReport::from_raw(&crate::chatpad::announcement_report()),
+            InReport::ChatpadAnnouncement
+        );
+        let keys = ChatpadKeys {
+            modifiers: crate::chatpad::modifier::SHIFT,
+            keys: [0x17, 0x42],
+        };
+        assert_eq!(
+            InReport::from_raw(&crate::chatpad::key_report(&keys)),
+            InReport::ChatpadKeys(keys)
+        );
+    }
+}
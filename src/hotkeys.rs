@@ -0,0 +1,263 @@
+//! Button chords held for a while that trigger built-in functions, e.g.
+//! switching profiles or entering a configuration mode.
+//!
+//! [`Hotkeys`] is a [`Transform`]: put it first in the pipeline, so chords
+//! are recognized on the physical buttons and hidden from the host.
+//!
+//! ```ignore
+//! static HOTKEYS: [Hotkey; 2] = [
+//!     Hotkey::new(&[Button::Start, Button::Back, Button::A], Duration::from_secs(2), enter_config),
+//!     Hotkey::new(&[Button::Start, Button::Back], Duration::from_secs(1), toggle_mirror),
+//! ];
+//! let hotkeys = Hotkeys::new(&HOTKEYS);
+//! ```
+
+use core::cell::Cell;
+
+use embassy_time::{Duration, Instant};
+
+use crate::controller::{Button, XboxGamepad};
+use crate::remap::Transform;
+
+/// Set of buttons that have to be held together.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Chord(u16);
+
+impl Chord {
+    pub const fn new(buttons: &[Button]) -> Self {
+        let mut bits = 0;
+        let mut i = 0;
+        while i < buttons.len() {
+            bits |= 1 << buttons[i] as u16;
+            i += 1;
+        }
+        Self(bits)
+    }
+
+    fn held(pad: &XboxGamepad) -> u16 {
+        Button::ALL
+            .into_iter()
+            .filter(|button| pad.button(*button))
+            .fold(0, |bits, button| bits | 1 << button as u16)
+    }
+}
+
+/// Chord that calls `action` once it was held for `hold`.
+#[derive(Clone, Copy)]
+pub struct Hotkey {
+    pub chord: Chord,
+    pub hold: Duration,
+    pub action: fn(),
+}
+
+impl Hotkey {
+    pub const fn new(buttons: &[Button], hold: Duration, action: fn()) -> Self {
+        Self {
+            chord: Chord::new(buttons),
+            hold,
+            action,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Tracked {
+    index: usize,
+    since: Instant,
+    fired: bool,
+}
+
+/// Recognizes [`Hotkey`]s in the gamepad states passing through.
+///
+/// The first hotkey in the list whose chord is held wins, so list longer
+/// chords before chords they contain. Once a chord is complete its buttons
+/// are reported released until they are let go, also after the action
+/// fired. Buttons pressed on their own are passed through unchanged.
+pub struct Hotkeys<'a> {
+    hotkeys: &'a [Hotkey],
+    tracked: Cell<Option<Tracked>>,
+    // Buttons hidden from the host until they are released.
+    suppressed: Cell<u16>,
+}
+
+impl<'a> Hotkeys<'a> {
+    pub const fn new(hotkeys: &'a [Hotkey]) -> Self {
+        Self {
+            hotkeys,
+            tracked: Cell::new(None),
+            suppressed: Cell::new(0),
+        }
+    }
+
+    /// Processes `pad` as sampled at `now`, see [`Transform::transform`].
+    pub fn apply_at(&self, mut pad: XboxGamepad, now: Instant) -> XboxGamepad {
+        let held = Chord::held(&pad);
+        let active = self
+            .hotkeys
+            .iter()
+            .position(|hotkey| hotkey.chord.0 != 0 && held & hotkey.chord.0 == hotkey.chord.0);
+
+        let tracked = match (active, self.tracked.get()) {
+            (Some(index), Some(tracked)) if tracked.index == index => Some(tracked),
+            (Some(index), _) => Some(Tracked {
+                index,
+                since: now,
+                fired: false,
+            }),
+            (None, _) => None,
+        };
+        let tracked = tracked.map(|mut tracked| {
+            let hotkey = &self.hotkeys[tracked.index];
+            if !tracked.fired && now - tracked.since >= hotkey.hold {
+                debug!("hotkey {} fired", tracked.index);
+                tracked.fired = true;
+                (hotkey.action)();
+            }
+            tracked
+        });
+        self.tracked.set(tracked);
+
+        let chord = tracked.map_or(0, |tracked| self.hotkeys[tracked.index].chord.0);
+        let suppressed = (self.suppressed.get() | chord) & held;
+        self.suppressed.set(suppressed);
+        for button in Button::ALL {
+            if suppressed & 1 << button as u16 != 0 {
+                pad.set_button(button, false);
+            }
+        }
+        pad
+    }
+}
+
+impl Transform for Hotkeys<'_> {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        self.apply_at(pad, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn pressed(buttons: &[Button]) -> XboxGamepad {
+        let mut pad = XboxGamepad::new();
+        for button in buttons {
+            pad.set_button(*button, true);
+        }
+        pad
+    }
+
+    #[test]
+    fn fires_once_after_the_hold_time() {
+        static FIRED: AtomicUsize = AtomicUsize::new(0);
+        let keys = [Hotkey::new(
+            &[Button::Start, Button::Back],
+            Duration::from_secs(1),
+            || {
+                FIRED.fetch_add(1, Ordering::Relaxed);
+            },
+        )];
+        let hotkeys = Hotkeys::new(&keys);
+        let chord = pressed(&[Button::Start, Button::Back]);
+
+        hotkeys.apply_at(chord, at(1000));
+        hotkeys.apply_at(chord, at(1999));
+        assert_eq!(FIRED.load(Ordering::Relaxed), 0);
+        hotkeys.apply_at(chord, at(2000));
+        assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+        hotkeys.apply_at(chord, at(5000));
+        assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+
+        // Releasing and holding again fires again.
+        hotkeys.apply_at(XboxGamepad::new(), at(5001));
+        hotkeys.apply_at(chord, at(5002));
+        hotkeys.apply_at(chord, at(6002));
+        assert_eq!(FIRED.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn letting_go_early_restarts_the_timer() {
+        static FIRED: AtomicUsize = AtomicUsize::new(0);
+        let keys = [Hotkey::new(
+            &[Button::Start, Button::Back],
+            Duration::from_secs(1),
+            || {
+                FIRED.fetch_add(1, Ordering::Relaxed);
+            },
+        )];
+        let hotkeys = Hotkeys::new(&keys);
+        let chord = pressed(&[Button::Start, Button::Back]);
+
+        hotkeys.apply_at(chord, at(0));
+        hotkeys.apply_at(pressed(&[Button::Start]), at(900));
+        hotkeys.apply_at(chord, at(950));
+        hotkeys.apply_at(chord, at(1900));
+        assert_eq!(FIRED.load(Ordering::Relaxed), 0);
+        hotkeys.apply_at(chord, at(1950));
+        assert_eq!(FIRED.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn chord_is_hidden_until_released() {
+        let keys = [Hotkey::new(
+            &[Button::Start, Button::Back],
+            Duration::from_secs(1),
+            || {},
+        )];
+        let hotkeys = Hotkeys::new(&keys);
+
+        // Start alone passes through until the chord is complete.
+        assert!(hotkeys.apply_at(pressed(&[Button::Start]), at(0)).btn_start);
+        let pad = hotkeys.apply_at(pressed(&[Button::Start, Button::Back, Button::A]), at(10));
+        assert_eq!(pad, pressed(&[Button::A]));
+        let pad = hotkeys.apply_at(pressed(&[Button::Start, Button::Back]), at(2000));
+        assert_eq!(pad, XboxGamepad::new());
+        // Back is still suppressed after the chord broke up.
+        let pad = hotkeys.apply_at(pressed(&[Button::Back]), at(2010));
+        assert_eq!(pad, XboxGamepad::new());
+        hotkeys.apply_at(XboxGamepad::new(), at(2020));
+        assert!(
+            hotkeys
+                .apply_at(pressed(&[Button::Back]), at(2030))
+                .btn_back
+        );
+    }
+
+    #[test]
+    fn first_listed_hotkey_wins() {
+        static LONG: AtomicUsize = AtomicUsize::new(0);
+        static SHORT: AtomicUsize = AtomicUsize::new(0);
+        let keys = [
+            Hotkey::new(
+                &[Button::Start, Button::Back, Button::A],
+                Duration::from_secs(2),
+                || {
+                    LONG.fetch_add(1, Ordering::Relaxed);
+                },
+            ),
+            Hotkey::new(
+                &[Button::Start, Button::Back],
+                Duration::from_secs(1),
+                || {
+                    SHORT.fetch_add(1, Ordering::Relaxed);
+                },
+            ),
+        ];
+        let hotkeys = Hotkeys::new(&keys);
+        let long = pressed(&[Button::Start, Button::Back, Button::A]);
+
+        hotkeys.apply_at(long, at(0));
+        hotkeys.apply_at(long, at(1500));
+        assert_eq!(SHORT.load(Ordering::Relaxed), 0);
+        hotkeys.apply_at(long, at(2000));
+        assert_eq!(LONG.load(Ordering::Relaxed), 1);
+        assert_eq!(SHORT.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,311 @@
+//! Input sources producing [`XboxGamepad`] state from physical hardware.
+//!
+//! Frontends implement [`Scan`], [`Periodic`] turns them into an
+//! [`InputSource`] and [`route`] forwards a source to any
+//! [`ReportSink`], so every frontend works with every backend:
+//!
+//! ```ignore
+//! let mut source = Periodic::new((buttons, sticks), Duration::from_millis(1));
+//! input::route(&mut source, &STATE, &BUTTON_MAP).await
+//! ```
+
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Ticker};
+
+use crate::controller::{Button, XboxGamepad};
+use crate::remap::Transform;
+use crate::transport::ReportSink;
+
+pub mod analog_adc;
+pub mod battery_adc;
+pub mod ble_hogp;
+pub mod can;
+pub mod dance_pad;
+pub mod encoder;
+pub mod gamecube;
+pub mod genesis;
+pub mod gpio;
+pub mod imu;
+pub mod joybus;
+pub mod maple;
+pub mod matrix;
+pub mod n64;
+pub mod nes_snes;
+pub mod players;
+pub mod psx;
+pub mod rapid_trigger;
+pub mod rf_module;
+pub mod saturn;
+pub mod shift_register;
+pub mod usb_host;
+pub mod wii_ext;
+
+/// Time based debouncer for a single digital input.
+///
+/// A new state is accepted once the raw input has been stable for the
+/// debounce time.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Debounce {
+    stable: bool,
+    candidate: bool,
+    since: Instant,
+}
+
+impl Default for Debounce {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debounce {
+    pub const fn new() -> Self {
+        Self {
+            stable: false,
+            candidate: false,
+            since: Instant::from_ticks(0),
+        }
+    }
+
+    /// Feeds a raw sample taken at `now`, returning the debounced state.
+    pub fn update(&mut self, raw: bool, now: Instant, debounce_time: Duration) -> bool {
+        if raw != self.candidate {
+            self.candidate = raw;
+            self.since = now;
+        }
+        if self.candidate != self.stable && now - self.since >= debounce_time {
+            self.stable = self.candidate;
+        }
+        self.stable
+    }
+
+    /// The debounced state.
+    pub fn state(&self) -> bool {
+        self.stable
+    }
+}
+
+/// Frontend that is sampled into a gamepad state.
+#[allow(async_fn_in_trait)]
+pub trait Scan {
+    /// Samples the inputs and writes them into `pad`. Inputs the frontend
+    /// does not have are left untouched.
+    ///
+    /// The scan is dropped at an await point when the [`Periodic`] running
+    /// it is cancelled, see [`InputSource::next`]. The partly written `pad`
+    /// is discarded and the next scan starts over, so frontends have to
+    /// cope with a bus transfer that was abandoned halfway.
+    async fn scan(&mut self, pad: &mut XboxGamepad);
+}
+
+impl<T: Scan + ?Sized> Scan for &mut T {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        (**self).scan(pad).await
+    }
+}
+
+/// Both frontends into the same state, e.g. GPIO buttons and ADC sticks.
+impl<A: Scan, B: Scan> Scan for (A, B) {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        self.0.scan(pad).await;
+        self.1.scan(pad).await;
+    }
+}
+
+impl<A: Scan, B: Scan, C: Scan> Scan for (A, B, C) {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        self.0.scan(pad).await;
+        self.1.scan(pad).await;
+        self.2.scan(pad).await;
+    }
+}
+
+/// Stream of gamepad states.
+#[allow(async_fn_in_trait)]
+pub trait InputSource {
+    /// Waits for the next state that differs from the previous one.
+    ///
+    /// [`Merge`], [`Transmitter`](crate::radio::tx::Transmitter) and
+    /// [`UartLink`](crate::link::uart::UartLink) drop this future when
+    /// something else is ready first, so implementations have to be cancel
+    /// safe: a cancelled call must not lose a state change or leave the
+    /// hardware in a state the next call cannot recover from. [`Periodic`]
+    /// is cancel safe if its [`Scan`] is.
+    async fn next(&mut self) -> XboxGamepad;
+}
+
+impl<T: InputSource + ?Sized> InputSource for &mut T {
+    async fn next(&mut self) -> XboxGamepad {
+        (**self).next().await
+    }
+}
+
+/// Scans a frontend at a fixed rate, yielding changed states.
+///
+/// Cancelling [`InputSource::next`] drops the scan in progress, the next
+/// call scans again at the following tick.
+pub struct Periodic<S> {
+    scanner: S,
+    ticker: Ticker,
+    last: Option<XboxGamepad>,
+}
+
+impl<S: Scan> Periodic<S> {
+    pub fn new(scanner: S, period: Duration) -> Self {
+        Self {
+            scanner,
+            ticker: Ticker::every(period),
+            last: None,
+        }
+    }
+}
+
+impl<S: Scan> InputSource for Periodic<S> {
+    async fn next(&mut self) -> XboxGamepad {
+        loop {
+            self.ticker.next().await;
+            let mut pad = XboxGamepad::new();
+            self.scanner.scan(&mut pad).await;
+            if self.last != Some(pad) {
+                self.last = Some(pad);
+                return pad;
+            }
+        }
+    }
+}
+
+/// Two sources acting as one controller, like the Xbox Copilot
+/// accessibility feature: a button is pressed while it is pressed on
+/// either source, sticks and triggers are the sum of both, clamped to their
+/// range. Created with [`merge`].
+///
+/// Waiting for one source cancels the pending [`InputSource::next`] of the
+/// other, so both must be cancel safe. A [`Periodic`] source is if its
+/// [`Scan`] tolerates being dropped mid scan.
+pub struct Merge<A, B> {
+    sources: (A, B),
+    states: (XboxGamepad, XboxGamepad),
+    last: Option<XboxGamepad>,
+}
+
+/// Merges two sources into one, see [`Merge`].
+pub fn merge<A: InputSource, B: InputSource>(a: A, b: B) -> Merge<A, B> {
+    Merge {
+        sources: (a, b),
+        states: (XboxGamepad::new(), XboxGamepad::new()),
+        last: None,
+    }
+}
+
+impl<A, B> Merge<A, B> {
+    /// Latest states of both sources.
+    pub fn states(&self) -> (XboxGamepad, XboxGamepad) {
+        self.states
+    }
+}
+
+/// Combines two states like [`Merge`].
+pub fn combine(a: &XboxGamepad, b: &XboxGamepad) -> XboxGamepad {
+    let mut pad = *a;
+    for button in Button::ALL {
+        pad.set_button(button, a.button(button) || b.button(button));
+    }
+    let axis = |a: i16, b: i16| a.saturating_add(b).max(-i16::MAX);
+    pad.thumb_left_x = axis(a.thumb_left_x, b.thumb_left_x);
+    pad.thumb_left_y = axis(a.thumb_left_y, b.thumb_left_y);
+    pad.thumb_right_x = axis(a.thumb_right_x, b.thumb_right_x);
+    pad.thumb_right_y = axis(a.thumb_right_y, b.thumb_right_y);
+    // Triggers carry unsigned values.
+    let trigger = |a: i8, b: i8| (a as u8).saturating_add(b as u8) as i8;
+    pad.trigger_left = trigger(a.trigger_left, b.trigger_left);
+    pad.trigger_right = trigger(a.trigger_right, b.trigger_right);
+    pad
+}
+
+impl<A: InputSource, B: InputSource> InputSource for Merge<A, B> {
+    async fn next(&mut self) -> XboxGamepad {
+        loop {
+            match select(self.sources.0.next(), self.sources.1.next()).await {
+                Either::First(pad) => self.states.0 = pad,
+                Either::Second(pad) => self.states.1 = pad,
+            }
+            let pad = combine(&self.states.0, &self.states.1);
+            if self.last != Some(pad) {
+                self.last = Some(pad);
+                return pad;
+            }
+        }
+    }
+}
+
+/// Forwards every state of `source` through `transform` to `sink`.
+///
+/// Use one `route` per source, joined or in separate tasks, to feed several
+/// [`State`](crate::xinput::State)s; a [`FanOut`](crate::transport::FanOut)
+/// sink presents one source on several backends.
+pub async fn route(
+    source: &mut impl InputSource,
+    sink: &impl ReportSink,
+    transform: impl Transform,
+) -> ! {
+    loop {
+        let pad = source.next().await;
+        sink.send(&transform.transform(pad));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBOUNCE: Duration = Duration::from_millis(5);
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    #[test]
+    fn press_is_accepted_after_debounce_time() {
+        let mut debounce = Debounce::new();
+        assert!(!debounce.update(true, at(100), DEBOUNCE));
+        assert!(!debounce.update(true, at(104), DEBOUNCE));
+        assert!(debounce.update(true, at(105), DEBOUNCE));
+        assert!(debounce.state());
+    }
+
+    #[test]
+    fn release_is_accepted_after_debounce_time() {
+        let mut debounce = Debounce::new();
+        debounce.update(true, at(100), DEBOUNCE);
+        debounce.update(true, at(105), DEBOUNCE);
+        assert!(debounce.update(false, at(200), DEBOUNCE));
+        assert!(debounce.update(false, at(204), DEBOUNCE));
+        assert!(!debounce.update(false, at(205), DEBOUNCE));
+    }
+
+    #[test]
+    fn bounce_restarts_the_debounce_time() {
+        let mut debounce = Debounce::new();
+        debounce.update(true, at(100), DEBOUNCE);
+        debounce.update(false, at(103), DEBOUNCE);
+        debounce.update(true, at(104), DEBOUNCE);
+        // Stable for 5 ms since the first edge, but only 4 ms since the last.
+        assert!(!debounce.update(true, at(108), DEBOUNCE));
+        assert!(debounce.update(true, at(109), DEBOUNCE));
+    }
+
+    #[test]
+    fn glitch_shorter_than_debounce_time_is_ignored() {
+        let mut debounce = Debounce::new();
+        assert!(!debounce.update(true, at(100), DEBOUNCE));
+        assert!(!debounce.update(false, at(102), DEBOUNCE));
+        assert!(!debounce.update(false, at(200), DEBOUNCE));
+    }
+
+    #[test]
+    fn zero_debounce_time_follows_the_input() {
+        let mut debounce = Debounce::new();
+        assert!(debounce.update(true, at(0), Duration::from_ticks(0)));
+        assert!(!debounce.update(false, at(0), Duration::from_ticks(0)));
+    }
+}
@@ -0,0 +1,219 @@
+//! Analog sticks and triggers sampled with an ADC.
+
+use super::Scan;
+use crate::analog::AnalogConfig;
+use crate::controller::XboxGamepad;
+use crate::remap::Transform;
+
+/// A single ADC channel, implemented for the HAL's ADC and pin pair.
+#[allow(async_fn_in_trait)]
+pub trait AnalogInput {
+    /// Takes a raw sample, right aligned with the resolution configured in
+    /// [`AdcSticks::new`].
+    async fn sample(&mut self) -> u16;
+}
+
+/// Gamepad value an ADC channel drives.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Axis {
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+/// First order IIR low-pass filter in fixed point.
+///
+/// Each step moves the output by `1 / 2^shift` of the distance to the input;
+/// a shift of 0 disables filtering.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LowPass {
+    // output with 8 fractional bits
+    state: i32,
+    shift: u8,
+    primed: bool,
+}
+
+impl LowPass {
+    pub const fn new(shift: u8) -> Self {
+        Self {
+            state: 0,
+            shift,
+            primed: false,
+        }
+    }
+
+    /// Feeds a sample, returning the filtered value.
+    pub fn update(&mut self, input: u16) -> u16 {
+        let input = i32::from(input) << 8;
+        if self.primed {
+            self.state += (input - self.state) >> self.shift;
+        } else {
+            // Start at the first sample instead of ramping up from zero.
+            self.state = input;
+            self.primed = true;
+        }
+        (self.state >> 8) as u16
+    }
+}
+
+/// Samples analog axes with oversampling and low-pass filtering, then
+/// passes the sticks through an [`AnalogConfig`] for calibration and
+/// deadzones.
+pub struct AdcSticks<A, const N: usize> {
+    channels: [(A, Axis); N],
+    filters: [LowPass; N],
+    resolution_bits: u8,
+    /// Number of samples averaged per reading.
+    pub oversampling: u8,
+    pub config: AnalogConfig,
+}
+
+impl<A: AnalogInput, const N: usize> AdcSticks<A, N> {
+    /// `resolution_bits` is the ADC resolution (e.g. 12), `filter_shift`
+    /// configures the [`LowPass`] filter of every channel.
+    pub fn new(channels: [(A, Axis); N], resolution_bits: u8, filter_shift: u8) -> Self {
+        assert!(
+            (1..=16).contains(&resolution_bits),
+            "ADC resolution must be 1 to 16 bits"
+        );
+        Self {
+            channels,
+            filters: [LowPass::new(filter_shift); N],
+            resolution_bits,
+            oversampling: 4,
+            config: AnalogConfig::default(),
+        }
+    }
+
+    /// Samples all channels and writes the results into `pad`. Axes without
+    /// a channel are left untouched.
+    pub async fn sample(&mut self, pad: &mut XboxGamepad) {
+        let oversampling = u32::from(self.oversampling.max(1));
+        for ((channel, axis), filter) in self.channels.iter_mut().zip(&mut self.filters) {
+            let mut sum = 0_u32;
+            for _ in 0..oversampling {
+                sum += u32::from(channel.sample().await);
+            }
+            // scale to 16 bits
+            let raw = ((sum / oversampling) << (16 - self.resolution_bits)) as u16;
+            let value = filter.update(raw);
+
+            let centered = (i32::from(value) - 0x8000).max(-i32::from(i16::MAX)) as i16;
+            // triggers are packed as their raw byte
+            let trigger = (value >> 8) as u8 as i8;
+            match axis {
+                Axis::LeftX => pad.thumb_left_x = centered,
+                Axis::LeftY => pad.thumb_left_y = centered,
+                Axis::RightX => pad.thumb_right_x = centered,
+                Axis::RightY => pad.thumb_right_y = centered,
+                Axis::LeftTrigger => pad.trigger_left = trigger,
+                Axis::RightTrigger => pad.trigger_right = trigger,
+            }
+        }
+        *pad = self.config.transform(*pad);
+    }
+}
+
+impl<A: AnalogInput, const N: usize> Scan for AdcSticks<A, N> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        self.sample(pad).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::controller::Trigger;
+
+    #[test]
+    fn first_sample_primes_the_filter() {
+        assert_eq!(LowPass::new(4).update(1000), 1000);
+    }
+
+    #[test]
+    fn shift_zero_follows_the_input() {
+        let mut filter = LowPass::new(0);
+        filter.update(5);
+        assert_eq!(filter.update(u16::MAX), u16::MAX);
+        assert_eq!(filter.update(0), 0);
+    }
+
+    #[test]
+    fn each_step_halves_the_distance_with_shift_one() {
+        let mut filter = LowPass::new(1);
+        filter.update(1000);
+        assert_eq!(filter.update(2000), 1500);
+        assert_eq!(filter.update(2000), 1750);
+        assert_eq!(filter.update(2000), 1875);
+        assert_eq!(filter.update(1875), 1875);
+    }
+
+    #[test]
+    fn filter_settles_on_a_steady_input() {
+        let mut filter = LowPass::new(4);
+        filter.update(0);
+        let mut value = 0;
+        for _ in 0..200 {
+            value = filter.update(1000);
+        }
+        // The fractional part below 1 / 2^shift never moves the state.
+        assert!((999..=1000).contains(&value));
+        for _ in 0..200 {
+            value = filter.update(0);
+        }
+        assert_eq!(value, 0);
+    }
+
+    /// Channel returning `samples` in turn.
+    struct FakeChannel {
+        samples: &'static [u16],
+        next: usize,
+    }
+
+    impl FakeChannel {
+        fn new(samples: &'static [u16]) -> Self {
+            Self { samples, next: 0 }
+        }
+    }
+
+    impl AnalogInput for FakeChannel {
+        async fn sample(&mut self) -> u16 {
+            let sample = self.samples[self.next % self.samples.len()];
+            self.next += 1;
+            sample
+        }
+    }
+
+    #[test]
+    fn samples_are_averaged_and_scaled_to_16_bits() {
+        let mut sticks = AdcSticks::new(
+            [
+                (FakeChannel::new(&[2047, 2049]), Axis::LeftX),
+                (FakeChannel::new(&[4095]), Axis::LeftY),
+                (FakeChannel::new(&[0]), Axis::RightX),
+                (FakeChannel::new(&[2048]), Axis::RightY),
+                (FakeChannel::new(&[100, 200, 300, 400]), Axis::LeftTrigger),
+                (FakeChannel::new(&[4095]), Axis::RightTrigger),
+            ],
+            12,
+            0,
+        );
+        let mut pad = XboxGamepad::new();
+        block_on(sticks.sample(&mut pad));
+        assert_eq!(pad.thumb_left_x, 0);
+        assert_eq!(pad.thumb_left_y, 0x7ff0);
+        // The bottom is clamped so the axis stays symmetric.
+        assert_eq!(pad.thumb_right_x, -i16::MAX);
+        assert_eq!(pad.thumb_right_y, 0);
+        // 250 scaled to 16 bits is 4000, packed as its high byte.
+        assert_eq!(pad.trigger(Trigger::Left), 15);
+        assert_eq!(pad.trigger(Trigger::Right), 0xff);
+    }
+}
@@ -0,0 +1,232 @@
+//! Battery level measured through a resistor divider on an ADC channel.
+//!
+//! [`BatteryAdc`] converts the divided cell voltage to millivolts, filters
+//! it, maps it to a charge with a [`DischargeCurve`] and only reports a
+//! new level once it moved by more than the hysteresis. [`BatteryAdc::run`]
+//! feeds the level into the XInput battery status of a [`State`] and into
+//! a [`BatteryStatus`] read by the LED and display hooks:
+//!
+//! ```ignore
+//! static BATTERY: BatteryStatus = BatteryStatus::new();
+//! let feedback = Feedback::new(Theme::default()).with_battery_hook(|| BATTERY.percent());
+//! let divider = Divider::new(3300, 12, 100_000, 100_000);
+//! BatteryAdc::new(adc_pin, divider, DischargeCurve::LIPO).run(&BATTERY, &STATE, Duration::from_secs(5)).await
+//! ```
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use embassy_time::{Duration, Ticker};
+
+use super::analog_adc::{AnalogInput, LowPass};
+use crate::xinput::{BatteryLevel, State};
+
+/// Resistor divider between the battery and the ADC pin.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Divider {
+    /// ADC reference voltage in millivolts.
+    pub reference_mv: u16,
+    /// ADC resolution, e.g. 12.
+    pub resolution_bits: u8,
+    /// Resistor from the battery to the ADC pin.
+    pub top_ohms: u32,
+    /// Resistor from the ADC pin to ground.
+    pub bottom_ohms: u32,
+}
+
+impl Divider {
+    pub const fn new(
+        reference_mv: u16,
+        resolution_bits: u8,
+        top_ohms: u32,
+        bottom_ohms: u32,
+    ) -> Self {
+        Self {
+            reference_mv,
+            resolution_bits,
+            top_ohms,
+            bottom_ohms,
+        }
+    }
+
+    /// Battery voltage in millivolts of a raw ADC sample.
+    pub fn millivolts(&self, raw: u16) -> u16 {
+        let full_scale = (1_u64 << self.resolution_bits) - 1;
+        let pin_mv = u64::from(raw) * u64::from(self.reference_mv) / full_scale.max(1);
+        let bottom = u64::from(self.bottom_ohms).max(1);
+        let battery_mv = pin_mv * (u64::from(self.top_ohms) + bottom) / bottom;
+        battery_mv.min(u64::from(u16::MAX)) as u16
+    }
+}
+
+/// Charge of a cell over its resting voltage, interpolated linearly
+/// between `(millivolts, percent)` points sorted by voltage.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DischargeCurve {
+    points: &'static [(u16, u8)],
+}
+
+impl DischargeCurve {
+    /// Single cell LiPo or Li-ion, flat around 3.8 V.
+    pub const LIPO: Self = Self::new(&[
+        (3300, 0),
+        (3500, 5),
+        (3600, 10),
+        (3700, 20),
+        (3750, 30),
+        (3790, 40),
+        (3830, 50),
+        (3870, 60),
+        (3920, 70),
+        (3970, 80),
+        (4050, 90),
+        (4200, 100),
+    ]);
+
+    /// Two alkaline or NiMH AA cells in series, roughly linear.
+    pub const TWO_AA: Self = Self::new(&[(2000, 0), (2400, 20), (2600, 60), (3000, 100)]);
+
+    pub const fn new(points: &'static [(u16, u8)]) -> Self {
+        Self { points }
+    }
+
+    /// Charge in percent at `millivolts`, clamped to the ends of the curve.
+    pub fn percent(&self, millivolts: u16) -> u8 {
+        let Some(&(first_mv, first_percent)) = self.points.first() else {
+            return 0;
+        };
+        if millivolts <= first_mv {
+            return first_percent;
+        }
+        for pair in self.points.windows(2) {
+            let [(low_mv, low_percent), (high_mv, high_percent)] = [pair[0], pair[1]];
+            if millivolts <= high_mv {
+                let span = i32::from(high_mv - low_mv).max(1);
+                let offset = i32::from(millivolts - low_mv);
+                let delta = i32::from(high_percent) - i32::from(low_percent);
+                return (i32::from(low_percent) + delta * offset / span) as u8;
+            }
+        }
+        self.points[self.points.len() - 1].1
+    }
+}
+
+/// Latest measured battery level, for the hooks of
+/// [`Feedback`](crate::output::led_ws2812::Feedback) and other indicators.
+///
+/// Like [`State`] it can be used from any context.
+pub struct BatteryStatus {
+    percent: AtomicU8,
+}
+
+impl Default for BatteryStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BatteryStatus {
+    /// Full until the first measurement.
+    pub const fn new() -> Self {
+        Self {
+            percent: AtomicU8::new(100),
+        }
+    }
+
+    pub fn set_percent(&self, percent: u8) {
+        self.percent.store(percent.min(100), Ordering::Relaxed);
+    }
+
+    pub fn percent(&self) -> u8 {
+        self.percent.load(Ordering::Relaxed)
+    }
+}
+
+/// Battery gauge on an ADC channel, see the
+/// [module documentation](self).
+pub struct BatteryAdc<A> {
+    channel: A,
+    divider: Divider,
+    curve: DischargeCurve,
+    filter: LowPass,
+    reported: Option<u8>,
+    /// Number of samples averaged per reading.
+    pub oversampling: u8,
+    /// Change in percent needed before a new level is reported, so the
+    /// level does not toggle when the voltage sags under load.
+    pub hysteresis: u8,
+    change_hook: Option<fn(u8)>,
+}
+
+impl<A: AnalogInput> BatteryAdc<A> {
+    pub fn new(channel: A, divider: Divider, curve: DischargeCurve) -> Self {
+        Self {
+            channel,
+            divider,
+            curve,
+            // The voltage changes over minutes, filter heavily.
+            filter: LowPass::new(3),
+            reported: None,
+            oversampling: 8,
+            hysteresis: 3,
+            change_hook: None,
+        }
+    }
+
+    /// Calls `hook` with every newly reported level in percent, e.g. to
+    /// queue a low battery [`Cue`](crate::output::buzzer::Cue) or update the
+    /// level of a BLE backend.
+    pub fn with_change_hook(mut self, hook: fn(u8)) -> Self {
+        self.change_hook = Some(hook);
+        self
+    }
+
+    /// Filtered battery voltage in millivolts.
+    pub async fn millivolts(&mut self) -> u16 {
+        let oversampling = u32::from(self.oversampling.max(1));
+        let mut sum = 0_u32;
+        for _ in 0..oversampling {
+            sum += u32::from(self.channel.sample().await);
+        }
+        let millivolts = self.divider.millivolts((sum / oversampling) as u16);
+        self.filter.update(millivolts)
+    }
+
+    /// Measures the battery, returning the level in percent if it changed
+    /// by more than the hysteresis since the last reported level. The first
+    /// measurement is always reported.
+    pub async fn update(&mut self) -> Option<u8> {
+        let millivolts = self.millivolts().await;
+        let percent = self.curve.percent(millivolts);
+        match self.reported {
+            Some(reported) if percent.abs_diff(reported) <= self.hysteresis => None,
+            _ => {
+                self.reported = Some(percent);
+                Some(percent)
+            }
+        }
+    }
+
+    /// Measures the battery every `period`, publishing level changes to
+    /// `status`, the XInput battery level of `state` and the change hook.
+    pub async fn run<const N: usize>(
+        mut self,
+        status: &BatteryStatus,
+        state: &State<N>,
+        period: Duration,
+    ) -> ! {
+        let mut ticker = Ticker::every(period);
+        loop {
+            if let Some(percent) = self.update().await {
+                debug!("battery: {}%", percent);
+                status.set_percent(percent);
+                state.set_battery_level(BatteryLevel::from_percent(percent));
+                if let Some(hook) = self.change_hook {
+                    hook(percent);
+                }
+            }
+            ticker.next().await;
+        }
+    }
+}
@@ -0,0 +1,691 @@
+//! Bluetooth LE gamepads using the HID over GATT profile, turning e.g. an
+//! nRF52840 into a BLE to XInput dongle.
+//!
+//! Like the [`ble_hid`](crate::ble_hid) backend this is independent of
+//! the BLE stack: the application's GATT client (TrouBLE,
+//! nrf-softdevice, ...) implements [`GattClient`], which connects to the
+//! pad, reads its report map and forwards input report notifications.
+//! [`ReportLayout`] is a small report descriptor interpreter that finds the
+//! buttons, sticks, triggers and hat switch in the report map, so pads are
+//! read without per-model tables.
+
+use crate::controller::{Button, XboxGamepad};
+
+use super::InputSource;
+
+/// Largest report map read from the pad.
+pub const REPORT_MAP_BUF_LEN: usize = 512;
+/// Largest input report read from the pad.
+pub const REPORT_BUF_LEN: usize = 64;
+
+const MAX_FIELDS: usize = 32;
+const MAX_REPORT_IDS: usize = 8;
+
+const PAGE_GENERIC_DESKTOP: u16 = 0x01;
+const PAGE_SIMULATION: u16 = 0x02;
+const PAGE_BUTTON: u16 = 0x09;
+
+/// Buttons in the order of the [`ble_hid`](crate::ble_hid) report map,
+/// which most gamepads share for the first eight buttons.
+pub const DEFAULT_BUTTON_MAP: [Option<Button>; 16] = [
+    Some(Button::A),
+    Some(Button::B),
+    Some(Button::X),
+    Some(Button::Y),
+    Some(Button::LeftShoulder),
+    Some(Button::RightShoulder),
+    Some(Button::Back),
+    Some(Button::Start),
+    Some(Button::Guide),
+    Some(Button::LeftThumb),
+    Some(Button::RightThumb),
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+/// Usages of the right stick and the triggers, which differ between pads.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AxisLayout {
+    /// Right stick on Rx/Ry, triggers on Z/Rz, like the
+    /// [`ble_hid`](crate::ble_hid) backend.
+    RxRy,
+    /// Right stick on Z/Rz, triggers on the simulation brake and
+    /// accelerator usages, like Xbox and most Android pads.
+    ZRz,
+}
+
+/// Errors of [`ReportLayout::parse`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    /// An item is cut off at the end of the report map.
+    Truncated,
+    /// More gamepad controls or report IDs than the interpreter tracks.
+    TooComplex,
+    /// No buttons, sticks or hat switch were found.
+    NotAGamepad,
+    /// A control is not 1 to 32 bits wide, or a report is longer than
+    /// the bit offsets can address.
+    Invalid,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Target {
+    Button(u8),
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    TriggerLeft,
+    TriggerRight,
+    Hat,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Field {
+    report_id: u8,
+    offset: u16,
+    // 1 to 32 bits, checked by the parser.
+    size: u8,
+    logical_min: i32,
+    logical_max: i32,
+    target: Target,
+}
+
+impl Field {
+    fn read(&self, data: &[u8]) -> Option<i32> {
+        let mut raw = 0_u32;
+        for bit in 0..self.size {
+            let pos = usize::from(self.offset) + usize::from(bit);
+            let byte = *data.get(pos / 8)?;
+            raw |= u32::from(byte >> (pos % 8) & 1) << bit;
+        }
+        let shift = 32 - u32::from(self.size);
+        Some(if self.logical_min < 0 {
+            // Sign extend.
+            ((raw << shift) as i32) >> shift
+        } else {
+            raw as i32
+        })
+    }
+
+    // Scaled to the full i16 range, 0 in the middle of the logical range.
+    fn axis(&self, value: i32) -> i16 {
+        let (min, max) = (i64::from(self.logical_min), i64::from(self.logical_max));
+        if max <= min {
+            return 0;
+        }
+        let value = (2 * i64::from(value) - min - max) * i64::from(i16::MAX) / (max - min);
+        value.clamp(-i64::from(i16::MAX), i64::from(i16::MAX)) as i16
+    }
+
+    // Scaled to 0..=255.
+    fn trigger(&self, value: i32) -> u8 {
+        let (min, max) = (i64::from(self.logical_min), i64::from(self.logical_max));
+        if max <= min {
+            return 0;
+        }
+        ((i64::from(value) - min) * 255 / (max - min)).clamp(0, 255) as u8
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Globals {
+    usage_page: u16,
+    logical_min: i32,
+    logical_max: i32,
+    report_size: u32,
+    report_count: u8,
+    report_id: u8,
+}
+
+/// Location of the gamepad controls in the input reports of a pad.
+pub struct ReportLayout {
+    fields: [Option<Field>; MAX_FIELDS],
+    uses_report_ids: bool,
+}
+
+impl ReportLayout {
+    /// Interprets the report map (HID report descriptor) `map`.
+    ///
+    /// Only input items are considered. Array items, push/pop and
+    /// delimiters are skipped, which no common gamepad needs.
+    pub fn parse(map: &[u8], axes: AxisLayout) -> Result<Self, ParseError> {
+        let mut layout = Self {
+            fields: [None; MAX_FIELDS],
+            uses_report_ids: false,
+        };
+        let mut globals = Globals::default();
+        // Local usages: either a list or a minimum/maximum range.
+        let mut usages = [0_u32; MAX_FIELDS];
+        let mut usage_count = 0;
+        let mut usage_range: Option<(u32, u32)> = None;
+        // Bit offset per report ID.
+        let mut offsets = [(0_u8, 0_u16); MAX_REPORT_IDS];
+        let mut report_ids = 1;
+
+        let mut rest = map;
+        while let [prefix, tail @ ..] = rest {
+            if *prefix == 0xFE {
+                // Long item, never used by gamepads.
+                let [len, _tag, tail @ ..] = tail else {
+                    return Err(ParseError::Truncated);
+                };
+                rest = tail.get(usize::from(*len)..).ok_or(ParseError::Truncated)?;
+                continue;
+            }
+            let len = match prefix & 0x03 {
+                3 => 4,
+                len => usize::from(len),
+            };
+            let data = tail.get(..len).ok_or(ParseError::Truncated)?;
+            rest = &tail[len..];
+            let unsigned = data
+                .iter()
+                .rev()
+                .fold(0_u32, |value, byte| value << 8 | u32::from(*byte));
+            let signed = match len {
+                1 => i32::from(data[0] as i8),
+                2 => i32::from(i16::from_le_bytes([data[0], data[1]])),
+                _ => unsigned as i32,
+            };
+            // Extended usages carry their page in the high half.
+            let usage = |globals: &Globals| {
+                if len == 4 {
+                    unsigned
+                } else {
+                    u32::from(globals.usage_page) << 16 | unsigned
+                }
+            };
+
+            match prefix & 0xFC {
+                // Input
+                0x80 => {
+                    let slot = match offsets[..report_ids]
+                        .iter()
+                        .position(|(id, _)| *id == globals.report_id)
+                    {
+                        Some(slot) => slot,
+                        None if report_ids < MAX_REPORT_IDS => {
+                            offsets[report_ids] = (globals.report_id, 0);
+                            report_ids += 1;
+                            report_ids - 1
+                        }
+                        None => return Err(ParseError::TooComplex),
+                    };
+                    let constant = unsigned & 0x01 != 0;
+                    let variable = unsigned & 0x02 != 0;
+                    // Padding may be wider, it is only skipped.
+                    if !constant && !(1..=32).contains(&globals.report_size) {
+                        return Err(ParseError::Invalid);
+                    }
+                    for i in 0..usize::from(globals.report_count) {
+                        let offset = offsets[slot].1;
+                        offsets[slot].1 = u16::try_from(globals.report_size)
+                            .ok()
+                            .and_then(|size| offset.checked_add(size))
+                            .ok_or(ParseError::Invalid)?;
+                        if constant || !variable {
+                            continue;
+                        }
+                        let usage = match usage_range {
+                            Some((min, max)) => min.saturating_add(i as u32).min(max),
+                            None if usage_count > 0 => usages[i.min(usage_count - 1)],
+                            None => continue,
+                        };
+                        let Some(target) = target(usage, axes) else {
+                            continue;
+                        };
+                        let slot = layout
+                            .fields
+                            .iter_mut()
+                            .find(|field| field.is_none())
+                            .ok_or(ParseError::TooComplex)?;
+                        *slot = Some(Field {
+                            report_id: globals.report_id,
+                            offset,
+                            size: globals.report_size as u8,
+                            logical_min: globals.logical_min,
+                            logical_max: globals.logical_max,
+                            target,
+                        });
+                    }
+                    usage_count = 0;
+                    usage_range = None;
+                }
+                // Output, feature, collection, end collection
+                0x90 | 0xB0 | 0xA0 | 0xC0 => {
+                    usage_count = 0;
+                    usage_range = None;
+                }
+                // Usage page
+                0x04 => globals.usage_page = unsigned as u16,
+                0x14 => globals.logical_min = signed,
+                0x24 => {
+                    // Logical maximum is unsigned when the minimum is not
+                    // negative, e.g. 0x00FF encoded in one byte.
+                    globals.logical_max = if globals.logical_min >= 0 {
+                        unsigned as i32
+                    } else {
+                        signed
+                    };
+                }
+                0x74 => globals.report_size = unsigned,
+                0x84 => {
+                    globals.report_id = unsigned as u8;
+                    layout.uses_report_ids = true;
+                }
+                0x94 => globals.report_count = unsigned.min(255) as u8,
+                // Usage
+                0x08 if usage_count < usages.len() => {
+                    usages[usage_count] = usage(&globals);
+                    usage_count += 1;
+                }
+                // Usage minimum and maximum
+                0x18 => {
+                    let min = usage(&globals);
+                    usage_range = Some((min, usage_range.map_or(min, |(_, max)| max)));
+                }
+                0x28 => {
+                    let max = usage(&globals);
+                    usage_range = Some((usage_range.map_or(max, |(min, _)| min), max));
+                }
+                _ => {}
+            }
+        }
+
+        if layout.fields.iter().all(Option::is_none) {
+            return Err(ParseError::NotAGamepad);
+        }
+        Ok(layout)
+    }
+
+    /// Writes input report `report` with ID `report_id` into `pad`, using
+    /// `buttons` to map HID button `i + 1` to `buttons[i]`.
+    ///
+    /// Returns `false` for reports without gamepad controls, which leave
+    /// `pad` untouched. Over GATT the report ID is not part of the
+    /// notification, it is read from the report reference descriptor.
+    pub fn apply(
+        &self,
+        report_id: u8,
+        report: &[u8],
+        buttons: &[Option<Button>],
+        pad: &mut XboxGamepad,
+    ) -> bool {
+        let report_id = if self.uses_report_ids { report_id } else { 0 };
+        let mut fields = self
+            .fields
+            .iter()
+            .flatten()
+            .filter(|field| field.report_id == report_id)
+            .peekable();
+        if fields.peek().is_none() {
+            return false;
+        }
+
+        *pad = XboxGamepad::new();
+        for field in fields {
+            let Some(value) = field.read(report) else {
+                continue;
+            };
+            match field.target {
+                Target::Button(index) => {
+                    if let Some(Some(button)) = buttons.get(usize::from(index)) {
+                        if value != 0 {
+                            pad.set_button(*button, true);
+                        }
+                    }
+                }
+                Target::LeftX => pad.thumb_left_x = field.axis(value),
+                // HID Y axes point down, xinput Y axes point up.
+                Target::LeftY => pad.thumb_left_y = field.axis(value).saturating_neg(),
+                Target::RightX => pad.thumb_right_x = field.axis(value),
+                Target::RightY => pad.thumb_right_y = field.axis(value).saturating_neg(),
+                Target::TriggerLeft => pad.trigger_left = field.trigger(value) as i8,
+                Target::TriggerRight => pad.trigger_right = field.trigger(value) as i8,
+                Target::Hat => {
+                    // 0 is up continuing clockwise, out of range is centered.
+                    let min = i64::from(field.logical_min);
+                    let positions = i64::from(field.logical_max) - min + 1;
+                    let step = if positions == 4 { 2 } else { 1 };
+                    let hat = (i64::from(value) - min) * step;
+                    if (0..8).contains(&hat) && positions >= 4 {
+                        pad.dpad_up = matches!(hat, 0 | 1 | 7);
+                        pad.dpad_right = matches!(hat, 1..=3);
+                        pad.dpad_down = matches!(hat, 3..=5);
+                        pad.dpad_left = matches!(hat, 5..=7);
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+fn target(usage: u32, axes: AxisLayout) -> Option<Target> {
+    let page = (usage >> 16) as u16;
+    let id = usage as u16;
+    match (page, id, axes) {
+        (PAGE_BUTTON, 1..=16, _) => Some(Target::Button((id - 1) as u8)),
+        (PAGE_GENERIC_DESKTOP, 0x30, _) => Some(Target::LeftX),
+        (PAGE_GENERIC_DESKTOP, 0x31, _) => Some(Target::LeftY),
+        (PAGE_GENERIC_DESKTOP, 0x39, _) => Some(Target::Hat),
+        (PAGE_GENERIC_DESKTOP, 0x33, AxisLayout::RxRy)
+        | (PAGE_GENERIC_DESKTOP, 0x32, AxisLayout::ZRz) => Some(Target::RightX),
+        (PAGE_GENERIC_DESKTOP, 0x34, AxisLayout::RxRy)
+        | (PAGE_GENERIC_DESKTOP, 0x35, AxisLayout::ZRz) => Some(Target::RightY),
+        (PAGE_GENERIC_DESKTOP, 0x32, AxisLayout::RxRy) | (PAGE_SIMULATION, 0xC5, _) => {
+            Some(Target::TriggerLeft)
+        }
+        (PAGE_GENERIC_DESKTOP, 0x35, AxisLayout::RxRy) | (PAGE_SIMULATION, 0xC4, _) => {
+            Some(Target::TriggerRight)
+        }
+        _ => None,
+    }
+}
+
+/// GATT client connected to a BLE HID gamepad.
+#[allow(async_fn_in_trait)]
+pub trait GattClient {
+    type Error;
+
+    /// Connects to the next pad, subscribes to its input reports and reads
+    /// its report map into `map`, returning the report map length.
+    ///
+    /// Lengths beyond `map` are treated as a truncated report map.
+    async fn connect(&mut self, map: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Waits for the next input report notification, returning its report
+    /// ID and length. Fails once the pad disconnected. Reports with a
+    /// length beyond `buf` are ignored.
+    async fn notification(&mut self, buf: &mut [u8]) -> Result<(u8, usize), Self::Error>;
+}
+
+/// BLE gamepad read through a [`GattClient`], yielding a state per changed
+/// report.
+///
+/// Pads with a report map that is not understood are skipped by waiting
+/// for the next [`GattClient::connect`]. When the pad disconnects a neutral
+/// state is yielded once.
+pub struct HogpPad<'a, C> {
+    client: C,
+    axes: AxisLayout,
+    buttons: &'a [Option<Button>],
+    layout: Option<ReportLayout>,
+    last: XboxGamepad,
+}
+
+impl<C: GattClient> HogpPad<'static, C> {
+    pub fn new(client: C, axes: AxisLayout) -> Self {
+        Self {
+            client,
+            axes,
+            buttons: &DEFAULT_BUTTON_MAP,
+            layout: None,
+            last: XboxGamepad::new(),
+        }
+    }
+}
+
+impl<'a, C: GattClient> HogpPad<'a, C> {
+    /// Maps HID button `i + 1` to `buttons[i]` instead of the
+    /// [`DEFAULT_BUTTON_MAP`].
+    pub fn with_button_map<'b>(self, buttons: &'b [Option<Button>]) -> HogpPad<'b, C> {
+        HogpPad {
+            client: self.client,
+            axes: self.axes,
+            buttons,
+            layout: self.layout,
+            last: self.last,
+        }
+    }
+
+    /// Whether a pad is connected.
+    pub fn is_connected(&self) -> bool {
+        self.layout.is_some()
+    }
+}
+
+impl<C: GattClient> InputSource for HogpPad<'_, C> {
+    async fn next(&mut self) -> XboxGamepad {
+        let mut buf = [0_u8; REPORT_BUF_LEN];
+        loop {
+            let Some(layout) = &self.layout else {
+                let mut map = [0_u8; REPORT_MAP_BUF_LEN];
+                let Ok(len) = self.client.connect(&mut map).await else {
+                    continue;
+                };
+                let layout = match map.get(..len) {
+                    Some(map) => ReportLayout::parse(map, self.axes),
+                    None => Err(ParseError::Truncated),
+                };
+                match layout {
+                    Ok(layout) => {
+                        info!("ble hogp: connected");
+                        self.layout = Some(layout);
+                    }
+                    Err(error) => warn!("ble hogp: unsupported report map {:?}", error),
+                }
+                continue;
+            };
+
+            let mut pad = self.last;
+            match self.client.notification(&mut buf).await {
+                Ok((id, n))
+                    if buf
+                        .get(..n)
+                        .is_some_and(|report| layout.apply(id, report, self.buttons, &mut pad)) => {
+                }
+                Ok(_) => continue,
+                Err(_) => {
+                    info!("ble hogp: disconnected");
+                    self.layout = None;
+                    pad = XboxGamepad::new();
+                }
+            }
+            if pad != self.last {
+                self.last = pad;
+                return pad;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::ble_hid;
+
+    // Xbox Wireless Controller style: report ID, sticks on X/Y/Z/Rz,
+    // 10 bit brake and accelerator, hat switch 1 to 8 and 15 buttons.
+    #[rustfmt::skip]
+    const XBOX_MAP: &[u8] = &[
+        0x05, 0x01,       // Usage Page (Generic Desktop)
+        0x09, 0x05,       // Usage (Game Pad)
+        0xA1, 0x01,       // Collection (Application)
+        0x85, 0x01,       //   Report ID (1)
+        0x09, 0x30,       //   Usage (X)
+        0x09, 0x31,       //   Usage (Y)
+        0x09, 0x32,       //   Usage (Z)
+        0x09, 0x35,       //   Usage (Rz)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x27, 0xFF, 0xFF, 0x00, 0x00, // Logical Maximum (65535)
+        0x75, 0x10,       //   Report Size (16)
+        0x95, 0x04,       //   Report Count (4)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        0x05, 0x02,       //   Usage Page (Simulation)
+        0x09, 0xC5,       //   Usage (Brake)
+        0x26, 0xFF, 0x03, //   Logical Maximum (1023)
+        0x75, 0x0A,       //   Report Size (10)
+        0x95, 0x01,       //   Report Count (1)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        0x75, 0x06,       //   Report Size (6)
+        0x81, 0x03,       //   Input (Const)
+        0x09, 0xC4,       //   Usage (Accelerator)
+        0x75, 0x0A,       //   Report Size (10)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        0x75, 0x06,       //   Report Size (6)
+        0x81, 0x03,       //   Input (Const)
+        0x05, 0x01,       //   Usage Page (Generic Desktop)
+        0x09, 0x39,       //   Usage (Hat switch)
+        0x15, 0x01,       //   Logical Minimum (1)
+        0x25, 0x08,       //   Logical Maximum (8)
+        0x75, 0x04,       //   Report Size (4)
+        0x81, 0x42,       //   Input (Data, Var, Abs, Null State)
+        0x81, 0x03,       //   Input (Const)
+        0x05, 0x09,       //   Usage Page (Button)
+        0x19, 0x01,       //   Usage Minimum (1)
+        0x29, 0x0F,       //   Usage Maximum (15)
+        0x15, 0x00,       //   Logical Minimum (0)
+        0x25, 0x01,       //   Logical Maximum (1)
+        0x75, 0x01,       //   Report Size (1)
+        0x95, 0x0F,       //   Report Count (15)
+        0x81, 0x02,       //   Input (Data, Var, Abs)
+        0x95, 0x01,       //   Report Count (1)
+        0x81, 0x03,       //   Input (Const)
+        0xC0,             // End Collection
+    ];
+
+    fn parse(map: &[u8]) -> Result<ReportLayout, ParseError> {
+        ReportLayout::parse(map, AxisLayout::ZRz)
+    }
+
+    #[test]
+    fn ble_hid_reports_round_trip() {
+        let layout = ReportLayout::parse(ble_hid::REPORT_MAP, AxisLayout::RxRy).unwrap();
+        let mut pad = XboxGamepad::new();
+        pad.btn_a = true;
+        pad.btn_guide = true;
+        pad.btn_right_thumb = true;
+        pad.dpad_down = true;
+        pad.dpad_left = true;
+        pad.thumb_left_x = -32767;
+        pad.thumb_left_y = 1000;
+        pad.thumb_right_x = 32767;
+        pad.thumb_right_y = -5;
+        pad.trigger_left = 0x40;
+        pad.trigger_right = -1;
+
+        let mut read = XboxGamepad::new();
+        let report = ble_hid::input_report(&pad);
+        assert!(layout.apply(0, &report, &DEFAULT_BUTTON_MAP, &mut read));
+        assert_eq!(read, pad);
+    }
+
+    #[test]
+    fn xbox_reports_are_read() {
+        let layout = parse(XBOX_MAP).unwrap();
+        #[rustfmt::skip]
+        let report = [
+            0xFF, 0xFF, // X right
+            0x00, 0x00, // Y up
+            0x00, 0x80, // Z centered
+            0x00, 0x80, // Rz centered
+            0xFF, 0x03, // brake
+            0x00, 0x00, // accelerator
+            0x03,       // hat right
+            0x81, 0x00, // buttons 1 and 8
+        ];
+
+        let mut pad = XboxGamepad::new();
+        assert!(layout.apply(1, &report, &DEFAULT_BUTTON_MAP, &mut pad));
+        let mut expected = XboxGamepad::new();
+        expected.thumb_left_x = i16::MAX;
+        expected.thumb_left_y = i16::MAX;
+        expected.trigger_left = -1;
+        expected.dpad_right = true;
+        expected.btn_a = true;
+        expected.btn_start = true;
+        assert_eq!(pad, expected);
+
+        // Other report IDs and short reports leave the pad alone.
+        assert!(!layout.apply(2, &report, &DEFAULT_BUTTON_MAP, &mut pad));
+        assert_eq!(pad, expected);
+        assert!(layout.apply(1, &report[..4], &DEFAULT_BUTTON_MAP, &mut pad));
+        assert_eq!(pad.thumb_left_x, i16::MAX);
+        assert!(!pad.btn_a);
+    }
+
+    #[test]
+    fn malformed_report_maps_are_rejected() {
+        // Report size 0 and 33.
+        assert_eq!(
+            parse(&[0x05, 0x01, 0x09, 0x30, 0x75, 0x00, 0x95, 0x01, 0x81, 0x02]).err(),
+            Some(ParseError::Invalid)
+        );
+        assert_eq!(
+            parse(&[0x05, 0x01, 0x09, 0x30, 0x75, 0x21, 0x95, 0x01, 0x81, 0x02]).err(),
+            Some(ParseError::Invalid)
+        );
+        // Reports longer than 65535 bits.
+        let mut map = std::vec![0x75, 0x20, 0x95, 0xFF];
+        for _ in 0..9 {
+            map.extend_from_slice(&[0x81, 0x03]);
+        }
+        assert_eq!(parse(&map).err(), Some(ParseError::Invalid));
+        // Cut off items.
+        assert_eq!(parse(&[0x05]).err(), Some(ParseError::Truncated));
+        assert_eq!(parse(&[0x26, 0xFF]).err(), Some(ParseError::Truncated));
+        assert_eq!(
+            parse(&[0xFE, 0x10, 0x00]).err(),
+            Some(ParseError::Truncated)
+        );
+        // Consumer control instead of a gamepad.
+        assert_eq!(
+            parse(&[0x05, 0x0C, 0x09, 0x01, 0xA1, 0x01, 0xC0]).err(),
+            Some(ParseError::NotAGamepad)
+        );
+    }
+
+    #[test]
+    fn extreme_logical_ranges_do_not_overflow() {
+        #[rustfmt::skip]
+        let map = [
+            0x05, 0x01,                   // Usage Page (Generic Desktop)
+            0x17, 0x00, 0x00, 0x00, 0x80, // Logical Minimum (i32::MIN)
+            0x27, 0xFF, 0xFF, 0xFF, 0x7F, // Logical Maximum (i32::MAX)
+            0x09, 0x39,                   // Usage (Hat switch)
+            0x09, 0x30,                   // Usage (X)
+            0x09, 0x32,                   // Usage (Z)
+            0x75, 0x20,                   // Report Size (32)
+            0x95, 0x03,                   // Report Count (3)
+            0x81, 0x02,                   // Input (Data, Var, Abs)
+            0x09, 0x31,                   // Usage (Y)
+            0x75, 0x01,                   // Report Size (1)
+            0x95, 0x01,                   // Report Count (1)
+            0x81, 0x02,                   // Input (Data, Var, Abs)
+        ];
+        let layout = parse(&map).unwrap();
+        let mut pad = XboxGamepad::new();
+        for byte in [0x00, 0x7F, 0x80, 0xFF] {
+            assert!(layout.apply(0, &[byte; 13], &DEFAULT_BUTTON_MAP, &mut pad));
+        }
+    }
+
+    #[test]
+    fn corrupted_report_maps_do_not_panic() {
+        for map in [ble_hid::REPORT_MAP, XBOX_MAP] {
+            for i in 0..map.len() {
+                for byte in [0x00, 0x01, 0x03, 0x20, 0x7F, 0x80, 0xFE, 0xFF] {
+                    let mut map = std::vec::Vec::from(map);
+                    map[i] = byte;
+                    let _ = parse(&map[..i + 1]);
+                    let Ok(layout) = parse(&map) else {
+                        continue;
+                    };
+                    let mut pad = XboxGamepad::new();
+                    for report in [&[0x00; 16], &[0xFF; 16]] {
+                        layout.apply(1, report, &DEFAULT_BUTTON_MAP, &mut pad);
+                    }
+                }
+            }
+        }
+    }
+}
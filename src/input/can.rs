@@ -0,0 +1,131 @@
+//! Controller state received over CAN, for sim racing rigs whose button
+//! boxes, wheels and pedals already share a bus.
+//!
+//! The state is split over two data frames with consecutive identifiers,
+//! `id` and `id + 1`, standard or extended. Multi byte values are little
+//! endian; the bytes are the [`ControllerData`] of the XInput report.
+//!
+//! Frame `id`, 8 bytes:
+//!
+//! | Byte | Content                                       |
+//! |------|-----------------------------------------------|
+//! | 0..2 | Buttons, XInput `wButtons` bit order          |
+//! | 2    | Left trigger                                  |
+//! | 3    | Right trigger                                 |
+//! | 4..6 | Left stick X, i16                             |
+//! | 6..8 | Left stick Y, i16, positive is up             |
+//!
+//! Frame `id + 1`, 4 bytes: right stick X and Y. Senders without a right
+//! stick may skip it.
+//!
+//! Frames with other identifiers, remote frames and frames of the wrong
+//! length are ignored; configure the controller's acceptance filters for
+//! the two identifiers to keep the receive FIFO free for them. Without a
+//! frame for the configured timeout the state falls back to neutral, so a
+//! disconnected button box does not leave buttons held.
+//!
+//! Any driver implementing [`embedded_can::nb::Can`], e.g. `bxcan` or an
+//! MCP2515 driver, is polled on every [`Scan::scan`].
+
+use embassy_time::{Duration, Instant};
+use embedded_can::nb::Can;
+use embedded_can::{ExtendedId, Frame, Id, StandardId};
+
+use super::Scan;
+use crate::controller::XboxGamepad;
+use crate::protocol::ControllerData;
+
+/// Default time without a frame after which the state is neutral.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Most frames read per scan, so a flooded bus cannot stall the scan.
+const MAX_FRAMES_PER_SCAN: usize = 16;
+
+/// Pad state received from a CAN bus.
+pub struct CanPad<C> {
+    can: C,
+    id: Id,
+    timeout: Duration,
+    data: ControllerData,
+    last_frame: Option<Instant>,
+}
+
+impl<C: Can> CanPad<C> {
+    /// Listens for the frames `id` and `id + 1`. `id` must be below the
+    /// largest identifier of its kind.
+    pub fn new(can: C, id: Id) -> Self {
+        Self {
+            can,
+            id,
+            timeout: DEFAULT_TIMEOUT,
+            data: ControllerData([0; 12]),
+            last_frame: None,
+        }
+    }
+
+    /// Time without a frame after which the state is neutral, defaults to
+    /// [`DEFAULT_TIMEOUT`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Whether a frame was received within the timeout.
+    pub fn is_connected(&self) -> bool {
+        self.last_frame
+            .is_some_and(|last| last.elapsed() < self.timeout)
+    }
+
+    /// Reads all pending frames and returns the current state.
+    pub fn poll(&mut self) -> ControllerData {
+        for _ in 0..MAX_FRAMES_PER_SCAN {
+            match self.can.receive() {
+                Ok(frame) => self.handle(&frame),
+                Err(nb::Error::WouldBlock) => break,
+                Err(nb::Error::Other(_)) => {
+                    warn!("can: receive failed");
+                    break;
+                }
+            }
+        }
+        if self.last_frame.is_some() && !self.is_connected() {
+            warn!("can: timeout");
+            self.last_frame = None;
+            self.data = ControllerData([0; 12]);
+        }
+        self.data
+    }
+
+    fn handle(&mut self, frame: &C::Frame) {
+        if frame.is_remote_frame() {
+            return;
+        }
+        let range = match (frame.id() == self.id, frame.id() == next_id(self.id)) {
+            (true, _) => 0..8,
+            (_, true) => 8..12,
+            _ => return,
+        };
+        if frame.data().len() != range.len() {
+            debug!("can: bad frame length {}", frame.data().len());
+            return;
+        }
+        self.data.0[range].copy_from_slice(frame.data());
+        self.last_frame = Some(Instant::now());
+    }
+}
+
+/// Identifier of the second frame, `id` itself if it would overflow.
+fn next_id(id: Id) -> Id {
+    match id {
+        Id::Standard(id) => StandardId::new(id.as_raw() + 1).map_or(Id::Standard(id), Id::Standard),
+        Id::Extended(id) => ExtendedId::new(id.as_raw() + 1).map_or(Id::Extended(id), Id::Extended),
+    }
+}
+
+impl<C: Can> Scan for CanPad<C> {
+    /// Overwrites the whole state; scan other frontends after this one to
+    /// add their inputs.
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        *pad = self.poll().into();
+    }
+}
@@ -0,0 +1,168 @@
+//! Dance pad panels: contact switches or force sensing resistors (FSR).
+//!
+//! Panels are stepped on and bounce off quickly, so presses are reported
+//! right away and only releases are debounced, see [`PanelTiming`]. Route
+//! the pad through [`Socd::dance_pad`](crate::socd::Socd::dance_pad), or no
+//! SOCD cleaning at all, so jumps on opposite arrows reach the game.
+
+use embassy_time::{Duration, Instant};
+use embedded_hal::digital::InputPin;
+
+use super::analog_adc::AnalogInput;
+use super::Scan;
+use crate::controller::{Button, XboxGamepad};
+
+/// Debounce times of a panel.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PanelTiming {
+    /// Time the panel must read pressed before the press is reported.
+    pub press: Duration,
+    /// Time the panel must read released before the release is reported.
+    pub release: Duration,
+}
+
+impl PanelTiming {
+    /// Metal contact panels, which chatter for a few milliseconds when
+    /// stepped off.
+    pub const CONTACT: Self = Self {
+        press: Duration::from_millis(0),
+        release: Duration::from_millis(15),
+    };
+    /// FSR panels, which have no contact bounce but flicker around the
+    /// threshold when a foot rests lightly.
+    pub const FSR: Self = Self {
+        press: Duration::from_millis(1),
+        release: Duration::from_millis(5),
+    };
+}
+
+/// Debouncer of a single panel with separate press and release times.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PanelDebounce {
+    stable: bool,
+    candidate: bool,
+    since: Instant,
+}
+
+impl Default for PanelDebounce {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PanelDebounce {
+    pub const fn new() -> Self {
+        Self {
+            stable: false,
+            candidate: false,
+            since: Instant::from_ticks(0),
+        }
+    }
+
+    /// Feeds a raw sample taken at `now`, returning the debounced state.
+    pub fn update(&mut self, raw: bool, now: Instant, timing: &PanelTiming) -> bool {
+        if raw != self.candidate {
+            self.candidate = raw;
+            self.since = now;
+        }
+        let hold = if self.candidate {
+            timing.press
+        } else {
+            timing.release
+        };
+        if self.candidate != self.stable && now - self.since >= hold {
+            self.stable = self.candidate;
+        }
+        self.stable
+    }
+}
+
+/// Contact panels wired to GPIO pins, pulling their pin low when stepped on.
+/// Pin read errors are treated as released panels.
+pub struct ContactPanels<P, const N: usize> {
+    pins: [(P, Button); N],
+    debounce: [PanelDebounce; N],
+    pub timing: PanelTiming,
+}
+
+impl<P: InputPin, const N: usize> ContactPanels<P, N> {
+    pub fn new(pins: [(P, Button); N]) -> Self {
+        Self {
+            pins,
+            debounce: [PanelDebounce::new(); N],
+            timing: PanelTiming::CONTACT,
+        }
+    }
+}
+
+impl<P: InputPin, const N: usize> Scan for ContactPanels<P, N> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        let now = Instant::now();
+        for ((pin, button), debounce) in self.pins.iter_mut().zip(&mut self.debounce) {
+            let raw = pin.is_low().unwrap_or(false);
+            pad.set_button(*button, debounce.update(raw, now, &self.timing));
+        }
+    }
+}
+
+/// Pressure thresholds of an FSR panel, in raw ADC counts.
+///
+/// The gap between them is the hysteresis that keeps a resting foot from
+/// toggling the panel.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FsrThreshold {
+    /// Reading from which the panel is pressed.
+    pub press: u16,
+    /// Reading below which the panel is released again.
+    pub release: u16,
+}
+
+/// FSR panels read through an ADC, higher readings meaning more pressure.
+pub struct FsrPanels<A, const N: usize> {
+    channels: [(A, Button, FsrThreshold); N],
+    pressed: [bool; N],
+    debounce: [PanelDebounce; N],
+    pub timing: PanelTiming,
+}
+
+impl<A: AnalogInput, const N: usize> FsrPanels<A, N> {
+    pub fn new(channels: [(A, Button, FsrThreshold); N]) -> Self {
+        Self {
+            channels,
+            pressed: [false; N],
+            debounce: [PanelDebounce::new(); N],
+            timing: PanelTiming::FSR,
+        }
+    }
+
+    /// Changes the thresholds of the panel at `index`, e.g. from a
+    /// calibration routine.
+    pub fn set_threshold(&mut self, index: usize, threshold: FsrThreshold) {
+        self.channels[index].2 = threshold;
+    }
+}
+
+impl<A: AnalogInput, const N: usize> Scan for FsrPanels<A, N> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        for (((channel, button, threshold), pressed), debounce) in self
+            .channels
+            .iter_mut()
+            .zip(&mut self.pressed)
+            .zip(&mut self.debounce)
+        {
+            let value = channel.sample().await;
+            if value >= threshold.press {
+                *pressed = true;
+            } else if value < threshold.release {
+                *pressed = false;
+            }
+            pad.set_button(
+                *button,
+                debounce.update(*pressed, Instant::now(), &self.timing),
+            );
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Quadrature encoders for arcade spinners, paddles and wheels.
+//!
+//! Counting is left to the application through [`Counter`], e.g. with a
+//! PIO state machine on RP2040 or a pulse counter (PCNT, timer encoder
+//! mode) elsewhere. Slow encoders can be decoded in software with
+//! [`PinCounter`]. [`Encoder`] maps the count to the gamepad, see
+//! [`Mapping`].
+
+use embedded_hal::digital::InputPin;
+
+use super::analog_adc::Axis;
+use super::Scan;
+use crate::controller::{Button, XboxGamepad};
+
+/// Position of a quadrature encoder, implemented for the hardware counter.
+#[allow(async_fn_in_trait)]
+pub trait Counter {
+    /// Counted quadrature steps, four per encoder cycle. May wrap around;
+    /// counters narrower than 32 bits must be sign extended.
+    async fn count(&mut self) -> i32;
+}
+
+/// Software decoder of the two encoder phases.
+///
+/// Every edge is one step, invalid transitions (both phases changed) are
+/// ignored.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Quadrature {
+    state: u8,
+    count: i32,
+}
+
+impl Quadrature {
+    pub const fn new() -> Self {
+        Self { state: 0, count: 0 }
+    }
+
+    /// Feeds the phase levels, returning the count.
+    pub fn update(&mut self, a: bool, b: bool) -> i32 {
+        // Gray code position of the phases: 00, 01, 11, 10.
+        let state: u8 = match (a, b) {
+            (false, false) => 0,
+            (true, false) => 1,
+            (true, true) => 2,
+            (false, true) => 3,
+        };
+        match state.wrapping_sub(self.state) & 0x03 {
+            1 => self.count = self.count.wrapping_add(1),
+            3 => self.count = self.count.wrapping_sub(1),
+            _ => {}
+        }
+        self.state = state;
+        self.count
+    }
+
+    pub fn count(&self) -> i32 {
+        self.count
+    }
+}
+
+/// [`Counter`] decoding two GPIO pins in software.
+///
+/// Steps are only counted when the pins are read, so the encoder must be
+/// scanned faster than its edges arrive; spinners turned fast need a
+/// hardware counter. Pin read errors are read as low.
+pub struct PinCounter<A, B> {
+    a: A,
+    b: B,
+    quadrature: Quadrature,
+}
+
+impl<A: InputPin, B: InputPin> PinCounter<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Self {
+            a,
+            b,
+            quadrature: Quadrature::new(),
+        }
+    }
+
+    /// Reads the pins, returning the count.
+    pub fn poll(&mut self) -> i32 {
+        let a = self.a.is_high().unwrap_or(false);
+        let b = self.b.is_high().unwrap_or(false);
+        self.quadrature.update(a, b)
+    }
+}
+
+impl<A: InputPin, B: InputPin> Counter for PinCounter<A, B> {
+    async fn count(&mut self) -> i32 {
+        self.poll()
+    }
+}
+
+/// How the encoder drives the gamepad.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mapping {
+    /// Absolute position like a paddle: the axis is centered where the
+    /// encoder started and deflected fully `range` steps to either side.
+    Paddle { axis: Axis, range: u16 },
+    /// Speed like a spinner or mouse: the steps since the previous scan
+    /// times `gain` deflect the axis.
+    Spinner { axis: Axis, gain: i16 },
+    /// Steps like a mouse wheel: every `steps` steps in either direction
+    /// press the button for one scan.
+    Wheel {
+        steps: u16,
+        decrement: Button,
+        increment: Button,
+    },
+}
+
+/// Encoder frontend, see the [module documentation](self).
+pub struct Encoder<C> {
+    counter: C,
+    pub mapping: Mapping,
+    last: Option<i32>,
+    position: i32,
+    // wheel steps not reported yet
+    pending: i32,
+    // wheel button pressed in the previous scan
+    pulse: bool,
+}
+
+impl<C: Counter> Encoder<C> {
+    pub fn new(counter: C, mapping: Mapping) -> Self {
+        Self {
+            counter,
+            mapping,
+            last: None,
+            position: 0,
+            pending: 0,
+            pulse: false,
+        }
+    }
+
+    /// Moves the paddle center to the current position.
+    pub fn recenter(&mut self) {
+        self.position = 0;
+    }
+
+    /// Reads the counter and writes the mapped value into `pad`. Inputs
+    /// the mapping does not drive are left untouched.
+    pub async fn sample(&mut self, pad: &mut XboxGamepad) {
+        let count = self.counter.count().await;
+        let delta = self.last.map_or(0, |last| count.wrapping_sub(last));
+        self.last = Some(count);
+
+        let full_scale = i32::from(i16::MAX);
+        match self.mapping {
+            Mapping::Paddle { axis, range } => {
+                let range = i32::from(range.max(1));
+                self.position = self.position.saturating_add(delta).clamp(-range, range);
+                set_axis(pad, axis, self.position * full_scale / range);
+            }
+            Mapping::Spinner { axis, gain } => {
+                set_axis(pad, axis, delta.saturating_mul(i32::from(gain)));
+            }
+            Mapping::Wheel {
+                steps,
+                decrement,
+                increment,
+            } => {
+                let steps = i32::from(steps.max(1));
+                self.position = self.position.saturating_add(delta);
+                self.pending = self.pending.saturating_add(self.position / steps);
+                self.position %= steps;
+                // Release between steps so every step is a new press.
+                if self.pulse || self.pending == 0 {
+                    self.pulse = false;
+                } else {
+                    let button = if self.pending > 0 {
+                        increment
+                    } else {
+                        decrement
+                    };
+                    pad.set_button(button, true);
+                    self.pending -= self.pending.signum();
+                    self.pulse = true;
+                }
+            }
+        }
+    }
+}
+
+// Writes a value in `-i16::MAX..=i16::MAX` to an axis, triggers use the
+// positive half.
+fn set_axis(pad: &mut XboxGamepad, axis: Axis, value: i32) {
+    let value = value.clamp(-i32::from(i16::MAX), i32::from(i16::MAX)) as i16;
+    let trigger = (value.max(0) >> 7) as u8 as i8;
+    match axis {
+        Axis::LeftX => pad.thumb_left_x = value,
+        Axis::LeftY => pad.thumb_left_y = value,
+        Axis::RightX => pad.thumb_right_x = value,
+        Axis::RightY => pad.thumb_right_y = value,
+        Axis::LeftTrigger => pad.trigger_left = trigger,
+        Axis::RightTrigger => pad.trigger_right = trigger,
+    }
+}
+
+impl<C: Counter> Scan for Encoder<C> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        self.sample(pad).await;
+    }
+}
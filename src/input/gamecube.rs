@@ -0,0 +1,183 @@
+//! Nintendo GameCube controller on the Joybus.
+//!
+//! The pad's rumble motor is switched by every poll, so a GC to XInput
+//! adapter forwards the host's rumble with:
+//!
+//! ```ignore
+//! let report = pad.poll(gamecube::rumble_active(state.rumble())).await?;
+//! ```
+
+use super::joybus::{self, Error, JoybusTransport, CMD_GC_ORIGIN, CMD_GC_POLL};
+use crate::controller::XboxGamepad;
+
+/// Device type reported by GameCube controllers, without the flag bits of
+/// the low byte.
+pub const DEVICE_GAMECUBE_CONTROLLER: u16 = 0x0900;
+
+/// Report mode 3: full resolution sticks and analog triggers.
+const REPORT_MODE: u8 = 0x03;
+
+/// Deflection of the main stick at its gate, about 100 on original pads.
+const STICK_RANGE: i32 = 100;
+
+/// Whether the pad's on/off motor should run for the host's
+/// (strong, weak) rumble values.
+pub fn rumble_active((strong, weak): (u8, u8)) -> bool {
+    strong.max(weak) >= 0x20
+}
+
+/// Resting position of the analog inputs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Origin {
+    pub stick: [u8; 2],
+    pub c_stick: [u8; 2],
+    pub triggers: [u8; 2],
+}
+
+impl Default for Origin {
+    fn default() -> Self {
+        Self {
+            stick: [128; 2],
+            c_stick: [128; 2],
+            triggers: [0; 2],
+        }
+    }
+}
+
+/// Decoded response to the poll command.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GameCubeReport {
+    pub a: bool,
+    pub b: bool,
+    pub x: bool,
+    pub y: bool,
+    pub start: bool,
+    pub z: bool,
+    pub l: bool,
+    pub r: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    /// The pad asks for a new origin, e.g. after X + Y + Start was held.
+    pub origin_request: bool,
+    pub stick: [u8; 2],
+    pub c_stick: [u8; 2],
+    pub triggers: [u8; 2],
+}
+
+impl GameCubeReport {
+    pub fn from_raw(raw: [u8; 8]) -> Self {
+        let bit = |byte: u8, n: u8| byte & (1 << n) != 0;
+        Self {
+            a: bit(raw[0], 0),
+            b: bit(raw[0], 1),
+            x: bit(raw[0], 2),
+            y: bit(raw[0], 3),
+            start: bit(raw[0], 4),
+            origin_request: bit(raw[0], 5),
+            left: bit(raw[1], 0),
+            right: bit(raw[1], 1),
+            down: bit(raw[1], 2),
+            up: bit(raw[1], 3),
+            z: bit(raw[1], 4),
+            r: bit(raw[1], 5),
+            l: bit(raw[1], 6),
+            stick: [raw[2], raw[3]],
+            c_stick: [raw[4], raw[5]],
+            triggers: [raw[6], raw[7]],
+        }
+    }
+
+    /// Buttons by position (B on X, X on B), Z on the right shoulder and
+    /// the analog triggers relative to `origin`. A fully clicked L or R
+    /// reports a full trigger press.
+    pub fn apply(&self, origin: &Origin, pad: &mut XboxGamepad) {
+        let axis = |value: u8, center: u8| {
+            ((i32::from(value) - i32::from(center)) * i32::from(i16::MAX) / STICK_RANGE)
+                .clamp(-i32::from(i16::MAX), i32::from(i16::MAX)) as i16
+        };
+        let trigger = |value: u8, rest: u8, clicked: bool| {
+            let value = if clicked {
+                u8::MAX
+            } else {
+                value.saturating_sub(rest)
+            };
+            value as i8
+        };
+        pad.thumb_left_x = axis(self.stick[0], origin.stick[0]);
+        pad.thumb_left_y = axis(self.stick[1], origin.stick[1]);
+        pad.thumb_right_x = axis(self.c_stick[0], origin.c_stick[0]);
+        pad.thumb_right_y = axis(self.c_stick[1], origin.c_stick[1]);
+        pad.trigger_left = trigger(self.triggers[0], origin.triggers[0], self.l);
+        pad.trigger_right = trigger(self.triggers[1], origin.triggers[1], self.r);
+        pad.dpad_up = self.up;
+        pad.dpad_down = self.down;
+        pad.dpad_left = self.left;
+        pad.dpad_right = self.right;
+        pad.btn_a = self.a;
+        pad.btn_x = self.b;
+        pad.btn_b = self.x;
+        pad.btn_y = self.y;
+        pad.btn_start = self.start;
+        pad.btn_right_shoulder = self.z;
+    }
+}
+
+/// GameCube controller driver.
+pub struct GameCube<T> {
+    transport: T,
+    origin: Origin,
+}
+
+impl<T: JoybusTransport> GameCube<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            origin: Origin::default(),
+        }
+    }
+
+    /// Checks that a GameCube controller is connected.
+    pub async fn detect(&mut self) -> Result<bool, Error<T::Error>> {
+        let status = joybus::status(&mut self.transport).await?;
+        Ok(status.device & 0xFF00 == DEVICE_GAMECUBE_CONTROLLER)
+    }
+
+    /// Reads the resting position of the analog inputs, used by
+    /// [`GameCube::poll`] to center them.
+    pub async fn calibrate(&mut self) -> Result<Origin, Error<T::Error>> {
+        let raw = joybus::exchange::<_, 10>(&mut self.transport, &[CMD_GC_ORIGIN]).await?;
+        self.origin = Origin {
+            stick: [raw[2], raw[3]],
+            c_stick: [raw[4], raw[5]],
+            triggers: [raw[6], raw[7]],
+        };
+        Ok(self.origin)
+    }
+
+    pub fn origin(&self) -> Origin {
+        self.origin
+    }
+
+    /// Reads the controller and switches its rumble motor on or off.
+    ///
+    /// Recalibrates when the pad requests a new origin.
+    pub async fn poll(&mut self, rumble: bool) -> Result<GameCubeReport, Error<T::Error>> {
+        let command = [CMD_GC_POLL, REPORT_MODE, u8::from(rumble)];
+        let report = joybus::exchange::<_, 8>(&mut self.transport, &command)
+            .await
+            .map(GameCubeReport::from_raw)?;
+        if report.origin_request {
+            self.calibrate().await?;
+        }
+        Ok(report)
+    }
+
+    /// Applies `report` relative to the current origin.
+    pub fn apply(&self, report: &GameCubeReport, pad: &mut XboxGamepad) {
+        report.apply(&self.origin, pad);
+    }
+}
@@ -0,0 +1,128 @@
+//! Sega Genesis / Mega Drive 3 and 6 button controllers.
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use super::Scan;
+use crate::controller::XboxGamepad;
+
+/// Time for the pad outputs to settle after toggling SELECT.
+const SETTLE_TIME: Duration = Duration::from_micros(10);
+/// Idle time after which 6 button pads reset their select counter.
+const RESET_TIME: Duration = Duration::from_micros(1800);
+
+/// Decoded controller state.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GenesisReport {
+    /// Whether a pad answered on the port.
+    pub connected: bool,
+    /// Whether the pad identified itself as 6 button pad.
+    pub six_button: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub a: bool,
+    pub b: bool,
+    pub c: bool,
+    pub x: bool,
+    pub y: bool,
+    pub z: bool,
+    pub start: bool,
+    pub mode: bool,
+}
+
+impl GenesisReport {
+    /// Maps the bottom row A/B/C to X/A/B and the top row X/Y/Z to
+    /// LB/Y/RB, MODE is reported as Back.
+    pub fn apply(&self, pad: &mut XboxGamepad) {
+        pad.dpad_up = self.up;
+        pad.dpad_down = self.down;
+        pad.dpad_left = self.left;
+        pad.dpad_right = self.right;
+        pad.btn_x = self.a;
+        pad.btn_a = self.b;
+        pad.btn_b = self.c;
+        pad.btn_left_shoulder = self.x;
+        pad.btn_y = self.y;
+        pad.btn_right_shoulder = self.z;
+        pad.btn_start = self.start;
+        pad.btn_back = self.mode;
+    }
+}
+
+/// Controller port driving SELECT (DB9 pin 7) and reading the six data pins.
+///
+/// Data pins need pull-ups. Pin errors read as released buttons.
+pub struct Genesis<S, I> {
+    select: S,
+    // DB9 pins 1, 2, 3, 4, 6, 9
+    data: [I; 6],
+    last_poll: Instant,
+}
+
+impl<S: OutputPin, I: InputPin> Genesis<S, I> {
+    /// `data` are the DB9 pins 1 (up), 2 (down), 3 (left), 4 (right),
+    /// 6 (TL) and 9 (TR).
+    pub fn new(select: S, data: [I; 6]) -> Self {
+        let mut port = Self {
+            select,
+            data,
+            last_poll: Instant::from_ticks(0),
+        };
+        let _ = port.select.set_high();
+        port
+    }
+
+    // Sets SELECT and returns the active (low) data pins.
+    async fn step(&mut self, select: bool) -> [bool; 6] {
+        let _ = self.select.set_state(select.into());
+        Timer::after(SETTLE_TIME).await;
+        let mut low = [false; 6];
+        for (pin, low) in self.data.iter_mut().zip(&mut low) {
+            *low = pin.is_low().unwrap_or(false);
+        }
+        low
+    }
+
+    /// Runs the select sequence and decodes the pad state.
+    pub async fn poll(&mut self) -> GenesisReport {
+        // Let 6 button pads reset their sequence counter.
+        Timer::at(self.last_poll + RESET_TIME).await;
+
+        let mut report = GenesisReport::default();
+
+        // SELECT high: up, down, left, right, B, C
+        let [up, down, left, right, b, c] = self.step(true).await;
+        // SELECT low: up, down, low, low, A, start
+        let [_, _, id_left, id_right, a, start] = self.step(false).await;
+        report.connected = id_left && id_right;
+        (report.up, report.down, report.left, report.right) = (up, down, left, right);
+        (report.a, report.b, report.c, report.start) = (a, b, c, start);
+
+        // Second cycle, then the third low phase pulls all directions low on
+        // 6 button pads and the following high phase returns the extra
+        // buttons.
+        self.step(true).await;
+        self.step(false).await;
+        self.step(true).await;
+        let [id_up, id_down, ..] = self.step(false).await;
+        if report.connected && id_up && id_down {
+            let [z, y, x, mode, ..] = self.step(true).await;
+            report.six_button = true;
+            (report.x, report.y, report.z, report.mode) = (x, y, z, mode);
+            self.step(false).await;
+        }
+
+        let _ = self.select.set_high();
+        self.last_poll = Instant::now();
+        report
+    }
+}
+
+impl<S: OutputPin, I: InputPin> Scan for Genesis<S, I> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        self.poll().await.apply(pad);
+    }
+}
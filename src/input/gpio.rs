@@ -0,0 +1,130 @@
+//! Buttons wired directly to GPIO pins.
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::InputPin;
+
+use super::players::KeyScan;
+use super::{Debounce, Scan};
+use crate::controller::{Button, XboxGamepad};
+use crate::transport::ReportSink;
+
+/// Scans a set of button pins with per-pin debouncing.
+///
+/// Buttons are expected to pull their pin low when pressed (internal
+/// pull-ups enabled). Pin read errors are treated as released buttons.
+pub struct GpioScanner<P, const N: usize> {
+    pins: [(P, Button); N],
+    debounce: [Debounce; N],
+    debounce_time: Duration,
+}
+
+impl<P: InputPin, const N: usize> GpioScanner<P, N> {
+    pub fn new(pins: [(P, Button); N], debounce_time: Duration) -> Self {
+        Self {
+            pins,
+            debounce: [Debounce::new(); N],
+            debounce_time,
+        }
+    }
+
+    /// Samples all pins at `now`, returning the debounced state of every
+    /// pin.
+    pub fn scan_keys(&mut self, now: Instant) -> [bool; N] {
+        let mut keys = [false; N];
+        for (((pin, _), debounce), key) in
+            self.pins.iter_mut().zip(&mut self.debounce).zip(&mut keys)
+        {
+            let raw = pin.is_low().unwrap_or(false);
+            *key = debounce.update(raw, now, self.debounce_time);
+        }
+        keys
+    }
+
+    /// Samples all pins at `now` and writes the debounced button states into
+    /// `pad`. Buttons without a pin are left untouched.
+    pub fn scan(&mut self, pad: &mut XboxGamepad, now: Instant) {
+        let keys = self.scan_keys(now);
+        for ((_, button), pressed) in self.pins.iter().zip(keys) {
+            pad.set_button(*button, pressed);
+        }
+    }
+
+    /// Scans the pins every `period` and reports changes to `sink`.
+    pub async fn run(&mut self, sink: &impl ReportSink, period: Duration) -> ! {
+        let mut reported = None;
+        loop {
+            let mut pad = XboxGamepad::new();
+            self.scan(&mut pad, Instant::now());
+            if reported != Some(pad) {
+                sink.send(&pad);
+                reported = Some(pad);
+            }
+            Timer::after(period).await;
+        }
+    }
+}
+
+impl<P: InputPin, const N: usize> Scan for GpioScanner<P, N> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        GpioScanner::scan(self, pad, Instant::now());
+    }
+}
+
+/// Keys are numbered in pin order, the buttons of the pins are not used.
+impl<P: InputPin, const N: usize> KeyScan for GpioScanner<P, N> {
+    async fn scan_keys(&mut self, keys: &mut [bool]) {
+        let scanned = GpioScanner::scan_keys(self, Instant::now());
+        for (key, pressed) in keys.iter_mut().zip(scanned) {
+            *key = pressed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::convert::Infallible;
+
+    use embedded_hal::digital::ErrorType;
+
+    use super::*;
+
+    /// Pin reading the level of a shared cell, `true` is high.
+    struct FakePin<'a>(&'a Cell<bool>);
+
+    impl ErrorType for FakePin<'_> {
+        type Error = Infallible;
+    }
+
+    impl InputPin for FakePin<'_> {
+        fn is_high(&mut self) -> Result<bool, Infallible> {
+            Ok(self.0.get())
+        }
+
+        fn is_low(&mut self) -> Result<bool, Infallible> {
+            Ok(!self.0.get())
+        }
+    }
+
+    #[test]
+    fn low_pins_are_debounced_presses() {
+        let a = Cell::new(true);
+        let b = Cell::new(true);
+        let mut scanner = GpioScanner::new(
+            [(FakePin(&a), Button::A), (FakePin(&b), Button::B)],
+            Duration::from_millis(5),
+        );
+        let mut pad = XboxGamepad::new();
+        scanner.scan(&mut pad, Instant::from_millis(0));
+        assert_eq!(pad, XboxGamepad::new());
+
+        a.set(false);
+        scanner.scan(&mut pad, Instant::from_millis(1));
+        assert!(!pad.button(Button::A));
+        b.set(false);
+        scanner.scan(&mut pad, Instant::from_millis(6));
+        assert!(pad.button(Button::A));
+        assert!(!pad.button(Button::B));
+        assert_eq!(scanner.scan_keys(Instant::from_millis(11)), [true, true]);
+    }
+}
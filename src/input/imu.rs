@@ -0,0 +1,210 @@
+//! I2C motion sensors producing [`ImuSample`]s.
+//!
+//! The drivers configure the sensors for about 1 kHz output and the full
+//! scale ranges of [`ImuSample`]. [`Sampler`] reads a sensor at a fixed
+//! rate, which backends with motion data expect.
+
+use embassy_time::{Duration, Ticker};
+use embedded_hal_async::i2c::I2c;
+
+use crate::controller::ImuSample;
+
+/// Error while talking to a motion sensor.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    I2c(E),
+    /// The `WHO_AM_I` register holds an unexpected value.
+    UnknownDevice(u8),
+}
+
+/// Motion sensor read by a [`Sampler`].
+#[allow(async_fn_in_trait)]
+pub trait Imu {
+    type Error;
+
+    /// Identifies and configures the sensor.
+    async fn init(&mut self) -> Result<(), Self::Error>;
+
+    /// Reads the latest sample.
+    async fn read(&mut self) -> Result<ImuSample, Self::Error>;
+}
+
+async fn write_registers<I: I2c>(
+    i2c: &mut I,
+    address: u8,
+    registers: &[(u8, u8)],
+) -> Result<(), I::Error> {
+    for &(register, value) in registers {
+        i2c.write(address, &[register, value]).await?;
+    }
+    Ok(())
+}
+
+async fn who_am_i<I: I2c>(
+    i2c: &mut I,
+    address: u8,
+    register: u8,
+    expected: &[u8],
+) -> Result<(), Error<I::Error>> {
+    let mut id = [0_u8];
+    i2c.write_read(address, &[register], &mut id)
+        .await
+        .map_err(Error::I2c)?;
+    if !expected.contains(&id[0]) {
+        return Err(Error::UnknownDevice(id[0]));
+    }
+    Ok(())
+}
+
+/// ST LSM6DS3 family (LSM6DS3, LSM6DS3TR-C, LSM6DSL, LSM6DSO) at 833 Hz.
+pub struct Lsm6ds3<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I: I2c> Lsm6ds3<I> {
+    /// Address with SA0 low, 0x6B with SA0 high.
+    pub const DEFAULT_ADDRESS: u8 = 0x6A;
+
+    const WHO_AM_I: u8 = 0x0F;
+    const CTRL1_XL: u8 = 0x10;
+    const CTRL2_G: u8 = 0x11;
+    const CTRL3_C: u8 = 0x12;
+    const OUTX_L_G: u8 = 0x22;
+
+    pub fn new(i2c: I, address: u8) -> Self {
+        Self { i2c, address }
+    }
+}
+
+impl<I: I2c> Imu for Lsm6ds3<I> {
+    type Error = Error<I::Error>;
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        who_am_i(
+            &mut self.i2c,
+            self.address,
+            Self::WHO_AM_I,
+            &[0x69, 0x6A, 0x6C],
+        )
+        .await?;
+        write_registers(
+            &mut self.i2c,
+            self.address,
+            &[
+                // block data update, register address auto increment
+                (Self::CTRL3_C, 0x44),
+                // 833 Hz, ±8 g
+                (Self::CTRL1_XL, 0x7C),
+                // 833 Hz, ±2000 °/s
+                (Self::CTRL2_G, 0x7C),
+            ],
+        )
+        .await
+        .map_err(Error::I2c)
+    }
+
+    async fn read(&mut self) -> Result<ImuSample, Self::Error> {
+        let mut raw = [0_u8; 12];
+        self.i2c
+            .write_read(self.address, &[Self::OUTX_L_G], &mut raw)
+            .await
+            .map_err(Error::I2c)?;
+        let value = |i: usize| i16::from_le_bytes([raw[2 * i], raw[2 * i + 1]]);
+        // 70 mdps per LSB, so the raw range is ±2293 °/s.
+        let gyro = |i: usize| {
+            let dps_x1000 = i32::from(value(i)) * 70;
+            (dps_x1000 * 32767 / (ImuSample::GYRO_FULL_SCALE_DPS * 1000)).clamp(-32768, 32767)
+                as i16
+        };
+        Ok(ImuSample {
+            gyro: [gyro(0), gyro(1), gyro(2)],
+            accel: [value(3), value(4), value(5)],
+        })
+    }
+}
+
+/// InvenSense MPU-6050 at 1 kHz.
+pub struct Mpu6050<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I: I2c> Mpu6050<I> {
+    /// Address with AD0 low, 0x69 with AD0 high.
+    pub const DEFAULT_ADDRESS: u8 = 0x68;
+
+    const SMPLRT_DIV: u8 = 0x19;
+    const CONFIG: u8 = 0x1A;
+    const GYRO_CONFIG: u8 = 0x1B;
+    const ACCEL_CONFIG: u8 = 0x1C;
+    const ACCEL_XOUT_H: u8 = 0x3B;
+    const PWR_MGMT_1: u8 = 0x6B;
+    const WHO_AM_I: u8 = 0x75;
+
+    pub fn new(i2c: I, address: u8) -> Self {
+        Self { i2c, address }
+    }
+}
+
+impl<I: I2c> Imu for Mpu6050<I> {
+    type Error = Error<I::Error>;
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        who_am_i(&mut self.i2c, self.address, Self::WHO_AM_I, &[0x68]).await?;
+        write_registers(
+            &mut self.i2c,
+            self.address,
+            &[
+                // wake up, clock from the x gyro PLL
+                (Self::PWR_MGMT_1, 0x01),
+                // 184 Hz low pass filter, 1 kHz gyro output
+                (Self::CONFIG, 0x01),
+                (Self::SMPLRT_DIV, 0x00),
+                // ±2000 °/s
+                (Self::GYRO_CONFIG, 0x18),
+                // ±8 g
+                (Self::ACCEL_CONFIG, 0x10),
+            ],
+        )
+        .await
+        .map_err(Error::I2c)
+    }
+
+    async fn read(&mut self) -> Result<ImuSample, Self::Error> {
+        // accel x, y, z, temperature, gyro x, y, z, big endian
+        let mut raw = [0_u8; 14];
+        self.i2c
+            .write_read(self.address, &[Self::ACCEL_XOUT_H], &mut raw)
+            .await
+            .map_err(Error::I2c)?;
+        let value = |i: usize| i16::from_be_bytes([raw[2 * i], raw[2 * i + 1]]);
+        Ok(ImuSample {
+            gyro: [value(4), value(5), value(6)],
+            accel: [value(0), value(1), value(2)],
+        })
+    }
+}
+
+/// Reads an [`Imu`] at a fixed rate.
+pub struct Sampler<M> {
+    imu: M,
+    ticker: Ticker,
+}
+
+impl<M: Imu> Sampler<M> {
+    /// `imu` must be initialized.
+    pub fn new(imu: M, period: Duration) -> Self {
+        Self {
+            imu,
+            ticker: Ticker::every(period),
+        }
+    }
+
+    /// Waits for the next sample time and reads the sensor.
+    pub async fn next(&mut self) -> Result<ImuSample, M::Error> {
+        self.ticker.next().await;
+        self.imu.read().await
+    }
+}
@@ -0,0 +1,81 @@
+//! Nintendo Joybus protocol shared by N64 and GameCube controllers.
+//!
+//! The 1-wire physical layer is timing critical and hardware specific, it is
+//! provided by the application through [`JoybusTransport`] (e.g. a PIO state
+//! machine on RP2040). This module only defines the command layer on top.
+
+/// Identifies the device and reports its status.
+pub const CMD_STATUS: u8 = 0x00;
+/// Reads the N64 controller buttons and stick.
+pub const CMD_N64_POLL: u8 = 0x01;
+/// Reads the GameCube controller state, followed by the report mode and the
+/// rumble byte.
+pub const CMD_GC_POLL: u8 = 0x40;
+/// Reads the GameCube controller's calibrated resting position.
+pub const CMD_GC_ORIGIN: u8 = 0x41;
+/// Resets the device, answered like [`CMD_STATUS`].
+pub const CMD_RESET: u8 = 0xFF;
+
+/// Physical Joybus layer.
+#[allow(async_fn_in_trait)]
+pub trait JoybusTransport {
+    type Error;
+
+    /// Sends `command` followed by the stop bit and receives the response
+    /// into `response`, returning the number of bytes received.
+    async fn transfer(&mut self, command: &[u8], response: &mut [u8])
+        -> Result<usize, Self::Error>;
+}
+
+/// Error of a Joybus exchange.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// The transport failed, usually because no device answered.
+    Transport(E),
+    /// The device answered with an unexpected number of bytes.
+    ResponseLength(usize),
+}
+
+/// Sends `command` and requires a response of exactly `N` bytes.
+pub async fn exchange<T: JoybusTransport, const N: usize>(
+    transport: &mut T,
+    command: &[u8],
+) -> Result<[u8; N], Error<T::Error>> {
+    let mut response = [0_u8; N];
+    let len = transport
+        .transfer(command, &mut response)
+        .await
+        .map_err(Error::Transport)?;
+    if len != N {
+        return Err(Error::ResponseLength(len));
+    }
+    Ok(response)
+}
+
+/// Response to [`CMD_STATUS`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status {
+    /// Device type, e.g. 0x0500 for N64 controllers and 0x0900 for GameCube
+    /// controllers.
+    pub device: u16,
+    /// Device specific status flags.
+    pub flags: u8,
+}
+
+impl Status {
+    pub fn from_raw(raw: [u8; 3]) -> Self {
+        Self {
+            device: u16::from_be_bytes([raw[0], raw[1]]),
+            flags: raw[2],
+        }
+    }
+}
+
+/// Queries the device type and status.
+pub async fn status<T: JoybusTransport>(transport: &mut T) -> Result<Status, Error<T::Error>> {
+    exchange::<_, 3>(transport, &[CMD_STATUS])
+        .await
+        .map(Status::from_raw)
+}
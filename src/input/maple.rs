@@ -0,0 +1,242 @@
+//! Dreamcast controller on the Maple bus.
+//!
+//! Like [`joybus`](super::joybus), the timing critical 2-wire layer is
+//! provided by the application through [`MapleTransport`] (e.g. a PIO
+//! state machine on RP2040), this module builds and checks the frames.
+//!
+//! Frames are handled in wire order: each 32-bit word most significant
+//! byte first, followed by the XOR checksum of all bytes.
+
+use crate::controller::XboxGamepad;
+
+/// Asks a device for its [`DeviceInfo`].
+pub const CMD_DEVICE_REQUEST: u8 = 0x01;
+/// Response to [`CMD_DEVICE_REQUEST`].
+pub const CMD_DEVICE_INFO: u8 = 0x05;
+/// Data response to [`CMD_GET_CONDITION`].
+pub const CMD_DATA_TRANSFER: u8 = 0x08;
+/// Reads the current condition of a device function.
+pub const CMD_GET_CONDITION: u8 = 0x09;
+
+/// Function code of standard controllers.
+pub const FUNCTION_CONTROLLER: u32 = 0x0000_0001;
+
+/// Address of the host on port A.
+const HOST_ADDRESS: u8 = 0x00;
+/// Address of the main peripheral on port A.
+const DEVICE_ADDRESS: u8 = 0x20;
+
+/// Words in the device info payload.
+const DEVICE_INFO_WORDS: usize = 28;
+
+/// Physical Maple bus layer.
+#[allow(async_fn_in_trait)]
+pub trait MapleTransport {
+    type Error;
+
+    /// Sends `frame` including its checksum byte and receives the response
+    /// frame into `response`, returning the number of bytes received.
+    async fn transfer(&mut self, frame: &[u8], response: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// Error of a Maple bus exchange.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// The transport failed, usually because no device answered.
+    Transport(E),
+    /// The response is truncated or its checksum does not match.
+    Corrupted,
+    /// The device answered with another command, e.g. an error code.
+    Unexpected(u8),
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, byte| crc ^ byte)
+}
+
+/// Sends a frame with `payload` words and returns the response payload
+/// words of a frame with command `expected`.
+async fn exchange<T: MapleTransport, const N: usize>(
+    transport: &mut T,
+    command: u8,
+    payload: &[u32],
+    expected: u8,
+) -> Result<[u32; N], Error<T::Error>> {
+    let mut frame = [0_u8; 4 + 4 * 4 + 1];
+    let len = 4 + 4 * payload.len();
+    frame[..4].copy_from_slice(&[payload.len() as u8, HOST_ADDRESS, DEVICE_ADDRESS, command]);
+    for (chunk, word) in frame[4..len].chunks_exact_mut(4).zip(payload) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    frame[len] = checksum(&frame[..len]);
+
+    let mut response = [0_u8; 4 + DEVICE_INFO_WORDS * 4 + 1];
+    let received = transport
+        .transfer(&frame[..=len], &mut response)
+        .await
+        .map_err(Error::Transport)?;
+    let response = response.get(..received).ok_or(Error::Corrupted)?;
+    let [words, _, _, response_command] = *response.first_chunk().ok_or(Error::Corrupted)?;
+    if response_command != expected {
+        return Err(Error::Unexpected(response_command));
+    }
+    let len = 4 + 4 * usize::from(words);
+    if usize::from(words) < N
+        || response.len() <= len
+        || checksum(&response[..len]) != response[len]
+    {
+        return Err(Error::Corrupted);
+    }
+
+    let mut words = [0_u32; N];
+    for (word, chunk) in words.iter_mut().zip(response[4..].chunks_exact(4)) {
+        *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    Ok(words)
+}
+
+/// Start of the response to [`CMD_DEVICE_REQUEST`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceInfo {
+    /// Bit mask of the supported function codes.
+    pub functions: u32,
+    /// Function specific data of the first three functions.
+    pub function_data: [u32; 3],
+}
+
+impl DeviceInfo {
+    pub fn is_controller(&self) -> bool {
+        self.functions & FUNCTION_CONTROLLER != 0
+    }
+}
+
+/// Decoded controller condition.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DreamcastReport {
+    /// Pressed buttons, see the `BTN_*` constants.
+    pub buttons: u16,
+    pub trigger_left: u8,
+    pub trigger_right: u8,
+    /// Stick position, 128 is centered and 0 is left and up.
+    pub stick: [u8; 2],
+    /// Second stick of some arcade sticks and the twin stick.
+    pub stick2: [u8; 2],
+}
+
+impl Default for DreamcastReport {
+    fn default() -> Self {
+        Self {
+            buttons: 0,
+            trigger_left: 0,
+            trigger_right: 0,
+            stick: [128; 2],
+            stick2: [128; 2],
+        }
+    }
+}
+
+impl DreamcastReport {
+    pub const BTN_C: u16 = 1 << 0;
+    pub const BTN_B: u16 = 1 << 1;
+    pub const BTN_A: u16 = 1 << 2;
+    pub const BTN_START: u16 = 1 << 3;
+    pub const BTN_UP: u16 = 1 << 4;
+    pub const BTN_DOWN: u16 = 1 << 5;
+    pub const BTN_LEFT: u16 = 1 << 6;
+    pub const BTN_RIGHT: u16 = 1 << 7;
+    pub const BTN_Z: u16 = 1 << 8;
+    pub const BTN_Y: u16 = 1 << 9;
+    pub const BTN_X: u16 = 1 << 10;
+    pub const BTN_D: u16 = 1 << 11;
+
+    /// Decodes the two condition words following the function code.
+    pub fn from_words(words: [u32; 2]) -> Self {
+        let [buttons_low, buttons_high, trigger_right, trigger_left] = words[0].to_le_bytes();
+        let [x, y, x2, y2] = words[1].to_le_bytes();
+        Self {
+            // Buttons are active low.
+            buttons: !u16::from_le_bytes([buttons_low, buttons_high]),
+            trigger_left,
+            trigger_right,
+            stick: [x, y],
+            stick2: [x2, y2],
+        }
+    }
+
+    pub fn pressed(&self, button: u16) -> bool {
+        self.buttons & button != 0
+    }
+
+    /// Buttons by name, C on the left shoulder, Z on the right shoulder
+    /// and D on back.
+    pub fn apply(&self, pad: &mut XboxGamepad) {
+        // Dreamcast Y axes point down.
+        let axis = |value: u8, invert: bool| {
+            let value = (i32::from(value) - 128) * 256;
+            let value = if invert { -value } else { value };
+            value.clamp(-i32::from(i16::MAX), i32::from(i16::MAX)) as i16
+        };
+        pad.thumb_left_x = axis(self.stick[0], false);
+        pad.thumb_left_y = axis(self.stick[1], true);
+        pad.thumb_right_x = axis(self.stick2[0], false);
+        pad.thumb_right_y = axis(self.stick2[1], true);
+        pad.trigger_left = self.trigger_left as i8;
+        pad.trigger_right = self.trigger_right as i8;
+        pad.dpad_up = self.pressed(Self::BTN_UP);
+        pad.dpad_down = self.pressed(Self::BTN_DOWN);
+        pad.dpad_left = self.pressed(Self::BTN_LEFT);
+        pad.dpad_right = self.pressed(Self::BTN_RIGHT);
+        pad.btn_a = self.pressed(Self::BTN_A);
+        pad.btn_b = self.pressed(Self::BTN_B);
+        pad.btn_x = self.pressed(Self::BTN_X);
+        pad.btn_y = self.pressed(Self::BTN_Y);
+        pad.btn_start = self.pressed(Self::BTN_START);
+        pad.btn_left_shoulder = self.pressed(Self::BTN_C);
+        pad.btn_right_shoulder = self.pressed(Self::BTN_Z);
+        pad.btn_back = self.pressed(Self::BTN_D);
+    }
+}
+
+/// Dreamcast controller driver for the main peripheral of one port.
+pub struct Maple<T> {
+    transport: T,
+}
+
+impl<T: MapleTransport> Maple<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    /// Identifies the connected device.
+    pub async fn device_info(&mut self) -> Result<DeviceInfo, Error<T::Error>> {
+        let [functions, data @ ..] = exchange::<_, 4>(
+            &mut self.transport,
+            CMD_DEVICE_REQUEST,
+            &[],
+            CMD_DEVICE_INFO,
+        )
+        .await?;
+        Ok(DeviceInfo {
+            functions,
+            function_data: data,
+        })
+    }
+
+    /// Reads the controller condition.
+    pub async fn poll(&mut self) -> Result<DreamcastReport, Error<T::Error>> {
+        let [function, words @ ..] = exchange::<_, 3>(
+            &mut self.transport,
+            CMD_GET_CONDITION,
+            &[FUNCTION_CONTROLLER],
+            CMD_DATA_TRANSFER,
+        )
+        .await?;
+        if function != FUNCTION_CONTROLLER {
+            return Err(Error::Corrupted);
+        }
+        Ok(DreamcastReport::from_words(words))
+    }
+}
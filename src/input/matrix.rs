@@ -0,0 +1,158 @@
+//! Buttons wired as a row/column matrix.
+
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use super::players::KeyScan;
+use super::{Debounce, Scan};
+use crate::controller::{Button, XboxGamepad};
+use crate::transport::ReportSink;
+
+/// Time between driving a row and sampling the columns.
+const SETTLE_TIME: Duration = Duration::from_micros(5);
+
+/// Scans a button matrix with per-key debouncing.
+///
+/// Rows are driven low one at a time, columns need pull-ups and read low
+/// for pressed keys. Without a diode per key, three pressed keys at the
+/// corners of a rectangle make the fourth corner appear pressed ("ghosting");
+/// unless `diodes` is set, keys involved in such a pattern keep their
+/// previous state until the ambiguity is gone. Pin errors are treated as
+/// released keys.
+pub struct MatrixScanner<R, C, const ROWS: usize, const COLS: usize> {
+    rows: [R; ROWS],
+    cols: [C; COLS],
+    map: [[Option<Button>; COLS]; ROWS],
+    debounce: [[Debounce; COLS]; ROWS],
+    debounce_time: Duration,
+    diodes: bool,
+}
+
+impl<R: OutputPin, C: InputPin, const ROWS: usize, const COLS: usize>
+    MatrixScanner<R, C, ROWS, COLS>
+{
+    /// `map[row][col]` is the button at that matrix position, if any.
+    pub fn new(
+        rows: [R; ROWS],
+        cols: [C; COLS],
+        map: [[Option<Button>; COLS]; ROWS],
+        debounce_time: Duration,
+        diodes: bool,
+    ) -> Self {
+        let mut scanner = Self {
+            rows,
+            cols,
+            map,
+            debounce: [[Debounce::new(); COLS]; ROWS],
+            debounce_time,
+            diodes,
+        };
+        for row in &mut scanner.rows {
+            let _ = row.set_high();
+        }
+        scanner
+    }
+
+    async fn read_raw(&mut self) -> [[bool; COLS]; ROWS] {
+        let mut raw = [[false; COLS]; ROWS];
+        for (row, raw_row) in self.rows.iter_mut().zip(&mut raw) {
+            let _ = row.set_low();
+            Timer::after(SETTLE_TIME).await;
+            for (col, pressed) in self.cols.iter_mut().zip(raw_row.iter_mut()) {
+                *pressed = col.is_low().unwrap_or(false);
+            }
+            let _ = row.set_high();
+        }
+        raw
+    }
+
+    // Marks keys that are part of a rectangle of pressed keys.
+    fn ghosted(raw: &[[bool; COLS]; ROWS]) -> [[bool; COLS]; ROWS] {
+        let mut ghosted = [[false; COLS]; ROWS];
+        for a in 0..ROWS {
+            for b in a + 1..ROWS {
+                let shared = (0..COLS).filter(|&c| raw[a][c] && raw[b][c]).count();
+                if shared >= 2 {
+                    for c in 0..COLS {
+                        ghosted[a][c] |= raw[a][c];
+                        ghosted[b][c] |= raw[b][c];
+                    }
+                }
+            }
+        }
+        ghosted
+    }
+
+    /// Scans the matrix, returning the debounced state of every key.
+    pub async fn scan_keys(&mut self) -> [[bool; COLS]; ROWS] {
+        let raw = self.read_raw().await;
+        let ghosted = if self.diodes {
+            [[false; COLS]; ROWS]
+        } else {
+            Self::ghosted(&raw)
+        };
+        let now = Instant::now();
+
+        let mut keys = [[false; COLS]; ROWS];
+        for r in 0..ROWS {
+            for c in 0..COLS {
+                let debounce = &mut self.debounce[r][c];
+                keys[r][c] = if ghosted[r][c] {
+                    debounce.state()
+                } else {
+                    debounce.update(raw[r][c], now, self.debounce_time)
+                };
+            }
+        }
+        keys
+    }
+
+    /// Scans the matrix and writes the debounced button states into `pad`.
+    /// Buttons not in the map are left untouched.
+    pub async fn scan(&mut self, pad: &mut XboxGamepad) {
+        let keys = self.scan_keys().await;
+        for button in self.map.iter().flatten().flatten() {
+            pad.set_button(*button, false);
+        }
+        for (map, pressed) in self.map.iter().flatten().zip(keys.iter().flatten()) {
+            // Several keys may be mapped to the same button.
+            if let (Some(button), true) = (map, pressed) {
+                pad.set_button(*button, true);
+            }
+        }
+    }
+
+    /// Scans the matrix every `period` and reports changes to `sink`.
+    pub async fn run(&mut self, sink: &impl ReportSink, period: Duration) -> ! {
+        let mut reported = None;
+        loop {
+            let mut pad = XboxGamepad::new();
+            self.scan(&mut pad).await;
+            if reported != Some(pad) {
+                sink.send(&pad);
+                reported = Some(pad);
+            }
+            Timer::after(period).await;
+        }
+    }
+}
+
+impl<R: OutputPin, C: InputPin, const ROWS: usize, const COLS: usize> Scan
+    for MatrixScanner<R, C, ROWS, COLS>
+{
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        MatrixScanner::scan(self, pad).await;
+    }
+}
+
+/// Keys are numbered row by row, key `row * COLS + col`.
+impl<R: OutputPin, C: InputPin, const ROWS: usize, const COLS: usize> KeyScan
+    for MatrixScanner<R, C, ROWS, COLS>
+{
+    async fn scan_keys(&mut self, keys: &mut [bool]) {
+        let scanned = MatrixScanner::scan_keys(self).await;
+        for (key, pressed) in keys.iter_mut().zip(scanned.iter().flatten()) {
+            *key = *pressed;
+        }
+    }
+}
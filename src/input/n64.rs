@@ -0,0 +1,137 @@
+//! Nintendo 64 controller on the Joybus.
+
+use super::joybus::{self, Error, JoybusTransport, CMD_N64_POLL};
+use crate::controller::XboxGamepad;
+
+/// Device type reported by N64 controllers.
+pub const DEVICE_N64_CONTROLLER: u16 = 0x0500;
+
+/// Deflection of the stick at its physical limit, about 80 on original pads.
+const STICK_RANGE: i32 = 80;
+
+/// Status of the accessory slot, from the status response flags.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PakStatus {
+    pub flags: u8,
+}
+
+impl PakStatus {
+    /// A Controller Pak (or other accessory) is inserted.
+    pub fn inserted(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    /// The accessory was removed since the last status request.
+    pub fn removed(&self) -> bool {
+        self.flags & 0x02 != 0
+    }
+
+    /// The last accessory transfer had an address CRC error.
+    pub fn crc_error(&self) -> bool {
+        self.flags & 0x04 != 0
+    }
+}
+
+/// Decoded response to the poll command.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct N64Report {
+    pub a: bool,
+    pub b: bool,
+    pub z: bool,
+    pub start: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub l: bool,
+    pub r: bool,
+    pub c_up: bool,
+    pub c_down: bool,
+    pub c_left: bool,
+    pub c_right: bool,
+    pub stick_x: i8,
+    pub stick_y: i8,
+}
+
+impl N64Report {
+    pub fn from_raw(raw: [u8; 4]) -> Self {
+        let bit = |byte: u8, n: u8| byte & (0x80 >> n) != 0;
+        Self {
+            a: bit(raw[0], 0),
+            b: bit(raw[0], 1),
+            z: bit(raw[0], 2),
+            start: bit(raw[0], 3),
+            up: bit(raw[0], 4),
+            down: bit(raw[0], 5),
+            left: bit(raw[0], 6),
+            right: bit(raw[0], 7),
+            l: bit(raw[1], 2),
+            r: bit(raw[1], 3),
+            c_up: bit(raw[1], 4),
+            c_down: bit(raw[1], 5),
+            c_left: bit(raw[1], 6),
+            c_right: bit(raw[1], 7),
+            stick_x: raw[2] as i8,
+            stick_y: raw[3] as i8,
+        }
+    }
+
+    /// Stick on the left stick, C buttons on the right stick, Z on the left
+    /// trigger, L/R on the shoulder buttons and B on X.
+    pub fn apply(&self, pad: &mut XboxGamepad) {
+        let stick = |v: i8| {
+            (i32::from(v) * i32::from(i16::MAX) / STICK_RANGE)
+                .clamp(-i32::from(i16::MAX), i32::from(i16::MAX)) as i16
+        };
+        let c_axis = |negative: bool, positive: bool| match (negative, positive) {
+            (true, false) => -i16::MAX,
+            (false, true) => i16::MAX,
+            _ => 0,
+        };
+        pad.thumb_left_x = stick(self.stick_x);
+        pad.thumb_left_y = stick(self.stick_y);
+        pad.thumb_right_x = c_axis(self.c_left, self.c_right);
+        pad.thumb_right_y = c_axis(self.c_down, self.c_up);
+        pad.dpad_up = self.up;
+        pad.dpad_down = self.down;
+        pad.dpad_left = self.left;
+        pad.dpad_right = self.right;
+        pad.btn_a = self.a;
+        pad.btn_x = self.b;
+        pad.btn_start = self.start;
+        pad.btn_left_shoulder = self.l;
+        pad.btn_right_shoulder = self.r;
+        pad.trigger_left = if self.z { u8::MAX as i8 } else { 0 };
+    }
+}
+
+/// N64 controller driver.
+pub struct N64<T> {
+    transport: T,
+}
+
+impl<T: JoybusTransport> N64<T> {
+    pub fn new(transport: T) -> Self {
+        Self { transport }
+    }
+
+    /// Checks that an N64 controller is connected and reports its
+    /// accessory slot status.
+    pub async fn status(&mut self) -> Result<Option<PakStatus>, Error<T::Error>> {
+        let status = joybus::status(&mut self.transport).await?;
+        Ok(
+            (status.device == DEVICE_N64_CONTROLLER).then_some(PakStatus {
+                flags: status.flags,
+            }),
+        )
+    }
+
+    /// Reads buttons and stick.
+    pub async fn poll(&mut self) -> Result<N64Report, Error<T::Error>> {
+        joybus::exchange::<_, 4>(&mut self.transport, &[CMD_N64_POLL])
+            .await
+            .map(N64Report::from_raw)
+    }
+}
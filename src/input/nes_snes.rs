@@ -0,0 +1,158 @@
+//! NES and SNES controllers using the latch/clock/data serial protocol.
+
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use super::Scan;
+use crate::controller::{Button, XboxGamepad};
+
+/// Controller type, determines the number of bits per pad.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PadType {
+    /// A, B, Select, Start, Up, Down, Left, Right.
+    Nes,
+    /// B, Y, Select, Start, Up, Down, Left, Right, A, X, L, R and 4 ID bits.
+    Snes,
+}
+
+impl PadType {
+    pub const fn bits(&self) -> usize {
+        match self {
+            PadType::Nes => 8,
+            PadType::Snes => 16,
+        }
+    }
+
+    // Buttons in shift order, mapped by position: the Nintendo B (bottom on
+    // SNES) button is reported as A.
+    const fn map(&self) -> &'static [Option<Button>] {
+        match self {
+            PadType::Nes => &[
+                Some(Button::B),
+                Some(Button::A),
+                Some(Button::Back),
+                Some(Button::Start),
+                Some(Button::DpadUp),
+                Some(Button::DpadDown),
+                Some(Button::DpadLeft),
+                Some(Button::DpadRight),
+            ],
+            PadType::Snes => &[
+                Some(Button::A),
+                Some(Button::X),
+                Some(Button::Back),
+                Some(Button::Start),
+                Some(Button::DpadUp),
+                Some(Button::DpadDown),
+                Some(Button::DpadLeft),
+                Some(Button::DpadRight),
+                Some(Button::B),
+                Some(Button::Y),
+                Some(Button::LeftShoulder),
+                Some(Button::RightShoulder),
+            ],
+        }
+    }
+}
+
+/// Buttons of one pad, bit `i` set when the `i`th shifted button is pressed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PadReport {
+    pub pad_type: PadType,
+    pub buttons: u16,
+}
+
+impl PadReport {
+    /// Writes the buttons into `pad`.
+    pub fn apply(&self, pad: &mut XboxGamepad) {
+        for (i, button) in self.pad_type.map().iter().enumerate() {
+            if let Some(button) = button {
+                pad.set_button(*button, self.buttons & (1 << i) != 0);
+            }
+        }
+    }
+}
+
+/// Protocol timing.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timing {
+    /// Length of the latch pulse, 12µs on the original consoles.
+    pub latch: Duration,
+    /// Clock half period, 6µs on the original consoles.
+    pub half_period: Duration,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            latch: Duration::from_micros(12),
+            half_period: Duration::from_micros(6),
+        }
+    }
+}
+
+/// Bit-banged NES/SNES controller port.
+///
+/// `PADS` controllers daisy chained on the same data line (e.g. behind a
+/// NES Four Score) are read within a single latch. Pin errors read as
+/// released buttons.
+pub struct NesSnes<L, C, D, const PADS: usize = 1> {
+    latch: L,
+    clock: C,
+    data: D,
+    pad_type: PadType,
+    pub timing: Timing,
+}
+
+impl<L: OutputPin, C: OutputPin, D: InputPin, const PADS: usize> NesSnes<L, C, D, PADS> {
+    pub fn new(latch: L, clock: C, data: D, pad_type: PadType) -> Self {
+        let mut port = Self {
+            latch,
+            clock,
+            data,
+            pad_type,
+            timing: Timing::default(),
+        };
+        let _ = port.latch.set_low();
+        let _ = port.clock.set_high();
+        port
+    }
+
+    /// Reads all pads on the chain.
+    pub async fn poll(&mut self) -> [PadReport; PADS] {
+        let _ = self.latch.set_high();
+        Timer::after(self.timing.latch).await;
+        let _ = self.latch.set_low();
+        Timer::after(self.timing.half_period).await;
+
+        let mut reports = [PadReport {
+            pad_type: self.pad_type,
+            buttons: 0,
+        }; PADS];
+        for report in &mut reports {
+            for i in 0..self.pad_type.bits() {
+                // Data is low while a button is pressed. The first bit is
+                // valid after latching, rising clock edges shift the next.
+                if self.data.is_low().unwrap_or(false) {
+                    report.buttons |= 1 << i;
+                }
+                let _ = self.clock.set_low();
+                Timer::after(self.timing.half_period).await;
+                let _ = self.clock.set_high();
+                Timer::after(self.timing.half_period).await;
+            }
+        }
+        reports
+    }
+}
+
+/// Single pad ports only, chains report one state per pad.
+impl<L: OutputPin, C: OutputPin, D: InputPin> Scan for NesSnes<L, C, D> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        let [report] = self.poll().await;
+        report.apply(pad);
+    }
+}
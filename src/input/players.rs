@@ -0,0 +1,104 @@
+//! Several players on one panel, e.g. a two-player arcade control panel
+//! wired to a single matrix or set of GPIO pins.
+//!
+//! [`PlayerSplit`] assigns every key of a [`KeyScan`] to a player and
+//! button, applies a [`ButtonMap`] per player and reports each player to
+//! its own sink, typically one slot of a multi-slot receiver:
+//!
+//! ```ignore
+//! let mut split = PlayerSplit::new(matrix, LAYOUT);
+//! split.run([&SLOT_1, &SLOT_2], Duration::from_millis(1)).await
+//! ```
+
+use embassy_time::{Duration, Timer};
+
+use crate::controller::{Button, XboxGamepad};
+use crate::remap::{ButtonMap, Transform};
+use crate::transport::ReportSink;
+
+/// Frontend reporting the debounced state of individual keys instead of
+/// gamepad buttons.
+#[allow(async_fn_in_trait)]
+pub trait KeyScan {
+    /// Samples the keys and writes their states into `keys`, in the order
+    /// documented by the implementation. Keys beyond the end of `keys`
+    /// are not reported.
+    async fn scan_keys(&mut self, keys: &mut [bool]);
+}
+
+impl<T: KeyScan + ?Sized> KeyScan for &mut T {
+    async fn scan_keys(&mut self, keys: &mut [bool]) {
+        (**self).scan_keys(keys).await
+    }
+}
+
+/// Player (index into the sinks) and button a key is reported as.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PlayerButton {
+    pub player: usize,
+    pub button: Button,
+}
+
+impl PlayerButton {
+    pub const fn new(player: usize, button: Button) -> Self {
+        Self { player, button }
+    }
+}
+
+/// Splits the keys of a [`KeyScan`] into `P` gamepads, see the
+/// [module documentation](self).
+pub struct PlayerSplit<S, const K: usize, const P: usize> {
+    scanner: S,
+    layout: [Option<PlayerButton>; K],
+    /// Remap table of each player, applied after splitting.
+    pub maps: [ButtonMap; P],
+}
+
+impl<S: KeyScan, const K: usize, const P: usize> PlayerSplit<S, K, P> {
+    /// `layout[key]` is the player and button of that key, if any. Keys
+    /// assigned to a player `P` or above are ignored.
+    pub fn new(scanner: S, layout: [Option<PlayerButton>; K]) -> Self {
+        Self {
+            scanner,
+            layout,
+            maps: [ButtonMap::identity(); P],
+        }
+    }
+
+    /// Scans the keys, returning the state of every player.
+    pub async fn scan(&mut self) -> [XboxGamepad; P] {
+        let mut keys = [false; K];
+        self.scanner.scan_keys(&mut keys).await;
+        let mut pads = [XboxGamepad::new(); P];
+        for (assignment, pressed) in self.layout.iter().zip(keys) {
+            let Some(PlayerButton { player, button }) = *assignment else {
+                continue;
+            };
+            // Several keys may be mapped to the same button.
+            if let (Some(pad), true) = (pads.get_mut(player), pressed) {
+                pad.set_button(button, true);
+            }
+        }
+        for (pad, map) in pads.iter_mut().zip(&self.maps) {
+            *pad = map.transform(*pad);
+        }
+        pads
+    }
+
+    /// Scans the keys every `period` and reports changes of each player to
+    /// its sink.
+    pub async fn run(&mut self, sinks: [impl ReportSink; P], period: Duration) -> ! {
+        let mut reported = [None; P];
+        loop {
+            let pads = self.scan().await;
+            for ((pad, sink), reported) in pads.iter().zip(&sinks).zip(&mut reported) {
+                if *reported != Some(*pad) {
+                    sink.send(pad);
+                    *reported = Some(*pad);
+                }
+            }
+            Timer::after(period).await;
+        }
+    }
+}
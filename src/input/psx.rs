@@ -0,0 +1,781 @@
+//! PlayStation controllers, connected directly or behind a multitap.
+//!
+//! The bus is SPI like (mode 3, LSB first, about 250 kHz) with an extra ACK
+//! line the pad pulses after each byte but the last. Driving it is left to
+//! the application through [`PsxTransport`], e.g. with a PIO state machine
+//! on RP2040 or an SPI peripheral plus an ACK interrupt; [`PsxBus`]
+//! implements the framing on top.
+
+use embassy_time::{Duration, Timer};
+
+use crate::controller::XboxGamepad;
+use crate::transport::ReportSink;
+use crate::xinput;
+
+/// Consecutive polls a port has to be empty before its pad is considered
+/// unplugged, so single corrupted responses are not reported to the host.
+const MISSED_POLLS: u8 = 3;
+/// Longest poll period while nothing answers.
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Bytes exchanged by a multitap poll: the header followed by one slot per
+/// port.
+pub const MULTITAP_FRAME_LEN: usize = 3 + 4 * SLOT_LEN;
+/// Bytes exchanged by the longest direct poll: the header and the 18 data
+/// bytes of a DualShock 2 with pressures.
+const DIRECT_FRAME_LEN: usize = 3 + 18;
+/// Bytes of a pad response inside a multitap frame: ID, 0x5A and data.
+const SLOT_LEN: usize = 8;
+
+/// Selects the controller port.
+const CMD_ADDRESS: u8 = 0x01;
+/// Reads the pad state.
+const CMD_POLL: u8 = 0x42;
+/// Enters (0x01) or leaves (0x00) config mode.
+const CMD_CONFIG: u8 = 0x43;
+/// Selects analog mode and locks the mode button. Config mode only.
+const CMD_SET_MODE: u8 = 0x44;
+/// Maps poll command bytes to the motors. Config mode only.
+const CMD_MAP_MOTORS: u8 = 0x4D;
+/// Selects the reported data, used to enable pressures. Config mode only.
+const CMD_SET_RESPONSE: u8 = 0x4F;
+/// Bytes exchanged by config commands.
+const CONFIG_FRAME_LEN: usize = 9;
+/// Addresses the multitap instead of the pad plugged into it.
+const MULTITAP_ACCESS: u8 = 0x01;
+/// ID byte of a multitap.
+const MULTITAP_ID: u8 = 0x80;
+/// Second byte of every valid response.
+const READY: u8 = 0x5A;
+
+/// Physical PSX bus layer.
+#[allow(async_fn_in_trait)]
+pub trait PsxTransport {
+    type Error;
+
+    /// Asserts the attention line and exchanges `tx` and `rx` byte by byte,
+    /// waiting for the ACK pulse between bytes.
+    ///
+    /// Bytes after a missing ACK, and all bytes when nothing is connected,
+    /// read as 0xFF.
+    async fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<(), Self::Error>;
+}
+
+/// Pressed buttons, see the `BTN_*` constants.
+pub type Buttons = u16;
+
+pub const BTN_SELECT: Buttons = 1 << 0;
+pub const BTN_L3: Buttons = 1 << 1;
+pub const BTN_R3: Buttons = 1 << 2;
+pub const BTN_START: Buttons = 1 << 3;
+pub const BTN_UP: Buttons = 1 << 4;
+pub const BTN_RIGHT: Buttons = 1 << 5;
+pub const BTN_DOWN: Buttons = 1 << 6;
+pub const BTN_LEFT: Buttons = 1 << 7;
+pub const BTN_L2: Buttons = 1 << 8;
+pub const BTN_R2: Buttons = 1 << 9;
+pub const BTN_L1: Buttons = 1 << 10;
+pub const BTN_R1: Buttons = 1 << 11;
+pub const BTN_TRIANGLE: Buttons = 1 << 12;
+pub const BTN_CIRCLE: Buttons = 1 << 13;
+pub const BTN_CROSS: Buttons = 1 << 14;
+pub const BTN_SQUARE: Buttons = 1 << 15;
+
+pub const BTN_MOUSE_RIGHT: Buttons = BTN_L1;
+pub const BTN_MOUSE_LEFT: Buttons = BTN_R1;
+pub const BTN_NEGCON_A: Buttons = BTN_CIRCLE;
+pub const BTN_NEGCON_B: Buttons = BTN_TRIANGLE;
+pub const BTN_GUNCON_TRIGGER: Buttons = BTN_CIRCLE;
+pub const BTN_GUNCON_A: Buttons = BTN_START;
+pub const BTN_GUNCON_B: Buttons = BTN_CROSS;
+
+/// Visible GunCon area on NTSC screens, `[min, max]` in dot clock ticks
+/// horizontally and in scanlines vertically. Varies somewhat between
+/// games and TVs.
+pub const GUNCON_RANGE_X: [u16; 2] = [77, 461];
+pub const GUNCON_RANGE_Y: [u16; 2] = [25, 248];
+
+/// Button pressures of a DualShock 2, in report order.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Pressures {
+    pub right: u8,
+    pub left: u8,
+    pub up: u8,
+    pub down: u8,
+    pub triangle: u8,
+    pub circle: u8,
+    pub cross: u8,
+    pub square: u8,
+    pub l1: u8,
+    pub r1: u8,
+    pub l2: u8,
+    pub r2: u8,
+}
+
+impl Pressures {
+    fn from_raw(raw: [u8; 12]) -> Self {
+        let [right, left, up, down, triangle, circle, cross, square, l1, r1, l2, r2] = raw;
+        Self {
+            right,
+            left,
+            up,
+            down,
+            triangle,
+            circle,
+            cross,
+            square,
+            l1,
+            r1,
+            l2,
+            r2,
+        }
+    }
+}
+
+/// State of one pad as reported by the bus.
+///
+/// Sticks are `[x, y]` with 128 centered, 0 left and up.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PsxPadReport {
+    Disconnected,
+    Digital {
+        buttons: Buttons,
+    },
+    Analog {
+        buttons: Buttons,
+        left: [u8; 2],
+        right: [u8; 2],
+    },
+    /// Analog mode with pressure sensitive buttons enabled.
+    DualShock2 {
+        buttons: Buttons,
+        left: [u8; 2],
+        right: [u8; 2],
+        pressures: Pressures,
+    },
+    /// PlayStation mouse, movement since the last poll with Y pointing down.
+    Mouse {
+        buttons: Buttons,
+        delta: [i8; 2],
+    },
+    /// NegCon, with the twist centered on 128.
+    NegCon {
+        buttons: Buttons,
+        twist: u8,
+        i: u8,
+        ii: u8,
+        l: u8,
+    },
+    /// GunCon, with the position in dot clock ticks and scanlines.
+    GunCon {
+        buttons: Buttons,
+        x: u16,
+        y: u16,
+    },
+}
+
+impl PsxPadReport {
+    /// Decodes the response to a poll: ID, 0x5A and the data bytes.
+    ///
+    /// Pressures do not fit the multitap slots, so pads in pressure mode
+    /// behind a multitap are reported as [`PsxPadReport::Analog`].
+    pub fn from_response(response: &[u8]) -> Self {
+        let [id, READY, data @ ..] = response else {
+            return Self::Disconnected;
+        };
+        let Some(&[b0, b1]) = data.first_chunk() else {
+            return Self::Disconnected;
+        };
+        // Buttons are active low.
+        let buttons = !u16::from_le_bytes([b0, b1]);
+        let sticks = data.get(2..6).map(|s| ([s[2], s[3]], [s[0], s[1]]));
+        let pressures = data
+            .get(6..18)
+            .and_then(|p| p.try_into().ok())
+            .map(Pressures::from_raw);
+        match (id >> 4, sticks, pressures) {
+            _ if *id == 0x12 && data.len() >= 4 => Self::Mouse {
+                buttons,
+                delta: [data[2] as i8, data[3] as i8],
+            },
+            (0x2, Some(_), _) => Self::NegCon {
+                buttons,
+                twist: data[2],
+                i: data[3],
+                ii: data[4],
+                l: data[5],
+            },
+            (0x6, Some(_), _) => Self::GunCon {
+                buttons,
+                x: u16::from_le_bytes([data[2], data[3]]),
+                y: u16::from_le_bytes([data[4], data[5]]),
+            },
+            (0x4, _, _) => Self::Digital { buttons },
+            (0x7, Some((left, right)), Some(pressures)) => Self::DualShock2 {
+                buttons,
+                left,
+                right,
+                pressures,
+            },
+            (0x5 | 0x7, Some((left, right)), _) => Self::Analog {
+                buttons,
+                left,
+                right,
+            },
+            _ => Self::Disconnected,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        *self != Self::Disconnected
+    }
+
+    /// Pressed buttons, see the `BTN_*` constants.
+    pub fn buttons(&self) -> Buttons {
+        match *self {
+            Self::Disconnected => 0,
+            Self::Digital { buttons }
+            | Self::Analog { buttons, .. }
+            | Self::DualShock2 { buttons, .. }
+            | Self::Mouse { buttons, .. }
+            | Self::NegCon { buttons, .. }
+            | Self::GunCon { buttons, .. } => buttons,
+        }
+    }
+
+    pub fn pressed(&self, button: Buttons) -> bool {
+        self.buttons() & button != 0
+    }
+
+    /// Face buttons by position (cross on A). L2/R2 are full trigger
+    /// presses unless their pressure is known.
+    ///
+    /// Mice move the right stick, the NegCon twist is the left stick X axis
+    /// with I and II on the triggers, and the GunCon aims with the left
+    /// stick and fires with the right trigger.
+    pub fn apply(&self, pad: &mut XboxGamepad) {
+        let trigger = |pressed: bool| if pressed { u8::MAX as i8 } else { 0 };
+        // PSX Y axes point down.
+        let axis = |value: u8, invert: bool| {
+            let value = (i32::from(value) - 128) * 256;
+            let value = if invert { -value } else { value };
+            value.clamp(-i32::from(i16::MAX), i32::from(i16::MAX)) as i16
+        };
+        let scale = |value: i32, [min, max]: [u16; 2]| {
+            let (min, max) = (i32::from(min), i32::from(max));
+            let value = (value - (min + max) / 2) * 2 * i32::from(i16::MAX) / (max - min);
+            value.clamp(-i32::from(i16::MAX), i32::from(i16::MAX)) as i16
+        };
+
+        match *self {
+            Self::Disconnected => {}
+            Self::Digital { .. } | Self::Analog { .. } | Self::DualShock2 { .. } => {
+                self.apply_buttons(pad);
+                pad.trigger_left = trigger(self.pressed(BTN_L2));
+                pad.trigger_right = trigger(self.pressed(BTN_R2));
+            }
+            Self::Mouse { delta, .. } => {
+                pad.btn_a = self.pressed(BTN_MOUSE_LEFT);
+                pad.btn_b = self.pressed(BTN_MOUSE_RIGHT);
+                let speed = |delta: i8| (i16::from(delta) * 1024).max(-i16::MAX);
+                pad.thumb_right_x = speed(delta[0]);
+                pad.thumb_right_y = speed(delta[1]).saturating_neg();
+            }
+            Self::NegCon {
+                twist, i, ii, l, ..
+            } => {
+                pad.dpad_up = self.pressed(BTN_UP);
+                pad.dpad_down = self.pressed(BTN_DOWN);
+                pad.dpad_left = self.pressed(BTN_LEFT);
+                pad.dpad_right = self.pressed(BTN_RIGHT);
+                pad.btn_start = self.pressed(BTN_START);
+                pad.btn_b = self.pressed(BTN_NEGCON_A);
+                pad.btn_y = self.pressed(BTN_NEGCON_B);
+                pad.btn_right_shoulder = self.pressed(BTN_R1);
+                pad.btn_left_shoulder = l >= 0x80;
+                pad.thumb_left_x = axis(twist, false);
+                pad.trigger_right = i as i8;
+                pad.trigger_left = ii as i8;
+            }
+            Self::GunCon { x, y, .. } => {
+                pad.btn_a = self.pressed(BTN_GUNCON_A);
+                pad.btn_b = self.pressed(BTN_GUNCON_B);
+                pad.trigger_right = trigger(self.pressed(BTN_GUNCON_TRIGGER));
+                // Off screen the gun reports a position left of the visible
+                // area.
+                if x >= GUNCON_RANGE_X[0] {
+                    pad.thumb_left_x = scale(x.into(), GUNCON_RANGE_X);
+                    pad.thumb_left_y = scale(y.into(), GUNCON_RANGE_Y).saturating_neg();
+                }
+            }
+        }
+
+        match *self {
+            Self::Analog { left, right, .. } | Self::DualShock2 { left, right, .. } => {
+                pad.thumb_left_x = axis(left[0], false);
+                pad.thumb_left_y = axis(left[1], true);
+                pad.thumb_right_x = axis(right[0], false);
+                pad.thumb_right_y = axis(right[1], true);
+            }
+            _ => {}
+        }
+        if let Self::DualShock2 { pressures, .. } = *self {
+            pad.trigger_left = pressures.l2 as i8;
+            pad.trigger_right = pressures.r2 as i8;
+        }
+    }
+
+    fn apply_buttons(&self, pad: &mut XboxGamepad) {
+        pad.dpad_up = self.pressed(BTN_UP);
+        pad.dpad_down = self.pressed(BTN_DOWN);
+        pad.dpad_left = self.pressed(BTN_LEFT);
+        pad.dpad_right = self.pressed(BTN_RIGHT);
+        pad.btn_a = self.pressed(BTN_CROSS);
+        pad.btn_b = self.pressed(BTN_CIRCLE);
+        pad.btn_x = self.pressed(BTN_SQUARE);
+        pad.btn_y = self.pressed(BTN_TRIANGLE);
+        pad.btn_start = self.pressed(BTN_START);
+        pad.btn_back = self.pressed(BTN_SELECT);
+        pad.btn_left_thumb = self.pressed(BTN_L3);
+        pad.btn_right_thumb = self.pressed(BTN_R3);
+        pad.btn_left_shoulder = self.pressed(BTN_L1);
+        pad.btn_right_shoulder = self.pressed(BTN_R1);
+    }
+}
+
+/// Motor bytes of a poll command for the (strong, weak) rumble values. The
+/// small motor only switches on or off.
+fn motor_bytes((strong, weak): (u8, u8)) -> [u8; 2] {
+    [if weak >= 0x40 { 0xFF } else { 0x00 }, strong]
+}
+
+/// Builds the command bytes of a multitap poll.
+fn multitap_command(rumble: &[(u8, u8); 4]) -> [u8; MULTITAP_FRAME_LEN] {
+    let mut tx = [0_u8; MULTITAP_FRAME_LEN];
+    tx[..3].copy_from_slice(&[CMD_ADDRESS, CMD_POLL, MULTITAP_ACCESS]);
+    for (slot, &rumble) in tx[3..].chunks_exact_mut(SLOT_LEN).zip(rumble) {
+        slot[0] = CMD_POLL;
+        slot[2..4].copy_from_slice(&motor_bytes(rumble));
+    }
+    tx
+}
+
+/// Builds the command bytes of a direct poll.
+fn direct_command(rumble: (u8, u8)) -> [u8; DIRECT_FRAME_LEN] {
+    let mut tx = [0_u8; DIRECT_FRAME_LEN];
+    tx[..3].copy_from_slice(&[CMD_ADDRESS, CMD_POLL, 0x00]);
+    tx[3..5].copy_from_slice(&motor_bytes(rumble));
+    tx
+}
+
+/// Bytes of a direct poll of the pad with the given ID.
+fn direct_len(id: u8) -> usize {
+    (3 + 2 * usize::from(id & 0x0F)).clamp(5, DIRECT_FRAME_LEN)
+}
+
+/// Splits the response of a multitap poll into the pads of its ports.
+fn multitap_response(rx: &[u8; MULTITAP_FRAME_LEN]) -> Option<[PsxPadReport; 4]> {
+    if rx[1] != MULTITAP_ID || rx[2] != READY {
+        return None;
+    }
+    let mut pads = [PsxPadReport::Disconnected; 4];
+    for (pad, slot) in pads.iter_mut().zip(rx[3..].chunks_exact(SLOT_LEN)) {
+        *pad = PsxPadReport::from_response(slot);
+    }
+    Some(pads)
+}
+
+/// A single pad or up to four pads on a multitap.
+///
+/// Whether a multitap is attached is detected by the first poll and again
+/// whenever nothing answers, so a multitap can replace a pad once the port
+/// was empty for a poll.
+///
+/// Newly connected pads are switched to analog mode with the mode button
+/// locked, with the motors enabled and, if `pressures` is set, pressure
+/// reporting.
+///
+/// Host rumble is forwarded to the pads with every poll:
+///
+/// ```ignore
+/// for (port, state) in states.iter().enumerate() {
+///     bus.set_rumble(port, state.rumble());
+/// }
+/// let pads = bus.poll().await?;
+/// ```
+pub struct PsxBus<T> {
+    transport: T,
+    pressures: bool,
+    configured: [bool; 4],
+    rumble: [(u8, u8); 4],
+    multitap: Option<bool>,
+    direct_len: usize,
+}
+
+impl<T: PsxTransport> PsxBus<T> {
+    pub fn new(transport: T, pressures: bool) -> Self {
+        Self {
+            transport,
+            pressures,
+            configured: [false; 4],
+            rumble: [(0, 0); 4],
+            multitap: None,
+            direct_len: DIRECT_FRAME_LEN,
+        }
+    }
+
+    /// Number of ports, 4 with a multitap and 1 otherwise. `None` until a
+    /// pad or multitap answered.
+    pub fn ports(&self) -> Option<usize> {
+        self.multitap.map(|multitap| if multitap { 4 } else { 1 })
+    }
+
+    /// Sets the (strong, weak) rumble values sent to the pad on `port` with
+    /// the next polls, e.g. from [`State::rumble`](crate::xinput::State::rumble).
+    /// Ports beyond 3 are ignored.
+    pub fn set_rumble(&mut self, port: usize, rumble: (u8, u8)) {
+        if let Some(motors) = self.rumble.get_mut(port) {
+            *motors = rumble;
+        }
+    }
+
+    async fn config_command(&mut self, port: usize, command: &[u8]) -> Result<(), T::Error> {
+        let mut tx = [0_u8; CONFIG_FRAME_LEN];
+        tx[0] = CMD_ADDRESS + port as u8;
+        tx[1..=command.len()].copy_from_slice(command);
+        let mut rx = [0xFF_u8; CONFIG_FRAME_LEN];
+        self.transport.transfer(&tx, &mut rx).await
+    }
+
+    /// Switches the pad on `port` (0 to 3) to analog mode and enables its
+    /// motors.
+    ///
+    /// Digital-only pads ignore the commands and keep reporting
+    /// [`PsxPadReport::Digital`]. Ports beyond 3 are ignored.
+    pub async fn configure(&mut self, port: usize) -> Result<(), T::Error> {
+        if port >= self.configured.len() {
+            return Ok(());
+        }
+        self.config_command(port, &[CMD_CONFIG, 0x00, 0x01]).await?;
+        self.config_command(port, &[CMD_SET_MODE, 0x00, 0x01, 0x03])
+            .await?;
+        // Small motor from the first poll byte, large motor from the second.
+        self.config_command(
+            port,
+            &[CMD_MAP_MOTORS, 0x00, 0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF],
+        )
+        .await?;
+        if self.pressures {
+            self.config_command(port, &[CMD_SET_RESPONSE, 0x00, 0xFF, 0xFF, 0x03])
+                .await?;
+        }
+        self.config_command(
+            port,
+            &[CMD_CONFIG, 0x00, 0x00, 0x5A, 0x5A, 0x5A, 0x5A, 0x5A],
+        )
+        .await
+    }
+
+    async fn poll_multitap(&mut self) -> Result<[PsxPadReport; 4], T::Error> {
+        let tx = multitap_command(&self.rumble);
+        let mut rx = [0xFF_u8; MULTITAP_FRAME_LEN];
+        self.transport.transfer(&tx, &mut rx).await?;
+
+        let mut pads = [PsxPadReport::Disconnected; 4];
+        if let Some(response) = multitap_response(&rx) {
+            if self.multitap != Some(true) {
+                debug!("PSX multitap detected");
+            }
+            self.multitap = Some(true);
+            pads = response;
+        } else if rx[2] == READY {
+            // A pad answers with its own ID and stops after its data.
+            if self.multitap != Some(false) {
+                debug!("PSX pad connected directly");
+            }
+            self.multitap = Some(false);
+            self.direct_len = direct_len(rx[1]);
+            pads[0] = PsxPadReport::from_response(&rx[1..self.direct_len]);
+        } else {
+            self.multitap = None;
+        }
+        Ok(pads)
+    }
+
+    async fn poll_direct(&mut self) -> Result<[PsxPadReport; 4], T::Error> {
+        let tx = direct_command(self.rumble[0]);
+        let mut rx = [0xFF_u8; DIRECT_FRAME_LEN];
+        let len = self.direct_len;
+        self.transport.transfer(&tx[..len], &mut rx[..len]).await?;
+
+        let mut pads = [PsxPadReport::Disconnected; 4];
+        pads[0] = PsxPadReport::from_response(&rx[1..len]);
+        if pads[0].is_connected() {
+            self.direct_len = direct_len(rx[1]);
+        } else {
+            self.multitap = None;
+            self.direct_len = DIRECT_FRAME_LEN;
+        }
+        Ok(pads)
+    }
+
+    /// Reads all ports, configuring newly connected pads. Ports without a
+    /// pad, and ports 1 to 3 without a multitap, are reported disconnected.
+    pub async fn poll(&mut self) -> Result<[PsxPadReport; 4], T::Error> {
+        let pads = match self.multitap {
+            Some(false) => self.poll_direct().await?,
+            _ => self.poll_multitap().await?,
+        };
+        for (port, pad) in pads.iter().enumerate() {
+            if !pad.is_connected() {
+                self.configured[port] = false;
+            } else if !self.configured[port] {
+                self.configured[port] = true;
+                self.configure(port).await?;
+            }
+        }
+        Ok(pads)
+    }
+
+    /// Polls every `period` and reports each port to its receiver slot's
+    /// `State`, including rumble, hot-plugging and pad removal.
+    ///
+    /// Transport errors are treated like empty ports. While no pad answers,
+    /// the period doubles up to one second.
+    pub async fn run<const N: usize>(
+        &mut self,
+        states: [&xinput::State<N>; 4],
+        period: Duration,
+    ) -> ! {
+        let mut misses = [MISSED_POLLS; 4];
+        let mut reported = [None; 4];
+        let mut backoff = period;
+        loop {
+            for (port, state) in states.iter().enumerate() {
+                self.set_rumble(port, state.rumble());
+            }
+            let pads = match self.poll().await {
+                Ok(pads) => pads,
+                Err(_) => {
+                    warn!("PSX transfer failed");
+                    [PsxPadReport::Disconnected; 4]
+                }
+            };
+
+            for (port, (pad, state)) in pads.iter().zip(states).enumerate() {
+                if pad.is_connected() {
+                    misses[port] = 0;
+                    if !state.is_present() {
+                        debug!("PSX port {} connected", port);
+                        state.connect();
+                    }
+                    let mut gamepad = XboxGamepad::new();
+                    pad.apply(&mut gamepad);
+                    if reported[port] != Some(gamepad) {
+                        state.send(&gamepad);
+                        reported[port] = Some(gamepad);
+                    }
+                } else if misses[port] < MISSED_POLLS {
+                    misses[port] += 1;
+                } else if state.is_present() {
+                    debug!("PSX port {} disconnected", port);
+                    state.disconnect();
+                    reported[port] = None;
+                }
+            }
+
+            backoff = if pads.iter().any(PsxPadReport::is_connected) {
+                period
+            } else {
+                (backoff * 2).min(MAX_BACKOFF).max(period)
+            };
+            Timer::after(backoff).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    // Buttons are sent active low.
+    fn raw_buttons(buttons: Buttons) -> [u8; 2] {
+        (!buttons).to_le_bytes()
+    }
+
+    #[test]
+    fn digital_response() {
+        let [b0, b1] = raw_buttons(BTN_CROSS | BTN_START | BTN_UP);
+        let report = PsxPadReport::from_response(&[0x41, READY, b0, b1]);
+        assert_eq!(
+            report,
+            PsxPadReport::Digital {
+                buttons: BTN_CROSS | BTN_START | BTN_UP
+            }
+        );
+
+        let mut pad = XboxGamepad::new();
+        report.apply(&mut pad);
+        let mut expected = XboxGamepad::new();
+        expected.btn_a = true;
+        expected.btn_start = true;
+        expected.dpad_up = true;
+        assert_eq!(pad, expected);
+    }
+
+    #[test]
+    fn analog_response() {
+        let [b0, b1] = raw_buttons(BTN_L2 | BTN_R3);
+        // Right stick centered, left stick full left and down.
+        let response = [0x73, READY, b0, b1, 0x80, 0x80, 0x00, 0xFF];
+        let report = PsxPadReport::from_response(&response);
+        assert_eq!(
+            report,
+            PsxPadReport::Analog {
+                buttons: BTN_L2 | BTN_R3,
+                left: [0x00, 0xFF],
+                right: [0x80, 0x80],
+            }
+        );
+
+        let mut pad = XboxGamepad::new();
+        report.apply(&mut pad);
+        assert_eq!(pad.thumb_left_x, -i16::MAX);
+        assert_eq!(pad.thumb_left_y, -(127 * 256));
+        assert_eq!((pad.thumb_right_x, pad.thumb_right_y), (0, 0));
+        assert_eq!(pad.trigger_left, -1);
+        assert_eq!(pad.trigger_right, 0);
+        assert!(pad.btn_right_thumb);
+    }
+
+    #[test]
+    fn pressure_response() {
+        let [b0, b1] = raw_buttons(BTN_R2);
+        let mut response = [0_u8; 2 + 18];
+        response[..8].copy_from_slice(&[0x79, READY, b0, b1, 0x80, 0x80, 0x80, 0x80]);
+        // Right, left, up, down, triangle, circle, cross, square, L1, R1, L2, R2
+        response[8..].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x20, 0xC0]);
+        let report = PsxPadReport::from_response(&response);
+        let PsxPadReport::DualShock2 { pressures, .. } = report else {
+            panic!("{report:?}");
+        };
+        assert_eq!((pressures.l2, pressures.r2), (0x20, 0xC0));
+
+        let mut pad = XboxGamepad::new();
+        report.apply(&mut pad);
+        assert_eq!((pad.trigger_left, pad.trigger_right), (0x20, 0xC0_u8 as i8));
+    }
+
+    #[test]
+    fn multitap_response_is_split_into_ports() {
+        let [b0, b1] = raw_buttons(BTN_SQUARE);
+        let mut rx = [0xFF_u8; MULTITAP_FRAME_LEN];
+        rx[1..3].copy_from_slice(&[MULTITAP_ID, READY]);
+        rx[3..7].copy_from_slice(&[0x41, READY, b0, b1]);
+        rx[11..19].copy_from_slice(&[0x73, READY, 0xFF, 0xFF, 0x80, 0x80, 0x80, 0x80]);
+        // Port 2 is empty.
+        rx[27..33].copy_from_slice(&[0x12, READY, 0xFF, 0xFF, 0x05, 0xFB]);
+
+        let pads = multitap_response(&rx).unwrap();
+        assert_eq!(
+            pads[0],
+            PsxPadReport::Digital {
+                buttons: BTN_SQUARE
+            }
+        );
+        assert!(matches!(pads[1], PsxPadReport::Analog { buttons: 0, .. }));
+        assert_eq!(pads[2], PsxPadReport::Disconnected);
+        assert_eq!(
+            pads[3],
+            PsxPadReport::Mouse {
+                buttons: 0,
+                delta: [5, -5]
+            }
+        );
+
+        // A pad answering directly is not a multitap.
+        rx[1] = 0x41;
+        assert_eq!(multitap_response(&rx), None);
+    }
+
+    #[test]
+    fn short_and_invalid_responses_are_disconnected() {
+        let responses: [&[u8]; 8] = [
+            &[],
+            &[0x41],
+            &[0x41, READY],
+            &[0x41, READY, 0xFF],
+            // Not ready.
+            &[0x41, 0x00, 0xFF, 0xFF],
+            // Nothing connected.
+            &[0xFF; 8],
+            // Analog and mouse IDs without their data.
+            &[0x73, READY, 0xFF, 0xFF, 0x80],
+            &[0x12, READY, 0xFF, 0xFF],
+        ];
+        for response in responses {
+            assert_eq!(
+                PsxPadReport::from_response(response),
+                PsxPadReport::Disconnected,
+                "{response:02x?}"
+            );
+        }
+    }
+
+    // Answers polls like a DualShock in analog mode plugged in directly.
+    struct FakePad {
+        sent: Vec<Vec<u8>>,
+    }
+
+    impl PsxTransport for FakePad {
+        type Error = ();
+
+        async fn transfer(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<(), ()> {
+            self.sent.push(tx.into());
+            if tx[1] == CMD_POLL {
+                let response = [0xFF, 0x73, READY, 0xFF, 0xFF, 0x80, 0x80, 0x80, 0x80];
+                for (rx, byte) in rx.iter_mut().zip(response) {
+                    *rx = byte;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn direct_pad_is_detected_and_configured() {
+        let mut bus = PsxBus::new(FakePad { sent: Vec::new() }, false);
+        bus.set_rumble(0, (0x80, 0xFF));
+        let pads = embassy_futures::block_on(bus.poll()).unwrap();
+        assert!(matches!(pads[0], PsxPadReport::Analog { .. }));
+        assert!(pads[1..].iter().all(|pad| !pad.is_connected()));
+        assert_eq!(bus.ports(), Some(1));
+        // The multitap poll, then config mode, analog mode, motor mapping
+        // and leaving config mode.
+        let commands: Vec<u8> = bus.transport.sent.iter().map(|tx| tx[1]).collect();
+        assert_eq!(
+            commands,
+            [
+                CMD_POLL,
+                CMD_CONFIG,
+                CMD_SET_MODE,
+                CMD_MAP_MOTORS,
+                CMD_CONFIG
+            ]
+        );
+
+        // Later polls address the pad directly with its length.
+        bus.transport.sent.clear();
+        embassy_futures::block_on(bus.poll()).unwrap();
+        assert_eq!(
+            bus.transport.sent,
+            [[CMD_ADDRESS, CMD_POLL, 0x00, 0xFF, 0x80, 0, 0, 0, 0]]
+        );
+    }
+}
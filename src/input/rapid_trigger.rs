@@ -0,0 +1,147 @@
+//! Rapid trigger for analog (hall effect) buttons.
+//!
+//! Instead of a fixed actuation point, a [`RapidTrigger`] button is pressed
+//! as soon as it moves down by the press sensitivity from its highest point
+//! and released as soon as it moves up by the release sensitivity from its
+//! lowest point, so it can be pressed again without travelling back past a
+//! fixed reset point.
+
+use super::analog_adc::AnalogInput;
+use super::Scan;
+use crate::controller::{Button, XboxGamepad};
+
+/// Settings of a single [`RapidTrigger`] button, in units of travel.
+///
+/// Travel is 0 at rest and `u16::MAX` fully pressed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RapidTriggerConfig {
+    /// Travel below which the button is always released, so resting fingers
+    /// and sensor noise do not press it.
+    pub actuation: u16,
+    /// Downward movement from the highest point that presses the button.
+    pub press_sensitivity: u16,
+    /// Upward movement from the lowest point that releases the button.
+    pub release_sensitivity: u16,
+}
+
+impl Default for RapidTriggerConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RapidTriggerConfig {
+    /// Actuation at 10 % of the travel, 2 % sensitivity in both directions.
+    pub const fn new() -> Self {
+        Self {
+            actuation: u16::MAX / 10,
+            press_sensitivity: u16::MAX / 50,
+            release_sensitivity: u16::MAX / 50,
+        }
+    }
+}
+
+/// Press and release detection of one analog button, see the
+/// [module documentation](self).
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RapidTrigger {
+    pub config: RapidTriggerConfig,
+    pressed: bool,
+    // lowest point while pressed, highest point while released, `None`
+    // below the actuation point
+    reset_point: Option<u16>,
+}
+
+impl RapidTrigger {
+    pub const fn new(config: RapidTriggerConfig) -> Self {
+        Self {
+            config,
+            pressed: false,
+            reset_point: None,
+        }
+    }
+
+    /// Feeds the current travel, returning whether the button is pressed.
+    pub fn update(&mut self, travel: u16) -> bool {
+        let config = &self.config;
+        let Some(reset_point) = self.reset_point.filter(|_| travel >= config.actuation) else {
+            // Crossing the actuation point presses right away.
+            self.pressed = travel >= config.actuation;
+            self.reset_point = self.pressed.then_some(travel);
+            return self.pressed;
+        };
+        if self.pressed {
+            if travel > reset_point {
+                self.reset_point = Some(travel);
+            } else if reset_point - travel >= config.release_sensitivity {
+                self.pressed = false;
+                self.reset_point = Some(travel);
+            }
+        } else if travel < reset_point {
+            self.reset_point = Some(travel);
+        } else if travel - reset_point >= config.press_sensitivity {
+            self.pressed = true;
+            self.reset_point = Some(travel);
+        }
+        self.pressed
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+}
+
+/// Analog buttons sampled with an ADC and reported through a
+/// [`RapidTrigger`] each.
+pub struct RapidTriggerButtons<A, const N: usize> {
+    channels: [(A, Button); N],
+    triggers: [RapidTrigger; N],
+    resolution_bits: u8,
+    /// Set for sensors whose reading drops when the button is pressed.
+    pub inverted: bool,
+}
+
+impl<A: AnalogInput, const N: usize> RapidTriggerButtons<A, N> {
+    /// `resolution_bits` is the ADC resolution (e.g. 12), `configs` holds
+    /// the settings of each channel.
+    pub fn new(
+        channels: [(A, Button); N],
+        configs: [RapidTriggerConfig; N],
+        resolution_bits: u8,
+    ) -> Self {
+        assert!(
+            (1..=16).contains(&resolution_bits),
+            "ADC resolution must be 1 to 16 bits"
+        );
+        Self {
+            channels,
+            triggers: configs.map(RapidTrigger::new),
+            resolution_bits,
+            inverted: false,
+        }
+    }
+
+    /// Changes the settings of the channel at `index`.
+    pub fn set_config(&mut self, index: usize, config: RapidTriggerConfig) {
+        self.triggers[index].config = config;
+    }
+
+    /// Samples all channels and writes the button states into `pad`.
+    /// Buttons without a channel are left untouched.
+    pub async fn sample(&mut self, pad: &mut XboxGamepad) {
+        for ((channel, button), trigger) in self.channels.iter_mut().zip(&mut self.triggers) {
+            // scale to 16 bits
+            let raw = channel.sample().await << (16 - self.resolution_bits);
+            let travel = if self.inverted { !raw } else { raw };
+            pad.set_button(*button, trigger.update(travel));
+        }
+    }
+}
+
+impl<A: AnalogInput, const N: usize> Scan for RapidTriggerButtons<A, N> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        self.sample(pad).await;
+    }
+}
@@ -0,0 +1,141 @@
+//! Radio module of an Xbox 360 console (the "RF module" behind the ring of
+//! light), for DIY receivers built around genuine radio hardware.
+//!
+//! The module carries the controller data on its own USB connection,
+//! which is the same protocol this crate emulates. Its second connector
+//! has a two wire serial command interface and the sync button, handled
+//! by [`RfModule`]: it initializes the ring of light, starts pairing when
+//! the sync button is pressed and shows the player LED of a [`State`] on
+//! the ring.
+//!
+//! Commands are 10 bit words. The host pulls DATA low, the module then
+//! generates the clock and samples one bit, most significant first, per
+//! clock cycle. DATA is open drain with a pull-up, so use an open drain
+//! output pin.
+
+use embassy_time::{with_timeout, Duration, Instant, Ticker};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::digital::Wait;
+
+use super::Debounce;
+use crate::protocol;
+use crate::xinput::State;
+
+/// Longest wait for a clock edge before a command is abandoned.
+const CLOCK_TIMEOUT: Duration = Duration::from_millis(10);
+const BUTTON_POLL_PERIOD: Duration = Duration::from_millis(10);
+const BUTTON_DEBOUNCE_TIME: Duration = Duration::from_millis(20);
+
+/// Command word of the module.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Command(pub u16);
+
+impl Command {
+    /// Initializes the ring of light, leaving the center LED lit. Has to be
+    /// sent after power up before any other LED command.
+    pub const INIT_LEDS: Command = Command(0x084);
+    /// Plays the startup animation on the ring.
+    pub const BOOT_ANIMATION: Command = Command(0x085);
+    /// Starts pairing with a controller, like the console's sync button.
+    pub const SYNC: Command = Command(0x004);
+
+    /// Lights the green quadrants set in `quadrants`, bit 0 is the top
+    /// left quadrant, continuing clockwise.
+    pub const fn green(quadrants: u8) -> Command {
+        Command(0x0A0 | (quadrants & 0x0F) as u16)
+    }
+
+    /// Lights the red quadrants set in `quadrants`, see [`Command::green`].
+    pub const fn red(quadrants: u8) -> Command {
+        Command(0x0B0 | (quadrants & 0x0F) as u16)
+    }
+}
+
+/// Errors of the command interface.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The module did not clock the command, it is missing or not powered.
+    Timeout,
+}
+
+/// Command interface and sync button of the radio module.
+pub struct RfModule<D, C, B> {
+    data: D,
+    clock: C,
+    sync_button: B,
+}
+
+impl<D: OutputPin, C: InputPin + Wait, B: InputPin> RfModule<D, C, B> {
+    /// `sync_button` reads low while the button is pressed.
+    pub fn new(data: D, clock: C, sync_button: B) -> Self {
+        let mut module = Self {
+            data,
+            clock,
+            sync_button,
+        };
+        let _ = module.data.set_high();
+        module
+    }
+
+    /// Sends `command`, waiting for the module to clock it in.
+    pub async fn send(&mut self, command: Command) -> Result<(), Error> {
+        trace!("rf module: command {:#X}", command.0);
+        let _ = self.data.set_low();
+        let result = self.shift_out(command).await;
+        let _ = self.data.set_high();
+        result
+    }
+
+    async fn shift_out(&mut self, command: Command) -> Result<(), Error> {
+        for i in (0..10).rev() {
+            // Each bit is set after the falling clock edge and sampled by
+            // the module before the next one.
+            with_timeout(CLOCK_TIMEOUT, self.clock.wait_for_falling_edge())
+                .await
+                .map_err(|_| Error::Timeout)?
+                .map_err(|_| Error::Timeout)?;
+            let _ = self.data.set_state((command.0 >> i & 1 != 0).into());
+            with_timeout(CLOCK_TIMEOUT, self.clock.wait_for_rising_edge())
+                .await
+                .map_err(|_| Error::Timeout)?
+                .map_err(|_| Error::Timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Initializes the module, then starts pairing on sync button presses
+    /// and shows the player assigned to `state` on the ring of light.
+    pub async fn run<const N: usize>(mut self, state: &State<N>) -> ! {
+        for command in [Command::INIT_LEDS, Command::BOOT_ANIMATION] {
+            if self.send(command).await.is_err() {
+                warn!("rf module: not responding");
+            }
+        }
+
+        let mut ticker = Ticker::every(BUTTON_POLL_PERIOD);
+        let mut button = Debounce::new();
+        let mut led = None;
+        loop {
+            ticker.next().await;
+
+            let pressed = self.sync_button.is_low().unwrap_or(false);
+            let was_pressed = button.state();
+            if button.update(pressed, Instant::now(), BUTTON_DEBOUNCE_TIME) && !was_pressed {
+                debug!("rf module: sync");
+                if self.send(Command::SYNC).await.is_err() {
+                    warn!("rf module: sync failed");
+                }
+            }
+
+            let player = protocol::player_index(state.led());
+            if led != Some(player) {
+                let quadrants = player.map_or(0, |player| 1 << player);
+                if self.send(Command::green(quadrants)).await.is_ok() {
+                    led = Some(player);
+                }
+            }
+        }
+    }
+}
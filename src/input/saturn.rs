@@ -0,0 +1,116 @@
+//! Sega Saturn digital pad.
+
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+
+use super::Scan;
+use crate::controller::XboxGamepad;
+
+/// Time for the pad outputs to settle after changing the select lines.
+const SETTLE_TIME: Duration = Duration::from_micros(10);
+
+/// Decoded digital pad state.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SaturnReport {
+    /// Whether a digital pad identified itself on the port.
+    pub connected: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub a: bool,
+    pub b: bool,
+    pub c: bool,
+    pub x: bool,
+    pub y: bool,
+    pub z: bool,
+    pub l: bool,
+    pub r: bool,
+    pub start: bool,
+}
+
+impl SaturnReport {
+    /// Maps the bottom row A/B/C to X/A/B, the top row X/Y/Z to LB/Y/RB and
+    /// the L/R shoulders to full trigger presses.
+    pub fn apply(&self, pad: &mut XboxGamepad) {
+        let trigger = |pressed: bool| if pressed { u8::MAX as i8 } else { 0 };
+        pad.dpad_up = self.up;
+        pad.dpad_down = self.down;
+        pad.dpad_left = self.left;
+        pad.dpad_right = self.right;
+        pad.btn_x = self.a;
+        pad.btn_a = self.b;
+        pad.btn_b = self.c;
+        pad.btn_left_shoulder = self.x;
+        pad.btn_y = self.y;
+        pad.btn_right_shoulder = self.z;
+        pad.trigger_left = trigger(self.l);
+        pad.trigger_right = trigger(self.r);
+        pad.btn_start = self.start;
+    }
+}
+
+/// Controller port driving the TH/TR select lines and reading the D0-D3
+/// nibble.
+///
+/// Data pins need pull-ups. Pin errors read as released buttons.
+pub struct Saturn<S, I> {
+    th: S,
+    tr: S,
+    data: [I; 4],
+}
+
+impl<S: OutputPin, I: InputPin> Saturn<S, I> {
+    /// `data` are the D0 to D3 pins.
+    pub fn new(th: S, tr: S, data: [I; 4]) -> Self {
+        let mut port = Self { th, tr, data };
+        let _ = port.th.set_high();
+        let _ = port.tr.set_high();
+        port
+    }
+
+    // Selects a nibble and returns the low (pressed) data pins.
+    async fn nibble(&mut self, th: bool, tr: bool) -> [bool; 4] {
+        let _ = self.th.set_state(th.into());
+        let _ = self.tr.set_state(tr.into());
+        Timer::after(SETTLE_TIME).await;
+        let mut low = [false; 4];
+        for (pin, low) in self.data.iter_mut().zip(&mut low) {
+            *low = pin.is_low().unwrap_or(false);
+        }
+        low
+    }
+
+    /// Reads all four nibbles and decodes the pad state.
+    pub async fn poll(&mut self) -> SaturnReport {
+        let [z, y, x, r] = self.nibble(false, false).await;
+        let [b, c, a, start] = self.nibble(true, false).await;
+        let [up, down, left, right] = self.nibble(false, true).await;
+        // The digital pad identifies itself with D0 and D1 low, D2 high.
+        let [id0, id1, id2, l] = self.nibble(true, true).await;
+
+        SaturnReport {
+            connected: id0 && id1 && !id2,
+            up,
+            down,
+            left,
+            right,
+            a,
+            b,
+            c,
+            x,
+            y,
+            z,
+            l,
+            r,
+            start,
+        }
+    }
+}
+
+impl<S: OutputPin, I: InputPin> Scan for Saturn<S, I> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        self.poll().await.apply(pad);
+    }
+}
@@ -0,0 +1,171 @@
+//! Buttons read through parallel-in serial-out shift registers, like a
+//! 74HC165 chain or the latch/clock/data interface of retro pads.
+
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::spi::SpiBus;
+
+use super::Scan;
+use crate::controller::{Button, XboxGamepad};
+
+/// Level of the latch pin that loads the parallel inputs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Latch {
+    /// 74HC165 style SH/LD: low loads, high shifts.
+    ActiveLow,
+    /// SNES style latch: a high pulse loads.
+    ActiveHigh,
+}
+
+// Writes the buttons mapped to the first `BITS` bits (first shifted bit is
+// bit 0). Buttons are pressed when their bit is 0 (pull-ups).
+fn apply_map<const BITS: usize>(bits: u32, map: &[Option<Button>; BITS], pad: &mut XboxGamepad) {
+    for (i, button) in map.iter().enumerate() {
+        if let Some(button) = button {
+            pad.set_button(*button, bits & (1 << i) == 0);
+        }
+    }
+}
+
+/// Bit-banged shift register chain of up to 32 bits.
+///
+/// Pin errors are ignored, reads then return released buttons.
+pub struct ShiftRegister<L, C, D, const BITS: usize> {
+    latch: L,
+    clock: C,
+    data: D,
+    latch_level: Latch,
+    map: [Option<Button>; BITS],
+    /// Duration of the latch pulse and of each clock half period.
+    pub half_period: Duration,
+}
+
+impl<L: OutputPin, C: OutputPin, D: InputPin, const BITS: usize> ShiftRegister<L, C, D, BITS> {
+    /// `map[i]` is the button read as the `i`th bit.
+    pub fn new(
+        latch: L,
+        clock: C,
+        data: D,
+        latch_level: Latch,
+        map: [Option<Button>; BITS],
+    ) -> Self {
+        assert!(BITS <= 32, "shift register chains are limited to 32 bits");
+        let mut register = Self {
+            latch,
+            clock,
+            data,
+            latch_level,
+            map,
+            half_period: Duration::from_micros(6),
+        };
+        register.set_latch(false);
+        let _ = register.clock.set_high();
+        register
+    }
+
+    fn set_latch(&mut self, active: bool) {
+        let high = active == (self.latch_level == Latch::ActiveHigh);
+        let _ = self.latch.set_state(high.into());
+    }
+
+    /// Latches the inputs and shifts out all bits, first bit in bit 0.
+    pub async fn read_bits(&mut self) -> u32 {
+        self.set_latch(true);
+        Timer::after(self.half_period).await;
+        self.set_latch(false);
+        Timer::after(self.half_period).await;
+
+        let mut bits = 0;
+        for i in 0..BITS {
+            // The first bit is valid right after latching, every falling
+            // clock edge shifts out the next one.
+            if self.data.is_high().unwrap_or(true) {
+                bits |= 1 << i;
+            }
+            let _ = self.clock.set_low();
+            Timer::after(self.half_period).await;
+            let _ = self.clock.set_high();
+            Timer::after(self.half_period).await;
+        }
+        bits
+    }
+
+    /// Reads the chain and writes the mapped buttons into `pad`.
+    pub async fn read(&mut self, pad: &mut XboxGamepad) {
+        let bits = self.read_bits().await;
+        apply_map(bits, &self.map, pad);
+    }
+}
+
+/// Shift register chain of up to 32 bits read through an SPI peripheral,
+/// with the latch driven by a GPIO. `BITS` must be a multiple of 8.
+///
+/// Bus and pin errors are ignored, reads then return released buttons.
+pub struct SpiShiftRegister<L, S, const BITS: usize> {
+    latch: L,
+    spi: S,
+    latch_level: Latch,
+    map: [Option<Button>; BITS],
+}
+
+impl<L: OutputPin, S: SpiBus, const BITS: usize> SpiShiftRegister<L, S, BITS> {
+    /// The SPI bus must be configured for mode 2 (74HC165 shifts on the
+    /// rising clock edge) and MSB first.
+    pub fn new(latch: L, spi: S, latch_level: Latch, map: [Option<Button>; BITS]) -> Self {
+        assert!(
+            BITS <= 32 && BITS % 8 == 0,
+            "SPI shift register chains are 8, 16, 24 or 32 bits"
+        );
+        let mut register = Self {
+            latch,
+            spi,
+            latch_level,
+            map,
+        };
+        register.set_latch(false);
+        register
+    }
+
+    fn set_latch(&mut self, active: bool) {
+        let high = active == (self.latch_level == Latch::ActiveHigh);
+        let _ = self.latch.set_state(high.into());
+    }
+
+    /// Latches the inputs and shifts out all bits, first bit in bit 0.
+    pub async fn read_bits(&mut self) -> u32 {
+        self.set_latch(true);
+        Timer::after_micros(1).await;
+        self.set_latch(false);
+
+        let mut buf = [0xFF_u8; 4];
+        let len = BITS / 8;
+        let _ = self.spi.read(&mut buf[..len]);
+        let _ = self.spi.flush();
+
+        // SPI receives MSB first, the first shifted bit is the MSB of byte 0.
+        buf[..len].iter().enumerate().fold(0, |bits, (i, byte)| {
+            bits | u32::from(byte.reverse_bits()) << (8 * i)
+        })
+    }
+
+    /// Reads the chain and writes the mapped buttons into `pad`.
+    pub async fn read(&mut self, pad: &mut XboxGamepad) {
+        let bits = self.read_bits().await;
+        apply_map(bits, &self.map, pad);
+    }
+}
+
+impl<L: OutputPin, C: OutputPin, D: InputPin, const BITS: usize> Scan
+    for ShiftRegister<L, C, D, BITS>
+{
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        self.read(pad).await;
+    }
+}
+
+impl<L: OutputPin, S: SpiBus, const BITS: usize> Scan for SpiShiftRegister<L, S, BITS> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        self.read(pad).await;
+    }
+}
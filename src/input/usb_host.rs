@@ -0,0 +1,190 @@
+//! Controllers on a USB host port, for protocol converter dongles that
+//! pass a real pad through to one of this crate's backends, e.g. a
+//! DualShock 4 presented as an XInput controller.
+//!
+//! The USB host stack is supplied by the application through
+//! [`HostPort`], e.g. PIO-USB on a second pair of RP2040 pins: it
+//! enumerates the attached device and reads its interrupt IN reports.
+//! [`UsbHostPad`] recognizes wired XInput pads and DualShock 4 controllers
+//! and turns their reports into gamepad states.
+
+use crate::controller::XboxGamepad;
+use crate::protocol::ControllerData;
+
+use super::InputSource;
+
+/// Longest input report read from the device.
+pub const REPORT_BUF_LEN: usize = 64;
+
+const SONY_VID: u16 = 0x054C;
+const DS4_PIDS: [u16; 2] = [0x05C4, 0x09CC];
+
+/// Enumerated device, as reported by [`HostPort::attach`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceInfo {
+    pub vid: u16,
+    pub pid: u16,
+    /// Class, subclass and protocol of the interface the reports are read
+    /// from.
+    pub interface_class: [u8; 3],
+}
+
+/// USB host stack with one device port.
+#[allow(async_fn_in_trait)]
+pub trait HostPort {
+    type Error;
+
+    /// Waits for the next device to be attached and enumerated, and
+    /// selects the interface reports are read from.
+    async fn attach(&mut self) -> DeviceInfo;
+
+    /// Reads the next interrupt IN report into `buf`, returning its length.
+    /// Fails once the device was detached.
+    async fn read_report(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+/// Controller families [`UsbHostPad`] understands.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PadKind {
+    /// Wired Xbox 360 controller or a compatible third party pad.
+    XInput,
+    DualShock4,
+}
+
+impl PadKind {
+    pub fn detect(info: &DeviceInfo) -> Option<PadKind> {
+        if info.interface_class == [0xFF, 0x5D, 0x01] {
+            Some(PadKind::XInput)
+        } else if info.vid == SONY_VID && DS4_PIDS.contains(&info.pid) {
+            Some(PadKind::DualShock4)
+        } else {
+            None
+        }
+    }
+
+    /// Writes the input report `report` into `pad`. Returns `false` for
+    /// reports without input data, which leave `pad` untouched.
+    pub fn parse(&self, report: &[u8], pad: &mut XboxGamepad) -> bool {
+        match self {
+            PadKind::XInput => parse_xinput(report, pad),
+            PadKind::DualShock4 => parse_ds4(report, pad),
+        }
+    }
+}
+
+fn parse_xinput(report: &[u8], pad: &mut XboxGamepad) -> bool {
+    // The wired input report carries the same data as the wireless one.
+    match report {
+        [0x00, 0x14, data @ ..] if data.len() >= 12 => {
+            let mut raw = [0_u8; 12];
+            raw.copy_from_slice(&data[..12]);
+            *pad = ControllerData(raw).into();
+            true
+        }
+        _ => false,
+    }
+}
+
+fn parse_ds4(report: &[u8], pad: &mut XboxGamepad) -> bool {
+    let [0x01, lx, ly, rx, ry, face, shoulder, system, l2, r2, ..] = *report else {
+        return false;
+    };
+    // DS4 Y axes point down.
+    let axis = |value: u8, invert: bool| {
+        let value = (i32::from(value) - 128) * 256;
+        let value = if invert { -value } else { value };
+        value.clamp(-i32::from(i16::MAX), i32::from(i16::MAX)) as i16
+    };
+    let bit = |byte: u8, mask: u8| byte & mask != 0;
+
+    *pad = XboxGamepad::new();
+    // Hat switch, 0 is up continuing clockwise, 8 is centered.
+    let hat = face & 0x0F;
+    pad.dpad_up = matches!(hat, 0 | 1 | 7);
+    pad.dpad_right = matches!(hat, 1..=3);
+    pad.dpad_down = matches!(hat, 3..=5);
+    pad.dpad_left = matches!(hat, 5..=7);
+    // Face buttons by position: cross is A.
+    pad.btn_x = bit(face, 0x10);
+    pad.btn_a = bit(face, 0x20);
+    pad.btn_b = bit(face, 0x40);
+    pad.btn_y = bit(face, 0x80);
+    pad.btn_left_shoulder = bit(shoulder, 0x01);
+    pad.btn_right_shoulder = bit(shoulder, 0x02);
+    pad.btn_back = bit(shoulder, 0x10);
+    pad.btn_start = bit(shoulder, 0x20);
+    pad.btn_left_thumb = bit(shoulder, 0x40);
+    pad.btn_right_thumb = bit(shoulder, 0x80);
+    pad.btn_guide = bit(system, 0x01);
+    pad.trigger_left = l2 as i8;
+    pad.trigger_right = r2 as i8;
+    pad.thumb_left_x = axis(lx, false);
+    pad.thumb_left_y = axis(ly, true);
+    pad.thumb_right_x = axis(rx, false);
+    pad.thumb_right_y = axis(ry, true);
+    true
+}
+
+/// Pad attached to a [`HostPort`], yielding a state per changed report.
+///
+/// Unsupported devices are ignored until the next one is attached. When
+/// the pad is detached a neutral state is yielded once.
+pub struct UsbHostPad<P> {
+    port: P,
+    kind: Option<PadKind>,
+    last: XboxGamepad,
+    buf: [u8; REPORT_BUF_LEN],
+}
+
+impl<P: HostPort> UsbHostPad<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            kind: None,
+            last: XboxGamepad::new(),
+            buf: [0; REPORT_BUF_LEN],
+        }
+    }
+
+    /// The attached pad, `None` while none or an unsupported device is
+    /// attached.
+    pub fn kind(&self) -> Option<PadKind> {
+        self.kind
+    }
+}
+
+impl<P: HostPort> InputSource for UsbHostPad<P> {
+    async fn next(&mut self) -> XboxGamepad {
+        loop {
+            let Some(kind) = self.kind else {
+                let info = self.port.attach().await;
+                self.kind = PadKind::detect(&info);
+                match self.kind {
+                    Some(kind) => info!("usb host: {:?} attached", kind),
+                    None => warn!(
+                        "usb host: unsupported device {:#X}:{:#X}",
+                        info.vid, info.pid
+                    ),
+                }
+                continue;
+            };
+
+            let mut pad = self.last;
+            match self.port.read_report(&mut self.buf).await {
+                Ok(n) if kind.parse(&self.buf[..n], &mut pad) => {}
+                Ok(_) => continue,
+                Err(_) => {
+                    info!("usb host: detached");
+                    self.kind = None;
+                    pad = XboxGamepad::new();
+                }
+            }
+            if pad != self.last {
+                self.last = pad;
+                return pad;
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Wii extension controllers (Nunchuk, Classic Controller) on I2C.
+
+use embassy_time::Timer;
+use embedded_hal_async::i2c::I2c;
+
+use super::Scan;
+use crate::controller::XboxGamepad;
+
+const ADDRESS: u8 = 0x52;
+
+/// Type of the connected extension, from its identification bytes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Extension {
+    Nunchuk,
+    Classic,
+    Unknown([u8; 6]),
+}
+
+impl Extension {
+    fn from_id(id: [u8; 6]) -> Self {
+        match id {
+            [_, _, 0xA4, 0x20, 0x00, 0x00] => Extension::Nunchuk,
+            [_, _, 0xA4, 0x20, 0x01, 0x01] => Extension::Classic,
+            id => Extension::Unknown(id),
+        }
+    }
+}
+
+/// Decoded Nunchuk report.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NunchukReport {
+    pub stick_x: u8,
+    pub stick_y: u8,
+    /// 10 bit accelerometer values.
+    pub accel: [u16; 3],
+    pub c: bool,
+    pub z: bool,
+}
+
+impl NunchukReport {
+    pub fn from_raw(raw: &[u8; 6]) -> Self {
+        let accel = |hi: u8, shift: u8| (u16::from(hi) << 2) | u16::from((raw[5] >> shift) & 0x03);
+        Self {
+            stick_x: raw[0],
+            stick_y: raw[1],
+            accel: [accel(raw[2], 2), accel(raw[3], 4), accel(raw[4], 6)],
+            c: raw[5] & 0x02 == 0,
+            z: raw[5] & 0x01 == 0,
+        }
+    }
+
+    /// Stick on the left stick, Z on A and C on B.
+    pub fn apply(&self, pad: &mut XboxGamepad) {
+        pad.thumb_left_x = scale_axis(self.stick_x, 8);
+        pad.thumb_left_y = scale_axis(self.stick_y, 8);
+        pad.btn_a = self.z;
+        pad.btn_b = self.c;
+    }
+}
+
+/// Decoded Classic Controller report (data format 1).
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClassicReport {
+    /// 6 bit
+    pub left_x: u8,
+    pub left_y: u8,
+    /// 5 bit
+    pub right_x: u8,
+    pub right_y: u8,
+    /// 5 bit
+    pub left_trigger: u8,
+    pub right_trigger: u8,
+    /// Button bits of bytes 4 and 5, inverted so set bits are pressed.
+    pub buttons: u16,
+}
+
+impl ClassicReport {
+    pub const DPAD_RIGHT: u16 = 1 << 15;
+    pub const DPAD_DOWN: u16 = 1 << 14;
+    pub const L: u16 = 1 << 13;
+    pub const MINUS: u16 = 1 << 12;
+    pub const HOME: u16 = 1 << 11;
+    pub const PLUS: u16 = 1 << 10;
+    pub const R: u16 = 1 << 9;
+    pub const ZL: u16 = 1 << 7;
+    pub const B: u16 = 1 << 6;
+    pub const Y: u16 = 1 << 5;
+    pub const A: u16 = 1 << 4;
+    pub const X: u16 = 1 << 3;
+    pub const ZR: u16 = 1 << 2;
+    pub const DPAD_LEFT: u16 = 1 << 1;
+    pub const DPAD_UP: u16 = 1 << 0;
+
+    pub fn from_raw(raw: &[u8; 6]) -> Self {
+        Self {
+            left_x: raw[0] & 0x3F,
+            left_y: raw[1] & 0x3F,
+            right_x: ((raw[0] & 0xC0) >> 3) | ((raw[1] & 0xC0) >> 5) | ((raw[2] & 0x80) >> 7),
+            right_y: raw[2] & 0x1F,
+            left_trigger: ((raw[2] & 0x60) >> 2) | ((raw[3] & 0xE0) >> 5),
+            right_trigger: raw[3] & 0x1F,
+            buttons: !u16::from_be_bytes([raw[4], raw[5]]),
+        }
+    }
+
+    pub fn pressed(&self, button: u16) -> bool {
+        self.buttons & button != 0
+    }
+
+    /// Maps buttons by position: the Nintendo B (bottom) button is reported
+    /// as A, ZL/ZR as shoulders and the analog L/R as triggers.
+    pub fn apply(&self, pad: &mut XboxGamepad) {
+        pad.thumb_left_x = scale_axis(self.left_x, 6);
+        pad.thumb_left_y = scale_axis(self.left_y, 6);
+        pad.thumb_right_x = scale_axis(self.right_x, 5);
+        pad.thumb_right_y = scale_axis(self.right_y, 5);
+        pad.trigger_left = (self.left_trigger << 3) as i8;
+        pad.trigger_right = (self.right_trigger << 3) as i8;
+
+        pad.dpad_up = self.pressed(Self::DPAD_UP);
+        pad.dpad_down = self.pressed(Self::DPAD_DOWN);
+        pad.dpad_left = self.pressed(Self::DPAD_LEFT);
+        pad.dpad_right = self.pressed(Self::DPAD_RIGHT);
+        pad.btn_start = self.pressed(Self::PLUS);
+        pad.btn_back = self.pressed(Self::MINUS);
+        pad.btn_guide = self.pressed(Self::HOME);
+        pad.btn_left_shoulder = self.pressed(Self::ZL);
+        pad.btn_right_shoulder = self.pressed(Self::ZR);
+        pad.btn_a = self.pressed(Self::B);
+        pad.btn_b = self.pressed(Self::A);
+        pad.btn_x = self.pressed(Self::Y);
+        pad.btn_y = self.pressed(Self::X);
+    }
+}
+
+// Centers an unsigned axis of `bits` resolution on the i16 range.
+fn scale_axis(value: u8, bits: u8) -> i16 {
+    let centered = (i32::from(value) << (16 - bits)) - 0x8000;
+    centered.max(-i32::from(i16::MAX)) as i16
+}
+
+/// Decoded report of the connected extension.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WiiReport {
+    Nunchuk(NunchukReport),
+    Classic(ClassicReport),
+    Unknown([u8; 6]),
+}
+
+impl WiiReport {
+    pub fn apply(&self, pad: &mut XboxGamepad) {
+        match self {
+            WiiReport::Nunchuk(report) => report.apply(pad),
+            WiiReport::Classic(report) => report.apply(pad),
+            WiiReport::Unknown(_) => {}
+        }
+    }
+}
+
+/// Wii extension controller driver.
+pub struct WiiExtension<I> {
+    i2c: I,
+    extension: Option<Extension>,
+}
+
+impl<I: I2c> WiiExtension<I> {
+    pub fn new(i2c: I) -> Self {
+        Self {
+            i2c,
+            extension: None,
+        }
+    }
+
+    /// Type detected by the last [`WiiExtension::init`].
+    pub fn extension(&self) -> Option<Extension> {
+        self.extension
+    }
+
+    async fn read_register(&mut self, register: u8) -> Result<[u8; 6], I::Error> {
+        self.i2c.write(ADDRESS, &[register]).await?;
+        // The extension needs some time before the data can be read.
+        Timer::after_micros(200).await;
+        let mut buf = [0_u8; 6];
+        self.i2c.read(ADDRESS, &mut buf).await?;
+        Ok(buf)
+    }
+
+    /// Initializes the extension without encryption and identifies it.
+    /// Needs to be repeated after the extension was unplugged.
+    pub async fn init(&mut self) -> Result<Extension, I::Error> {
+        self.extension = None;
+        self.i2c.write(ADDRESS, &[0xF0, 0x55]).await?;
+        Timer::after_millis(1).await;
+        self.i2c.write(ADDRESS, &[0xFB, 0x00]).await?;
+        Timer::after_millis(1).await;
+        let extension = Extension::from_id(self.read_register(0xFA).await?);
+        self.extension = Some(extension);
+        Ok(extension)
+    }
+
+    /// Reads the current report, initializing the extension first if needed.
+    pub async fn poll(&mut self) -> Result<WiiReport, I::Error> {
+        let extension = match self.extension {
+            Some(extension) => extension,
+            None => self.init().await?,
+        };
+        let raw = match self.read_register(0x00).await {
+            Ok(raw) => raw,
+            Err(e) => {
+                // Most likely unplugged, identify again on the next poll.
+                self.extension = None;
+                return Err(e);
+            }
+        };
+        Ok(match extension {
+            Extension::Nunchuk => WiiReport::Nunchuk(NunchukReport::from_raw(&raw)),
+            Extension::Classic => WiiReport::Classic(ClassicReport::from_raw(&raw)),
+            Extension::Unknown(_) => WiiReport::Unknown(raw),
+        })
+    }
+}
+
+/// Bus errors, e.g. while the extension is unplugged, leave `pad` untouched.
+impl<I: I2c> Scan for WiiExtension<I> {
+    async fn scan(&mut self, pad: &mut XboxGamepad) {
+        if let Ok(report) = self.poll().await {
+            report.apply(pad);
+        }
+    }
+}
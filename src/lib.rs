@@ -1,4 +1,48 @@
 #![no_std]
 
+// This mod MUST go first, so that the others see its macros.
+pub(crate) mod fmt;
+
+pub mod analog;
+pub mod ble_hid;
+pub mod bootloader;
+pub mod chatpad;
+#[cfg(feature = "config-drive")]
+pub mod config_drive;
+#[cfg(feature = "config-hid")]
+pub mod config_hid;
+#[cfg(feature = "config-serial")]
+pub mod config_serial;
+#[cfg(feature = "consumer-control")]
+pub mod consumer_control;
 pub mod controller;
+pub mod diagnostics;
+#[cfg(feature = "hid-dancepad")]
+pub mod hid_dancepad;
+pub mod hid_descriptor;
+#[cfg(feature = "hid-flightstick")]
+pub mod hid_flightstick;
+#[cfg(feature = "hid-lightgun")]
+pub mod hid_lightgun;
+#[cfg(feature = "hid-mouse")]
+pub mod hid_mouse;
+#[cfg(feature = "hid-wheel")]
+pub mod hid_wheel;
+pub mod host;
+pub mod hotkeys;
+pub mod input;
+pub mod link;
+pub mod macros;
+pub mod output;
+pub mod power;
+pub mod presets;
+pub mod profiles;
+pub mod protocol;
+pub mod radio;
+pub mod remap;
+pub mod settings;
+pub mod socd;
+pub mod touchpad;
+pub mod transport;
+pub mod turbo;
 pub mod xinput;
@@ -0,0 +1,5 @@
+//! Wired links between two boards running this crate, for split designs
+//! such as a button board in the grip and the USB board in the dock.
+
+pub mod i2c_target;
+pub mod uart;
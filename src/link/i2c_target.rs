@@ -0,0 +1,212 @@
+//! I2C target (slave) mode, so a board becomes a smart input module read
+//! by a larger host MCU.
+//!
+//! The module routes its input into a [`Registers`] block, e.g. with
+//! [`input::route`](crate::input::route), and [`I2cTargetLink::run`] serves
+//! it on the bus. The host writes the register address, then reads or
+//! writes from there; the address increments with every byte.
+//!
+//! | Address     | Access | Content                                      |
+//! |-------------|--------|----------------------------------------------|
+//! | 0x00        | R      | [`ID`]                                       |
+//! | 0x01        | R      | Protocol version, [`VERSION`]                |
+//! | 0x02        | R      | Status, bit 0: input changed since last read |
+//! | 0x03..=0x0E | R      | [`ControllerData`]                           |
+//! | 0x0F        | RW     | Strong (left) rumble                         |
+//! | 0x10        | RW     | Weak (right) rumble                          |
+//! | 0x11        | RW     | LED pattern, see [`State::led`]              |
+//!
+//! Reading the status register clears the changed bit, so a host can poll
+//! 0x02..=0x0E in one transfer.
+//!
+//! [`State::led`]: crate::xinput::State::led
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::controller::XboxGamepad;
+use crate::protocol::ControllerData;
+use crate::radio::Feedback;
+use crate::transport::ReportSink;
+
+/// Value of the ID register.
+pub const ID: u8 = 0x58;
+/// Register layout version.
+pub const VERSION: u8 = 1;
+
+pub const REG_ID: u8 = 0x00;
+pub const REG_VERSION: u8 = 0x01;
+pub const REG_STATUS: u8 = 0x02;
+pub const REG_INPUT: u8 = 0x03;
+pub const REG_RUMBLE_STRONG: u8 = 0x0F;
+pub const REG_RUMBLE_WEAK: u8 = 0x10;
+pub const REG_LED: u8 = 0x11;
+/// Number of registers.
+pub const REG_COUNT: usize = 0x12;
+
+const STATUS_CHANGED: u8 = 0x01;
+
+/// Transfer addressed to the target, see [`I2cTarget::listen`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Transaction {
+    /// The controller wrote this many bytes.
+    Write(usize),
+    /// The controller reads, answer with [`I2cTarget::respond`].
+    Read,
+    /// The controller wrote this many bytes, then reads after a repeated
+    /// start.
+    WriteRead(usize),
+}
+
+/// I2C peripheral in target mode, e.g. embassy-rp's `I2cSlave` or the
+/// nRF TWIS.
+#[allow(async_fn_in_trait)]
+pub trait I2cTarget {
+    type Error;
+
+    /// Waits for the next transfer to the target's address, storing
+    /// written bytes in `buf`.
+    async fn listen(&mut self, buf: &mut [u8]) -> Result<Transaction, Self::Error>;
+
+    /// Answers a read with `data`.
+    async fn respond(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+/// Register contents, shared between the input task and the
+/// [`I2cTargetLink`]. Can be used from any context.
+pub struct Registers {
+    registers: Mutex<CriticalSectionRawMutex, Cell<[u8; REG_COUNT]>>,
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Registers {
+    pub const fn new() -> Self {
+        let mut registers = [0; REG_COUNT];
+        registers[REG_ID as usize] = ID;
+        registers[REG_VERSION as usize] = VERSION;
+        // Neutral sticks and released buttons encode to zero.
+        Self {
+            registers: Mutex::new(Cell::new(registers)),
+        }
+    }
+
+    /// Publishes new controller data to the host MCU.
+    pub fn send_xinput(&self, data: ControllerData) {
+        self.update(|registers| {
+            let input = &mut registers[usize::from(REG_INPUT)..usize::from(REG_INPUT) + 12];
+            if input != data.0 {
+                input.copy_from_slice(&data.0);
+                registers[usize::from(REG_STATUS)] |= STATUS_CHANGED;
+            }
+        });
+    }
+
+    /// Rumble and LED state last written by the host MCU.
+    pub fn feedback(&self) -> Feedback {
+        let registers = self.registers.lock(Cell::get);
+        Feedback {
+            rumble: (
+                registers[usize::from(REG_RUMBLE_STRONG)],
+                registers[usize::from(REG_RUMBLE_WEAK)],
+            ),
+            led: registers[usize::from(REG_LED)],
+        }
+    }
+
+    fn update<R>(&self, f: impl FnOnce(&mut [u8; REG_COUNT]) -> R) -> R {
+        self.registers.lock(|cell| {
+            let mut registers = cell.get();
+            let result = f(&mut registers);
+            cell.set(registers);
+            result
+        })
+    }
+
+    // Copies the registers from `address` into `buf`, clearing the changed
+    // bit if the status register was read. Returns the number of bytes.
+    fn read(&self, address: u8, buf: &mut [u8; REG_COUNT]) -> usize {
+        self.update(|registers| {
+            let start = usize::from(address).min(REG_COUNT);
+            let len = REG_COUNT - start;
+            buf[..len].copy_from_slice(&registers[start..]);
+            if start <= usize::from(REG_STATUS) {
+                registers[usize::from(REG_STATUS)] &= !STATUS_CHANGED;
+            }
+            len
+        })
+    }
+
+    // Writes `data` from `address` on, ignoring read only registers.
+    fn write(&self, address: u8, data: &[u8]) {
+        self.update(|registers| {
+            for (offset, byte) in data.iter().enumerate() {
+                let register = usize::from(address) + offset;
+                if (usize::from(REG_RUMBLE_STRONG)..REG_COUNT).contains(&register) {
+                    registers[register] = *byte;
+                }
+            }
+        });
+    }
+}
+
+impl ReportSink for Registers {
+    fn send(&self, pad: &XboxGamepad) {
+        self.send_xinput((*pad).into());
+    }
+}
+
+/// Serves a [`Registers`] block on an [`I2cTarget`].
+pub struct I2cTargetLink<T> {
+    target: T,
+    address: u8,
+}
+
+impl<T: I2cTarget> I2cTargetLink<T> {
+    pub fn new(target: T) -> Self {
+        Self { target, address: 0 }
+    }
+
+    /// Answers transfers from the host MCU forever.
+    pub async fn run(mut self, registers: &Registers) -> ! {
+        let mut buf = [0_u8; REG_COUNT + 1];
+        loop {
+            let transaction = match self.target.listen(&mut buf).await {
+                Ok(transaction) => transaction,
+                Err(_) => {
+                    warn!("i2c target: listen failed");
+                    continue;
+                }
+            };
+            trace!("i2c target: {:?}", transaction);
+            let (Transaction::Write(n) | Transaction::WriteRead(n)) = transaction else {
+                self.respond(registers).await;
+                continue;
+            };
+            if let [address, data @ ..] = &buf[..n.min(buf.len())] {
+                self.address = *address;
+                registers.write(*address, data);
+            }
+            if matches!(transaction, Transaction::WriteRead(_)) {
+                self.respond(registers).await;
+            }
+        }
+    }
+
+    async fn respond(&mut self, registers: &Registers) {
+        let mut data = [0_u8; REG_COUNT];
+        let len = registers.read(self.address, &mut data);
+        // Reads past the last register return zeros.
+        let len = len.max(1);
+        if self.target.respond(&data[..len]).await.is_err() {
+            warn!("i2c target: respond failed");
+        }
+    }
+}
@@ -0,0 +1,586 @@
+//! Framed UART link between a pad board and a dock board.
+//!
+//! Messages are COBS encoded and terminated by a zero byte, so a receiver
+//! that starts listening mid frame resynchronizes on the next delimiter.
+//! Each message starts with a type byte and ends with a CRC-16, see
+//! [`radio::crc16`](crate::radio::crc16).
+//!
+//! Both ends send [`Message::Hello`] with their protocol version range
+//! until they heard from the other end, and use the highest version both
+//! support. Every hello is answered with a hello marked as reply, also by a
+//! linked end, so an end that restarted links again right away. The pad then sends its state on every change and at least every
+//! [`KEEPALIVE_PERIOD`], the dock answers with the host's rumble and LED
+//! state. Without a valid message for [`LINK_TIMEOUT`] the link is
+//! considered lost and negotiated again, e.g. after a cable was replugged.
+//!
+//! Use a buffered UART: the byte stream is read while waiting for other
+//! events, which drops bytes on unbuffered drivers.
+
+use embassy_futures::select::{select3, Either3};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_io_async::{Read, Write};
+
+use crate::controller::XboxGamepad;
+use crate::input::InputSource;
+use crate::protocol::ControllerData;
+use crate::radio::{crc16, Feedback, FrameError};
+use crate::xinput::State;
+
+/// Protocol version sent by this crate.
+pub const VERSION: u8 = 1;
+/// Oldest protocol version this crate understands.
+pub const MIN_VERSION: u8 = 1;
+
+/// Longest time between two messages of a linked end.
+pub const KEEPALIVE_PERIOD: Duration = Duration::from_millis(50);
+/// Time without a valid message after which the link is lost.
+pub const LINK_TIMEOUT: Duration = Duration::from_millis(200);
+/// Interval of the hello messages while not linked.
+const HELLO_PERIOD: Duration = Duration::from_millis(100);
+/// Interval the dock checks the host's rumble and LED state at.
+const FEEDBACK_POLL_PERIOD: Duration = Duration::from_millis(4);
+
+const TYPE_HELLO: u8 = 0x01;
+const TYPE_INPUT: u8 = 0x02;
+const TYPE_FEEDBACK: u8 = 0x03;
+const TYPE_HELLO_REPLY: u8 = 0x04;
+
+/// Longest message: type, controller data and CRC.
+const MAX_MESSAGE_LEN: usize = 15;
+/// Longest encoded frame: COBS overhead byte and delimiter.
+const MAX_FRAME_LEN: usize = MAX_MESSAGE_LEN + 2;
+
+/// Message exchanged over the link.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Message {
+    /// Supported protocol versions of the sender. `reply` is set when
+    /// answering a hello, which is not answered again.
+    Hello {
+        version: u8,
+        min_version: u8,
+        reply: bool,
+    },
+    /// Pad state, sent by the pad.
+    Input(ControllerData),
+    /// Host output, sent by the dock.
+    Feedback(Feedback),
+}
+
+impl Message {
+    /// Encodes the message with its CRC, returning the length.
+    fn encode(&self, buf: &mut [u8; MAX_MESSAGE_LEN]) -> usize {
+        let len = match *self {
+            Message::Hello {
+                version,
+                min_version,
+                reply,
+            } => {
+                let kind = if reply { TYPE_HELLO_REPLY } else { TYPE_HELLO };
+                buf[..3].copy_from_slice(&[kind, version, min_version]);
+                3
+            }
+            Message::Input(data) => {
+                buf[0] = TYPE_INPUT;
+                buf[1..13].copy_from_slice(&data.0);
+                13
+            }
+            Message::Feedback(feedback) => {
+                let (strong, weak) = feedback.rumble;
+                buf[..4].copy_from_slice(&[TYPE_FEEDBACK, strong, weak, feedback.led]);
+                4
+            }
+        };
+        let crc = crc16(&buf[..len]);
+        buf[len..len + 2].copy_from_slice(&crc.to_le_bytes());
+        len + 2
+    }
+
+    fn decode(message: &[u8]) -> Result<Self, FrameError> {
+        let [body @ .., crc_low, crc_high] = message else {
+            return Err(FrameError::Length);
+        };
+        if crc16(body).to_le_bytes() != [*crc_low, *crc_high] {
+            return Err(FrameError::Crc);
+        }
+        match *body {
+            [kind @ (TYPE_HELLO | TYPE_HELLO_REPLY), version, min_version] => Ok(Message::Hello {
+                version,
+                min_version,
+                reply: kind == TYPE_HELLO_REPLY,
+            }),
+            [TYPE_INPUT, ref data @ ..] => {
+                let data = <[u8; 12]>::try_from(data).map_err(|_| FrameError::Length)?;
+                Ok(Message::Input(ControllerData(data)))
+            }
+            [TYPE_FEEDBACK, strong, weak, led] => Ok(Message::Feedback(Feedback {
+                rumble: (strong, weak),
+                led,
+            })),
+            [TYPE_HELLO | TYPE_HELLO_REPLY | TYPE_FEEDBACK, ..] => Err(FrameError::Length),
+            _ => Err(FrameError::Type),
+        }
+    }
+}
+
+/// Highest version both ends support, `None` if the ranges do not overlap.
+pub fn negotiate(version: u8, min_version: u8) -> Option<u8> {
+    let agreed = VERSION.min(version);
+    (agreed >= MIN_VERSION.max(min_version)).then_some(agreed)
+}
+
+// COBS encodes `data` into `out` and appends the delimiter.
+fn cobs_encode(data: &[u8], out: &mut [u8; MAX_FRAME_LEN]) -> usize {
+    let mut code_pos = 0;
+    let mut len = 1;
+    for &byte in data {
+        if byte == 0 {
+            out[code_pos] = (len - code_pos) as u8;
+            code_pos = len;
+        } else {
+            out[len] = byte;
+        }
+        len += 1;
+    }
+    out[code_pos] = (len - code_pos) as u8;
+    out[len] = 0;
+    len + 1
+}
+
+// Decodes a COBS frame without delimiter in place, returning the length.
+fn cobs_decode(frame: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+    while read < frame.len() {
+        let code = usize::from(frame[read]);
+        if code == 0 || read + code > frame.len() {
+            return None;
+        }
+        read += 1;
+        for _ in 1..code {
+            frame[write] = frame[read];
+            write += 1;
+            read += 1;
+        }
+        if code < 0xFF && read < frame.len() {
+            frame[write] = 0;
+            write += 1;
+        }
+    }
+    Some(write)
+}
+
+/// Change of the link state caused by a received message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Linked {
+    /// The link was just established.
+    New,
+    /// The other end restarted while linked.
+    Restarted,
+}
+
+/// Errors of the link.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    Uart(E),
+    Frame(FrameError),
+}
+
+/// One end of the link.
+pub struct UartLink<U> {
+    uart: U,
+    rx: [u8; MAX_FRAME_LEN],
+    rx_len: usize,
+    // Discards the rest of a frame that did not fit into `rx`.
+    rx_overflow: bool,
+    version: Option<u8>,
+    last_received: Instant,
+}
+
+impl<U: Read + Write> UartLink<U> {
+    pub fn new(uart: U) -> Self {
+        Self {
+            uart,
+            rx: [0; MAX_FRAME_LEN],
+            rx_len: 0,
+            rx_overflow: false,
+            version: None,
+            last_received: Instant::from_ticks(0),
+        }
+    }
+
+    /// Negotiated protocol version, `None` while not linked.
+    pub fn version(&self) -> Option<u8> {
+        self.version
+    }
+
+    pub async fn send(&mut self, message: &Message) -> Result<(), Error<U::Error>> {
+        let mut encoded = [0_u8; MAX_MESSAGE_LEN];
+        let len = message.encode(&mut encoded);
+        let mut frame = [0_u8; MAX_FRAME_LEN];
+        let len = cobs_encode(&encoded[..len], &mut frame);
+        self.uart
+            .write_all(&frame[..len])
+            .await
+            .map_err(Error::Uart)
+    }
+
+    /// Waits for the next message. Cancel safe, partially received frames
+    /// are kept.
+    pub async fn receive(&mut self) -> Result<Message, Error<U::Error>> {
+        loop {
+            let mut byte = [0_u8];
+            if self.uart.read(&mut byte).await.map_err(Error::Uart)? == 0 {
+                continue;
+            }
+            if byte[0] != 0 {
+                if self.rx_len < self.rx.len() {
+                    self.rx[self.rx_len] = byte[0];
+                    self.rx_len += 1;
+                } else {
+                    self.rx_overflow = true;
+                }
+                continue;
+            }
+
+            let len = core::mem::take(&mut self.rx_len);
+            if core::mem::take(&mut self.rx_overflow) {
+                return Err(Error::Frame(FrameError::Length));
+            }
+            if len == 0 {
+                continue;
+            }
+            let len = cobs_decode(&mut self.rx[..len]).ok_or(Error::Frame(FrameError::Length))?;
+            return Message::decode(&self.rx[..len]).map_err(Error::Frame);
+        }
+    }
+
+    // Updates the link state with a received message.
+    async fn handle(&mut self, message: &Message) -> Option<Linked> {
+        self.last_received = Instant::now();
+        let Message::Hello {
+            version,
+            min_version,
+            reply,
+        } = *message
+        else {
+            return None;
+        };
+        let Some(agreed) = negotiate(version, min_version) else {
+            warn!("uart link: incompatible version {}", version);
+            self.version = None;
+            return None;
+        };
+        let current = self.version.replace(agreed);
+        if !reply {
+            // Let the other end know right away, it is not linked.
+            let _ = self.send(&hello(true)).await;
+        }
+        match current {
+            None => {
+                info!("uart link: linked, version {}", agreed);
+                Some(Linked::New)
+            }
+            // A hello of a linked end only answers ours.
+            Some(_) if reply => None,
+            Some(_) => {
+                info!("uart link: other end restarted, version {}", agreed);
+                Some(Linked::Restarted)
+            }
+        }
+    }
+
+    // Drops the link after `LINK_TIMEOUT` without messages. Returns `true`
+    // if the link was just lost.
+    fn check_timeout(&mut self) -> bool {
+        if self.version.is_some() && self.last_received.elapsed() >= LINK_TIMEOUT {
+            warn!("uart link: lost");
+            self.version = None;
+            return true;
+        }
+        false
+    }
+
+    /// Runs the pad end: sends the states of `source` and calls `feedback`
+    /// with the rumble and LED state sent by the dock.
+    ///
+    /// The pending [`InputSource::next`] is cancelled whenever a message
+    /// arrives or one is due, so `source` must be cancel safe like
+    /// [`Periodic`](crate::input::Periodic).
+    pub async fn run_pad(mut self, source: &mut impl InputSource, feedback: fn(Feedback)) -> ! {
+        let mut pad = XboxGamepad::new();
+        let mut next_send = Instant::now();
+        loop {
+            let period = if self.version.is_some() {
+                KEEPALIVE_PERIOD
+            } else {
+                HELLO_PERIOD
+            };
+            match select3(source.next(), self.receive(), Timer::at(next_send)).await {
+                Either3::First(next) => {
+                    pad = next;
+                    if self.version.is_some() {
+                        next_send = Instant::now();
+                    }
+                }
+                Either3::Second(Ok(message)) => {
+                    self.handle(&message).await;
+                    if let (Message::Feedback(data), Some(_)) = (message, self.version) {
+                        feedback(data);
+                    }
+                }
+                Either3::Second(Err(error)) => log_error(error),
+                Either3::Third(()) => {
+                    self.check_timeout();
+                    let message = match self.version {
+                        Some(_) => Message::Input(pad.into()),
+                        None => hello(false),
+                    };
+                    if self.send(&message).await.is_err() {
+                        warn!("uart link: write failed");
+                    }
+                    next_send = Instant::now() + period;
+                }
+            }
+        }
+    }
+
+    /// Runs the dock end: publishes the pad states to `state`, which is
+    /// connected while the link is up, and sends the host's rumble and LED
+    /// state back.
+    pub async fn run_dock<const N: usize>(mut self, state: &State<N>) -> ! {
+        state.disconnect();
+        let mut sent = None;
+        let mut next_send = Instant::now();
+        loop {
+            let feedback = Feedback {
+                rumble: state.rumble(),
+                led: state.led(),
+            };
+            if self.version.is_some() && sent != Some(feedback) {
+                next_send = Instant::now();
+            }
+            match select3(
+                self.receive(),
+                Timer::at(next_send),
+                Timer::after(FEEDBACK_POLL_PERIOD),
+            )
+            .await
+            {
+                Either3::First(Ok(message)) => {
+                    match self.handle(&message).await {
+                        Some(Linked::New) => state.connect(),
+                        // The restarted pad needs the current feedback.
+                        Some(Linked::Restarted) => sent = None,
+                        None => {}
+                    }
+                    if let (Message::Input(data), Some(_)) = (message, self.version) {
+                        state.send_xinput(data);
+                    }
+                }
+                Either3::First(Err(error)) => log_error(error),
+                Either3::Second(()) => {
+                    let (message, period) = match self.version {
+                        Some(_) => (Message::Feedback(feedback), KEEPALIVE_PERIOD),
+                        None => (hello(false), HELLO_PERIOD),
+                    };
+                    if self.send(&message).await.is_err() {
+                        warn!("uart link: write failed");
+                    }
+                    sent = self.version.map(|_| feedback);
+                    next_send = Instant::now() + period;
+                }
+                Either3::Third(()) => {}
+            }
+            if self.check_timeout() {
+                state.disconnect();
+                sent = None;
+            }
+        }
+    }
+}
+
+fn log_error<E>(error: Error<E>) {
+    match error {
+        Error::Uart(_) => warn!("uart link: read failed"),
+        Error::Frame(error) => debug!("uart link: bad frame {:?}", error),
+    }
+}
+
+fn hello(reply: bool) -> Message {
+    Message::Hello {
+        version: VERSION,
+        min_version: MIN_VERSION,
+        reply,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::convert::Infallible;
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+    use embedded_io_async::ErrorType;
+
+    use super::*;
+
+    // UART reading queued bytes and recording written ones.
+    #[derive(Default)]
+    struct FakeUart {
+        rx: VecDeque<u8>,
+        tx: Vec<u8>,
+    }
+
+    impl ErrorType for FakeUart {
+        type Error = Infallible;
+    }
+
+    impl Read for FakeUart {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Infallible> {
+            let byte = self.rx.pop_front().expect("read past the queued bytes");
+            buf[0] = byte;
+            Ok(1)
+        }
+    }
+
+    impl Write for FakeUart {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Infallible> {
+            self.tx.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    // Messages written by `from`, as received by the other end.
+    fn sent(from: &mut UartLink<FakeUart>) -> Vec<Message> {
+        let mut to = UartLink::new(FakeUart::default());
+        to.uart.rx.extend(from.uart.tx.drain(..));
+        let mut messages = Vec::new();
+        while !to.uart.rx.is_empty() {
+            messages.push(block_on(to.receive()).unwrap());
+        }
+        messages
+    }
+
+    #[test]
+    fn cobs_round_trip() {
+        let cases: [&[u8]; 5] = [
+            &[],
+            &[0],
+            &[0, 0],
+            &[1, 2, 3],
+            &[0x11, 0, 0, 0x22, 0, 0xFF, 0xFF, 0, 1, 2, 3, 4, 5, 6, 0],
+        ];
+        for data in cases {
+            let mut frame = [0_u8; MAX_FRAME_LEN];
+            let len = cobs_encode(data, &mut frame);
+            assert_eq!(len, data.len() + 2);
+            assert_eq!(frame[len - 1], 0);
+            assert!(!frame[..len - 1].contains(&0), "{data:02X?}");
+            let decoded = cobs_decode(&mut frame[..len - 1]);
+            assert_eq!(decoded, Some(data.len()));
+            assert_eq!(&frame[..data.len()], data);
+        }
+        // A code pointing past the end.
+        assert_eq!(cobs_decode(&mut [0x05, 1, 2]), None);
+        assert_eq!(cobs_decode(&mut [0x00, 1]), None);
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            hello(false),
+            hello(true),
+            Message::Input(ControllerData([0, 1, 2, 3, 0, 0, 0xFF, 0x80, 7, 8, 9, 0])),
+            Message::Feedback(Feedback {
+                rumble: (0xFF, 0),
+                led: 0x06,
+            }),
+        ];
+        for message in messages {
+            let mut buf = [0_u8; MAX_MESSAGE_LEN];
+            let len = message.encode(&mut buf);
+            assert_eq!(Message::decode(&buf[..len]), Ok(message));
+        }
+    }
+
+    #[test]
+    fn corrupted_messages_are_rejected() {
+        let mut buf = [0_u8; MAX_MESSAGE_LEN];
+        let len = hello(false).encode(&mut buf);
+        buf[1] ^= 0x01;
+        assert_eq!(Message::decode(&buf[..len]), Err(FrameError::Crc));
+
+        let unknown = [0x7F, 0x00];
+        let crc = crc16(&unknown).to_le_bytes();
+        assert_eq!(
+            Message::decode(&[unknown[0], unknown[1], crc[0], crc[1]]),
+            Err(FrameError::Type)
+        );
+        let short_input = [TYPE_INPUT, 1, 2];
+        let crc = crc16(&short_input).to_le_bytes();
+        let message = [
+            short_input[0],
+            short_input[1],
+            short_input[2],
+            crc[0],
+            crc[1],
+        ];
+        assert_eq!(Message::decode(&message), Err(FrameError::Length));
+        assert_eq!(Message::decode(&[0x01]), Err(FrameError::Length));
+    }
+
+    #[test]
+    fn versions_are_negotiated() {
+        assert_eq!(negotiate(VERSION, MIN_VERSION), Some(VERSION));
+        // A newer end that still speaks our version.
+        assert_eq!(negotiate(VERSION + 1, MIN_VERSION), Some(VERSION));
+        // A newer end that dropped our version.
+        assert_eq!(negotiate(VERSION + 2, VERSION + 1), None);
+        assert_eq!(negotiate(0, 0), None);
+    }
+
+    #[test]
+    fn restarted_pad_links_again() {
+        let mut dock = UartLink::new(FakeUart::default());
+        let mut pad = UartLink::new(FakeUart::default());
+
+        // The dock answers the pad's hello, the pad takes the reply.
+        assert_eq!(block_on(dock.handle(&hello(false))), Some(Linked::New));
+        assert_eq!(sent(&mut dock), [hello(true)]);
+        assert_eq!(block_on(pad.handle(&hello(true))), Some(Linked::New));
+        assert_eq!(sent(&mut pad), []);
+        assert_eq!(pad.version(), Some(VERSION));
+
+        // The pad restarts while the dock stays linked. Its hello is
+        // answered again.
+        let mut pad = UartLink::new(FakeUart::default());
+        assert_eq!(
+            block_on(dock.handle(&hello(false))),
+            Some(Linked::Restarted)
+        );
+        assert_eq!(sent(&mut dock), [hello(true)]);
+        assert_eq!(block_on(pad.handle(&hello(true))), Some(Linked::New));
+        assert_eq!(pad.version(), Some(VERSION));
+        // Replies are never answered, so the ends do not ping-pong.
+        assert_eq!(block_on(dock.handle(&hello(true))), None);
+        assert_eq!(sent(&mut dock), []);
+    }
+
+    #[test]
+    fn incompatible_hello_unlinks() {
+        let mut dock = UartLink::new(FakeUart::default());
+        block_on(dock.handle(&hello(false)));
+        let newer = Message::Hello {
+            version: VERSION + 2,
+            min_version: VERSION + 1,
+            reply: false,
+        };
+        assert_eq!(block_on(dock.handle(&newer)), None);
+        assert_eq!(dock.version(), None);
+    }
+}
@@ -0,0 +1,85 @@
+//! Recording and playback of timed input sequences.
+//!
+//! Storage is provided by the caller, so sequences can live in a static
+//! buffer or be copied to flash.
+
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::controller::XboxGamepad;
+use crate::transport::ReportSink;
+
+/// Recorded gamepad state.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Frame {
+    /// Time since the previous frame.
+    pub delay: Duration,
+    pub pad: XboxGamepad,
+}
+
+/// Records gamepad states into a caller provided buffer.
+pub struct Recorder<'a> {
+    frames: &'a mut [Frame],
+    len: usize,
+    last: Option<Instant>,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(storage: &'a mut [Frame]) -> Self {
+        Self {
+            frames: storage,
+            len: 0,
+            last: None,
+        }
+    }
+
+    /// Records `pad` as received now, see [`Recorder::record_at`].
+    pub fn record(&mut self, pad: &XboxGamepad) -> bool {
+        self.record_at(pad, Instant::now())
+    }
+
+    /// Records `pad` as received at `time`.
+    ///
+    /// States equal to the previous frame are skipped. Returns `false` when
+    /// the storage is full and the state was dropped.
+    pub fn record_at(&mut self, pad: &XboxGamepad, time: Instant) -> bool {
+        if self.len > 0 && self.frames[self.len - 1].pad == *pad {
+            return true;
+        }
+        let Some(frame) = self.frames.get_mut(self.len) else {
+            return false;
+        };
+        *frame = Frame {
+            delay: self
+                .last
+                .map_or(Duration::from_ticks(0), |last| time - last),
+            pad: *pad,
+        };
+        self.len += 1;
+        self.last = Some(time);
+        true
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == self.frames.len()
+    }
+
+    /// Discards all recorded frames.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.last = None;
+    }
+
+    /// The recorded sequence.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames[..self.len]
+    }
+}
+
+/// Replays a recorded sequence into `sink`, honoring the recorded timing.
+pub async fn play(frames: &[Frame], sink: &impl ReportSink) {
+    for frame in frames {
+        Timer::after(frame.delay).await;
+        sink.send(&frame.pad);
+    }
+}
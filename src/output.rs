@@ -0,0 +1,8 @@
+//! Drivers for physical outputs controlled by the host, the counterpart of
+//! [`input`](crate::input).
+
+pub mod buzzer;
+#[cfg(feature = "display")]
+pub mod display;
+pub mod led_ws2812;
+pub mod rumble_pwm;
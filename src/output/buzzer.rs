@@ -0,0 +1,246 @@
+//! Buzzer playing short tunes on status events, for adapters without a
+//! display or LEDs.
+//!
+//! Events are queued as [`Cue`]s in a [`Cues`] from any context, e.g. the
+//! [`Profiles`](crate::profiles::Profiles) change hook, and played by
+//! [`Buzzer::run`]. With [`Buzzer::with_events`] USB configuration changes
+//! are turned into cues as well:
+//!
+//! ```ignore
+//! static CUES: Cues = Cues::new();
+//! let profiles = Profiles::new(pipeline, &mut slots).with_change_hook(|_| CUES.play(Cue::ProfileSwitched));
+//! Buzzer::new(tone, Tunes::new()).with_events(&EVENTS).run(&CUES).await
+//! ```
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::{Channel, TrySendError};
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::OutputPin;
+
+use crate::xinput::{XInputEvent, XInputEvents};
+
+/// Number of cues queued while a tune is playing; older cues are dropped.
+pub const CUE_QUEUE_LEN: usize = 4;
+
+/// Tone generator, implemented for the HAL's PWM with adjustable frequency.
+pub trait ToneOutput {
+    type Error;
+
+    /// Starts a square wave of `frequency` Hz.
+    fn start(&mut self, frequency: u16) -> Result<(), Self::Error>;
+
+    /// Silences the output.
+    fn stop(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Active buzzer with its own oscillator on a GPIO pin, high while sounding.
+/// Frequencies are ignored.
+pub struct ActiveBuzzer<P>(pub P);
+
+impl<P: OutputPin> ToneOutput for ActiveBuzzer<P> {
+    type Error = P::Error;
+
+    fn start(&mut self, _frequency: u16) -> Result<(), Self::Error> {
+        self.0.set_high()
+    }
+
+    fn stop(&mut self) -> Result<(), Self::Error> {
+        self.0.set_low()
+    }
+}
+
+/// Tone of a tune.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Note {
+    /// Frequency in Hz, 0 for a rest.
+    pub frequency: u16,
+    pub duration: Duration,
+}
+
+impl Note {
+    pub const fn new(frequency: u16, millis: u64) -> Self {
+        Self {
+            frequency,
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    pub const fn rest(millis: u64) -> Self {
+        Self::new(0, millis)
+    }
+}
+
+/// Sequence of notes played for a cue.
+pub type Tune = &'static [Note];
+
+/// Status event announced by the buzzer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Cue {
+    /// The host configured the device.
+    Connected,
+    /// The host removed the configuration or the cable was pulled.
+    Disconnected,
+    ProfileSwitched,
+    LowBattery,
+    /// Pairing with a controller or host started.
+    Pairing,
+    /// Pairing succeeded.
+    Paired,
+}
+
+impl Cue {
+    /// Cue announcing an XInput event, if any.
+    pub fn from_event(event: &XInputEvent) -> Option<Cue> {
+        match event {
+            XInputEvent::Configured(true) => Some(Cue::Connected),
+            XInputEvent::Configured(false) => Some(Cue::Disconnected),
+            _ => None,
+        }
+    }
+}
+
+const CONNECTED: Tune = &[Note::new(1047, 60), Note::new(1568, 90)];
+const DISCONNECTED: Tune = &[Note::new(1568, 60), Note::new(1047, 90)];
+const PROFILE_SWITCHED: Tune = &[Note::new(1319, 40)];
+const LOW_BATTERY: Tune = &[Note::new(440, 120), Note::rest(80), Note::new(440, 120)];
+const PAIRING: Tune = &[Note::new(880, 40), Note::rest(40), Note::new(880, 40)];
+const PAIRED: Tune = &[
+    Note::new(1047, 50),
+    Note::new(1319, 50),
+    Note::new(1568, 100),
+];
+
+/// Tune of every [`Cue`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Tunes {
+    pub connected: Tune,
+    pub disconnected: Tune,
+    pub profile_switched: Tune,
+    pub low_battery: Tune,
+    pub pairing: Tune,
+    pub paired: Tune,
+}
+
+impl Default for Tunes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tunes {
+    /// Short rising and falling beeps that are told apart without looking.
+    pub const fn new() -> Self {
+        Self {
+            connected: CONNECTED,
+            disconnected: DISCONNECTED,
+            profile_switched: PROFILE_SWITCHED,
+            low_battery: LOW_BATTERY,
+            pairing: PAIRING,
+            paired: PAIRED,
+        }
+    }
+
+    pub fn tune(&self, cue: Cue) -> Tune {
+        match cue {
+            Cue::Connected => self.connected,
+            Cue::Disconnected => self.disconnected,
+            Cue::ProfileSwitched => self.profile_switched,
+            Cue::LowBattery => self.low_battery,
+            Cue::Pairing => self.pairing,
+            Cue::Paired => self.paired,
+        }
+    }
+}
+
+/// Queue of cues waiting to be played. Like
+/// [`xinput::State`](crate::xinput::State) it can be used from any context.
+pub struct Cues {
+    cues: Channel<CriticalSectionRawMutex, Cue, CUE_QUEUE_LEN>,
+}
+
+impl Default for Cues {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cues {
+    pub const fn new() -> Self {
+        Self {
+            cues: Channel::new(),
+        }
+    }
+
+    /// Queues `cue`, dropping the oldest queued cue when the queue is full.
+    pub fn play(&self, mut cue: Cue) {
+        while let Err(TrySendError::Full(rejected)) = self.cues.try_send(cue) {
+            let _ = self.cues.try_receive();
+            cue = rejected;
+        }
+    }
+}
+
+/// Buzzer task, see the [module documentation](self).
+pub struct Buzzer<'a, T> {
+    output: T,
+    pub tunes: Tunes,
+    events: Option<&'a XInputEvents>,
+}
+
+impl<'a, T: ToneOutput> Buzzer<'a, T> {
+    pub fn new(output: T, tunes: Tunes) -> Self {
+        Self {
+            output,
+            tunes,
+            events: None,
+        }
+    }
+
+    /// Also plays the cues of `events`, see [`Cue::from_event`]. This task
+    /// then consumes the events, so nothing else may wait on them.
+    pub fn with_events(mut self, events: &'a XInputEvents) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Plays a tune, waiting until it is finished.
+    ///
+    /// Output errors are logged and end the tune early.
+    pub async fn play(&mut self, tune: Tune) {
+        for note in tune {
+            let result = match note.frequency {
+                0 => self.output.stop(),
+                frequency => self.output.start(frequency),
+            };
+            if result.is_err() {
+                warn!("buzzer: starting a tone failed");
+                break;
+            }
+            Timer::after(note.duration).await;
+        }
+        if self.output.stop().is_err() {
+            warn!("buzzer: stopping the tone failed");
+        }
+    }
+
+    /// Plays the tune of every queued cue.
+    pub async fn run(mut self, cues: &Cues) -> ! {
+        loop {
+            let cue = match self.events {
+                Some(events) => match select(cues.cues.receive(), events.receive()).await {
+                    Either::First(cue) => Some(cue),
+                    Either::Second(event) => Cue::from_event(&event),
+                },
+                None => Some(cues.cues.receive().await),
+            };
+            if let Some(cue) = cue {
+                debug!("buzzer: playing {:?}", cue);
+                self.play(self.tunes.tune(cue)).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,539 @@
+//! Status display on an SSD1306 OLED: connection state, player LEDs,
+//! battery, active profile and live stick positions.
+//!
+//! [`Status`] is a snapshot of everything shown, rendered with
+//! [`Status::render`] into any 128x64 monochrome `DrawTarget` of
+//! embedded-graphics. [`StatusDisplay::run`] samples it every
+//! [`FRAME_PERIOD`] from the XInput [`State`], a [`Monitor`] of the
+//! reported gamepad and the optional battery and profile hooks, and
+//! flushes the [`FrameBuffer`] when something changed:
+//!
+//! ```ignore
+//! static MONITOR: Monitor = Monitor::new();
+//! let interface = I2CDisplayInterface::new(i2c);
+//! let mut oled = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
+//!     .into_buffered_graphics_mode();
+//! oled.init().unwrap();
+//! input::route(&mut source, &FanOut(&STATE, &MONITOR), transform).await; // input task
+//! StatusDisplay::new(oled).with_events(&EVENTS).run(&STATE, &MONITOR).await
+//! ```
+//!
+//! The SSD1306 driver writes the I2C bus blocking; a full frame takes about
+//! 25 ms at 400 kHz, so run the display from a low priority executor.
+//! Only the embedded-graphics core traits are used, text is drawn with a
+//! built-in 3x5 font.
+//!
+//! With [`StatusDisplay::with_diagnostics`] a [`TestPattern`] replaces the
+//! status while the [diagnostics mode](crate::diagnostics) is on.
+
+use core::cell::Cell;
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_graphics_core::draw_target::DrawTarget;
+use embedded_graphics_core::geometry::{Point, Size};
+use embedded_graphics_core::pixelcolor::BinaryColor;
+use embedded_graphics_core::primitives::Rectangle;
+use ssd1306::mode::BufferedGraphicsMode;
+use ssd1306::prelude::{DisplaySize, WriteOnlyDataCommand};
+use ssd1306::Ssd1306;
+
+use crate::controller::{Trigger, XboxGamepad};
+use crate::diagnostics::Diagnostics;
+use crate::profiles::Profile;
+use crate::protocol;
+use crate::transport::ReportSink;
+use crate::xinput::{State, XInputEvent, XInputEvents};
+
+/// Time between redraws of [`StatusDisplay::run`].
+pub const FRAME_PERIOD: Duration = Duration::from_millis(100);
+
+// Glyph cell of the built-in font, including one column of spacing.
+const GLYPH_WIDTH: i32 = 3;
+const GLYPH_HEIGHT: i32 = 5;
+const GLYPH_ADVANCE: i32 = GLYPH_WIDTH + 1;
+
+const STICK_SIZE: u32 = 40;
+const STICK_DOT: u32 = 4;
+const TRIGGER_WIDTH: u32 = 8;
+const GAUGE_TOP: i32 = 24;
+const PATTERN_SQUARE: u32 = 8;
+/// Time between inversions of the [`TestPattern`].
+const PATTERN_PERIOD: Duration = Duration::from_secs(1);
+
+/// Frame buffer the status is rendered into, flushed to the panel after
+/// every redraw.
+pub trait FrameBuffer: DrawTarget<Color = BinaryColor> {
+    /// Writes the changed parts of the buffer to the panel.
+    fn flush(&mut self) -> Result<(), Self::Error>;
+}
+
+impl<DI, SIZE> FrameBuffer for Ssd1306<DI, SIZE, BufferedGraphicsMode<SIZE>>
+where
+    DI: WriteOnlyDataCommand,
+    SIZE: DisplaySize,
+{
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ssd1306::flush(self)
+    }
+}
+
+/// Latest gamepad state reported to the host, for the stick and trigger
+/// gauges. Combine it with the real transport using
+/// [`FanOut`](crate::transport::FanOut).
+///
+/// Like [`xinput::State`](crate::xinput::State) it can be used from any
+/// context.
+pub struct Monitor {
+    pad: Mutex<CriticalSectionRawMutex, Cell<XboxGamepad>>,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Monitor {
+    pub const fn new() -> Self {
+        Self {
+            pad: Mutex::new(Cell::new(XboxGamepad::new())),
+        }
+    }
+
+    pub fn gamepad(&self) -> XboxGamepad {
+        self.pad.lock(Cell::get)
+    }
+}
+
+impl ReportSink for Monitor {
+    fn send(&self, pad: &XboxGamepad) {
+        self.pad.lock(|cell| cell.set(*pad));
+    }
+}
+
+/// Everything shown on the display.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status {
+    /// The host configured the device.
+    pub connected: bool,
+    /// LED pattern set by the host, see [`State::led`].
+    pub led: u8,
+    /// Battery level in percent, if the device runs from a battery.
+    pub battery: Option<u8>,
+    pub profile: Option<Profile>,
+    pub pad: XboxGamepad,
+}
+
+impl Status {
+    /// Draws the status for a 128x64 panel. The target is not cleared
+    /// first; smaller panels crop the stick gauges.
+    ///
+    /// The top row shows the player number and the four LEDs of the ring,
+    /// the connection state and the battery, the second row the profile
+    /// name. The sticks and triggers are drawn as gauges below.
+    pub fn render<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let player = protocol::player_index(self.led);
+        let number = match player {
+            Some(index) => b'1' + index,
+            None => b'-',
+        };
+        draw_text(target, Point::new(0, 0), &[b'P', number], 2)?;
+        for index in 0..4 {
+            let led = Rectangle::new(Point::new(18 + 7 * index, 2), Size::new(5, 5));
+            if player == Some(index as u8) {
+                target.fill_solid(&led, BinaryColor::On)?;
+            } else {
+                draw_outline(target, &led)?;
+            }
+        }
+
+        let connection: &[u8] = if self.connected {
+            b"ONLINE"
+        } else {
+            b"OFFLINE"
+        };
+        draw_text(target, Point::new(50, 3), connection, 1)?;
+
+        if let Some(percent) = self.battery {
+            let percent = percent.min(100);
+            let mut label = [b' '; 4];
+            let digits = format_percent(percent, &mut label);
+            let x = 108 - GLYPH_ADVANCE * digits.len() as i32;
+            draw_text(target, Point::new(x, 3), digits, 1)?;
+            draw_battery(target, Point::new(110, 2), percent)?;
+        }
+
+        if let Some(profile) = &self.profile {
+            draw_text(target, Point::new(0, 14), profile.name().as_bytes(), 1)?;
+        }
+
+        let pad = &self.pad;
+        draw_stick(
+            target,
+            Point::new(0, GAUGE_TOP),
+            pad.thumb_left_x,
+            pad.thumb_left_y,
+        )?;
+        draw_trigger(
+            target,
+            Point::new(48, GAUGE_TOP),
+            pad.trigger(Trigger::Left),
+        )?;
+        draw_trigger(
+            target,
+            Point::new(72, GAUGE_TOP),
+            pad.trigger(Trigger::Right),
+        )?;
+        draw_stick(
+            target,
+            Point::new(88, GAUGE_TOP),
+            pad.thumb_right_x,
+            pad.thumb_right_y,
+        )
+    }
+}
+
+/// Checkerboard covering the whole panel, drawn inverted every other
+/// second so every pixel is seen on and off, with an outline to check the
+/// panel edges.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TestPattern {
+    pub inverted: bool,
+}
+
+impl TestPattern {
+    /// Pattern to show at `now`.
+    pub fn at(now: Instant) -> Self {
+        Self {
+            inverted: (now.as_ticks() / PATTERN_PERIOD.as_ticks()) % 2 == 1,
+        }
+    }
+
+    /// Draws the pattern over the bounding box of `target`.
+    pub fn render<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let area = target.bounding_box();
+        let square = PATTERN_SQUARE as i32;
+        let columns = area.size.width.div_ceil(PATTERN_SQUARE) as i32;
+        let rows = area.size.height.div_ceil(PATTERN_SQUARE) as i32;
+        for row in 0..rows {
+            for column in 0..columns {
+                if ((row + column) % 2 == 1) == self.inverted {
+                    continue;
+                }
+                let at = area.top_left + Point::new(column * square, row * square);
+                let cell = Rectangle::new(at, Size::new(PATTERN_SQUARE, PATTERN_SQUARE));
+                target.fill_solid(&cell.intersection(&area), BinaryColor::On)?;
+            }
+        }
+        draw_outline(target, &area)
+    }
+}
+
+/// What [`StatusDisplay::run`] last drew.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Screen {
+    Status(Status),
+    TestPattern(TestPattern),
+}
+
+/// Writes `percent` followed by `%` into `buf`, returning the used part.
+fn format_percent(percent: u8, buf: &mut [u8; 4]) -> &[u8] {
+    let mut len = 0;
+    for divisor in [100, 10, 1] {
+        if percent >= divisor || divisor == 1 {
+            buf[len] = b'0' + percent / divisor % 10;
+            len += 1;
+        }
+    }
+    buf[len] = b'%';
+    &buf[..=len]
+}
+
+fn draw_outline<D>(target: &mut D, rect: &Rectangle) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let Size { width, height } = rect.size;
+    let Point { x, y } = rect.top_left;
+    let right = x + width as i32 - 1;
+    let bottom = y + height as i32 - 1;
+    for edge in [
+        Rectangle::new(Point::new(x, y), Size::new(width, 1)),
+        Rectangle::new(Point::new(x, bottom), Size::new(width, 1)),
+        Rectangle::new(Point::new(x, y), Size::new(1, height)),
+        Rectangle::new(Point::new(right, y), Size::new(1, height)),
+    ] {
+        target.fill_solid(&edge, BinaryColor::On)?;
+    }
+    Ok(())
+}
+
+/// 16x7 battery icon with a fill proportional to `percent`.
+fn draw_battery<D>(target: &mut D, at: Point, percent: u8) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    draw_outline(target, &Rectangle::new(at, Size::new(14, 7)))?;
+    target.fill_solid(
+        &Rectangle::new(at + Point::new(14, 2), Size::new(2, 3)),
+        BinaryColor::On,
+    )?;
+    let level = u32::from(percent) * 10 / 100;
+    target.fill_solid(
+        &Rectangle::new(at + Point::new(2, 2), Size::new(level, 3)),
+        BinaryColor::On,
+    )
+}
+
+/// Square gauge with a dot at the stick position, up being positive Y.
+fn draw_stick<D>(target: &mut D, at: Point, x: i16, y: i16) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    draw_outline(
+        target,
+        &Rectangle::new(at, Size::new(STICK_SIZE, STICK_SIZE)),
+    )?;
+    let center = at + Point::new(STICK_SIZE as i32 / 2, STICK_SIZE as i32 / 2);
+    target.fill_solid(&Rectangle::new(center, Size::new(1, 1)), BinaryColor::On)?;
+
+    // Dot positions inside the border, 0 to `travel`.
+    let travel = (STICK_SIZE - 2 - STICK_DOT) as i32;
+    let scale = |value: i16| (i32::from(value) + 32768) * travel / 65535;
+    let dot = at + Point::new(1 + scale(x), 1 + travel - scale(y));
+    target.fill_solid(
+        &Rectangle::new(dot, Size::new(STICK_DOT, STICK_DOT)),
+        BinaryColor::On,
+    )
+}
+
+/// Vertical bar filling up from the bottom.
+fn draw_trigger<D>(target: &mut D, at: Point, value: u8) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    draw_outline(
+        target,
+        &Rectangle::new(at, Size::new(TRIGGER_WIDTH, STICK_SIZE)),
+    )?;
+    let height = (STICK_SIZE - 4) * u32::from(value) / 255;
+    let top = at.y + STICK_SIZE as i32 - 2 - height as i32;
+    target.fill_solid(
+        &Rectangle::new(
+            Point::new(at.x + 2, top),
+            Size::new(TRIGGER_WIDTH - 4, height),
+        ),
+        BinaryColor::On,
+    )
+}
+
+/// Draws ASCII `text` in the built-in font, every font pixel `scale`
+/// display pixels wide. Lowercase letters are drawn in uppercase.
+fn draw_text<D>(target: &mut D, at: Point, text: &[u8], scale: u32) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    for (index, &c) in text.iter().enumerate() {
+        let origin = at + Point::new(index as i32 * GLYPH_ADVANCE * scale as i32, 0);
+        let bits = glyph(c);
+        for row in 0..GLYPH_HEIGHT {
+            for column in 0..GLYPH_WIDTH {
+                let bit = (GLYPH_HEIGHT - row) * GLYPH_WIDTH - column - 1;
+                if bits >> bit & 1 != 0 {
+                    let pixel = origin + Point::new(column, row) * scale as i32;
+                    target.fill_solid(
+                        &Rectangle::new(pixel, Size::new(scale, scale)),
+                        BinaryColor::On,
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 3x5 glyph of `c`, the top row in the highest bits and the left column
+/// in the highest bit of a row.
+#[rustfmt::skip]
+fn glyph(c: u8) -> u16 {
+    match c.to_ascii_uppercase() {
+        b' ' => 0b000_000_000_000_000,
+        b'0' => 0b111_101_101_101_111,
+        b'1' => 0b010_110_010_010_111,
+        b'2' => 0b111_001_111_100_111,
+        b'3' => 0b111_001_111_001_111,
+        b'4' => 0b101_101_111_001_001,
+        b'5' => 0b111_100_111_001_111,
+        b'6' => 0b111_100_111_101_111,
+        b'7' => 0b111_001_001_001_001,
+        b'8' => 0b111_101_111_101_111,
+        b'9' => 0b111_101_111_001_111,
+        b'A' => 0b010_101_111_101_101,
+        b'B' => 0b110_101_110_101_110,
+        b'C' => 0b011_100_100_100_011,
+        b'D' => 0b110_101_101_101_110,
+        b'E' => 0b111_100_110_100_111,
+        b'F' => 0b111_100_110_100_100,
+        b'G' => 0b011_100_101_101_011,
+        b'H' => 0b101_101_111_101_101,
+        b'I' => 0b111_010_010_010_111,
+        b'J' => 0b001_001_001_101_010,
+        b'K' => 0b101_101_110_101_101,
+        b'L' => 0b100_100_100_100_111,
+        b'M' => 0b101_111_111_101_101,
+        b'N' => 0b110_101_101_101_101,
+        b'O' => 0b010_101_101_101_010,
+        b'P' => 0b110_101_110_100_100,
+        b'Q' => 0b010_101_101_110_011,
+        b'R' => 0b110_101_110_101_101,
+        b'S' => 0b011_100_010_001_110,
+        b'T' => 0b111_010_010_010_010,
+        b'U' => 0b101_101_101_101_111,
+        b'V' => 0b101_101_101_101_010,
+        b'W' => 0b101_101_111_111_101,
+        b'X' => 0b101_101_010_101_101,
+        b'Y' => 0b101_101_010_010_010,
+        b'Z' => 0b111_001_010_100_111,
+        b'-' => 0b000_000_111_000_000,
+        b'_' => 0b000_000_000_000_111,
+        b'.' => 0b000_000_000_000_010,
+        b':' => 0b000_010_000_010_000,
+        b'%' => 0b101_001_010_100_101,
+        _    => 0b111_001_010_000_010,
+    }
+}
+
+/// Display task, see the [module documentation](self).
+pub struct StatusDisplay<'a, F> {
+    display: F,
+    battery: Option<fn() -> u8>,
+    profile: Option<fn() -> Option<Profile>>,
+    events: Option<&'a XInputEvents>,
+    diagnostics: Option<&'a Diagnostics>,
+    configured: bool,
+    suspended: bool,
+}
+
+impl<'a, F: FrameBuffer> StatusDisplay<'a, F> {
+    /// `display` is an initialized frame buffer, e.g. an SSD1306 in
+    /// buffered graphics mode.
+    pub fn new(display: F) -> Self {
+        Self {
+            display,
+            battery: None,
+            profile: None,
+            events: None,
+            diagnostics: None,
+            configured: false,
+            suspended: false,
+        }
+    }
+
+    /// Shows the battery level in percent returned by `hook`.
+    pub fn with_battery_hook(mut self, hook: fn() -> u8) -> Self {
+        self.battery = Some(hook);
+        self
+    }
+
+    /// Shows the name of the profile returned by `hook`, usually the
+    /// [`Profiles::active`](crate::profiles::Profiles::active) slot of a
+    /// static.
+    pub fn with_profile_hook(mut self, hook: fn() -> Option<Profile>) -> Self {
+        self.profile = Some(hook);
+        self
+    }
+
+    /// Takes the connection state from the configuration and suspend
+    /// events. This task then consumes the events, so nothing else may wait
+    /// on them. Without events the device counts as connected once the host
+    /// set the LED pattern.
+    pub fn with_events(mut self, events: &'a XInputEvents) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Shows a [`TestPattern`] instead of the status while the diagnostics
+    /// mode of `diagnostics` is on.
+    pub fn with_diagnostics(mut self, diagnostics: &'a Diagnostics) -> Self {
+        self.diagnostics = Some(diagnostics);
+        self
+    }
+
+    /// Current status of `state` and `monitor`.
+    pub fn status<const N: usize>(&self, state: &State<N>, monitor: &Monitor) -> Status {
+        let connected = match self.events {
+            Some(_) => self.configured && !self.suspended,
+            None => state.led() != 0,
+        };
+        Status {
+            connected,
+            led: state.led(),
+            battery: self.battery.map(|hook| hook()),
+            profile: self.profile.and_then(|hook| hook()),
+            pad: monitor.gamepad(),
+        }
+    }
+
+    fn handle_event(&mut self, event: XInputEvent) {
+        match event {
+            XInputEvent::Configured(configured) => self.configured = configured,
+            XInputEvent::Suspended(suspended) => self.suspended = suspended,
+            _ => {}
+        }
+    }
+
+    /// Redraws the display every [`FRAME_PERIOD`] when the status or the
+    /// test pattern changed.
+    pub async fn run<const N: usize>(mut self, state: &State<N>, monitor: &Monitor) -> ! {
+        let mut ticker = Ticker::every(FRAME_PERIOD);
+        let mut shown = None;
+        loop {
+            if let Some(events) = self.events {
+                match select(ticker.next(), events.receive()).await {
+                    Either::First(()) => {}
+                    Either::Second(event) => {
+                        self.handle_event(event);
+                        continue;
+                    }
+                }
+            } else {
+                ticker.next().await;
+            }
+
+            let screen = match self.diagnostics {
+                Some(diagnostics) if diagnostics.is_enabled() => {
+                    Screen::TestPattern(TestPattern::at(Instant::now()))
+                }
+                _ => Screen::Status(self.status(state, monitor)),
+            };
+            if shown == Some(screen) {
+                continue;
+            }
+            shown = Some(screen);
+            let drawn = self
+                .display
+                .clear(BinaryColor::Off)
+                .and_then(|()| match screen {
+                    Screen::Status(status) => status.render(&mut self.display),
+                    Screen::TestPattern(pattern) => pattern.render(&mut self.display),
+                })
+                .and_then(|()| self.display.flush());
+            if drawn.is_err() {
+                warn!("display: drawing failed");
+                // Draw again on the next frame.
+                shown = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,239 @@
+//! WS2812 (NeoPixel) LED feedback: player number, low battery and profile
+//! colors.
+//!
+//! [`Feedback`] renders the LED pattern set by the host through
+//! [`State::led`] and the optional battery and profile hooks into a frame of
+//! [`Rgb`] values, [`Feedback::run`] animates it on any [`LedWriter`].
+//! [`Ws2812Spi`] drives the LEDs from the MOSI pin of an SPI bus clocked at
+//! 3.2 MHz. On the RP2040, implement [`LedWriter`] for embassy-rp's PIO
+//! WS2812 program to keep the SPI free.
+//!
+//! With four or more LEDs, the LED of the assigned player lights up like
+//! the ring of a genuine controller; fewer LEDs all show the player color.
+
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_hal_async::spi::SpiBus;
+
+use crate::protocol;
+use crate::xinput::State;
+
+/// Time between animation frames of [`Feedback::run`].
+pub const FRAME_PERIOD: Duration = Duration::from_millis(30);
+/// How long the profile color is shown after a profile change.
+pub const PROFILE_FLASH_TIME: Duration = Duration::from_secs(1);
+
+// SPI bits per LED bit: 0 is sent as 1000, 1 as 1110.
+const SPI_BYTES_PER_LED: usize = 12;
+// Low time latching the colors, 280 µs at 3.2 MHz.
+const SPI_RESET_BYTES: usize = 112;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const OFF: Rgb = Rgb::new(0, 0, 0);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Scales all channels by `brightness` / 255.
+    pub fn dim(self, brightness: u8) -> Self {
+        let scale = |value: u8| (u16::from(value) * u16::from(brightness) / 255) as u8;
+        Self::new(scale(self.r), scale(self.g), scale(self.b))
+    }
+}
+
+/// Sink for LED frames.
+#[allow(async_fn_in_trait)]
+pub trait LedWriter {
+    type Error;
+
+    async fn write(&mut self, leds: &[Rgb]) -> Result<(), Self::Error>;
+}
+
+/// Size of the [`Ws2812Spi`] buffer for `leds` LEDs.
+pub const fn spi_buffer_len(leds: usize) -> usize {
+    leds * SPI_BYTES_PER_LED + SPI_RESET_BYTES
+}
+
+/// WS2812 chain on the MOSI pin of an SPI bus clocked at 3.2 MHz.
+pub struct Ws2812Spi<'a, S> {
+    spi: S,
+    buf: &'a mut [u8],
+}
+
+impl<'a, S: SpiBus> Ws2812Spi<'a, S> {
+    /// `buf` is sized with [`spi_buffer_len`], LEDs that do not fit are
+    /// not written.
+    pub fn new(spi: S, buf: &'a mut [u8]) -> Self {
+        Self { spi, buf }
+    }
+}
+
+impl<S: SpiBus> LedWriter for Ws2812Spi<'_, S> {
+    type Error = S::Error;
+
+    async fn write(&mut self, leds: &[Rgb]) -> Result<(), Self::Error> {
+        let capacity = self.buf.len().saturating_sub(SPI_RESET_BYTES) / SPI_BYTES_PER_LED;
+        let leds = &leds[..leds.len().min(capacity)];
+        let (data, reset) = self.buf.split_at_mut(leds.len() * SPI_BYTES_PER_LED);
+        for (led, out) in leds.iter().zip(data.chunks_exact_mut(SPI_BYTES_PER_LED)) {
+            // WS2812 expects green, red, blue, most significant bit first.
+            for (color, out) in [led.g, led.r, led.b]
+                .into_iter()
+                .zip(out.chunks_exact_mut(4))
+            {
+                for (pair, out) in out.iter_mut().enumerate() {
+                    let bit = |shift: usize| (color >> (7 - 2 * pair - shift)) & 1 != 0;
+                    let nibble = |set: bool| if set { 0b1110 } else { 0b1000 };
+                    *out = nibble(bit(0)) << 4 | nibble(bit(1));
+                }
+            }
+        }
+        reset[..SPI_RESET_BYTES].fill(0);
+        self.spi
+            .write(&self.buf[..leds.len() * SPI_BYTES_PER_LED + SPI_RESET_BYTES])
+            .await?;
+        self.spi.flush().await
+    }
+}
+
+const DEFAULT_PROFILE_COLORS: [Rgb; 4] = [
+    Rgb::new(0, 0, 255),
+    Rgb::new(255, 0, 255),
+    Rgb::new(255, 255, 0),
+    Rgb::new(0, 255, 255),
+];
+
+/// Colors used by [`Feedback`].
+#[derive(Clone, Copy)]
+pub struct Theme<'a> {
+    pub players: [Rgb; 4],
+    /// Blinks while the host has not assigned a player number.
+    pub searching: Rgb,
+    pub low_battery: Rgb,
+    /// Battery level in percent at and below which the last LED blinks.
+    pub low_battery_level: u8,
+    /// Color per profile slot, repeated for further slots.
+    pub profiles: &'a [Rgb],
+    pub brightness: u8,
+}
+
+impl Default for Theme<'_> {
+    fn default() -> Self {
+        Self {
+            players: [Rgb::new(0, 255, 0); 4],
+            searching: Rgb::new(0, 255, 0),
+            low_battery: Rgb::new(255, 0, 0),
+            low_battery_level: 15,
+            profiles: &DEFAULT_PROFILE_COLORS,
+            brightness: 64,
+        }
+    }
+}
+
+/// Renders the controller status into LED frames.
+pub struct Feedback<'a> {
+    theme: Theme<'a>,
+    battery: Option<fn() -> u8>,
+    profile: Option<fn() -> Option<usize>>,
+    last_profile: Option<usize>,
+    flash_until: Instant,
+}
+
+impl<'a> Feedback<'a> {
+    pub fn new(theme: Theme<'a>) -> Self {
+        Self {
+            theme,
+            battery: None,
+            profile: None,
+            last_profile: None,
+            flash_until: Instant::from_ticks(0),
+        }
+    }
+
+    /// Shows a low battery warning from the level in percent returned by
+    /// `hook`.
+    pub fn with_battery_hook(mut self, hook: fn() -> u8) -> Self {
+        self.battery = Some(hook);
+        self
+    }
+
+    /// Flashes the profile color when the slot returned by `hook` changes,
+    /// usually [`Profiles::active`](crate::profiles::Profiles::active) of a
+    /// static.
+    pub fn with_profile_hook(mut self, hook: fn() -> Option<usize>) -> Self {
+        self.profile = Some(hook);
+        self
+    }
+
+    /// Renders the frame at `now` for the host LED pattern `led` into `leds`.
+    pub fn render(&mut self, led: u8, now: Instant, leds: &mut [Rgb]) {
+        // 2 Hz and 1 Hz blink phases
+        let fast_blink = now.as_millis() % 500 < 250;
+        let slow_blink = now.as_millis() % 1000 < 500;
+
+        leds.fill(Rgb::OFF);
+        match protocol::player_index(led) {
+            Some(player) => {
+                let color = self.theme.players[usize::from(player)];
+                if leds.len() >= 4 {
+                    leds[usize::from(player)] = color;
+                } else {
+                    leds.fill(color);
+                }
+            }
+            None if led != 0 && fast_blink => leds.fill(self.theme.searching),
+            None => {}
+        }
+
+        if let Some(profile) = self.profile.and_then(|hook| hook()) {
+            if self.last_profile.is_some_and(|last| last != profile) {
+                self.flash_until = now + PROFILE_FLASH_TIME;
+            }
+            self.last_profile = Some(profile);
+            if now < self.flash_until && !self.theme.profiles.is_empty() {
+                leds.fill(self.theme.profiles[profile % self.theme.profiles.len()]);
+            }
+        }
+
+        let low_battery = self
+            .battery
+            .is_some_and(|hook| hook() <= self.theme.low_battery_level);
+        if let (true, Some(last)) = (low_battery, leds.last_mut()) {
+            *last = if slow_blink {
+                self.theme.low_battery
+            } else {
+                Rgb::OFF
+            };
+        }
+
+        for led in leds.iter_mut() {
+            *led = led.dim(self.theme.brightness);
+        }
+    }
+
+    /// Renders and writes a frame every [`FRAME_PERIOD`]. `leds` holds one
+    /// entry per LED of the chain.
+    pub async fn run<W: LedWriter, const N: usize>(
+        mut self,
+        writer: &mut W,
+        state: &State<N>,
+        leds: &mut [Rgb],
+    ) -> ! {
+        let mut ticker = Ticker::every(FRAME_PERIOD);
+        loop {
+            ticker.next().await;
+            self.render(state.led(), Instant::now(), leds);
+            if writer.write(leds).await.is_err() {
+                warn!("led feedback: write failed");
+            }
+        }
+    }
+}
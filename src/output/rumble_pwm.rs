@@ -0,0 +1,117 @@
+//! Rumble motors driven by PWM from the host's rumble commands.
+//!
+//! [`RumblePwm`] slews the duty cycles towards the requested speeds, which
+//! spares small motors and transistors, and stops the motors when the host
+//! has not sent a rumble command for [`RumbleConfig::watchdog`] or the
+//! controller is disconnected. Reading [`State::rumble`] directly leaves
+//! motors running when the host goes away.
+//!
+//! ```ignore
+//! let pwm = Pwm::new_output_ab(p.PWM_SLICE0, p.PIN_0, p.PIN_1, pwm::Config::default());
+//! let (strong, weak) = pwm.split();
+//! RumblePwm::new(unwrap!(strong), unwrap!(weak), RumbleConfig::default())
+//!     .run(&STATE)
+//!     .await
+//! ```
+
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_hal::pwm::SetDutyCycle;
+
+use crate::xinput::State;
+
+const FULL: u32 = u16::MAX as u32;
+
+/// Options for [`RumblePwm`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RumbleConfig {
+    /// Time for a motor to go from stop to full speed or back, 0 to follow
+    /// the host immediately.
+    pub ramp: Duration,
+    /// Motors stop when the last rumble command is older. Games that hold a
+    /// constant rumble longer than this are cut off.
+    pub watchdog: Duration,
+    /// Interval of [`RumblePwm::run`].
+    pub update_period: Duration,
+}
+
+impl Default for RumbleConfig {
+    fn default() -> Self {
+        Self {
+            ramp: Duration::from_millis(50),
+            watchdog: Duration::from_secs(10),
+            update_period: Duration::from_millis(5),
+        }
+    }
+}
+
+/// Strong (left) and weak (right) motor on two PWM channels.
+pub struct RumblePwm<P> {
+    strong: P,
+    weak: P,
+    config: RumbleConfig,
+    // current duty cycles as a fraction of `FULL`
+    levels: [u32; 2],
+    last_update: Option<Instant>,
+}
+
+impl<P: SetDutyCycle> RumblePwm<P> {
+    pub fn new(strong: P, weak: P, config: RumbleConfig) -> Self {
+        Self {
+            strong,
+            weak,
+            config,
+            levels: [0; 2],
+            last_update: None,
+        }
+    }
+
+    /// Moves the duty cycles towards the speeds requested through `state`,
+    /// as of `now`.
+    pub fn update<const N: usize>(
+        &mut self,
+        state: &State<N>,
+        now: Instant,
+    ) -> Result<(), P::Error> {
+        let (strong, weak) = state.rumble();
+        let alive = state.is_present() && state.rumble_age() <= self.config.watchdog;
+        let targets = match alive {
+            true => [strong, weak].map(|speed| u32::from(speed) * FULL / 255),
+            false => [0; 2],
+        };
+
+        let elapsed = self
+            .last_update
+            .map_or(Duration::MAX, |last| now.saturating_duration_since(last));
+        self.last_update = Some(now);
+        let max_step = match self.config.ramp.as_micros() {
+            0 => FULL,
+            ramp => (u64::from(FULL) * elapsed.as_micros() / ramp).min(u64::from(FULL)) as u32,
+        };
+
+        for (level, target) in self.levels.iter_mut().zip(targets) {
+            *level = if target > *level {
+                target.min(*level + max_step)
+            } else {
+                target.max(level.saturating_sub(max_step))
+            };
+        }
+        self.strong
+            .set_duty_cycle_fraction(self.levels[0] as u16, FULL as u16)?;
+        self.weak
+            .set_duty_cycle_fraction(self.levels[1] as u16, FULL as u16)
+    }
+
+    /// Updates the motors every [`RumbleConfig::update_period`].
+    ///
+    /// PWM errors are logged and retried on the next update.
+    pub async fn run<const N: usize>(mut self, state: &State<N>) -> ! {
+        let mut ticker = Ticker::every(self.config.update_period);
+        loop {
+            ticker.next().await;
+            if self.update(state, Instant::now()).is_err() {
+                warn!("rumble pwm: setting the duty cycle failed");
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Idle auto-sleep for battery powered builds.
+//!
+//! [`PowerManager::route`] replaces [`input::route`](crate::input::route):
+//! it forwards the input like before, and once the input has been
+//! [neutral](PowerConfig::is_neutral) for [`PowerConfig::timeout`] it
+//! powers the frontend down through [`LowPower`], waits for a [`Wake`]
+//! source such as a button press on a [`WakePins`] interrupt and powers
+//! the frontend up again:
+//!
+//! ```ignore
+//! let mut power = PowerManager::new(PowerConfig::new(Duration::from_secs(600)), WakePins::new(pins), Hal)
+//!     .with_sleep_hook(|sleeping| if sleeping { STATE.disconnect() } else { STATE.connect() });
+//! power.route(&mut source, &STATE, &BUTTON_MAP).await
+//! ```
+//!
+//! The pending [`InputSource::next`] is cancelled when going to sleep, so
+//! the source must tolerate that, as [`Periodic`](crate::input::Periodic)
+//! does.
+
+use embassy_futures::select::{select, select_array, Either};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::digital::Wait as WaitPin;
+
+use crate::controller::{Button, Trigger, XboxGamepad};
+use crate::input::InputSource;
+use crate::remap::Transform;
+use crate::transport::ReportSink;
+
+/// When the input counts as idle.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PowerConfig {
+    /// Time the input must stay neutral before going to sleep.
+    pub timeout: Duration,
+    /// Stick deflection on either axis still counted as neutral, to ignore
+    /// drift of worn sticks.
+    pub stick_deadzone: i16,
+    /// Trigger value still counted as released.
+    pub trigger_deadzone: u8,
+}
+
+impl PowerConfig {
+    /// Sleeps after `timeout`, ignoring about 10% of stick and trigger
+    /// travel.
+    pub const fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            stick_deadzone: 3200,
+            trigger_deadzone: 25,
+        }
+    }
+
+    /// Whether `pad` has no button pressed and the sticks and triggers at
+    /// rest.
+    pub fn is_neutral(&self, pad: &XboxGamepad) -> bool {
+        let at_rest = |value: i16| value.unsigned_abs() <= self.stick_deadzone.unsigned_abs();
+        Button::ALL.iter().all(|&button| !pad.button(button))
+            && Trigger::ALL
+                .iter()
+                .all(|&trigger| pad.trigger(trigger) <= self.trigger_deadzone)
+            && [
+                pad.thumb_left_x,
+                pad.thumb_left_y,
+                pad.thumb_right_x,
+                pad.thumb_right_y,
+            ]
+            .into_iter()
+            .all(at_rest)
+    }
+}
+
+/// HAL specific power down of the frontend, e.g. switching off sensor
+/// supplies and radios and lowering the clocks. Both steps do nothing by
+/// default, so `()` only stops reading the input.
+#[allow(async_fn_in_trait)]
+pub trait LowPower {
+    /// Called when going to sleep, before waiting for a wake-up.
+    async fn enter(&mut self) {}
+
+    /// Called after a wake-up, before the input is read again.
+    async fn exit(&mut self) {}
+}
+
+impl LowPower for () {}
+
+impl<T: LowPower + ?Sized> LowPower for &mut T {
+    async fn enter(&mut self) {
+        (**self).enter().await
+    }
+
+    async fn exit(&mut self) {
+        (**self).exit().await
+    }
+}
+
+/// Source of wake-ups while sleeping.
+#[allow(async_fn_in_trait)]
+pub trait Wake {
+    /// Waits until the device should wake up.
+    async fn wait_for_wake(&mut self);
+}
+
+impl<T: Wake + ?Sized> Wake for &mut T {
+    async fn wait_for_wake(&mut self) {
+        (**self).wait_for_wake().await
+    }
+}
+
+/// Wakes up when any of the button pins is pulled low, using the HAL's
+/// GPIO interrupts through [`embedded_hal_async::digital::Wait`]. Pin
+/// errors wake up as well.
+///
+/// Sticks and triggers do not wake the device, so include at least one
+/// button the player presses first, like start or guide.
+pub struct WakePins<P, const N: usize> {
+    pins: [P; N],
+}
+
+impl<P: WaitPin, const N: usize> WakePins<P, N> {
+    pub fn new(pins: [P; N]) -> Self {
+        Self { pins }
+    }
+
+    /// Returns the pins, e.g. to hand them back to the frontend.
+    pub fn release(self) -> [P; N] {
+        self.pins
+    }
+}
+
+impl<P: WaitPin, const N: usize> Wake for WakePins<P, N> {
+    async fn wait_for_wake(&mut self) {
+        let pins = self.pins.each_mut().map(|pin| pin.wait_for_low());
+        let (result, pin) = select_array(pins).await;
+        if result.is_err() {
+            warn!("power: wake pin {} failed", pin);
+        }
+    }
+}
+
+/// Routes input and puts the frontend to sleep when idle, see the
+/// [module documentation](self).
+pub struct PowerManager<W, L> {
+    pub config: PowerConfig,
+    wake: W,
+    low_power: L,
+    sleep_hook: Option<fn(bool)>,
+}
+
+impl<W: Wake, L: LowPower> PowerManager<W, L> {
+    pub fn new(config: PowerConfig, wake: W, low_power: L) -> Self {
+        Self {
+            config,
+            wake,
+            low_power,
+            sleep_hook: None,
+        }
+    }
+
+    /// Calls `hook` with `true` when going to sleep and with `false` after
+    /// waking up, e.g. to report the controller as disconnected with
+    /// [`State::disconnect`](crate::xinput::State::disconnect) so the host
+    /// does not wait for a sleeping pad.
+    pub fn with_sleep_hook(mut self, hook: fn(bool)) -> Self {
+        self.sleep_hook = Some(hook);
+        self
+    }
+
+    /// Powers down, waits for a wake-up and powers up again.
+    pub async fn sleep(&mut self) {
+        debug!("power: going to sleep");
+        if let Some(hook) = self.sleep_hook {
+            hook(true);
+        }
+        self.low_power.enter().await;
+        self.wake.wait_for_wake().await;
+        self.low_power.exit().await;
+        if let Some(hook) = self.sleep_hook {
+            hook(false);
+        }
+        debug!("power: woke up");
+    }
+
+    /// Forwards every state of `source` through `transform` to `sink` like
+    /// [`input::route`](crate::input::route), sleeping whenever the input
+    /// stays neutral for the configured timeout.
+    pub async fn route(
+        &mut self,
+        source: &mut impl InputSource,
+        sink: &impl ReportSink,
+        transform: impl Transform,
+    ) -> ! {
+        let mut idle_since = Some(Instant::now());
+        loop {
+            // Held buttons or sticks produce no new states, so only count
+            // from the last state that was neutral.
+            let deadline = match idle_since {
+                Some(since) => since + self.config.timeout,
+                None => Instant::MAX,
+            };
+            match select(source.next(), Timer::at(deadline)).await {
+                Either::First(pad) => {
+                    if !self.config.is_neutral(&pad) {
+                        idle_since = None;
+                    } else if idle_since.is_none() {
+                        idle_since = Some(Instant::now());
+                    }
+                    sink.send(&transform.transform(pad));
+                }
+                Either::Second(()) => {
+                    self.sleep().await;
+                    idle_since = Some(Instant::now());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! USB device configurations and descriptor buffer sizes for the devices
+//! this crate emulates, so the magic numbers don't have to be copied from
+//! a descriptor dump.
+//!
+//! ```ignore
+//! let mut config = presets::usb_config_wireless_receiver();
+//! config.serial_number = Some("E0CB7AD0");
+//! let mut device_descriptor = [0; presets::DEVICE_DESCRIPTOR_LEN];
+//! let mut config_descriptor = [0; presets::WIRELESS_RECEIVER_CONFIG_DESCRIPTOR_LEN];
+//! let mut bos_descriptor = [0; presets::BOS_DESCRIPTOR_LEN];
+//! let mut control_buf = [0; presets::CONTROL_BUF_LEN];
+//! ```
+//!
+//! The presets enable remote wakeup. Run the device with
+//! [`RemoteWakeup::run`] instead of `UsbDevice::run` so controller input
+//! wakes a suspended host:
+//!
+//! ```ignore
+//! static WAKEUP: RemoteWakeup = RemoteWakeup::new();
+//! input::route(&mut source, &FanOut(&STATE, &WAKEUP), transform).await; // input task
+//! WAKEUP.run(&mut usb).await // USB task
+//! ```
+//!
+//! embassy-usb writes the BOS descriptor itself: a USB 2.0 extension
+//! capability with all attributes cleared, so hosts do not use link power
+//! management (LPM) with the device and only suspend it as a whole. That is
+//! what a genuine full-speed receiver supports as well.
+
+use embassy_futures::select::{select, Either};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_usb::driver::Driver;
+use embassy_usb::{Config, UsbDevice};
+
+use crate::controller::XboxGamepad;
+use crate::transport::ReportSink;
+use crate::xinput;
+
+pub use crate::xinput::BOS_DESCRIPTOR_LEN;
+
+/// Size of the device descriptor buffer.
+pub const DEVICE_DESCRIPTOR_LEN: usize = 18;
+/// Size of the control buffer, large enough for every request the
+/// emulated devices answer.
+pub const CONTROL_BUF_LEN: usize = 64;
+
+const _: () = assert!(CONTROL_BUF_LEN >= xinput::CONTROL_BUF_MIN_LEN);
+// Feature reports are transferred with a leading report ID.
+#[cfg(feature = "config-hid")]
+const _: () = assert!(CONTROL_BUF_LEN > crate::config_hid::REPORT_LEN);
+#[cfg(feature = "hid-wheel")]
+const _: () = assert!(CONTROL_BUF_LEN >= crate::hid_wheel::pid::OUT_REPORT_LEN);
+
+const CONFIGURATION_LEN: usize = 9;
+// interface, HID class and IN endpoint descriptors
+const HID_INTERFACE_LEN: usize = 9 + 9 + 7;
+const ENDPOINT_LEN: usize = 7;
+
+/// Size of the configuration descriptor written by `slots` calls of
+/// [`XInput::new_wireless`](crate::xinput::XInput::new_wireless), see
+/// [`xinput::config_descriptor_len`].
+pub const fn wireless_receiver_config_descriptor_len(slots: usize, headset: bool) -> usize {
+    xinput::config_descriptor_len(slots, headset)
+}
+
+/// Size of the configuration descriptor of a full receiver with four slots
+/// and headset interfaces.
+pub const WIRELESS_RECEIVER_CONFIG_DESCRIPTOR_LEN: usize =
+    wireless_receiver_config_descriptor_len(4, true);
+
+// wTotalLength of the genuine receiver, see notes/usb_descriptor_wireless_receiver.txt
+const _: () = assert!(WIRELESS_RECEIVER_CONFIG_DESCRIPTOR_LEN == 0x141);
+
+/// Device configuration of the Xbox 360 Wireless Receiver for Windows, to
+/// use with [`XInput::new_wireless`](crate::xinput::XInput::new_wireless).
+///
+/// Genuine receivers report a unique serial number, set
+/// [`Config::serial_number`] if the host should tell several adapters
+/// apart.
+pub fn usb_config_wireless_receiver() -> Config<'static> {
+    let mut config = Config::new(0x045E, 0x0719);
+    config.device_class = 0xFF;
+    config.device_sub_class = 0xFF;
+    config.device_protocol = 0xFF;
+    config.device_release = 0x0100;
+    config.max_packet_size_0 = 8;
+    config.manufacturer = Some("©Microsoft");
+    config.product = Some("Xbox 360 Wireless Receiver for Windows");
+    config.composite_with_iads = false;
+    config.self_powered = false;
+    config.supports_remote_wakeup = true;
+    config.max_power = 260;
+    config
+}
+
+/// [`usb_config_wireless_receiver`] for a composite device with further
+/// functions after the XInput interfaces, e.g. the `config_serial` or
+/// `consumer_control` interfaces.
+///
+/// The device class is set to the interface association class, so every
+/// function, including the vendor specific XInput interfaces, becomes a
+/// child device with its own driver.
+pub fn usb_config_wireless_receiver_composite() -> Config<'static> {
+    let mut config = usb_config_wireless_receiver();
+    config.device_class = 0xEF;
+    config.device_sub_class = 0x02;
+    config.device_protocol = 0x01;
+    config.composite_with_iads = true;
+    config
+}
+
+/// Device configuration of a wired Xbox 360 Controller.
+///
+/// The xinput drivers bind it to the wired controller interface, which
+/// this crate does not emit: [`XInput`](crate::xinput::XInput) writes the
+/// receiver's interface and needs [`usb_config_wireless_receiver`]. Use it
+/// for a wired controller function of your own.
+pub fn usb_config_wired_pad() -> Config<'static> {
+    let mut config = Config::new(0x045E, 0x028E);
+    config.device_class = 0xFF;
+    config.device_sub_class = 0xFF;
+    config.device_protocol = 0xFF;
+    config.device_release = 0x0114;
+    config.max_packet_size_0 = 8;
+    config.manufacturer = Some("©Microsoft Corporation");
+    config.product = Some("Controller");
+    config.composite_with_iads = false;
+    config.self_powered = false;
+    config.supports_remote_wakeup = true;
+    config.max_power = 500;
+    config
+}
+
+/// Size of the configuration descriptor of a device made of `interfaces`
+/// HID interfaces, e.g. the HID backends and the `config_hid` interface,
+/// `out_endpoints` of which have an OUT endpoint like the force feedback
+/// wheel.
+pub const fn hid_config_descriptor_len(interfaces: usize, out_endpoints: usize) -> usize {
+    CONFIGURATION_LEN + interfaces * HID_INTERFACE_LEN + out_endpoints * ENDPOINT_LEN
+}
+
+/// Size of the configuration descriptor of a single HID backend.
+pub const HID_GAMEPAD_CONFIG_DESCRIPTOR_LEN: usize = hid_config_descriptor_len(1, 0);
+
+/// Device configuration of a plain HID game controller, to use with the
+/// HID backends such as `hid_flightstick`.
+///
+/// HID devices bind to the generic driver of every host whatever their
+/// IDs, so the vendor and product ID are the caller's. The class is
+/// defined per interface, so further HID interfaces need no interface
+/// association descriptors.
+pub fn usb_config_hid_gamepad(vendor_id: u16, product_id: u16) -> Config<'static> {
+    let mut config = Config::new(vendor_id, product_id);
+    config.device_class = 0x00;
+    config.device_sub_class = 0x00;
+    config.device_protocol = 0x00;
+    config.device_release = 0x0100;
+    config.max_packet_size_0 = 64;
+    config.product = Some("Gamepad");
+    config.composite_with_iads = false;
+    config.self_powered = false;
+    config.supports_remote_wakeup = true;
+    config.max_power = 100;
+    config
+}
+
+/// Wakes a suspended host on controller input, see the
+/// [module documentation](self).
+///
+/// The host decides whether the device may wake it, e.g. Windows only
+/// allows it when "Allow this device to wake the computer" is checked.
+/// Otherwise input is ignored until the host resumes the bus.
+pub struct RemoteWakeup {
+    input: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl Default for RemoteWakeup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RemoteWakeup {
+    pub const fn new() -> Self {
+        Self {
+            input: Signal::new(),
+        }
+    }
+
+    /// Runs `device` like `UsbDevice::run`, sending a remote wakeup when a
+    /// button is pressed or a stick or trigger moved while the bus is
+    /// suspended.
+    pub async fn run<'d, D: Driver<'d>>(&self, device: &mut UsbDevice<'d, D>) -> ! {
+        loop {
+            device.run_until_suspend().await;
+            // Only input that arrives while suspended wakes the host.
+            self.input.reset();
+            loop {
+                match select(device.wait_resume(), self.input.wait()).await {
+                    Either::First(()) => break,
+                    Either::Second(()) => match device.remote_wakeup().await {
+                        Ok(()) => {
+                            debug!("usb: woke up the host");
+                            break;
+                        }
+                        Err(_) => debug!("usb: remote wakeup not enabled by the host"),
+                    },
+                }
+            }
+        }
+    }
+}
+
+impl ReportSink for RemoteWakeup {
+    fn send(&self, pad: &XboxGamepad) {
+        if *pad != XboxGamepad::new() {
+            self.input.signal(());
+        }
+    }
+}
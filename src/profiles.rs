@@ -0,0 +1,260 @@
+//! Named [`Settings`] profiles that can be switched at runtime.
+//!
+//! Storage is provided by the caller. Profiles are plain data, so they can
+//! be persisted with [`Settings::encode`] next to their name.
+
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::controller::{Button, XboxGamepad};
+use crate::remap::Transform;
+use crate::settings::{Pipeline, Settings};
+
+/// Maximum length of a profile name in bytes.
+pub const NAME_LEN: usize = 16;
+
+/// Settings stored under a name.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Profile {
+    name: [u8; NAME_LEN],
+    name_len: u8,
+    pub settings: Settings,
+}
+
+impl Profile {
+    /// `name` is truncated to [`NAME_LEN`] bytes.
+    pub fn new(name: &str, settings: Settings) -> Self {
+        let mut len = name.len().min(NAME_LEN);
+        while !name.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut profile = Self {
+            name: [0; NAME_LEN],
+            name_len: len as u8,
+            settings,
+        };
+        profile.name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        profile
+    }
+
+    pub fn name(&self) -> &str {
+        // Only ever built from a `&str` cut at a char boundary.
+        core::str::from_utf8(&self.name[..usize::from(self.name_len)]).unwrap_or("")
+    }
+}
+
+struct Inner<'a> {
+    slots: &'a mut [Option<Profile>],
+    active: Option<usize>,
+}
+
+/// Profile slots applied to a [`Pipeline`].
+///
+/// Selecting a profile applies it to all pipeline stages at once and calls
+/// the change hook, e.g. to blink an LED with the profile number.
+pub struct Profiles<'a> {
+    pipeline: Pipeline<'a>,
+    inner: Mutex<CriticalSectionRawMutex, RefCell<Inner<'a>>>,
+    on_change: Option<fn(usize)>,
+}
+
+impl<'a> Profiles<'a> {
+    /// `slots` holds the stored profiles, `None` for empty slots.
+    pub fn new(pipeline: Pipeline<'a>, slots: &'a mut [Option<Profile>]) -> Self {
+        Self {
+            pipeline,
+            inner: Mutex::new(RefCell::new(Inner {
+                slots,
+                active: None,
+            })),
+            on_change: None,
+        }
+    }
+
+    /// Calls `hook` with the slot index after every profile change.
+    pub fn with_change_hook(mut self, hook: fn(usize)) -> Self {
+        self.on_change = Some(hook);
+        self
+    }
+
+    /// Number of slots.
+    pub fn len(&self) -> usize {
+        self.inner.lock(|inner| inner.borrow().slots.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<Profile> {
+        self.inner
+            .lock(|inner| inner.borrow().slots.get(index).copied().flatten())
+    }
+
+    /// Slot of the profile applied last, `None` before the first selection
+    /// or when the active slot was cleared.
+    pub fn active(&self) -> Option<usize> {
+        self.inner.lock(|inner| inner.borrow().active)
+    }
+
+    /// Slot of the first profile called `name`.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.inner.lock(|inner| {
+            inner
+                .borrow()
+                .slots
+                .iter()
+                .position(|slot| slot.is_some_and(|profile| profile.name() == name))
+        })
+    }
+
+    /// Stores `profile` in slot `index`, applying it if the slot is active.
+    ///
+    /// Returns `false` if there is no such slot.
+    pub fn store(&self, index: usize, profile: Profile) -> bool {
+        let stored = self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            *inner.slots.get_mut(index)? = Some(profile);
+            Some(inner.active == Some(index))
+        });
+        match stored {
+            Some(true) => self.pipeline.apply(&profile.settings),
+            Some(false) => {}
+            None => return false,
+        }
+        true
+    }
+
+    /// Stores the current pipeline settings as profile `name` in slot
+    /// `index`, which becomes the active one.
+    pub fn save(&self, index: usize, name: &str) -> bool {
+        let profile = Profile::new(name, self.pipeline.settings());
+        let saved = self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            let Some(slot) = inner.slots.get_mut(index) else {
+                return false;
+            };
+            *slot = Some(profile);
+            inner.active = Some(index);
+            true
+        });
+        if saved {
+            debug!("saved profile {}", index);
+        }
+        saved
+    }
+
+    /// Empties slot `index`.
+    pub fn clear(&self, index: usize) {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            if let Some(slot) = inner.slots.get_mut(index) {
+                *slot = None;
+                if inner.active == Some(index) {
+                    inner.active = None;
+                }
+            }
+        });
+    }
+
+    /// Applies the profile in slot `index`.
+    ///
+    /// Returns `false` if the slot is empty.
+    pub fn select(&self, index: usize) -> bool {
+        // Apply while holding the lock, so concurrent selections can not
+        // leave the pipeline and `active` out of sync.
+        let selected = self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            let Some(Some(profile)) = inner.slots.get(index) else {
+                return false;
+            };
+            self.pipeline.apply(&profile.settings);
+            inner.active = Some(index);
+            true
+        });
+        if selected {
+            info!("selected profile {}", index);
+            if let Some(hook) = self.on_change {
+                hook(index);
+            }
+        }
+        selected
+    }
+
+    /// Selects the next stored profile after the active one, wrapping
+    /// around, or the previous one if `forward` is `false`.
+    ///
+    /// Returns the selected slot.
+    pub fn step(&self, forward: bool) -> Option<usize> {
+        let index = self.inner.lock(|inner| {
+            let inner = inner.borrow();
+            let len = inner.slots.len();
+            if len == 0 {
+                return None;
+            }
+            let start = inner.active.unwrap_or(if forward { len - 1 } else { 0 });
+            (1..=len)
+                .map(|offset| {
+                    if forward {
+                        (start + offset) % len
+                    } else {
+                        (start + len - offset % len) % len
+                    }
+                })
+                .find(|&index| inner.slots[index].is_some())
+        })?;
+        self.select(index).then_some(index)
+    }
+}
+
+/// Switches profiles with a button chord: while `modifier` is held,
+/// pressing `next` or `previous` steps through the stored profiles.
+///
+/// Both step buttons are reported released while `modifier` is held, so
+/// switching does not leak into the game.
+pub struct ProfileChord<'p, 'a> {
+    profiles: &'p Profiles<'a>,
+    pub modifier: Button,
+    pub next: Button,
+    pub previous: Button,
+    // (next, previous) pressed in the last state
+    held: Cell<(bool, bool)>,
+}
+
+impl<'p, 'a> ProfileChord<'p, 'a> {
+    pub fn new(
+        profiles: &'p Profiles<'a>,
+        modifier: Button,
+        next: Button,
+        previous: Button,
+    ) -> Self {
+        Self {
+            profiles,
+            modifier,
+            next,
+            previous,
+            held: Cell::new((false, false)),
+        }
+    }
+}
+
+impl Transform for ProfileChord<'_, '_> {
+    fn transform(&self, mut pad: XboxGamepad) -> XboxGamepad {
+        let (next, previous) = (pad.button(self.next), pad.button(self.previous));
+        let (was_next, was_previous) = self.held.replace((next, previous));
+        if !pad.button(self.modifier) {
+            return pad;
+        }
+        if next && !was_next {
+            self.profiles.step(true);
+        } else if previous && !was_previous {
+            self.profiles.step(false);
+        }
+        pad.set_button(self.next, false);
+        pad.set_button(self.previous, false);
+        pad
+    }
+}
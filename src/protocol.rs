@@ -0,0 +1,529 @@
+//! Wire format of the xbox 360 wireless receiver protocol.
+//!
+//! Everything in here is plain data encoding/decoding without any embassy
+//! dependencies, so it can be compiled and exercised on the host.
+
+/// Length of the reports sent to the host on the IN endpoint.
+pub const IN_REPORT_LEN: usize = 29;
+/// Length of the commands sent by the host on the OUT endpoint.
+pub const OUT_REPORT_LEN: usize = 12;
+
+/// Binary encoding of xbox 360 controller input (buttons/axis) state
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ControllerData(pub [u8; 12]);
+
+impl ControllerData {
+    /// Whether the guide button is pressed.
+    pub fn guide(&self) -> bool {
+        self.0[1] & 0x04 != 0
+    }
+}
+
+/// Commands received from the host on the OUT endpoint.
+///
+/// Besides the wireless receiver commands the LED and rumble commands of
+/// the wired controller are understood, which some third-party tools send
+/// to every xinput device.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OutData<'d> {
+    ConnectionStatus,
+    Ack,
+    Led(u8),
+    Rumble(u8, u8),
+    /// Turns the controller off, like holding the guide button.
+    PowerOff,
+    Unknown(&'d [u8]),
+}
+
+impl<'d> OutData<'d> {
+    pub fn from_raw(out_data: &'d [u8]) -> Self {
+        match out_data {
+            // Wired controller commands, with their own lengths.
+            &[0x01, 0x03, led] => OutData::Led(led & 0x0F),
+            &[0x00, 0x08, 0x00, strong, weak, 0x00, 0x00, 0x00] => OutData::Rumble(strong, weak),
+            data if data.len() != OUT_REPORT_LEN => OutData::Unknown(data),
+            &[0x08, 0x00, 0x0F, 0xC0, ..] => OutData::ConnectionStatus,
+            &[0x00, 0x00, 0x00, 0x40, ..] => OutData::Ack,
+            &[0x00, 0x00, 0x08, 0xC0, ..] => OutData::PowerOff,
+            &[0x00, 0x00, 0x08, led, ..] if led & 0x40 == 0x40 => OutData::Led(led & 0x0F),
+            &[0x00, 0x01, 0x0F, 0xC0, 0x00, strong, weak, ..] => OutData::Rumble(strong, weak),
+            data => OutData::Unknown(data),
+        }
+    }
+}
+
+/// Player number (0 to 3) shown by an LED pattern from [`OutData::Led`],
+/// `None` for patterns not tied to a player (off, blinking, rotating).
+pub fn player_index(led: u8) -> Option<u8> {
+    match led {
+        0x02..=0x05 => Some(led - 0x02),
+        0x06..=0x09 => Some(led - 0x06),
+        _ => None,
+    }
+}
+
+/// Report announcing that a controller was connected or disconnected.
+pub fn connection_status_report(connected: bool) -> [u8; 2] {
+    if connected {
+        [0x08, 0x80]
+    } else {
+        [0x08, 0x08]
+    }
+}
+
+/// Report carrying controller input data.
+pub fn input_report(data: &ControllerData) -> [u8; IN_REPORT_LEN] {
+    let mut report = [0_u8; IN_REPORT_LEN];
+    report[0] = 0x00; // Outer message type?
+    report[1] = 0x01; // Message contains xinput data
+    report[3] = 0xF0; // Unused
+    report[4] = 0x00; // Inner message type
+    report[5] = 0x13; // Inner message length
+    report[6..18].copy_from_slice(&data.0);
+    report
+}
+
+/// Two pre-formatted input reports used alternately, so only the controller
+/// data is written per report.
+///
+/// The report handed out last stays untouched until the next one is
+/// returned, so it may still be read by a DMA transfer while the following
+/// report is prepared.
+pub struct InputReports {
+    reports: [[u8; IN_REPORT_LEN]; 2],
+    next: usize,
+}
+
+impl Default for InputReports {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputReports {
+    pub fn new() -> Self {
+        let empty = input_report(&ControllerData([0; 12]));
+        Self {
+            reports: [empty; 2],
+            next: 0,
+        }
+    }
+
+    /// Same as [`input_report`] without rebuilding the header.
+    pub fn fill(&mut self, data: &ControllerData) -> &[u8; IN_REPORT_LEN] {
+        let report = &mut self.reports[self.next];
+        self.next ^= 1;
+        report[6..18].copy_from_slice(&data.0);
+        report
+    }
+}
+
+/// Report sent when there was no change in input data for a while.
+pub fn idle_report() -> [u8; IN_REPORT_LEN] {
+    let mut report = [0_u8; IN_REPORT_LEN];
+    report[3] = 0xF0;
+    report
+}
+
+/// Power source of the emulated controller, see [`Capabilities`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BatteryType {
+    /// Powered by the host, e.g. an adapter for a wired controller. Reports
+    /// a full level without the battery bit.
+    Wired,
+    /// Runs on batteries, like a genuine wireless controller.
+    Battery,
+}
+
+/// Charge of a battery powered controller, as reported in the controller
+/// info report and shown by the host's XInput battery API.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BatteryLevel {
+    Empty,
+    Low,
+    Medium,
+    Full,
+}
+
+impl BatteryLevel {
+    /// Level of a battery charged `percent` percent, in the steps Windows
+    /// shows for a genuine controller.
+    pub fn from_percent(percent: u8) -> Self {
+        match percent {
+            0..=5 => BatteryLevel::Empty,
+            6..=30 => BatteryLevel::Low,
+            31..=70 => BatteryLevel::Medium,
+            _ => BatteryLevel::Full,
+        }
+    }
+
+    /// Value of the low bits of the battery byte.
+    pub const fn bits(self) -> u8 {
+        self as u8
+    }
+
+    pub const fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            0 => BatteryLevel::Empty,
+            1 => BatteryLevel::Low,
+            2 => BatteryLevel::Medium,
+            _ => BatteryLevel::Full,
+        }
+    }
+}
+
+/// Features of the emulated controller reported to the host in the
+/// controller info report and the capabilities control requests.
+///
+/// Host software such as Steam Input picks glyphs and features from these,
+/// so an adapter for a pad without rumble or guide button should say so.
+/// The defaults reproduce the reports of a genuine wireless controller.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities {
+    pub rumble: bool,
+    pub guide: bool,
+    pub wireless: bool,
+    pub battery: BatteryType,
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::WIRELESS_CONTROLLER
+    }
+}
+
+impl Capabilities {
+    /// Genuine wireless controller.
+    pub const WIRELESS_CONTROLLER: Capabilities = Capabilities {
+        rumble: true,
+        guide: true,
+        wireless: true,
+        battery: BatteryType::Battery,
+    };
+
+    /// Wired controller behind the receiver, e.g. in an adapter.
+    pub const WIRED_CONTROLLER: Capabilities = Capabilities {
+        rumble: true,
+        guide: true,
+        wireless: false,
+        battery: BatteryType::Wired,
+    };
+
+    /// Report sent in reply to the controller info request, with a full
+    /// battery.
+    pub const fn controller_info(&self) -> [u8; IN_REPORT_LEN] {
+        self.controller_info_with_battery(BatteryLevel::Full)
+    }
+
+    /// Like [`Capabilities::controller_info`], reporting `level` if the
+    /// controller runs on batteries.
+    #[rustfmt::skip]
+    pub const fn controller_info_with_battery(&self, level: BatteryLevel) -> [u8; IN_REPORT_LEN] {
+        // Bit 0 and 1 match the XINPUT_CAPS_FFB_SUPPORTED and
+        // XINPUT_CAPS_WIRELESS flags, bit 4 is always set. Genuine pads send
+        // 0x13, which windows needs to detect the pad.
+        let mut flags = 0x10;
+        if self.rumble {
+            flags |= 0x01;
+        }
+        if self.wireless {
+            flags |= 0x02;
+        }
+        // Bit 7 is set on batteries, the low bits hold the level. A wired
+        // pad always reports full.
+        let battery = match self.battery {
+            BatteryType::Wired => 0x23,
+            BatteryType::Battery => 0xA0 | level.bits(),
+        };
+        [
+            0x00, 0x0F, 0x00, 0xF0, // Controller info message
+            0xF0, // Ignored
+            0xCC, // Important for windows to detect the pad
+            0xFF, 0xFF, 0xFF, 0xFF, // Wireless adapter serial number
+            0x58, 0x91, 0xb3, 0xf0, 0x00, 0x09, // Controller serial number?
+            flags, battery,
+            // The windows driver does not care about the remaining bytes.
+            0x20, 0x1D, 0x30, 0x03, 0x40, 0x01, 0x50, 0x01, 0xFF, 0xFF, 0xFF,
+        ]
+    }
+
+    /// Reply to the input capabilities request: the buttons, triggers and
+    /// stick bits in use.
+    #[rustfmt::skip]
+    pub const fn input_capabilities(&self) -> [u8; 20] {
+        // Bit 11 is not assigned, bit 10 is the guide button.
+        let buttons_high = if self.guide { 0xF7 } else { 0xF3 };
+        [
+            0x00, 0x14, // Message type, length
+            0xFF, buttons_high, // Buttons
+            0xFF, 0xFF, // Triggers
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // Sticks
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]
+    }
+
+    /// Reply to the vibration capabilities request: the resolution of the
+    /// two motors.
+    pub const fn vibration_capabilities(&self) -> [u8; 8] {
+        let motor = if self.rumble { 0xFF } else { 0x00 };
+        [0x00, 0x08, 0x00, motor, motor, 0x00, 0x00, 0x00]
+    }
+}
+
+/// Controller info of a genuine wireless controller, see
+/// [`Capabilities::controller_info`].
+///
+/// This message is required for windows to detect the controller.
+/// Interestingly Steam detects the controller without that message.
+pub const CONTROLLER_INFO: [u8; IN_REPORT_LEN] =
+    Capabilities::WIRELESS_CONTROLLER.controller_info();
+
+/// Progress of the handshake that follows a controller connection.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Handshake {
+    Disconnected,
+    None,
+    Unknown1,
+    Unknown2,
+}
+
+/// What to do in response to an ACK from the host.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AckResponse {
+    /// The host acknowledged something we never sent.
+    Unexpected,
+    /// Send the controller info report.
+    ControllerInfo,
+    /// Nothing to send, the handshake is complete.
+    Done,
+}
+
+impl Handshake {
+    pub fn is_connected(&self) -> bool {
+        !matches!(self, Handshake::Disconnected)
+    }
+
+    /// Advances the handshake on an ACK from the host.
+    pub fn ack(&mut self) -> AckResponse {
+        match self {
+            Handshake::Disconnected | Handshake::None => AckResponse::Unexpected,
+            Handshake::Unknown1 => {
+                *self = Handshake::Unknown2;
+                AckResponse::ControllerInfo
+            }
+            Handshake::Unknown2 => {
+                *self = Handshake::None;
+                // The original adapter sends 4 additional messages:
+                // let mut unknown2a = [0_u8; 29];
+                // unknown2a[3] = 0x13;
+                // unknown2a[4] = 0xA2;
+                // let mut unknown2b = [0_u8; 29];
+                // unknown2b[3] = 0xF0;
+                // Timer::after(Duration::from_millis(8)).await;
+                // for buf in [&unknown2a, &unknown2b, &unknown2a, &unknown2b] {
+                //     Timer::after(Duration::from_millis(8)).await;
+                //     debug!("{=u8}-> {=[u8]:#X}...", self.ep_in_addr(), buf[..6]);
+                //     unwrap!(self.ep_in.write(buf).await);
+                // }
+                AckResponse::Done
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn out(header: [u8; 4], rest: [u8; 8]) -> [u8; OUT_REPORT_LEN] {
+        let mut report = [0_u8; OUT_REPORT_LEN];
+        report[..4].copy_from_slice(&header);
+        report[4..].copy_from_slice(&rest);
+        report
+    }
+
+    #[test]
+    fn connection_status_command() {
+        let raw = out([0x08, 0x00, 0x0F, 0xC0], [0; 8]);
+        assert_eq!(OutData::from_raw(&raw), OutData::ConnectionStatus);
+    }
+
+    #[test]
+    fn ack_command() {
+        let raw = out([0x00, 0x00, 0x00, 0x40], [0; 8]);
+        assert_eq!(OutData::from_raw(&raw), OutData::Ack);
+    }
+
+    #[test]
+    fn wireless_led_command() {
+        for led in 0..=0x0F {
+            let raw = out([0x00, 0x00, 0x08, 0x40 | led], [0; 8]);
+            assert_eq!(OutData::from_raw(&raw), OutData::Led(led));
+        }
+    }
+
+    #[test]
+    fn wired_led_command() {
+        assert_eq!(OutData::from_raw(&[0x01, 0x03, 0x06]), OutData::Led(0x06));
+        assert_eq!(OutData::from_raw(&[0x01, 0x03, 0xF2]), OutData::Led(0x02));
+    }
+
+    #[test]
+    fn wireless_rumble_command() {
+        let raw = out([0x00, 0x01, 0x0F, 0xC0], [0x00, 0x12, 0x34, 0, 0, 0, 0, 0]);
+        assert_eq!(OutData::from_raw(&raw), OutData::Rumble(0x12, 0x34));
+    }
+
+    #[test]
+    fn wired_rumble_command() {
+        let raw = [0x00, 0x08, 0x00, 0xAB, 0xCD, 0x00, 0x00, 0x00];
+        assert_eq!(OutData::from_raw(&raw), OutData::Rumble(0xAB, 0xCD));
+    }
+
+    #[test]
+    fn power_off_command() {
+        let raw = out([0x00, 0x00, 0x08, 0xC0], [0; 8]);
+        assert_eq!(OutData::from_raw(&raw), OutData::PowerOff);
+    }
+
+    #[test]
+    fn unknown_commands() {
+        // Right header, wrong length.
+        let short = [0x00, 0x00, 0x00, 0x40];
+        assert_eq!(OutData::from_raw(&short), OutData::Unknown(&short));
+        assert_eq!(OutData::from_raw(&[]), OutData::Unknown(&[]));
+        // LED command without the 0x40 flag.
+        let raw = out([0x00, 0x00, 0x08, 0x06], [0; 8]);
+        assert_eq!(OutData::from_raw(&raw), OutData::Unknown(&raw));
+        let raw = out([0x12, 0x34, 0x56, 0x78], [0xFF; 8]);
+        assert_eq!(OutData::from_raw(&raw), OutData::Unknown(&raw));
+    }
+
+    #[test]
+    fn player_indices() {
+        assert_eq!(player_index(0x00), None);
+        assert_eq!(player_index(0x01), None);
+        assert_eq!(player_index(0x02), Some(0));
+        assert_eq!(player_index(0x05), Some(3));
+        assert_eq!(player_index(0x06), Some(0));
+        assert_eq!(player_index(0x09), Some(3));
+        assert_eq!(player_index(0x0A), None);
+    }
+
+    #[test]
+    fn connection_status_reports() {
+        assert_eq!(connection_status_report(true), [0x08, 0x80]);
+        assert_eq!(connection_status_report(false), [0x08, 0x08]);
+    }
+
+    #[rustfmt::skip]
+    const INPUT: [u8; IN_REPORT_LEN] = [
+        0x00, 0x01, 0x00, 0xF0, 0x00, 0x13,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn input_report_bytes() {
+        let data = ControllerData([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        assert_eq!(input_report(&data), INPUT);
+    }
+
+    #[test]
+    fn input_reports_alternate() {
+        let mut reports = InputReports::new();
+        let data = ControllerData([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        let first = reports.fill(&data) as *const _;
+        assert_eq!(*reports.fill(&data), INPUT);
+        let third = reports.fill(&ControllerData([0; 12])) as *const _;
+        assert_eq!(first, third);
+        assert_eq!(reports.fill(&data), &INPUT);
+    }
+
+    #[test]
+    fn idle_report_bytes() {
+        let mut expected = [0_u8; IN_REPORT_LEN];
+        expected[3] = 0xF0;
+        assert_eq!(idle_report(), expected);
+    }
+
+    #[test]
+    fn controller_info_bytes() {
+        // The capture in notes/wireless_receiver_usb_notes.txt, with the
+        // adapter serial number replaced by FF.
+        #[rustfmt::skip]
+        let expected = [
+            0x00, 0x0F, 0x00, 0xF0, 0xF0, 0xCC, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x58, 0x91, 0xB3, 0xF0, 0x00, 0x09, 0x13, 0xA3, 0x20, 0x1D,
+            0x30, 0x03, 0x40, 0x01, 0x50, 0x01, 0xFF, 0xFF, 0xFF,
+        ];
+        assert_eq!(CONTROLLER_INFO, expected);
+        assert_eq!(Capabilities::default().controller_info(), expected);
+    }
+
+    #[test]
+    fn controller_info_reports_capabilities_and_battery() {
+        assert_eq!(CONTROLLER_INFO[16..18], [0x13, 0xA3]);
+        let pad = Capabilities::WIRELESS_CONTROLLER;
+        for level in [BatteryLevel::Empty, BatteryLevel::Low, BatteryLevel::Medium] {
+            let info = pad.controller_info_with_battery(level);
+            assert_eq!(info[17], 0xA0 | level.bits());
+            assert_eq!(info[..17], CONTROLLER_INFO[..17]);
+            assert_eq!(info[18..], CONTROLLER_INFO[18..]);
+        }
+
+        // A wired pad is told apart from a full battery and ignores the level.
+        let wired = Capabilities::WIRED_CONTROLLER;
+        let info = wired.controller_info_with_battery(BatteryLevel::Low);
+        assert_eq!(info[16..18], [0x11, 0x23]);
+        assert_eq!(info[..16], CONTROLLER_INFO[..16]);
+        assert_eq!(info[18..], CONTROLLER_INFO[18..]);
+        let basic = Capabilities {
+            rumble: false,
+            ..wired
+        };
+        assert_eq!(basic.controller_info()[16], 0x10);
+    }
+
+    #[test]
+    fn capabilities_replies() {
+        let pad = Capabilities::WIRELESS_CONTROLLER;
+        assert_eq!(pad.input_capabilities()[3], 0xF7);
+        assert_eq!(pad.vibration_capabilities(), [0, 8, 0, 0xFF, 0xFF, 0, 0, 0]);
+        let basic = Capabilities {
+            rumble: false,
+            guide: false,
+            ..pad
+        };
+        assert_eq!(basic.input_capabilities()[3], 0xF3);
+        assert_eq!(basic.vibration_capabilities(), [0, 8, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn handshake_sends_controller_info_once() {
+        // The host sends two ACKs after the connection status.
+        let mut handshake = Handshake::Unknown1;
+        assert!(handshake.is_connected());
+        assert_eq!(handshake.ack(), AckResponse::ControllerInfo);
+        assert_eq!(handshake, Handshake::Unknown2);
+        assert_eq!(handshake.ack(), AckResponse::Done);
+        assert_eq!(handshake, Handshake::None);
+        assert_eq!(handshake.ack(), AckResponse::Unexpected);
+        assert_eq!(handshake, Handshake::None);
+    }
+
+    #[test]
+    fn handshake_ignores_ack_when_disconnected() {
+        let mut handshake = Handshake::Disconnected;
+        assert!(!handshake.is_connected());
+        assert_eq!(handshake.ack(), AckResponse::Unexpected);
+        assert_eq!(handshake, Handshake::Disconnected);
+    }
+}
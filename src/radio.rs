@@ -0,0 +1,207 @@
+//! Over the air frames for a proprietary 2.4 GHz link between a battery
+//! powered handheld and a USB receiver, both built with this crate.
+//!
+//! The handheld runs a [`tx::Transmitter`], the receiver an
+//! [`rx::Receiver`] feeding an [`xinput::State`](crate::xinput::State).
+//! Frames are small enough for a single nRF24L01+ or nRF Enhanced
+//! ShockBurst (ESB) payload; the application implements [`Radio`] on top of
+//! its radio driver. The receiver answers every input frame with a
+//! feedback frame in the acknowledgement payload, so rumble and the player
+//! LED reach the handheld without a second transmission.
+//!
+//! Input frame:
+//!
+//! | Byte   | Content                                   |
+//! |--------|-------------------------------------------|
+//! | 0      | [`FRAME_INPUT`]                           |
+//! | 1      | Sequence number, wrapping                 |
+//! | 2      | Battery level in percent                  |
+//! | 3..15  | [`ControllerData`]                        |
+//! | 15..17 | CRC-16/CCITT-FALSE of bytes 0..15, LE     |
+//!
+//! Feedback frame: [`FRAME_FEEDBACK`], strong and weak rumble, LED
+//! pattern, CRC.
+
+use crate::protocol::ControllerData;
+
+pub mod rx;
+pub mod tx;
+
+/// Frame type of input frames.
+pub const FRAME_INPUT: u8 = 0x01;
+/// Frame type of feedback frames.
+pub const FRAME_FEEDBACK: u8 = 0x02;
+/// Length of an input frame.
+pub const INPUT_FRAME_LEN: usize = 17;
+/// Length of a feedback frame.
+pub const FEEDBACK_FRAME_LEN: usize = 6;
+/// Largest frame, for receive buffers.
+pub const MAX_FRAME_LEN: usize = INPUT_FRAME_LEN;
+
+/// CRC-16/CCITT-FALSE.
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xFFFF, |crc, byte| {
+        (0..8).fold(crc ^ u16::from(*byte) << 8, |crc, _| {
+            if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn seal<const LEN: usize>(mut frame: [u8; LEN]) -> [u8; LEN] {
+    let crc = crc16(&frame[..LEN - 2]);
+    [frame[LEN - 2], frame[LEN - 1]] = crc.to_le_bytes();
+    frame
+}
+
+fn check<const LEN: usize>(frame: &[u8], frame_type: u8) -> Result<[u8; LEN], FrameError> {
+    let frame = <[u8; LEN]>::try_from(frame).map_err(|_| FrameError::Length)?;
+    if frame[0] != frame_type {
+        return Err(FrameError::Type);
+    }
+    if crc16(&frame[..LEN - 2]).to_le_bytes() != frame[LEN - 2..] {
+        return Err(FrameError::Crc);
+    }
+    Ok(frame)
+}
+
+/// Errors decoding a frame.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    Length,
+    Type,
+    Crc,
+}
+
+/// Controller state sent by the handheld.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InputFrame {
+    pub sequence: u8,
+    pub battery: u8,
+    pub data: ControllerData,
+}
+
+impl InputFrame {
+    pub fn encode(&self) -> [u8; INPUT_FRAME_LEN] {
+        let mut frame = [0_u8; INPUT_FRAME_LEN];
+        frame[0] = FRAME_INPUT;
+        frame[1] = self.sequence;
+        frame[2] = self.battery;
+        frame[3..15].copy_from_slice(&self.data.0);
+        seal(frame)
+    }
+
+    pub fn decode(frame: &[u8]) -> Result<Self, FrameError> {
+        let frame = check::<INPUT_FRAME_LEN>(frame, FRAME_INPUT)?;
+        let mut data = [0_u8; 12];
+        data.copy_from_slice(&frame[3..15]);
+        Ok(Self {
+            sequence: frame[1],
+            battery: frame[2],
+            data: ControllerData(data),
+        })
+    }
+}
+
+/// Host output sent back to the handheld.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Feedback {
+    /// Strong (left) and weak (right) rumble, see
+    /// [`State::rumble`](crate::xinput::State::rumble).
+    pub rumble: (u8, u8),
+    /// LED pattern, see [`State::led`](crate::xinput::State::led).
+    pub led: u8,
+}
+
+impl Feedback {
+    pub fn encode(&self) -> [u8; FEEDBACK_FRAME_LEN] {
+        let (strong, weak) = self.rumble;
+        seal([FRAME_FEEDBACK, strong, weak, self.led, 0, 0])
+    }
+
+    pub fn decode(frame: &[u8]) -> Result<Self, FrameError> {
+        let frame = check::<FEEDBACK_FRAME_LEN>(frame, FRAME_FEEDBACK)?;
+        Ok(Self {
+            rumble: (frame[1], frame[2]),
+            led: frame[3],
+        })
+    }
+}
+
+/// Packet radio with acknowledgement payloads, such as an nRF24L01+ or the
+/// ESB protocol of the nRF52 radio, configured with the same address and
+/// channel on both ends.
+#[allow(async_fn_in_trait)]
+pub trait Radio {
+    type Error;
+
+    /// Transmits `frame` with automatic retransmission, returning the
+    /// length of the acknowledgement payload written to `ack`, 0 if there
+    /// was none. Fails when the frame was not acknowledged.
+    async fn transmit(&mut self, frame: &[u8], ack: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Waits for the next frame and writes it to `buf`, returning its
+    /// length. `ack` is the payload to acknowledge the frame with.
+    async fn receive(&mut self, buf: &mut [u8], ack: &[u8]) -> Result<usize, Self::Error>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let input = InputFrame {
+            sequence: 0xFE,
+            battery: 42,
+            data: ControllerData([0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]),
+        };
+        let frame = input.encode();
+        assert_eq!(frame[..3], [FRAME_INPUT, 0xFE, 42]);
+        assert_eq!(frame[15..], crc16(&frame[..15]).to_le_bytes());
+        assert_eq!(InputFrame::decode(&frame), Ok(input));
+
+        let feedback = Feedback {
+            rumble: (0xFF, 0x10),
+            led: 0x06,
+        };
+        let frame = feedback.encode();
+        assert_eq!(frame[..4], [FRAME_FEEDBACK, 0xFF, 0x10, 0x06]);
+        assert_eq!(Feedback::decode(&frame), Ok(feedback));
+    }
+
+    #[test]
+    fn damaged_frames_are_rejected() {
+        let frame = InputFrame {
+            sequence: 1,
+            battery: 100,
+            data: ControllerData([0x55; 12]),
+        }
+        .encode();
+        for byte in 1..INPUT_FRAME_LEN {
+            let mut damaged = frame;
+            damaged[byte] ^= 0x01;
+            assert_eq!(InputFrame::decode(&damaged), Err(FrameError::Crc));
+        }
+        assert_eq!(
+            InputFrame::decode(&frame[..INPUT_FRAME_LEN - 1]),
+            Err(FrameError::Length)
+        );
+        assert_eq!(Feedback::decode(&frame), Err(FrameError::Length));
+        let mut wrong_type = frame;
+        wrong_type[0] = FRAME_FEEDBACK;
+        assert_eq!(InputFrame::decode(&wrong_type), Err(FrameError::Type));
+    }
+}
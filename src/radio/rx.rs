@@ -0,0 +1,154 @@
+//! Receiver side of the radio link.
+
+use super::{Feedback, InputFrame, Radio, MAX_FRAME_LEN};
+use crate::protocol::BatteryLevel;
+use crate::xinput::State;
+
+/// Frames expected per link quality update.
+const QUALITY_WINDOW: u16 = 64;
+
+/// Publishes the frames of a [`tx::Transmitter`](super::tx::Transmitter)
+/// to a [`State`], with the handheld's
+/// [battery level](State::set_battery_level) and the share of frames
+/// received as its [link quality](State::set_link_quality).
+///
+/// Combine it with
+/// [`XInputConfig::input_timeout`](crate::xinput::XInputConfig::input_timeout)
+/// longer than [`KEEPALIVE_PERIOD`](super::tx::KEEPALIVE_PERIOD), so the
+/// pad reverts to neutral when the handheld goes out of range.
+pub struct Receiver<R> {
+    radio: R,
+    last_sequence: Option<u8>,
+    // Frames sent and received since the last link quality update.
+    expected: u16,
+    received: u16,
+    battery: Option<fn(u8)>,
+}
+
+impl<R: Radio> Receiver<R> {
+    pub fn new(radio: R) -> Self {
+        Self {
+            radio,
+            last_sequence: None,
+            expected: 0,
+            received: 0,
+            battery: None,
+        }
+    }
+
+    /// Calls `hook` with the battery level in percent of every frame, e.g.
+    /// to update a [`BatteryStatus`](crate::input::battery_adc::BatteryStatus)
+    /// for the LED and display hooks.
+    pub fn with_battery_hook(mut self, hook: fn(u8)) -> Self {
+        self.battery = Some(hook);
+        self
+    }
+
+    fn handle<const N: usize>(&mut self, frame: InputFrame, state: &State<N>) {
+        // Retransmitted after a lost acknowledgement.
+        if self.last_sequence == Some(frame.sequence) {
+            return;
+        }
+        let lost = self
+            .last_sequence
+            .map_or(0, |last| frame.sequence.wrapping_sub(last).wrapping_sub(1));
+        self.last_sequence = Some(frame.sequence);
+        if lost > 0 {
+            debug!("radio rx: lost {} frames", lost);
+        }
+
+        self.expected += u16::from(lost) + 1;
+        self.received += 1;
+        if self.expected >= QUALITY_WINDOW {
+            let quality = u32::from(self.received) * 255 / u32::from(self.expected);
+            state.set_link_quality(quality as u8);
+            self.expected = 0;
+            self.received = 0;
+        }
+
+        state.send_xinput(frame.data);
+        state.set_battery_level(BatteryLevel::from_percent(frame.battery));
+        if let Some(hook) = self.battery {
+            hook(frame.battery);
+        }
+    }
+
+    /// Receives frames forever, acknowledging them with the rumble and LED
+    /// state of `state`.
+    pub async fn run<const N: usize>(mut self, state: &State<N>) -> ! {
+        let mut buf = [0_u8; MAX_FRAME_LEN];
+        loop {
+            let feedback = Feedback {
+                rumble: state.rumble(),
+                led: state.led(),
+            };
+            let Ok(n) = self.radio.receive(&mut buf, &feedback.encode()).await else {
+                warn!("radio rx: receive failed");
+                continue;
+            };
+            let Some(frame) = buf.get(..n) else {
+                warn!("radio rx: receive overran the buffer");
+                continue;
+            };
+            match InputFrame::decode(frame) {
+                Ok(frame) => self.handle(frame, state),
+                Err(error) => debug!("radio rx: bad frame {:?}", error),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::future::pending;
+
+    use super::*;
+    use crate::protocol::ControllerData;
+
+    struct NoRadio;
+
+    impl Radio for NoRadio {
+        type Error = ();
+
+        async fn transmit(&mut self, _frame: &[u8], _ack: &mut [u8]) -> Result<usize, ()> {
+            pending().await
+        }
+
+        async fn receive(&mut self, _buf: &mut [u8], _ack: &[u8]) -> Result<usize, ()> {
+            pending().await
+        }
+    }
+
+    fn frame(sequence: u8, battery: u8) -> InputFrame {
+        InputFrame {
+            sequence,
+            battery,
+            data: ControllerData([0; 12]),
+        }
+    }
+
+    #[test]
+    fn battery_level_reaches_the_state() {
+        let state = State::<1>::new();
+        let mut receiver = Receiver::new(NoRadio);
+        receiver.handle(frame(0, 20), &state);
+        assert_eq!(state.battery_level(), BatteryLevel::Low);
+        receiver.handle(frame(1, 90), &state);
+        assert_eq!(state.battery_level(), BatteryLevel::Full);
+    }
+
+    #[test]
+    fn link_quality_counts_lost_frames() {
+        let state = State::<1>::new();
+        let mut receiver = Receiver::new(NoRadio);
+        // Every other frame lost, retransmissions do not count.
+        for sequence in (0..=u8::MAX).step_by(2).take(32) {
+            receiver.handle(frame(sequence, 100), &state);
+            receiver.handle(frame(sequence, 100), &state);
+        }
+        assert_eq!(state.link_quality(), None);
+        receiver.handle(frame(64, 100), &state);
+        // 33 of 65 frames received.
+        assert_eq!(state.link_quality(), Some(129));
+    }
+}
@@ -0,0 +1,82 @@
+//! Handheld side of the radio link.
+
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Timer};
+
+use super::{Feedback, InputFrame, Radio, FEEDBACK_FRAME_LEN};
+use crate::controller::XboxGamepad;
+use crate::input::InputSource;
+
+/// Longest time without a frame; the state is resent after this so the
+/// receiver can tell a quiet pad from a lost link.
+pub const KEEPALIVE_PERIOD: Duration = Duration::from_millis(50);
+
+/// Sends the states of an input source to an [`rx::Receiver`](super::rx::Receiver).
+pub struct Transmitter<R> {
+    radio: R,
+    sequence: u8,
+    battery: Option<fn() -> u8>,
+    feedback: Option<fn(Feedback)>,
+}
+
+impl<R: Radio> Transmitter<R> {
+    pub fn new(radio: R) -> Self {
+        Self {
+            radio,
+            sequence: 0,
+            battery: None,
+            feedback: None,
+        }
+    }
+
+    /// Sends the battery level in percent returned by `hook`, 100 without.
+    pub fn with_battery_hook(mut self, hook: fn() -> u8) -> Self {
+        self.battery = Some(hook);
+        self
+    }
+
+    /// Calls `hook` with the rumble and LED state of every acknowledgement.
+    pub fn with_feedback_hook(mut self, hook: fn(Feedback)) -> Self {
+        self.feedback = Some(hook);
+        self
+    }
+
+    /// Sends one state.
+    pub async fn send(&mut self, pad: &XboxGamepad) -> Result<(), R::Error> {
+        let frame = InputFrame {
+            sequence: self.sequence,
+            battery: self.battery.map_or(100, |hook| hook()),
+            data: (*pad).into(),
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+
+        let mut ack = [0_u8; FEEDBACK_FRAME_LEN];
+        let n = self.radio.transmit(&frame.encode(), &mut ack).await?;
+        if n > 0 {
+            match Feedback::decode(&ack[..n]) {
+                Ok(feedback) => {
+                    if let Some(hook) = self.feedback {
+                        hook(feedback);
+                    }
+                }
+                Err(error) => debug!("radio tx: bad feedback {:?}", error),
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends every state of `source`, and the last one again after
+    /// [`KEEPALIVE_PERIOD`] without changes.
+    pub async fn run(mut self, source: &mut impl InputSource) -> ! {
+        let mut pad = XboxGamepad::new();
+        loop {
+            if let Either::First(next) = select(source.next(), Timer::after(KEEPALIVE_PERIOD)).await
+            {
+                pad = next;
+            }
+            if self.send(&pad).await.is_err() {
+                trace!("radio tx: not acknowledged");
+            }
+        }
+    }
+}
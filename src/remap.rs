@@ -0,0 +1,261 @@
+//! Input remapping applied to [`XboxGamepad`] state before it is converted
+//! to [`ControllerData`](crate::protocol::ControllerData).
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::controller::{Button, Trigger, XboxGamepad};
+
+/// Left stick deflection at which it is reported as a dpad direction.
+const STICK_DPAD_THRESHOLD: i16 = i16::MAX / 2;
+
+/// A stage in the input pipeline that rewrites gamepad state.
+///
+/// Stages compose as tuples: `(a, b)` applies `a` first, then `b`.
+pub trait Transform {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad;
+}
+
+/// Identity transform.
+impl Transform for () {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        pad
+    }
+}
+
+impl<T: Transform + ?Sized> Transform for &T {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        (**self).transform(pad)
+    }
+}
+
+impl<A: Transform, B: Transform> Transform for (A, B) {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        self.1.transform(self.0.transform(pad))
+    }
+}
+
+impl<A: Transform, B: Transform, C: Transform> Transform for (A, B, C) {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        self.2.transform(self.1.transform(self.0.transform(pad)))
+    }
+}
+
+/// Logical button pressed by pulling an analog trigger, see
+/// [`ButtonMap::map_trigger_to_button`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TriggerButton {
+    pub button: Button,
+    /// Trigger value from which the button is pressed.
+    pub threshold: u8,
+}
+
+/// Table mapping physical buttons to the logical buttons reported to the host.
+///
+/// Triggers can also act as buttons and buttons as triggers, for pads
+/// without analog triggers or games that expect digital bumpers.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ButtonMap {
+    // physical source of each logical button, indexed by `Button as usize`
+    sources: [Button; Button::ALL.len()],
+    // logical button driven by each physical trigger, indexed by `Trigger as usize`
+    trigger_buttons: [Option<TriggerButton>; Trigger::ALL.len()],
+    // physical button driving each logical trigger, indexed by `Trigger as usize`
+    button_triggers: [Option<Button>; Trigger::ALL.len()],
+    /// Exchange the left and right stick.
+    pub swap_sticks: bool,
+    /// Report the dpad as left stick and the left stick as dpad.
+    pub swap_dpad_and_left_stick: bool,
+}
+
+impl Default for ButtonMap {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl ButtonMap {
+    /// Map that reports every input unchanged.
+    pub const fn identity() -> Self {
+        Self {
+            sources: Button::ALL,
+            trigger_buttons: [None; Trigger::ALL.len()],
+            button_triggers: [None; Trigger::ALL.len()],
+            swap_sticks: false,
+            swap_dpad_and_left_stick: false,
+        }
+    }
+
+    /// Reports the `physical` button as `logical` button.
+    ///
+    /// One physical button may drive several logical ones. Logical buttons
+    /// that are not a source anymore become unreachable.
+    pub fn map(&mut self, physical: Button, logical: Button) {
+        self.sources[logical as usize] = physical;
+    }
+
+    /// Exchanges two logical buttons.
+    pub fn swap(&mut self, a: Button, b: Button) {
+        self.sources.swap(a as usize, b as usize);
+    }
+
+    /// Physical button reported as `logical` button.
+    pub fn source(&self, logical: Button) -> Button {
+        self.sources[logical as usize]
+    }
+
+    /// Presses `logical` while the `physical` trigger is at `threshold` or
+    /// above, in addition to its physical source. `None` only reports the
+    /// trigger as trigger again.
+    pub fn map_trigger_to_button(&mut self, physical: Trigger, logical: Option<TriggerButton>) {
+        self.trigger_buttons[physical as usize] = logical;
+    }
+
+    /// Logical button driven by the `physical` trigger.
+    pub fn trigger_button(&self, physical: Trigger) -> Option<TriggerButton> {
+        self.trigger_buttons[physical as usize]
+    }
+
+    /// Reports the `logical` trigger fully pulled while `physical` is
+    /// pressed, otherwise its analog value. `None` removes the mapping.
+    pub fn map_button_to_trigger(&mut self, physical: Option<Button>, logical: Trigger) {
+        self.button_triggers[logical as usize] = physical;
+    }
+
+    /// Physical button driving the `logical` trigger.
+    pub fn button_trigger(&self, logical: Trigger) -> Option<Button> {
+        self.button_triggers[logical as usize]
+    }
+}
+
+impl Transform for ButtonMap {
+    fn transform(&self, mut pad: XboxGamepad) -> XboxGamepad {
+        if self.swap_sticks {
+            core::mem::swap(&mut pad.thumb_left_x, &mut pad.thumb_right_x);
+            core::mem::swap(&mut pad.thumb_left_y, &mut pad.thumb_right_y);
+            core::mem::swap(&mut pad.btn_left_thumb, &mut pad.btn_right_thumb);
+        }
+
+        if self.swap_dpad_and_left_stick {
+            let axis = |negative, positive| match (negative, positive) {
+                (true, false) => i16::MIN,
+                (false, true) => i16::MAX,
+                _ => 0,
+            };
+            let (x, y) = (pad.thumb_left_x, pad.thumb_left_y);
+            pad.thumb_left_x = axis(pad.dpad_left, pad.dpad_right);
+            pad.thumb_left_y = axis(pad.dpad_down, pad.dpad_up);
+            pad.dpad_left = x < -STICK_DPAD_THRESHOLD;
+            pad.dpad_right = x > STICK_DPAD_THRESHOLD;
+            pad.dpad_down = y < -STICK_DPAD_THRESHOLD;
+            pad.dpad_up = y > STICK_DPAD_THRESHOLD;
+        }
+
+        let physical = pad;
+        for logical in Button::ALL {
+            pad.set_button(logical, physical.button(self.source(logical)));
+        }
+        for trigger in Trigger::ALL {
+            if let Some(TriggerButton { button, threshold }) = self.trigger_button(trigger) {
+                if physical.trigger(trigger) >= threshold {
+                    pad.set_button(button, true);
+                }
+            }
+            if let Some(button) = self.button_trigger(trigger) {
+                if physical.button(button) {
+                    pad.set_trigger(trigger, u8::MAX);
+                }
+            }
+        }
+        pad
+    }
+}
+
+/// Handedness mirroring: swaps the sticks, mirrors their X axes and swaps
+/// left and right shoulder buttons and triggers.
+///
+/// Can be toggled at runtime from any context.
+#[derive(Default)]
+pub struct Mirror {
+    enabled: AtomicBool,
+}
+
+impl Mirror {
+    pub const fn new(enabled: bool) -> Self {
+        Self {
+            enabled: AtomicBool::new(enabled),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Flips the mirror mode, returning the new setting.
+    pub fn toggle(&self) -> bool {
+        let enabled = !self.is_enabled();
+        self.set_enabled(enabled);
+        enabled
+    }
+}
+
+impl Transform for Mirror {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        if !self.is_enabled() {
+            return pad;
+        }
+
+        XboxGamepad {
+            btn_left_thumb: pad.btn_right_thumb,
+            btn_right_thumb: pad.btn_left_thumb,
+            btn_left_shoulder: pad.btn_right_shoulder,
+            btn_right_shoulder: pad.btn_left_shoulder,
+            trigger_left: pad.trigger_right,
+            trigger_right: pad.trigger_left,
+            thumb_left_x: pad.thumb_right_x.saturating_neg(),
+            thumb_left_y: pad.thumb_right_y,
+            thumb_right_x: pad.thumb_left_x.saturating_neg(),
+            thumb_right_y: pad.thumb_left_y,
+            ..pad
+        }
+    }
+}
+
+/// Transform that can be replaced at runtime, e.g. from a button chord
+/// handler or a configuration task, while another task applies it.
+pub struct Shared<T> {
+    inner: Mutex<CriticalSectionRawMutex, RefCell<T>>,
+}
+
+impl<T> Shared<T> {
+    pub const fn new(transform: T) -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(transform)),
+        }
+    }
+
+    /// Replaces the transform, returning the previous one.
+    pub fn replace(&self, transform: T) -> T {
+        self.inner.lock(|inner| inner.replace(transform))
+    }
+
+    /// Modifies the transform in place.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.inner.lock(|inner| f(&mut inner.borrow_mut()))
+    }
+}
+
+impl<T: Transform> Transform for Shared<T> {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        self.inner.lock(|inner| inner.borrow().transform(pad))
+    }
+}
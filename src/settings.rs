@@ -0,0 +1,830 @@
+//! User adjustable settings of the input pipeline and their binary
+//! encoding for configuration tools.
+//!
+//! Settings are encoded as a version byte followed by TLV records
+//! (`tag`, `length`, `value`). A `0` tag ends the blob, so zero padded
+//! reports decode fine. Records with unknown tags are skipped, and when
+//! decoding, settings without a record keep their current value.
+//!
+//! | Tag  | Length | Value                                                       |
+//! |------|--------|-------------------------------------------------------------|
+//! | 0x01 | 16     | Button map: physical source index per logical button, flags |
+//! | 0x02 | 6      | Left stick: inner, outer deadzone (u16 LE), shape, curve    |
+//! | 0x03 | 6      | Right stick, like 0x02                                      |
+//! | 0x04 | 2      | SOCD policy: horizontal, vertical                           |
+//! | 0x05 | 6      | Trigger map: button and threshold per trigger, button per trigger |
+//! | 0x06 | 2      | Stick orientation flags: left, right                        |
+//! | 0x07 | 3      | Turbo: button bits (u16 LE), presses per second             |
+//!
+//! Buttons are indexed in [`Button::ALL`] order. Button map flags are bit 0
+//! for `swap_sticks` and bit 1 for `swap_dpad_and_left_stick`. The trigger
+//! map holds the logical button and threshold of the left and right
+//! trigger, then the physical buttons driving the left and right trigger,
+//! with `0xFF` for no button. Stick orientation flags are bit 0 for
+//! `invert_x`, bit 1 for `invert_y` and bit 2 for `swap_axes`.
+//! Stick shapes are 0 for axial and 1 for radial, curves 0 for linear and
+//! 1 for cubic. Lookup table curves are reported as `0xFF` and are kept when
+//! a blob sets `0xFF`. SOCD policies are 0 for neutral, 1 for last input,
+//! 2 for first input, 3 for up priority and 4 for both. Turbo button bits
+//! are `1 << index` in [`Button::ALL`] order.
+//!
+//! For files a user edits by hand, settings also have a text form with one
+//! setting per line and `#` starting a comment:
+//!
+//! ```text
+//! map a b                       # logical button a is driven by physical b
+//! swap_sticks no
+//! swap_dpad_and_left_stick no
+//! stick left 2000 32000 radial linear
+//! invert left no yes            # x, y
+//! swap_axes left no
+//! socd last up                  # horizontal, vertical
+//! trigger_button left lb 128    # left trigger pulled to 128 presses lb
+//! button_trigger right none     # or a physical button pulling it fully
+//! turbo 10 a b                  # presses per second, buttons or none
+//! ```
+//!
+//! Button names are the ones of [`Button::name`], trigger names the ones of
+//! [`Trigger::name`]. Curves are `linear`,
+//! `cubic` or `custom`, which keeps a lookup table curve. SOCD policies are
+//! `neutral`, `last`, `first`, `up` and `both`.
+
+use core::fmt;
+
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex};
+
+use crate::analog::{AnalogConfig, Curve, DeadzoneShape, StickConfig};
+use crate::controller::{Button, Trigger};
+use crate::remap::{ButtonMap, Shared, TriggerButton};
+use crate::socd::{Policy, Socd};
+use crate::turbo::Turbo;
+
+/// Version byte at the start of every blob.
+pub const VERSION: u8 = 1;
+/// Length of a blob with all records.
+pub const ENCODED_LEN: usize = 1 + (2 + 16) + 2 * (2 + 6) + (2 + 2) + (2 + 6) + (2 + 2) + (2 + 3);
+
+const TAG_END: u8 = 0x00;
+const TAG_BUTTON_MAP: u8 = 0x01;
+const TAG_LEFT_STICK: u8 = 0x02;
+const TAG_RIGHT_STICK: u8 = 0x03;
+const TAG_SOCD: u8 = 0x04;
+const TAG_TRIGGER_MAP: u8 = 0x05;
+const TAG_STICK_ORIENTATION: u8 = 0x06;
+const TAG_TURBO: u8 = 0x07;
+
+const NO_BUTTON: u8 = 0xFF;
+
+const CURVE_CUSTOM: u8 = 0xFF;
+
+/// Reason a blob was rejected. Nothing was changed in that case.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The blob is empty or was written for another version.
+    Version,
+    /// A record extends past the end of the blob.
+    Truncated,
+    /// The record with this tag has the wrong length or an invalid value.
+    InvalidRecord(u8),
+    /// The output buffer is shorter than [`ENCODED_LEN`].
+    BufferTooSmall,
+}
+
+/// Line of a settings text that could not be parsed, counting from 1.
+/// Nothing was changed in that case.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TextError {
+    pub line: usize,
+}
+
+/// Snapshot of everything a configuration tool can change.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Settings {
+    pub map: ButtonMap,
+    pub left: StickConfig,
+    pub right: StickConfig,
+    pub socd_horizontal: Policy,
+    pub socd_vertical: Policy,
+    /// Buttons that repeat while held, see [`Turbo::buttons`].
+    pub turbo_buttons: u16,
+    pub turbo_rate: u8,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let socd = Socd::default();
+        let turbo = Turbo::default();
+        Self {
+            map: ButtonMap::identity(),
+            left: StickConfig::new(),
+            right: StickConfig::new(),
+            socd_horizontal: socd.horizontal,
+            socd_vertical: socd.vertical,
+            turbo_buttons: turbo.buttons,
+            turbo_rate: turbo.rate,
+        }
+    }
+}
+
+fn encode_policy(policy: Policy) -> u8 {
+    match policy {
+        Policy::Neutral => 0,
+        Policy::LastInputPriority => 1,
+        Policy::FirstInputPriority => 2,
+        Policy::UpPriority => 3,
+        Policy::Both => 4,
+    }
+}
+
+fn decode_policy(value: u8) -> Option<Policy> {
+    match value {
+        0 => Some(Policy::Neutral),
+        1 => Some(Policy::LastInputPriority),
+        2 => Some(Policy::FirstInputPriority),
+        3 => Some(Policy::UpPriority),
+        4 => Some(Policy::Both),
+        _ => None,
+    }
+}
+
+fn decode_socd(value: &[u8], settings: &mut Settings) -> Option<()> {
+    let &[horizontal, vertical] = value else {
+        return None;
+    };
+    settings.socd_horizontal = decode_policy(horizontal)?;
+    settings.socd_vertical = decode_policy(vertical)?;
+    Some(())
+}
+
+// Bits of all buttons.
+const TURBO_BUTTONS: u16 = (1 << Button::ALL.len()) - 1;
+
+fn decode_turbo(value: &[u8], settings: &mut Settings) -> Option<()> {
+    let &[buttons_lo, buttons_hi, rate] = value else {
+        return None;
+    };
+    let buttons = u16::from_le_bytes([buttons_lo, buttons_hi]);
+    if buttons & !TURBO_BUTTONS != 0 {
+        return None;
+    }
+    settings.turbo_buttons = buttons;
+    settings.turbo_rate = rate;
+    Some(())
+}
+
+const POLICIES: [Policy; 5] = [
+    Policy::Neutral,
+    Policy::LastInputPriority,
+    Policy::FirstInputPriority,
+    Policy::UpPriority,
+    Policy::Both,
+];
+
+fn policy_name(policy: Policy) -> &'static str {
+    match policy {
+        Policy::Neutral => "neutral",
+        Policy::LastInputPriority => "last",
+        Policy::FirstInputPriority => "first",
+        Policy::UpPriority => "up",
+        Policy::Both => "both",
+    }
+}
+
+fn parse_policy(name: &str) -> Option<Policy> {
+    POLICIES
+        .into_iter()
+        .find(|policy| policy_name(*policy) == name)
+}
+
+fn flag_name(flag: bool) -> &'static str {
+    if flag {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+fn parse_flag(name: &str) -> Option<bool> {
+    match name {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+fn write_stick<W: fmt::Write>(out: &mut W, name: &str, stick: &StickConfig) -> fmt::Result {
+    let shape = match stick.shape {
+        DeadzoneShape::Axial => "axial",
+        DeadzoneShape::Radial => "radial",
+    };
+    let curve = match stick.curve {
+        Curve::Linear => "linear",
+        Curve::Cubic => "cubic",
+        Curve::Lut(_) => "custom",
+    };
+    writeln!(
+        out,
+        "stick {} {} {} {} {}",
+        name, stick.inner_deadzone, stick.outer_deadzone, shape, curve
+    )?;
+    writeln!(
+        out,
+        "invert {} {} {}",
+        name,
+        flag_name(stick.invert_x),
+        flag_name(stick.invert_y)
+    )?;
+    writeln!(out, "swap_axes {} {}", name, flag_name(stick.swap_axes))
+}
+
+fn stick_by_name<'a>(settings: &'a mut Settings, name: &str) -> Option<&'a mut StickConfig> {
+    match name {
+        "left" => Some(&mut settings.left),
+        "right" => Some(&mut settings.right),
+        _ => None,
+    }
+}
+
+fn parse_line(words: &[&str], settings: &mut Settings) -> Option<()> {
+    match *words {
+        ["map", logical, physical] => settings
+            .map
+            .map(Button::from_name(physical)?, Button::from_name(logical)?),
+        ["swap_sticks", flag] => settings.map.swap_sticks = parse_flag(flag)?,
+        ["swap_dpad_and_left_stick", flag] => {
+            settings.map.swap_dpad_and_left_stick = parse_flag(flag)?
+        }
+        ["stick", name, inner, outer, shape, curve] => {
+            let stick = stick_by_name(settings, name)?;
+            let shape = match shape {
+                "axial" => DeadzoneShape::Axial,
+                "radial" => DeadzoneShape::Radial,
+                _ => return None,
+            };
+            let curve = match curve {
+                "linear" => Curve::Linear,
+                "cubic" => Curve::Cubic,
+                "custom" => stick.curve,
+                _ => return None,
+            };
+            *stick = StickConfig {
+                inner_deadzone: inner.parse().ok()?,
+                outer_deadzone: outer.parse().ok()?,
+                shape,
+                curve,
+                ..*stick
+            };
+        }
+        ["trigger_button", trigger, "none"] => settings
+            .map
+            .map_trigger_to_button(Trigger::from_name(trigger)?, None),
+        ["trigger_button", trigger, button, threshold] => settings.map.map_trigger_to_button(
+            Trigger::from_name(trigger)?,
+            Some(TriggerButton {
+                button: Button::from_name(button)?,
+                threshold: threshold.parse().ok()?,
+            }),
+        ),
+        ["button_trigger", trigger, "none"] => settings
+            .map
+            .map_button_to_trigger(None, Trigger::from_name(trigger)?),
+        ["button_trigger", trigger, button] => settings.map.map_button_to_trigger(
+            Some(Button::from_name(button)?),
+            Trigger::from_name(trigger)?,
+        ),
+        ["invert", name, x, y] => {
+            let stick = stick_by_name(settings, name)?;
+            stick.invert_x = parse_flag(x)?;
+            stick.invert_y = parse_flag(y)?;
+        }
+        ["swap_axes", name, flag] => stick_by_name(settings, name)?.swap_axes = parse_flag(flag)?,
+        ["socd", horizontal, vertical] => {
+            settings.socd_horizontal = parse_policy(horizontal)?;
+            settings.socd_vertical = parse_policy(vertical)?;
+        }
+        ["turbo", rate, "none"] => {
+            settings.turbo_rate = rate.parse().ok()?;
+            settings.turbo_buttons = 0;
+        }
+        ["turbo", rate, ref buttons @ ..] if !buttons.is_empty() => {
+            settings.turbo_rate = rate.parse().ok()?;
+            settings.turbo_buttons = 0;
+            for name in buttons {
+                settings.turbo_buttons |= 1 << Button::from_name(name)? as u16;
+            }
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+fn encode_stick(stick: &StickConfig) -> [u8; 6] {
+    let [inner_lo, inner_hi] = stick.inner_deadzone.to_le_bytes();
+    let [outer_lo, outer_hi] = stick.outer_deadzone.to_le_bytes();
+    let shape = match stick.shape {
+        DeadzoneShape::Axial => 0,
+        DeadzoneShape::Radial => 1,
+    };
+    let curve = match stick.curve {
+        Curve::Linear => 0,
+        Curve::Cubic => 1,
+        Curve::Lut(_) => CURVE_CUSTOM,
+    };
+    [inner_lo, inner_hi, outer_lo, outer_hi, shape, curve]
+}
+
+fn decode_stick(value: &[u8], stick: &mut StickConfig) -> Option<()> {
+    let &[inner_lo, inner_hi, outer_lo, outer_hi, shape, curve] = value else {
+        return None;
+    };
+    let shape = match shape {
+        0 => DeadzoneShape::Axial,
+        1 => DeadzoneShape::Radial,
+        _ => return None,
+    };
+    let curve = match curve {
+        0 => Curve::Linear,
+        1 => Curve::Cubic,
+        CURVE_CUSTOM => stick.curve,
+        _ => return None,
+    };
+    *stick = StickConfig {
+        inner_deadzone: u16::from_le_bytes([inner_lo, inner_hi]),
+        outer_deadzone: u16::from_le_bytes([outer_lo, outer_hi]),
+        shape,
+        curve,
+        ..*stick
+    };
+    Some(())
+}
+
+fn encode_map(map: &ButtonMap) -> [u8; 16] {
+    let mut value = [0_u8; 16];
+    for (logical, index) in Button::ALL.into_iter().zip(&mut value) {
+        *index = map.source(logical) as u8;
+    }
+    value[15] = u8::from(map.swap_sticks) | u8::from(map.swap_dpad_and_left_stick) << 1;
+    value
+}
+
+fn decode_map(value: &[u8], map: &mut ButtonMap) -> Option<()> {
+    let (&flags, sources) = value.split_last()?;
+    if sources.len() != Button::ALL.len() {
+        return None;
+    }
+    let mut decoded = *map;
+    for (logical, source) in Button::ALL.into_iter().zip(sources) {
+        decoded.map(*Button::ALL.get(usize::from(*source))?, logical);
+    }
+    decoded.swap_sticks = flags & 0x01 != 0;
+    decoded.swap_dpad_and_left_stick = flags & 0x02 != 0;
+    *map = decoded;
+    Some(())
+}
+
+fn encode_orientation(stick: &StickConfig) -> u8 {
+    u8::from(stick.invert_x) | u8::from(stick.invert_y) << 1 | u8::from(stick.swap_axes) << 2
+}
+
+fn decode_orientation(value: &[u8], settings: &mut Settings) -> Option<()> {
+    let &[left, right] = value else {
+        return None;
+    };
+    for (flags, stick) in [(left, &mut settings.left), (right, &mut settings.right)] {
+        if flags & !0x07 != 0 {
+            return None;
+        }
+        stick.invert_x = flags & 0x01 != 0;
+        stick.invert_y = flags & 0x02 != 0;
+        stick.swap_axes = flags & 0x04 != 0;
+    }
+    Some(())
+}
+
+fn encode_trigger_map(map: &ButtonMap) -> [u8; 6] {
+    let mut value = [NO_BUTTON, 0, NO_BUTTON, 0, NO_BUTTON, NO_BUTTON];
+    for trigger in Trigger::ALL {
+        let index = trigger as usize;
+        if let Some(TriggerButton { button, threshold }) = map.trigger_button(trigger) {
+            value[2 * index] = button as u8;
+            value[2 * index + 1] = threshold;
+        }
+        if let Some(button) = map.button_trigger(trigger) {
+            value[4 + index] = button as u8;
+        }
+    }
+    value
+}
+
+fn decode_button(index: u8) -> Option<Option<Button>> {
+    match index {
+        NO_BUTTON => Some(None),
+        index => Button::ALL.get(usize::from(index)).copied().map(Some),
+    }
+}
+
+fn decode_trigger_map(value: &[u8], map: &mut ButtonMap) -> Option<()> {
+    let &[left, left_threshold, right, right_threshold, left_source, right_source] = value else {
+        return None;
+    };
+    let trigger_button = |index, threshold| {
+        decode_button(index).map(|button| button.map(|button| TriggerButton { button, threshold }))
+    };
+    map.map_trigger_to_button(Trigger::Left, trigger_button(left, left_threshold)?);
+    map.map_trigger_to_button(Trigger::Right, trigger_button(right, right_threshold)?);
+    map.map_button_to_trigger(decode_button(left_source)?, Trigger::Left);
+    map.map_button_to_trigger(decode_button(right_source)?, Trigger::Right);
+    Some(())
+}
+
+impl Settings {
+    /// Writes all settings to `buf`, returning the blob length.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let buf = buf.get_mut(..ENCODED_LEN).ok_or(Error::BufferTooSmall)?;
+        buf[0] = VERSION;
+        let mut len = 1;
+        let mut record = |tag: u8, value: &[u8]| {
+            buf[len] = tag;
+            buf[len + 1] = value.len() as u8;
+            buf[len + 2..len + 2 + value.len()].copy_from_slice(value);
+            len += 2 + value.len();
+        };
+        record(TAG_BUTTON_MAP, &encode_map(&self.map));
+        record(TAG_LEFT_STICK, &encode_stick(&self.left));
+        record(TAG_RIGHT_STICK, &encode_stick(&self.right));
+        record(
+            TAG_SOCD,
+            &[
+                encode_policy(self.socd_horizontal),
+                encode_policy(self.socd_vertical),
+            ],
+        );
+        record(TAG_TRIGGER_MAP, &encode_trigger_map(&self.map));
+        record(
+            TAG_STICK_ORIENTATION,
+            &[
+                encode_orientation(&self.left),
+                encode_orientation(&self.right),
+            ],
+        );
+        let [buttons_lo, buttons_hi] = self.turbo_buttons.to_le_bytes();
+        record(TAG_TURBO, &[buttons_lo, buttons_hi, self.turbo_rate]);
+        Ok(len)
+    }
+
+    /// Updates the settings contained in `blob`.
+    ///
+    /// Either all records are applied or, on error, none.
+    pub fn decode(&mut self, blob: &[u8]) -> Result<(), Error> {
+        let Some((&VERSION, mut records)) = blob.split_first() else {
+            return Err(Error::Version);
+        };
+        let mut settings = *self;
+        while let Some((&tag, rest)) = records.split_first() {
+            if tag == TAG_END {
+                break;
+            }
+            let (&len, rest) = rest.split_first().ok_or(Error::Truncated)?;
+            let (value, rest) = rest
+                .split_at_checked(usize::from(len))
+                .ok_or(Error::Truncated)?;
+            records = rest;
+
+            let valid = match tag {
+                TAG_BUTTON_MAP => decode_map(value, &mut settings.map),
+                TAG_LEFT_STICK => decode_stick(value, &mut settings.left),
+                TAG_RIGHT_STICK => decode_stick(value, &mut settings.right),
+                TAG_SOCD => decode_socd(value, &mut settings),
+                TAG_TRIGGER_MAP => decode_trigger_map(value, &mut settings.map),
+                TAG_STICK_ORIENTATION => decode_orientation(value, &mut settings),
+                TAG_TURBO => decode_turbo(value, &mut settings),
+                _ => {
+                    debug!("skipping unknown settings tag {:#X}", tag);
+                    Some(())
+                }
+            };
+            valid.ok_or(Error::InvalidRecord(tag))?;
+        }
+        *self = settings;
+        Ok(())
+    }
+
+    /// Writes all settings in their text form.
+    pub fn write_text<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        for logical in Button::ALL {
+            writeln!(
+                out,
+                "map {} {}",
+                logical.name(),
+                self.map.source(logical).name()
+            )?;
+        }
+        writeln!(out, "swap_sticks {}", flag_name(self.map.swap_sticks))?;
+        writeln!(
+            out,
+            "swap_dpad_and_left_stick {}",
+            flag_name(self.map.swap_dpad_and_left_stick)
+        )?;
+        write_stick(out, "left", &self.left)?;
+        write_stick(out, "right", &self.right)?;
+        writeln!(
+            out,
+            "socd {} {}",
+            policy_name(self.socd_horizontal),
+            policy_name(self.socd_vertical)
+        )?;
+        for trigger in Trigger::ALL {
+            match self.map.trigger_button(trigger) {
+                Some(TriggerButton { button, threshold }) => writeln!(
+                    out,
+                    "trigger_button {} {} {}",
+                    trigger.name(),
+                    button.name(),
+                    threshold
+                )?,
+                None => writeln!(out, "trigger_button {} none", trigger.name())?,
+            }
+        }
+        for trigger in Trigger::ALL {
+            let button = self
+                .map
+                .button_trigger(trigger)
+                .map_or("none", Button::name);
+            writeln!(out, "button_trigger {} {}", trigger.name(), button)?;
+        }
+        write!(out, "turbo {}", self.turbo_rate)?;
+        let mut turbo_buttons = Button::ALL
+            .into_iter()
+            .filter(|button| self.turbo_buttons & 1 << *button as u16 != 0)
+            .peekable();
+        if turbo_buttons.peek().is_none() {
+            out.write_str(" none")?;
+        }
+        for button in turbo_buttons {
+            write!(out, " {}", button.name())?;
+        }
+        writeln!(out)
+    }
+
+    /// Updates the settings contained in `text`.
+    ///
+    /// Like [`Settings::decode`], either all lines are applied or none.
+    pub fn parse_text(&mut self, text: &str) -> Result<(), TextError> {
+        // Some editors start UTF-8 files with a byte order mark.
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        let mut settings = *self;
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            // Turbo lines list up to every button.
+            let mut words = [""; 2 + Button::ALL.len()];
+            let mut count = 0;
+            for word in line.split_ascii_whitespace() {
+                let slot = words.get_mut(count).ok_or(TextError { line: index + 1 })?;
+                *slot = word;
+                count += 1;
+            }
+            if count == 0 {
+                continue;
+            }
+            parse_line(&words[..count], &mut settings).ok_or(TextError { line: index + 1 })?;
+        }
+        *self = settings;
+        Ok(())
+    }
+}
+
+/// Pipeline stages the settings are read from and applied to at runtime.
+#[derive(Clone, Copy)]
+pub struct Pipeline<'a> {
+    pub map: &'a Shared<ButtonMap>,
+    pub analog: &'a Shared<AnalogConfig>,
+    pub socd: &'a Shared<Socd>,
+    pub turbo: &'a Shared<Turbo>,
+}
+
+impl Pipeline<'_> {
+    /// Current settings of the pipeline, read in one critical section.
+    pub fn settings(&self) -> Settings {
+        CriticalSectionRawMutex::new().lock(|| {
+            let map = self.map.update(|map| *map);
+            let (left, right) = self.analog.update(|analog| (analog.left, analog.right));
+            let (socd_horizontal, socd_vertical) =
+                self.socd.update(|socd| (socd.horizontal, socd.vertical));
+            let (turbo_buttons, turbo_rate) =
+                self.turbo.update(|turbo| (turbo.buttons, turbo.rate));
+            Settings {
+                map,
+                left,
+                right,
+                socd_horizontal,
+                socd_vertical,
+                turbo_buttons,
+                turbo_rate,
+            }
+        })
+    }
+
+    /// Applies `settings` to all stages. Calibrations are left untouched.
+    ///
+    /// The stages are updated in one critical section, so [`Pipeline::settings`]
+    /// never sees a partial update. A transform chain locks every stage on
+    /// its own though, so an input passing the chain during the update may
+    /// see the new settings in some stages only.
+    pub fn apply(&self, settings: &Settings) {
+        CriticalSectionRawMutex::new().lock(|| {
+            self.map.replace(settings.map);
+            self.analog.update(|analog| {
+                analog.left = settings.left;
+                analog.right = settings.right;
+            });
+            self.socd.update(|socd| {
+                socd.horizontal = settings.socd_horizontal;
+                socd.vertical = settings.socd_vertical;
+            });
+            self.turbo.update(|turbo| {
+                turbo.buttons = settings.turbo_buttons;
+                turbo.rate = settings.turbo_rate;
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analog::CurveTable;
+
+    fn custom() -> Settings {
+        let mut settings = Settings::default();
+        settings.map.map(Button::B, Button::A);
+        settings.map.swap_sticks = true;
+        settings.map.map_trigger_to_button(
+            Trigger::Left,
+            Some(TriggerButton {
+                button: Button::X,
+                threshold: 128,
+            }),
+        );
+        settings
+            .map
+            .map_button_to_trigger(Some(Button::RightShoulder), Trigger::Right);
+        settings.left = StickConfig {
+            inner_deadzone: 2000,
+            outer_deadzone: 32000,
+            shape: DeadzoneShape::Axial,
+            curve: Curve::Cubic,
+            invert_y: true,
+            ..StickConfig::new()
+        };
+        settings.right.swap_axes = true;
+        settings.socd_horizontal = Policy::LastInputPriority;
+        settings.socd_vertical = Policy::Both;
+        settings.turbo_buttons = 1 << Button::A as u16 | 1 << Button::Y as u16;
+        settings.turbo_rate = 15;
+        settings
+    }
+
+    fn encoded(settings: &Settings) -> [u8; ENCODED_LEN] {
+        let mut blob = [0; ENCODED_LEN];
+        assert_eq!(settings.encode(&mut blob), Ok(ENCODED_LEN));
+        blob
+    }
+
+    #[test]
+    fn blob_round_trip() {
+        let blob = encoded(&custom());
+        let mut decoded = Settings::default();
+        decoded.decode(&blob).unwrap();
+        assert_eq!(decoded, custom());
+    }
+
+    #[test]
+    fn turbo_record() {
+        let blob = encoded(&custom());
+        let turbo = &blob[ENCODED_LEN - 5..];
+        let buttons = (1_u16 << Button::A as u16 | 1 << Button::Y as u16).to_le_bytes();
+        assert_eq!(turbo, [TAG_TURBO, 3, buttons[0], buttons[1], 15]);
+    }
+
+    #[test]
+    fn zero_padding_ends_the_blob() {
+        let mut report = [0; 63];
+        report[..ENCODED_LEN].copy_from_slice(&encoded(&custom()));
+        let mut decoded = Settings::default();
+        decoded.decode(&report).unwrap();
+        assert_eq!(decoded, custom());
+    }
+
+    #[test]
+    fn missing_records_keep_their_settings() {
+        let mut settings = custom();
+        settings.decode(&[VERSION, TAG_SOCD, 2, 0, 0]).unwrap();
+        assert_eq!(settings.socd_horizontal, Policy::Neutral);
+        assert_eq!(settings.socd_vertical, Policy::Neutral);
+        assert_eq!(settings.turbo_rate, 15);
+        assert_eq!(settings.left, custom().left);
+    }
+
+    #[test]
+    fn unknown_records_are_skipped() {
+        let mut settings = Settings::default();
+        settings
+            .decode(&[VERSION, 0x7F, 2, 0xAA, 0xBB, TAG_TURBO, 3, 0x01, 0x00, 20])
+            .unwrap();
+        assert_eq!(settings.turbo_buttons, 1);
+        assert_eq!(settings.turbo_rate, 20);
+    }
+
+    #[test]
+    fn invalid_blobs_change_nothing() {
+        let cases: [(&[u8], Error); 6] = [
+            (&[], Error::Version),
+            (&[VERSION + 1, TAG_END], Error::Version),
+            (&[VERSION, TAG_SOCD, 2, 0], Error::Truncated),
+            (&[VERSION, TAG_SOCD], Error::Truncated),
+            (
+                &[VERSION, TAG_SOCD, 2, 0, 9],
+                Error::InvalidRecord(TAG_SOCD),
+            ),
+            (
+                &[VERSION, TAG_TURBO, 3, 0x00, 0x80, 10],
+                Error::InvalidRecord(TAG_TURBO),
+            ),
+        ];
+        for (blob, error) in cases {
+            let mut settings = custom();
+            assert_eq!(settings.decode(blob), Err(error), "{blob:02x?}");
+            assert_eq!(settings, custom());
+        }
+        // Valid records before the invalid one are not applied either.
+        let mut settings = custom();
+        let blob = [VERSION, TAG_TURBO, 3, 0, 0, 1, TAG_SOCD, 1, 0];
+        assert_eq!(settings.decode(&blob), Err(Error::InvalidRecord(TAG_SOCD)));
+        assert_eq!(settings, custom());
+    }
+
+    #[test]
+    fn short_buffer_is_rejected() {
+        let mut blob = [0; ENCODED_LEN - 1];
+        assert_eq!(custom().encode(&mut blob), Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    fn custom_curve_is_kept() {
+        static TABLE: [u16; 2] = [0, u16::MAX];
+        let lut = Curve::Lut(CurveTable::new(&TABLE).unwrap());
+        let mut settings = custom();
+        settings.left.curve = lut;
+        let blob = encoded(&settings);
+        assert_eq!(blob[1 + (2 + 16) + 2 + 5], CURVE_CUSTOM);
+        let mut decoded = settings;
+        decoded.decode(&blob).unwrap();
+        assert_eq!(decoded.left.curve, lut);
+    }
+
+    struct Text {
+        buf: [u8; 1024],
+        len: usize,
+    }
+
+    impl fmt::Write for Text {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            self.buf
+                .get_mut(self.len..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut text = Text {
+            buf: [0; 1024],
+            len: 0,
+        };
+        custom().write_text(&mut text).unwrap();
+        let text = core::str::from_utf8(&text.buf[..text.len]).unwrap();
+        assert!(text.contains("turbo 15 a y\n"));
+        let mut parsed = Settings::default();
+        parsed.parse_text(text).unwrap();
+        assert_eq!(parsed, custom());
+    }
+
+    #[test]
+    fn turbo_text() {
+        let mut settings = custom();
+        settings.parse_text("turbo 8 none # off").unwrap();
+        assert_eq!((settings.turbo_buttons, settings.turbo_rate), (0, 8));
+        assert_eq!(
+            settings.parse_text("\nturbo 8 a nope"),
+            Err(TextError { line: 2 })
+        );
+        assert_eq!(settings.parse_text("turbo 8"), Err(TextError { line: 1 }));
+    }
+}
@@ -0,0 +1,171 @@
+//! SOCD (simultaneous opposite cardinal directions) cleaning for digital
+//! direction inputs, as used by leverless and hitbox style controllers.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::controller::XboxGamepad;
+use crate::remap::Transform;
+
+/// How to resolve both directions of an axis being held at the same time.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Policy {
+    /// Neither direction.
+    Neutral,
+    /// The direction pressed most recently.
+    LastInputPriority,
+    /// The direction that was held first.
+    FirstInputPriority,
+    /// Up on the vertical axis. Behaves like `Neutral` on the horizontal axis.
+    UpPriority,
+    /// Both directions, for dance pads where jumps press opposite arrows.
+    Both,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Direction {
+    Negative,
+    Positive,
+}
+
+#[derive(Clone, Copy)]
+struct AxisState {
+    negative: bool,
+    positive: bool,
+    latest: Option<Direction>,
+}
+
+impl AxisState {
+    const fn new() -> Self {
+        Self {
+            negative: false,
+            positive: false,
+            latest: None,
+        }
+    }
+
+    // Returns the cleaned (negative, positive) pair. `up` is the direction
+    // favoured by `Policy::UpPriority` on this axis, if any.
+    fn resolve(
+        &mut self,
+        policy: Policy,
+        up: Option<Direction>,
+        negative: bool,
+        positive: bool,
+    ) -> (bool, bool) {
+        match (negative && !self.negative, positive && !self.positive) {
+            (true, false) => self.latest = Some(Direction::Negative),
+            (false, true) => self.latest = Some(Direction::Positive),
+            (true, true) => self.latest = None,
+            (false, false) => {}
+        }
+        self.negative = negative;
+        self.positive = positive;
+
+        if !(negative && positive) {
+            return (negative, positive);
+        }
+
+        let winner = match policy {
+            Policy::Neutral => None,
+            Policy::LastInputPriority => self.latest,
+            Policy::FirstInputPriority => self.latest.map(|latest| match latest {
+                Direction::Negative => Direction::Positive,
+                Direction::Positive => Direction::Negative,
+            }),
+            Policy::UpPriority => up,
+            Policy::Both => return (true, true),
+        };
+        (
+            winner == Some(Direction::Negative),
+            winner == Some(Direction::Positive),
+        )
+    }
+}
+
+/// SOCD cleaner with a policy per axis.
+///
+/// Tracks press order, so use one instance per input source. The policies
+/// can be changed at any time, e.g. through a
+/// [`Shared`](crate::remap::Shared) wrapper.
+pub struct Socd {
+    pub horizontal: Policy,
+    pub vertical: Policy,
+    state: Mutex<CriticalSectionRawMutex, Cell<[AxisState; 2]>>,
+}
+
+impl Default for Socd {
+    /// The common hitbox default: left + right is neutral, up + down is up.
+    fn default() -> Self {
+        Self::new(Policy::Neutral, Policy::UpPriority)
+    }
+}
+
+impl Socd {
+    /// Passes opposite directions through unchanged, as dance games expect.
+    pub const fn dance_pad() -> Self {
+        Self::new(Policy::Both, Policy::Both)
+    }
+
+    pub const fn new(horizontal: Policy, vertical: Policy) -> Self {
+        Self {
+            horizontal,
+            vertical,
+            state: Mutex::new(Cell::new([AxisState::new(); 2])),
+        }
+    }
+
+    /// Cleans a set of directions, returning `(up, down, left, right)`.
+    pub fn resolve(
+        &self,
+        up: bool,
+        down: bool,
+        left: bool,
+        right: bool,
+    ) -> (bool, bool, bool, bool) {
+        self.state.lock(|state| {
+            let [mut horizontal, mut vertical] = state.get();
+            let (left, right) = horizontal.resolve(self.horizontal, None, left, right);
+            let (down, up) = vertical.resolve(self.vertical, Some(Direction::Positive), down, up);
+            state.set([horizontal, vertical]);
+            (up, down, left, right)
+        })
+    }
+}
+
+impl Transform for Socd {
+    fn transform(&self, mut pad: XboxGamepad) -> XboxGamepad {
+        (pad.dpad_up, pad.dpad_down, pad.dpad_left, pad.dpad_right) =
+            self.resolve(pad.dpad_up, pad.dpad_down, pad.dpad_left, pad.dpad_right);
+        pad
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_resolve_opposite_directions() {
+        // Shared between tasks, so it has to be Sync.
+        static SOCD: Socd = Socd::new(Policy::Neutral, Policy::UpPriority);
+        assert_eq!(
+            SOCD.resolve(true, true, true, true),
+            (true, false, false, false)
+        );
+
+        let last = Socd::new(Policy::LastInputPriority, Policy::FirstInputPriority);
+        last.resolve(false, true, true, false);
+        assert_eq!(
+            last.resolve(true, true, true, true),
+            (false, true, false, true)
+        );
+        assert_eq!(
+            last.resolve(false, false, true, false),
+            (false, false, true, false)
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! Touchpad points in the DualShock 4 touch packet format, e.g. from a
+//! small capacitive pad or a trackball.
+//!
+//! The application publishes up to two points with [`State::send_touch`],
+//! a DS4 report writer turns them into touch packets with a
+//! [`TouchEncoder`], which assigns the tracking IDs and counts packets like
+//! a genuine controller.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+/// Horizontal resolution of the DS4 touchpad.
+pub const WIDTH: u16 = 1920;
+/// Vertical resolution of the DS4 touchpad.
+pub const HEIGHT: u16 = 943;
+/// Length of a touch packet: packet counter and two fingers.
+pub const PACKET_LEN: usize = 9;
+
+const NOT_TOUCHING: u8 = 0x80;
+const MAX_TRACKING_ID: u8 = 0x7F;
+
+/// Finger position, clamped to [`WIDTH`] x [`HEIGHT`] when encoded.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TouchPoint {
+    pub x: u16,
+    pub y: u16,
+}
+
+/// Fingers on the pad, `None` for lifted ones.
+pub type Touches = [Option<TouchPoint>; 2];
+
+/// Latest touch points, shared between the application and the report
+/// writer. Can be used from any context.
+pub struct State {
+    touches: Mutex<CriticalSectionRawMutex, Cell<Touches>>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    pub const fn new() -> Self {
+        Self {
+            touches: Mutex::new(Cell::new([None; 2])),
+        }
+    }
+
+    /// Publishes the fingers currently on the pad.
+    pub fn send_touch(&self, touches: Touches) {
+        self.touches.lock(|cell| cell.set(touches));
+    }
+
+    pub fn touches(&self) -> Touches {
+        self.touches.lock(Cell::get)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Finger {
+    id: u8,
+    point: TouchPoint,
+    touching: bool,
+}
+
+/// Encodes touch points into DS4 touch packets.
+pub struct TouchEncoder {
+    counter: u8,
+    next_id: u8,
+    fingers: [Finger; 2],
+}
+
+impl Default for TouchEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TouchEncoder {
+    pub const fn new() -> Self {
+        const LIFTED: Finger = Finger {
+            id: 0,
+            point: TouchPoint { x: 0, y: 0 },
+            touching: false,
+        };
+        Self {
+            counter: 0,
+            next_id: 0,
+            fingers: [LIFTED; 2],
+        }
+    }
+
+    /// Encodes the next packet.
+    ///
+    /// Every new contact gets a new tracking ID. Lifted fingers keep their
+    /// last ID and position with the not touching bit set, as the host
+    /// expects.
+    pub fn encode(&mut self, touches: Touches) -> [u8; PACKET_LEN] {
+        let mut packet = [0_u8; PACKET_LEN];
+        packet[0] = self.counter;
+        self.counter = self.counter.wrapping_add(1);
+
+        for ((finger, touch), out) in self
+            .fingers
+            .iter_mut()
+            .zip(touches)
+            .zip(packet[1..].chunks_exact_mut(4))
+        {
+            match touch {
+                Some(point) => {
+                    if !finger.touching {
+                        finger.id = self.next_id;
+                        self.next_id = (self.next_id + 1) & MAX_TRACKING_ID;
+                    }
+                    finger.touching = true;
+                    finger.point = TouchPoint {
+                        x: point.x.min(WIDTH - 1),
+                        y: point.y.min(HEIGHT - 1),
+                    };
+                }
+                None => finger.touching = false,
+            }
+            let TouchPoint { x, y } = finger.point;
+            out[0] = finger.id | if finger.touching { 0 } else { NOT_TOUCHING };
+            out[1] = x as u8;
+            out[2] = ((x >> 8) & 0x0F) as u8 | ((y & 0x0F) << 4) as u8;
+            out[3] = (y >> 4) as u8;
+        }
+        packet
+    }
+}
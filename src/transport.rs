@@ -0,0 +1,278 @@
+//! Abstraction over the destinations controller state is reported to, so the
+//! same input can be presented on several transports at once.
+
+use core::cell::Cell;
+
+use crate::controller::{Button, XboxGamepad};
+use crate::xinput;
+
+/// Destination for controller state, e.g. an XInput [`State`](xinput::State).
+pub trait ReportSink {
+    fn send(&self, pad: &XboxGamepad);
+
+    /// Whether a host is currently listening on this transport.
+    fn is_connected(&self) -> bool {
+        true
+    }
+}
+
+/// Features a backend can present to the host.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities {
+    pub rumble: bool,
+    pub leds: bool,
+    pub motion: bool,
+    pub touch: bool,
+    pub analog_triggers: bool,
+}
+
+impl Capabilities {
+    /// Features supported by both.
+    pub fn intersection(self, other: Self) -> Self {
+        Self {
+            rumble: self.rumble && other.rumble,
+            leds: self.leds && other.leds,
+            motion: self.motion && other.motion,
+            touch: self.touch && other.touch,
+            analog_triggers: self.analog_triggers && other.analog_triggers,
+        }
+    }
+}
+
+/// Output backend, queried by generic application code to adapt to the
+/// selected backend.
+pub trait Backend {
+    fn capabilities(&self) -> Capabilities;
+}
+
+impl<T: Backend + ?Sized> Backend for &T {
+    fn capabilities(&self) -> Capabilities {
+        (**self).capabilities()
+    }
+}
+
+impl<T: ReportSink + ?Sized> ReportSink for &T {
+    fn send(&self, pad: &XboxGamepad) {
+        (**self).send(pad)
+    }
+
+    fn is_connected(&self) -> bool {
+        (**self).is_connected()
+    }
+}
+
+impl<const N: usize> ReportSink for xinput::State<N> {
+    fn send(&self, pad: &XboxGamepad) {
+        self.send_xinput((*pad).into());
+    }
+}
+
+impl<const N: usize> Backend for xinput::State<N> {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            rumble: true,
+            leds: true,
+            motion: false,
+            touch: false,
+            analog_triggers: true,
+        }
+    }
+}
+
+/// Reports to both transports at the same time.
+pub struct FanOut<A, B>(pub A, pub B);
+
+impl<A: ReportSink, B: ReportSink> ReportSink for FanOut<A, B> {
+    fn send(&self, pad: &XboxGamepad) {
+        self.0.send(pad);
+        self.1.send(pad);
+    }
+
+    fn is_connected(&self) -> bool {
+        self.0.is_connected() || self.1.is_connected()
+    }
+}
+
+/// Only features both transports support, as the same input goes to both.
+impl<A: Backend, B: Backend> Backend for FanOut<A, B> {
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities().intersection(self.1.capabilities())
+    }
+}
+
+/// Reports to `primary` while it is connected and to `secondary` otherwise,
+/// e.g. USB while plugged in and a wireless transport on battery.
+pub struct Failover<A, B> {
+    pub primary: A,
+    pub secondary: B,
+}
+
+impl<A: ReportSink, B: ReportSink> ReportSink for Failover<A, B> {
+    fn send(&self, pad: &XboxGamepad) {
+        if self.primary.is_connected() {
+            self.primary.send(pad);
+        } else {
+            self.secondary.send(pad);
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.primary.is_connected() || self.secondary.is_connected()
+    }
+}
+
+/// Features of the transport currently reported to.
+impl<A: ReportSink + Backend, B: Backend> Backend for Failover<A, B> {
+    fn capabilities(&self) -> Capabilities {
+        if self.primary.is_connected() {
+            self.primary.capabilities()
+        } else {
+            self.secondary.capabilities()
+        }
+    }
+}
+
+/// Routes one physical controller to one of several receiver slots, e.g.
+/// to test local multiplayer games with a single pad.
+///
+/// Only the active slot is connected. Pressing `next` or `previous` while
+/// `modifier` is held moves the controller to the neighbouring slot: the
+/// old slot is released and disconnected, the new one connected. Both
+/// buttons are hidden from the host while `modifier` is held.
+pub struct SlotSelect<'a, const N: usize, const SLOTS: usize> {
+    slots: [&'a xinput::State<N>; SLOTS],
+    active: Cell<usize>,
+    pub modifier: Button,
+    pub next: Button,
+    pub previous: Button,
+    // (next, previous) pressed in the last state
+    held: Cell<(bool, bool)>,
+}
+
+impl<'a, const N: usize, const SLOTS: usize> SlotSelect<'a, N, SLOTS> {
+    /// Starts on the first slot and disconnects all others.
+    pub fn new(
+        slots: [&'a xinput::State<N>; SLOTS],
+        modifier: Button,
+        next: Button,
+        previous: Button,
+    ) -> Self {
+        assert!(SLOTS > 0, "SlotSelect needs at least one slot");
+        for (index, slot) in slots.iter().enumerate() {
+            if index == 0 {
+                slot.connect();
+            } else {
+                slot.disconnect();
+            }
+        }
+        Self {
+            slots,
+            active: Cell::new(0),
+            modifier,
+            next,
+            previous,
+            held: Cell::new((false, false)),
+        }
+    }
+
+    /// Index of the slot the controller is reported on.
+    pub fn active(&self) -> usize {
+        self.active.get()
+    }
+
+    /// Moves the controller to slot `index`. Returns `false` if there is no
+    /// such slot.
+    pub fn select(&self, index: usize) -> bool {
+        let Some(new) = self.slots.get(index) else {
+            return false;
+        };
+        let old = self.slots[self.active.replace(index)];
+        if !core::ptr::eq(old, *new) {
+            debug!("slot select: slot {}", index);
+            old.send(&XboxGamepad::new());
+            old.disconnect();
+            new.connect();
+        }
+        true
+    }
+}
+
+impl<const N: usize, const SLOTS: usize> ReportSink for SlotSelect<'_, N, SLOTS> {
+    fn send(&self, pad: &XboxGamepad) {
+        let mut pad = *pad;
+        let (next, previous) = (pad.button(self.next), pad.button(self.previous));
+        let (was_next, was_previous) = self.held.replace((next, previous));
+        if pad.button(self.modifier) {
+            let active = self.active.get();
+            if next && !was_next {
+                self.select((active + 1) % SLOTS);
+            } else if previous && !was_previous {
+                self.select((active + SLOTS - 1) % SLOTS);
+            }
+            pad.set_button(self.next, false);
+            pad.set_button(self.previous, false);
+        }
+        self.slots[self.active.get()].send(&pad);
+    }
+}
+
+impl<const N: usize, const SLOTS: usize> Backend for SlotSelect<'_, N, SLOTS> {
+    fn capabilities(&self) -> Capabilities {
+        self.slots[self.active.get()].capabilities()
+    }
+}
+
+/// Merges gamepad states between reports of a transport with a fixed report
+/// rate, e.g. a BLE connection interval.
+///
+/// Analog values are reported as their latest value. Buttons pressed at any
+/// time since the previous report are reported as pressed, so taps shorter
+/// than the report interval are not lost; the release follows in the next
+/// report.
+#[derive(Default)]
+pub struct Coalescer {
+    latest: Option<XboxGamepad>,
+    pressed: XboxGamepad,
+}
+
+impl Coalescer {
+    pub const fn new() -> Self {
+        Self {
+            latest: None,
+            pressed: XboxGamepad::new(),
+        }
+    }
+
+    /// Records a new gamepad state.
+    pub fn push(&mut self, pad: XboxGamepad) {
+        for button in Button::ALL {
+            if pad.button(button) {
+                self.pressed.set_button(button, true);
+            }
+        }
+        self.latest = Some(pad);
+    }
+
+    /// Whether [`Coalescer::take`] has a report to send.
+    pub fn is_pending(&self) -> bool {
+        self.latest.is_some()
+    }
+
+    /// Returns the next state to report, if anything changed since the last
+    /// report.
+    pub fn take(&mut self) -> Option<XboxGamepad> {
+        let latest = self.latest?;
+        let mut report = latest;
+        for button in Button::ALL {
+            if self.pressed.button(button) {
+                report.set_button(button, true);
+            }
+        }
+        self.pressed = XboxGamepad::new();
+        if report == latest {
+            self.latest = None;
+        }
+        Some(report)
+    }
+}
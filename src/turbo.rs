@@ -0,0 +1,152 @@
+//! Turbo (auto fire): buttons that are pressed and released repeatedly
+//! while held, for games that expect a button to be mashed.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+
+use crate::controller::{Button, XboxGamepad};
+use crate::remap::Transform;
+
+/// Repeats held buttons at a fixed rate.
+///
+/// A held button reads pressed for the first half of every period, counted
+/// from its press, so short taps pass through unchanged. The buttons and
+/// the rate can be changed at any time, e.g. through a
+/// [`Shared`](crate::remap::Shared) wrapper.
+pub struct Turbo {
+    /// Buttons that repeat, bit `button as u16` for every button.
+    pub buttons: u16,
+    /// Presses per second, `0` passes held buttons through.
+    pub rate: u8,
+    // time every held button was pressed
+    pressed: Mutex<CriticalSectionRawMutex, Cell<[Option<Instant>; Button::ALL.len()]>>,
+}
+
+impl Default for Turbo {
+    /// No turbo buttons, 10 presses per second once enabled.
+    fn default() -> Self {
+        Self::new(10)
+    }
+}
+
+impl Turbo {
+    /// Repeats at `rate` presses per second. No button repeats until it is
+    /// enabled with [`Turbo::set_button`].
+    pub const fn new(rate: u8) -> Self {
+        Self {
+            buttons: 0,
+            rate,
+            pressed: Mutex::new(Cell::new([None; Button::ALL.len()])),
+        }
+    }
+
+    /// Enables or disables turbo for `button`.
+    pub fn set_button(&mut self, button: Button, enabled: bool) {
+        let bit = 1 << button as u16;
+        if enabled {
+            self.buttons |= bit;
+        } else {
+            self.buttons &= !bit;
+        }
+    }
+
+    /// Whether `button` repeats while held.
+    pub fn button(&self, button: Button) -> bool {
+        self.buttons & 1 << button as u16 != 0
+    }
+
+    /// Processes `pad` as sampled at `now`, see [`Transform::transform`].
+    pub fn apply_at(&self, mut pad: XboxGamepad, now: Instant) -> XboxGamepad {
+        self.pressed.lock(|pressed| {
+            let mut times = pressed.get();
+            for (button, since) in Button::ALL.into_iter().zip(&mut times) {
+                if !pad.button(button) {
+                    *since = None;
+                    continue;
+                }
+                let since = *since.get_or_insert(now);
+                if self.rate == 0 || !self.button(button) {
+                    continue;
+                }
+                // Odd half periods since the press read released.
+                let half_periods = (now - since).as_micros() * 2 * u64::from(self.rate) / 1_000_000;
+                if half_periods % 2 == 1 {
+                    pad.set_button(button, false);
+                }
+            }
+            pressed.set(times);
+        });
+        pad
+    }
+}
+
+impl Transform for Turbo {
+    fn transform(&self, pad: XboxGamepad) -> XboxGamepad {
+        self.apply_at(pad, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: u64) -> Instant {
+        Instant::from_millis(ms)
+    }
+
+    fn pressed(button: Button) -> XboxGamepad {
+        let mut pad = XboxGamepad::new();
+        pad.set_button(button, true);
+        pad
+    }
+
+    #[test]
+    fn held_button_repeats_at_the_rate() {
+        let mut turbo = Turbo::new(10);
+        turbo.set_button(Button::A, true);
+        let a = pressed(Button::A);
+        let samples = [
+            (0, true),
+            (49, true),
+            (50, false),
+            (99, false),
+            (100, true),
+            (150, false),
+        ];
+        for (ms, expected) in samples {
+            assert_eq!(
+                turbo.apply_at(a, at(1000 + ms)).btn_a,
+                expected,
+                "at {ms} ms"
+            );
+        }
+    }
+
+    #[test]
+    fn period_restarts_with_every_press() {
+        let mut turbo = Turbo::new(10);
+        turbo.set_button(Button::A, true);
+        let a = pressed(Button::A);
+        turbo.apply_at(a, at(0));
+        assert!(!turbo.apply_at(a, at(50)).btn_a);
+        turbo.apply_at(XboxGamepad::new(), at(60));
+        assert!(turbo.apply_at(a, at(70)).btn_a);
+    }
+
+    #[test]
+    fn other_buttons_and_rate_zero_pass_through() {
+        let mut turbo = Turbo::new(10);
+        turbo.set_button(Button::A, true);
+        let b = pressed(Button::B);
+        assert!(turbo.apply_at(b, at(0)).btn_b);
+        assert!(turbo.apply_at(b, at(50)).btn_b);
+
+        turbo.rate = 0;
+        let a = pressed(Button::A);
+        assert!(turbo.apply_at(a, at(100)).btn_a);
+        assert!(turbo.apply_at(a, at(150)).btn_a);
+    }
+}
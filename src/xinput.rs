@@ -1,54 +1,708 @@
-use core::sync::atomic::{AtomicU16, Ordering};
+#[cfg(feature = "latency")]
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU32, AtomicU8, Ordering};
 
-#[cfg(feature = "defmt")]
-use defmt::{debug, info, unwrap, warn};
-
-use embassy_futures::select::{select3, Either3};
-use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex};
+#[cfg(feature = "latency")]
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::channel::{Channel, TrySendError};
 use embassy_sync::signal::Signal;
 use embassy_time::{Duration, Instant, Timer};
-use embassy_usb::control::{InResponse, Request, RequestType};
-use embassy_usb::driver::{Driver, Endpoint, EndpointIn, EndpointOut};
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut};
+use embassy_usb::types::InterfaceNumber;
 use embassy_usb::Handler;
 
-/// Binary encoding of xbox 360 controller input (buttons/axis) state
-pub struct ControllerData(pub [u8; 12]);
-pub struct SerialNumberHandler(pub [u8; 7]);
+use crate::chatpad::{self, ChatpadKeys};
+use crate::controller::XboxGamepad;
+use crate::fmt::Bytes;
+use crate::protocol::{self, AckResponse, Handshake, InputReports, OutData};
+use crate::remap::Transform;
+
+pub use crate::protocol::{BatteryLevel, BatteryType, Capabilities, ControllerData};
+
+pub mod capture;
+#[cfg(feature = "usb-device")]
+pub mod usbd;
+
+/// Size of the BOS descriptor buffer to pass to [`embassy_usb::Builder::new`].
+///
+/// embassy-usb always writes the BOS header followed by a USB 2.0 extension
+/// capability, so an empty buffer panics while building the device. That
+/// capability reports LPM as unsupported, which hosts handle fine for a
+/// full-speed receiver; embassy-usb does not allow changing it.
+pub const BOS_DESCRIPTOR_LEN: usize = 12;
+
+/// Longest class specific descriptor written by [`XInput::new_wireless`],
+/// without the length and type header.
+const CLASS_DESCRIPTOR_MAX_LEN: usize = 18;
+/// Descriptor type of the class specific XInput descriptors.
+const CLASS_DESCRIPTOR_TYPE: u8 = 0x22;
+
+/// Length of the class specific descriptor of the headset interface,
+/// without the length and type header.
+const HEADSET_DESCRIPTOR_LEN: usize = 10;
+
+/// Max packet size of the interrupt endpoints. The xinput driver expects
+/// the 32 bytes of a genuine receiver, the reports themselves are shorter.
+pub const ENDPOINT_SIZE: u16 = 32;
+
+const CONFIGURATION_LEN: usize = 9;
+const INTERFACE_LEN: usize = 9;
+const ENDPOINT_LEN: usize = 7;
+
+/// Size of the configuration descriptor buffer for `slots` receiver slots
+/// added with [`XInput::new_wireless`] or [`XInput::new`], with or without
+/// the headset interfaces.
+///
+/// Further interfaces, and the interface association descriptors of a
+/// composite device, need room on top of this.
+pub const fn config_descriptor_len(slots: usize, headset: bool) -> usize {
+    let controller = INTERFACE_LEN + 2 + CLASS_DESCRIPTOR_MAX_LEN + 2 * ENDPOINT_LEN;
+    let headset = if headset {
+        INTERFACE_LEN + 2 + HEADSET_DESCRIPTOR_LEN + 2 * ENDPOINT_LEN
+    } else {
+        0
+    };
+    CONFIGURATION_LEN + slots * (controller + headset)
+}
+
+/// Smallest control buffer, see [`embassy_usb::Builder::new`], that holds
+/// every response of the [`XInputControlHandler`]. Shorter buffers truncate
+/// the capabilities the host reads.
+pub const CONTROL_BUF_MIN_LEN: usize = INPUT_CAPABILITIES.len();
+
+const CLASS_VENDOR: u8 = 0xFF;
+const SUBCLASS_XINPUT: u8 = 0x5D;
+const PROTOCOL_WIRELESS: u8 = 0x81;
+const PROTOCOL_WIRELESS_UNKNOWN: u8 = 0x82;
+
+/// Class specific descriptor of one interface, returned by
+/// [`XInput::class_descriptors`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClassDescriptor {
+    interface: u8,
+    data: [u8; CLASS_DESCRIPTOR_MAX_LEN],
+    len: u8,
+}
+
+impl ClassDescriptor {
+    fn new(interface: InterfaceNumber, data: &[u8]) -> Self {
+        let mut descriptor = Self {
+            interface: interface.into(),
+            data: [0; CLASS_DESCRIPTOR_MAX_LEN],
+            len: data.len() as u8,
+        };
+        descriptor.data[..data.len()].copy_from_slice(data);
+        descriptor
+    }
+
+    /// Descriptor contents without the length and type header.
+    pub fn data(&self) -> &[u8] {
+        &self.data[..usize::from(self.len)]
+    }
+}
+
+/// Input capabilities of a genuine wireless controller: every button,
+/// trigger and stick bit is used.
+pub const INPUT_CAPABILITIES: [u8; 20] = Capabilities::WIRELESS_CONTROLLER.input_capabilities();
+
+/// Vibration capabilities of a genuine wireless controller: both motors
+/// with full resolution.
+pub const VIBRATION_CAPABILITIES: [u8; 8] =
+    Capabilities::WIRELESS_CONTROLLER.vibration_capabilities();
+
+/// Answers the control requests the xinput drivers send besides the
+/// standard enumeration:
+///
+/// - the receiver serial number (vendor device request 1),
+/// - input and vibration capabilities (vendor interface request 1),
+/// - LED commands on the control endpoint (vendor interface OUT request 0),
+///   which are accepted and ignored as the LED is set through the OUT
+///   endpoint,
+/// - GET_DESCRIPTOR for the class specific 0x22 descriptors of interfaces
+///   added with [`XInputControlHandler::add`].
+///
+/// Up to `M` class specific descriptors can be added, a full receiver has
+/// 8 interfaces.
+pub struct XInputControlHandler<const M: usize = 8> {
+    pub serial_number: [u8; 7],
+    pub input_capabilities: [u8; 20],
+    pub vibration_capabilities: [u8; 8],
+    class_descriptors: [Option<ClassDescriptor>; M],
+    events: Option<&'static XInputEvents>,
+}
+
+impl<const M: usize> XInputControlHandler<M> {
+    pub const fn new(serial_number: [u8; 7]) -> Self {
+        Self {
+            serial_number,
+            input_capabilities: INPUT_CAPABILITIES,
+            vibration_capabilities: VIBRATION_CAPABILITIES,
+            class_descriptors: [None; M],
+            events: None,
+        }
+    }
+
+    /// Answers the capabilities requests with `capabilities` instead of
+    /// those of a genuine controller. Use the same value as
+    /// [`XInputConfig::capabilities`].
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.input_capabilities = capabilities.input_capabilities();
+        self.vibration_capabilities = capabilities.vibration_capabilities();
+        self
+    }
+
+    /// Reports configuration and suspend changes of the device to `events`.
+    pub fn with_events(mut self, events: &'static XInputEvents) -> Self {
+        self.events = Some(events);
+        self
+    }
 
-impl Handler for SerialNumberHandler {
+    /// Adds the class specific descriptors of `xinput`'s interfaces.
+    ///
+    /// Panics if more than `M` descriptors are added. With the `panic-free`
+    /// feature the surplus descriptors are dropped and reported as
+    /// [`XInputEvent::ClassDescriptorDropped`] instead, so attach the events
+    /// with [`XInputControlHandler::with_events`] first.
+    pub fn add<'d, D: Driver<'d>, const N: usize>(&mut self, xinput: &XInput<'d, D, N>) {
+        for descriptor in xinput.class_descriptors() {
+            let Some(slot) = self
+                .class_descriptors
+                .iter_mut()
+                .find(|slot| slot.is_none())
+            else {
+                #[cfg(feature = "panic-free")]
+                {
+                    warn!("too many xinput interfaces");
+                    if let Some(events) = self.events {
+                        events.send(XInputEvent::ClassDescriptorDropped {
+                            interface: descriptor.interface,
+                        });
+                    }
+                    continue;
+                }
+                #[cfg(not(feature = "panic-free"))]
+                panic!("too many xinput interfaces");
+            };
+            *slot = Some(descriptor);
+        }
+    }
+
+    fn class_descriptor(&self, interface: u16) -> Option<&ClassDescriptor> {
+        self.class_descriptors
+            .iter()
+            .flatten()
+            .find(|descriptor| u16::from(descriptor.interface) == interface)
+    }
+}
+
+fn respond<'a>(buf: &'a mut [u8], data: &[u8]) -> Option<InResponse<'a>> {
+    let len = data.len().min(buf.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    Some(InResponse::Accepted(&buf[..len]))
+}
+
+impl<const M: usize> Handler for XInputControlHandler<M> {
     fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
-        if req.request_type == RequestType::Vendor
-            && req.request == 1
-            && req.value == 1
-            && req.index == 0
-            && req.length >= self.0.len() as u16
-        {
-            buf[..self.0.len()].copy_from_slice(&self.0);
-            Some(InResponse::Accepted(&buf[..self.0.len()]))
-        } else {
-            None
+        match (req.request_type, req.recipient, req.request, req.value) {
+            (RequestType::Vendor, Recipient::Device, 0x01, 0x0001) if req.index == 0 => {
+                respond(buf, &self.serial_number)
+            }
+            (RequestType::Vendor, Recipient::Interface, 0x01, 0x0100) => {
+                respond(buf, &self.input_capabilities)
+            }
+            (RequestType::Vendor, Recipient::Interface, 0x01, 0x0000) => {
+                respond(buf, &self.vibration_capabilities)
+            }
+            (RequestType::Standard, Recipient::Interface, Request::GET_DESCRIPTOR, value)
+                if (value >> 8) as u8 == CLASS_DESCRIPTOR_TYPE =>
+            {
+                let descriptor = self.class_descriptor(req.index)?;
+                let data = descriptor.data();
+                let len = (data.len() + 2).min(buf.len());
+                let mut full = [0_u8; CLASS_DESCRIPTOR_MAX_LEN + 2];
+                full[0] = data.len() as u8 + 2;
+                full[1] = CLASS_DESCRIPTOR_TYPE;
+                full[2..data.len() + 2].copy_from_slice(data);
+                buf[..len].copy_from_slice(&full[..len]);
+                Some(InResponse::Accepted(&buf[..len]))
+            }
+            _ => None,
+        }
+    }
+
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        match (req.request_type, req.recipient, req.request) {
+            (RequestType::Vendor, Recipient::Interface, 0x00) => {
+                debug!("<- Control LED data {:#X}", req.value);
+                Some(OutResponse::Accepted)
+            }
+            _ => None,
+        }
+    }
+
+    fn configured(&mut self, configured: bool) {
+        if let Some(events) = self.events {
+            events.send(XInputEvent::Configured(configured));
+        }
+    }
+
+    fn suspended(&mut self, suspended: bool) {
+        if let Some(events) = self.events {
+            events.send(XInputEvent::Suspended(suspended));
+        }
+    }
+}
+
+/// Number of guide button events buffered by [`State`].
+const GUIDE_EVENT_QUEUE_LEN: usize = 4;
+/// How long the guide button has to be held to turn the controller off,
+/// like on a genuine wireless controller.
+pub const GUIDE_POWER_OFF_TIME: Duration = Duration::from_secs(10);
+
+/// Interval at which a changed battery level is sent, see
+/// [`State::set_battery_level`].
+const BATTERY_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// [`State`] link quality value while no quality was set.
+const LINK_QUALITY_UNSET: u16 = u16::MAX;
+
+/// Number of chatpad key reports buffered by [`State`].
+const CHATPAD_QUEUE_LEN: usize = 4;
+
+/// Guide button transition, see [`State::guide_event`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GuideEvent {
+    pub pressed: bool,
+    /// Time the transition was passed to [`State::send_xinput`].
+    pub timestamp: Instant,
+}
+
+/// Number of events buffered by [`XInputEvents`].
+const EVENT_QUEUE_LEN: usize = 8;
+
+/// USB status change, see [`XInputEvents`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum XInputEvent {
+    /// The host set (`true`) or cleared (`false`) the device configuration.
+    Configured(bool),
+    /// The bus was suspended (`true`) or resumed (`false`).
+    Suspended(bool),
+    /// A transfer on endpoint `ep` failed, the data was dropped.
+    EndpointError { ep: u8, error: EndpointError },
+    /// The host wrote to OUT endpoint `ep`, e.g. a rumble or LED command.
+    HostOutActivity { ep: u8 },
+    /// The class specific descriptor of `interface` did not fit into the
+    /// [`XInputControlHandler`], so the host cannot read it. Only sent with
+    /// the `panic-free` feature, which panics otherwise.
+    ClassDescriptorDropped { interface: u8 },
+}
+
+/// USB status reported by the [`XInput`] tasks and the
+/// [`XInputControlHandler`], e.g. to drive a status LED or to restart the
+/// input frontend when the host misbehaves.
+///
+/// Attach it with [`XInput::with_events`] and
+/// [`XInputControlHandler::with_events`]; one handle can be shared by all
+/// slots. Like [`State`] it can be used from any context. Only the most
+/// recent events are buffered when nobody is waiting.
+pub struct XInputEvents {
+    events: Channel<CriticalSectionRawMutex, XInputEvent, EVENT_QUEUE_LEN>,
+}
+
+impl Default for XInputEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XInputEvents {
+    pub const fn new() -> Self {
+        Self {
+            events: Channel::new(),
         }
     }
+
+    fn send(&self, mut event: XInputEvent) {
+        while let Err(TrySendError::Full(rejected)) = self.events.try_send(event) {
+            let _ = self.events.try_receive();
+            event = rejected;
+        }
+    }
+
+    /// Waits for the next event.
+    pub async fn receive(&self) -> XInputEvent {
+        self.events.receive().await
+    }
+
+    pub fn try_receive(&self) -> Option<XInputEvent> {
+        self.events.try_receive().ok()
+    }
 }
 
-#[derive(Default)]
-pub struct State {
-    xinput: Signal<CriticalSectionRawMutex, ControllerData>,
+/// Controller data with the time its input was sampled, see
+/// [`State::send_timed`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TimedControllerData {
+    pub data: ControllerData,
+    pub timestamp: Instant,
+}
+
+impl TimedControllerData {
+    /// Stamps `data` with the current time.
+    pub fn now(data: ControllerData) -> Self {
+        Self {
+            data,
+            timestamp: Instant::now(),
+        }
+    }
+}
+
+/// Input update counters, see [`State::counters`].
+///
+/// Both wrap around. `queued - sent` grows by the updates dropped because
+/// the queue was full or the controller was not present.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PacketCounters {
+    /// Updates passed to [`State::send_xinput`] or [`State::send_timed`].
+    pub queued: u32,
+    /// Input reports written to the IN endpoint.
+    pub sent: u32,
+}
+
+/// Time from the timestamp of the input data until the input report was
+/// written to the IN endpoint, see [`State::latency_stats`].
+#[cfg(feature = "latency")]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LatencyStats {
+    pub min: Duration,
+    pub max: Duration,
+    /// Number of reports measured.
+    pub count: u32,
+    total_us: u64,
+}
+
+#[cfg(feature = "latency")]
+impl LatencyStats {
+    const fn new() -> Self {
+        Self {
+            min: Duration::MAX,
+            max: Duration::from_ticks(0),
+            count: 0,
+            total_us: 0,
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        self.min = self.min.min(latency);
+        self.max = self.max.max(latency);
+        self.count = self.count.saturating_add(1);
+        self.total_us = self.total_us.saturating_add(latency.as_micros());
+    }
+
+    pub fn average(&self) -> Option<Duration> {
+        (self.count > 0).then(|| Duration::from_micros(self.total_us / u64::from(self.count)))
+    }
+}
+
+/// Shared state between the application and the [`XInput`] task.
+///
+/// All methods take `&self`, never block and do a bounded amount of work, so
+/// they can be called from any context, including interrupt handlers. The only
+/// synchronization used are short critical sections, as inside
+/// [`State::send_xinput`] and [`State::try_send_xinput`], and relaxed atomic
+/// loads/stores of up to 32 bit values, which are available on every target
+/// embassy supports (including `thumbv6m`, which lacks compare-and-swap).
+///
+/// Up to `N` input updates are queued until the [`XInput`] task sends them,
+/// so short button presses sampled faster than the USB polling rate still
+/// reach the host. With the default of `N = 1` only the latest update is kept;
+/// `N = 0` does not compile.
+///
+/// A state takes at most [`STATE_MAX_SIZE`] bytes plus 24 bytes for every
+/// queued update beyond the first. Together with the [`XInput`] task data
+/// (about 200 bytes besides the driver's endpoints) and a
+/// [`ClassDescriptor`] in the [`XInputControlHandler`] this is the RAM cost
+/// of a receiver slot; size the handler's `M` to the interfaces actually
+/// added.
+pub struct State<const N: usize = 1> {
+    xinput: Channel<CriticalSectionRawMutex, TimedControllerData, N>,
     // right (weak) rumble in high byte
     // left (strong) rumble in low byte
     rumble: AtomicU16,
+    // time of the last rumble command in milliseconds, wrapping
+    rumble_updated: AtomicU32,
+    guide: AtomicBool,
+    guide_events: Channel<CriticalSectionRawMutex, GuideEvent, GUIDE_EVENT_QUEUE_LEN>,
+    present: AtomicBool,
+    presence: Signal<CriticalSectionRawMutex, bool>,
+    power_off: Signal<CriticalSectionRawMutex, ()>,
+    led: AtomicU8,
+    chatpad: Channel<CriticalSectionRawMutex, ChatpadKeys, CHATPAD_QUEUE_LEN>,
+    guide_power_off: AtomicBool,
+    battery_level: AtomicU8,
+    link_quality: AtomicU16,
+    queued_count: AtomicU32,
+    // only written by the task owning the IN endpoint
+    sent_count: AtomicU32,
+    #[cfg(feature = "latency")]
+    latency: Mutex<CriticalSectionRawMutex, Cell<LatencyStats>>,
 }
 
-impl State {
+impl<const N: usize> Default for State<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> State<N> {
+    // Sending drops queued updates until the new one fits, which never ends
+    // without room for one.
+    const QUEUE_NOT_EMPTY: () = assert!(N > 0, "State needs room for at least one update");
+
     pub const fn new() -> Self {
+        let () = Self::QUEUE_NOT_EMPTY;
         State {
-            xinput: Signal::new(),
+            xinput: Channel::new(),
             rumble: AtomicU16::new(0),
+            rumble_updated: AtomicU32::new(0),
+            guide: AtomicBool::new(false),
+            guide_events: Channel::new(),
+            present: AtomicBool::new(true),
+            presence: Signal::new(),
+            power_off: Signal::new(),
+            led: AtomicU8::new(0),
+            chatpad: Channel::new(),
+            guide_power_off: AtomicBool::new(true),
+            battery_level: AtomicU8::new(BatteryLevel::Full.bits()),
+            link_quality: AtomicU16::new(LINK_QUALITY_UNSET),
+            queued_count: AtomicU32::new(0),
+            sent_count: AtomicU32::new(0),
+            #[cfg(feature = "latency")]
+            latency: Mutex::new(Cell::new(LatencyStats::new())),
         }
     }
 
+    /// Publishes new controller data to the host.
+    ///
+    /// Safe to call from interrupt handlers: it only queues the value inside
+    /// a critical section and wakes the [`XInput`] task. If the queue is full
+    /// the oldest pending update is dropped to make room.
     pub fn send_xinput(&self, data: ControllerData) {
-        self.xinput.signal(data);
+        self.send_timed(TimedControllerData::now(data));
+    }
+
+    /// Like [`State::send_xinput`], for data stamped when its input was
+    /// sampled, e.g. at the start of a matrix scan. Latency measurements and
+    /// guide events then start at the scan.
+    pub fn send_timed(&self, timed: TimedControllerData) {
+        // Like try_send_timed, send and count in one critical section.
+        CriticalSectionRawMutex::new().lock(|| {
+            let mut queued = timed;
+            while let Err(TrySendError::Full(rejected)) = self.xinput.try_send(queued) {
+                let _ = self.xinput.try_receive();
+                queued = rejected;
+            }
+            self.count_queued(&timed);
+        })
+    }
+
+    /// Like [`State::send_xinput`], but keeps the pending updates when the
+    /// queue is full and hands `data` back instead.
+    ///
+    /// For interrupt handlers that must not lose an edge, e.g. encoder
+    /// steps: retry on the next interrupt, or merge the rejected data into
+    /// the next update.
+    pub fn try_send_xinput(&self, data: ControllerData) -> Result<(), ControllerData> {
+        self.try_send_timed(TimedControllerData::now(data))
+            .map_err(|rejected| rejected.data)
+    }
+
+    /// Like [`State::try_send_xinput`], for data stamped when its input was
+    /// sampled.
+    pub fn try_send_timed(&self, timed: TimedControllerData) -> Result<(), TimedControllerData> {
+        // Send and count in one critical section, so guide events and
+        // counters of preempting senders stay in queue order.
+        CriticalSectionRawMutex::new().lock(|| match self.xinput.try_send(timed) {
+            Ok(()) => {
+                self.count_queued(&timed);
+                Ok(())
+            }
+            Err(TrySendError::Full(rejected)) => Err(rejected),
+        })
+    }
+
+    // Tracks guide transitions and counts the update as queued. Called in
+    // the critical section that queued the update.
+    fn count_queued(&self, timed: &TimedControllerData) {
+        let pressed = timed.data.guide();
+        if self.guide.load(Ordering::Relaxed) != pressed {
+            self.guide.store(pressed, Ordering::Relaxed);
+            let mut event = GuideEvent {
+                pressed,
+                timestamp: timed.timestamp,
+            };
+            while let Err(TrySendError::Full(rejected)) = self.guide_events.try_send(event) {
+                let _ = self.guide_events.try_receive();
+                event = rejected;
+            }
+        }
+
+        // There is no compare-and-swap on every target, the critical
+        // section keeps preempting senders out.
+        let count = self.queued_count.load(Ordering::Relaxed);
+        self.queued_count
+            .store(count.wrapping_add(1), Ordering::Relaxed);
+    }
+
+    /// Number of input updates queued and sent so far, for correlating
+    /// scans with USB writes when diagnosing dropped inputs.
+    pub fn counters(&self) -> PacketCounters {
+        PacketCounters {
+            queued: self.queued_count.load(Ordering::Relaxed),
+            sent: self.sent_count.load(Ordering::Relaxed),
+        }
+    }
+
+    // Counts a written input report, returning its packet number.
+    fn count_sent(&self) -> u32 {
+        let count = self.sent_count.load(Ordering::Relaxed).wrapping_add(1);
+        self.sent_count.store(count, Ordering::Relaxed);
+        count
+    }
+
+    /// Publishes gamepad state after passing it through `transform`, e.g. a
+    /// [`ButtonMap`](crate::remap::ButtonMap).
+    pub fn send_gamepad(&self, pad: XboxGamepad, transform: impl Transform) {
+        self.send_xinput(transform.transform(pad).into());
+    }
+
+    /// Waits for the next guide button press or release.
+    ///
+    /// The guide button is still part of the regular input reports, this is
+    /// for application code that only cares about guide transitions. Only the
+    /// most recent events are buffered when nobody is waiting.
+    pub async fn guide_event(&self) -> GuideEvent {
+        self.guide_events.receive().await
+    }
+
+    /// Waits until the host turns the controller off, e.g. from the guide
+    /// menu, to power down a bridged radio pad.
+    ///
+    /// The [`XInput`] task then reports the controller as disconnected and
+    /// ignores input until the guide button is pressed, like after holding
+    /// the guide button. Only the latest request is kept when nobody is
+    /// waiting.
+    pub async fn power_off_event(&self) {
+        self.power_off.wait().await
+    }
+
+    /// Publishes the keys held on the chatpad, e.g. from an adapter keypad.
+    ///
+    /// The chatpad is announced to the host with the first keys. Like
+    /// [`State::send_xinput`] this drops the oldest pending update when the
+    /// queue is full.
+    pub fn send_chatpad_keys(&self, mut keys: ChatpadKeys) {
+        while let Err(TrySendError::Full(rejected)) = self.chatpad.try_send(keys) {
+            let _ = self.chatpad.try_receive();
+            keys = rejected;
+        }
+    }
+
+    /// Enables turning the controller off by holding the guide button for
+    /// [`GUIDE_POWER_OFF_TIME`], which is the default.
+    ///
+    /// The [`XInput`] task then reports the controller as disconnected and
+    /// ignores input until the guide button is pressed again, so the host
+    /// sees the same sequence as for a genuine wireless controller.
+    pub fn set_guide_power_off(&self, enabled: bool) {
+        self.guide_power_off.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Sets the battery level reported to the host, e.g. from
+    /// [`input::battery_adc`](crate::input::battery_adc). Only reported if
+    /// the [`Capabilities`] say the controller runs on batteries. A change
+    /// is sent to a connected host in a new controller info report, within
+    /// a second with the [`XInput`] task.
+    pub fn set_battery_level(&self, level: BatteryLevel) {
+        self.battery_level.store(level.bits(), Ordering::Relaxed);
+    }
+
+    /// Battery level last set with [`State::set_battery_level`], full by
+    /// default.
+    pub fn battery_level(&self) -> BatteryLevel {
+        BatteryLevel::from_bits(self.battery_level.load(Ordering::Relaxed))
+    }
+
+    /// Sets the quality of the wireless link to the pad, from 0 (worst) to
+    /// 255 (best), e.g. the RSSI of a bridged radio scaled to that range.
+    /// [`radio::rx::Receiver`](crate::radio::rx::Receiver) sets the share
+    /// of frames received.
+    ///
+    /// The receiver protocol has no link quality message (`00 00 00 13`
+    /// carries the battery status), so the quality is not sent over the
+    /// XInput interface. The `link` command of the `config-serial` channel
+    /// reports it.
+    pub fn set_link_quality(&self, quality: u8) {
+        self.link_quality
+            .store(u16::from(quality), Ordering::Relaxed);
+    }
+
+    /// Link quality last set with [`State::set_link_quality`], `None`
+    /// until it is first set.
+    pub fn link_quality(&self) -> Option<u8> {
+        u8::try_from(self.link_quality.load(Ordering::Relaxed)).ok()
+    }
+
+    /// Reports the controller as plugged in. Controllers are present by
+    /// default, so this is only needed after [`State::disconnect`].
+    pub fn connect(&self) {
+        self.present.store(true, Ordering::Relaxed);
+        self.presence.signal(true);
+    }
+
+    /// Reports the controller as unplugged, e.g. when the physical pad of a
+    /// receiver slot is removed. Input is discarded until
+    /// [`State::connect`] is called.
+    pub fn disconnect(&self) {
+        self.present.store(false, Ordering::Relaxed);
+        self.presence.signal(false);
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present.load(Ordering::Relaxed)
+    }
+
+    /// LED pattern last set by the host, 0 (off) until the host assigned a
+    /// player number.
+    pub fn led(&self) -> u8 {
+        self.led.load(Ordering::Relaxed)
+    }
+
+    /// Player number (0 to 3) assigned by the host, from the LED pattern.
+    pub fn player_index(&self) -> Option<u8> {
+        protocol::player_index(self.led())
+    }
+
+    /// Latency statistics since the start or the last
+    /// [`State::reset_latency_stats`]. Updates dropped because the queue was
+    /// full are not measured.
+    #[cfg(feature = "latency")]
+    pub fn latency_stats(&self) -> LatencyStats {
+        self.latency.lock(Cell::get)
+    }
+
+    #[cfg(feature = "latency")]
+    pub fn reset_latency_stats(&self) {
+        self.latency.lock(|stats| stats.set(LatencyStats::new()));
     }
 
     // Returns the (strong, weak) rumble data pair.
@@ -56,99 +710,411 @@ impl State {
         let [strong, weak] = self.rumble.load(Ordering::Relaxed).to_le_bytes();
         (strong, weak)
     }
+
+    /// Time since the host last sent a rumble command, with millisecond
+    /// resolution. Wraps after about 49 days.
+    pub fn rumble_age(&self) -> Duration {
+        let now = Instant::now().as_millis() as u32;
+        let updated = self.rumble_updated.load(Ordering::Relaxed);
+        Duration::from_millis(u64::from(now.wrapping_sub(updated)))
+    }
 }
 
-enum OutData<'d> {
-    ConnectionStatus,
-    Ack,
-    Led(u8),
-    Rumble(u8, u8),
-    Unknown(&'d [u8]),
+/// Upper bound of `size_of::<State<1>>()` with the `latency` feature
+/// enabled, checked at compile time for the target being built. CI builds
+/// the host tests and `thumbv6m-none-eabi`.
+pub const STATE_MAX_SIZE: usize = 392;
+
+const _: () = assert!(core::mem::size_of::<State<1>>() <= STATE_MAX_SIZE);
+
+/// The [`State`]s of all slots of a receiver, in the order the [`XInput`]
+/// interfaces were created.
+///
+/// The host numbers players per slot, this maps them back to the physical
+/// ports of an adapter, e.g. to light the port's player LED.
+pub struct Receiver<'a, const SLOTS: usize, const N: usize = 1> {
+    pub slots: [&'a State<N>; SLOTS],
+}
+
+impl<'a, const SLOTS: usize, const N: usize> Receiver<'a, SLOTS, N> {
+    pub const fn new(slots: [&'a State<N>; SLOTS]) -> Self {
+        Self { slots }
+    }
+
+    /// Player number the host assigned to `slot`.
+    pub fn player_index(&self, slot: usize) -> Option<u8> {
+        self.slots.get(slot)?.player_index()
+    }
+
+    /// Slot the host assigned player number `player` to.
+    pub fn slot_of_player(&self, player: u8) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|state| state.player_index() == Some(player))
+    }
+
+    /// Emulates the sync button of a genuine receiver: reserves the first
+    /// slot without a controller for the controller being bound, `None` if
+    /// all slots are in use.
+    ///
+    /// Slots are present by default, so [`State::disconnect`] the unused
+    /// ones at startup. Run one pairing at a time.
+    pub fn start_pairing(&self) -> Option<Pairing<'a, N>> {
+        let slot = self.slots.iter().position(|state| !state.is_present())?;
+        debug!("pairing slot {}", slot);
+        Some(Pairing {
+            slot,
+            state: self.slots[slot],
+        })
+    }
 }
 
-impl<'d> OutData<'d> {
-    fn from_raw(out_data: &'d [u8]) -> Self {
-        if out_data.len() != 12 {
-            return OutData::Unknown(out_data);
+/// Interval of the player number checks in [`Pairing::complete`].
+const PAIRING_POLL_PERIOD: Duration = Duration::from_millis(10);
+
+/// Pairing started with [`Receiver::start_pairing`].
+///
+/// Bind the physical controller, e.g. over the radio link, then call
+/// [`Pairing::complete`]. Dropping it leaves the slot free.
+pub struct Pairing<'a, const N: usize = 1> {
+    slot: usize,
+    state: &'a State<N>,
+}
+
+impl<'a, const N: usize> Pairing<'a, N> {
+    /// Slot the controller is bound to.
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    /// State to route the bound controller's input to.
+    pub fn state(&self) -> &'a State<N> {
+        self.state
+    }
+
+    /// Connects the controller to the host like a freshly synced one: the
+    /// [`XInput`] task announces it and answers the driver's handshake,
+    /// after which the driver assigns a player number.
+    ///
+    /// Returns the player number, or `None` if none was assigned within
+    /// `timeout`, e.g. without a driver. The slot stays connected either
+    /// way.
+    pub async fn complete(self, timeout: Duration) -> Option<u8> {
+        // Forget the player number of the slot's previous controller.
+        self.state.led.store(0, Ordering::Relaxed);
+        self.state.connect();
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(player) = self.state.player_index() {
+                debug!("slot {} paired as player {}", self.slot, player);
+                return Some(player);
+            }
+            if Instant::now() >= deadline {
+                warn!("slot {}: no player assigned", self.slot);
+                return None;
+            }
+            Timer::after(PAIRING_POLL_PERIOD).await;
+        }
+    }
+}
+
+/// Options for [`XInput::new`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct XInputConfig {
+    /// Adds the headset interface of a genuine receiver slot.
+    pub headset: bool,
+    /// Polling interval of the IN (input report) endpoint in milliseconds.
+    ///
+    /// The default of 1 ms, as on a genuine receiver, is the fastest a
+    /// full-speed device can be polled and gives 1000 Hz reporting. Windows
+    /// and Linux honour it; some hubs, docks and virtual machines poll less
+    /// often regardless. Rates beyond 1000 Hz need a high-speed device,
+    /// which the xinput driver does not expect from a receiver.
+    pub poll_interval: u8,
+    /// Polling interval of the OUT (rumble, LED) endpoint in milliseconds.
+    pub out_poll_interval: u8,
+    /// Delay of the idle and battery timers, see
+    /// [`XInputConfig::staggered`].
+    pub phase_offset: Duration,
+    /// Reports neutral input when no update was passed to
+    /// [`State::send_xinput`] for this long, so a stalled frontend does not
+    /// leave buttons or triggers held on the host. `None` (the default)
+    /// disables the watchdog.
+    ///
+    /// Sources that only publish changes, like
+    /// [`Periodic`](crate::input::Periodic), must resend their state more
+    /// often than this.
+    pub input_timeout: Option<Duration>,
+    /// Additionally reports the controller as disconnected when the input
+    /// watchdog fires. The next update connects it again.
+    pub disconnect_on_timeout: bool,
+    /// Features reported in the controller info, see [`Capabilities`].
+    pub capabilities: Capabilities,
+    /// Host driver the connection handshake is tuned for.
+    pub os_compat: OsCompat,
+}
+
+impl Default for XInputConfig {
+    fn default() -> Self {
+        Self {
+            headset: false,
+            poll_interval: 1,
+            out_poll_interval: 8,
+            phase_offset: Duration::from_ticks(0),
+            input_timeout: None,
+            disconnect_on_timeout: false,
+            capabilities: Capabilities::default(),
+            os_compat: OsCompat::default(),
         }
+    }
+}
+
+/// Host driver family, see [`XInputConfig::os_compat`].
+///
+/// The descriptors are the same for every variant: Linux xpad ignores the
+/// class specific descriptors and binds by vendor ID and interface
+/// protocol, so keep a vendor ID xpad knows, like the one of
+/// [`usb_config_wireless_receiver`](crate::presets::usb_config_wireless_receiver).
+///
+/// macOS has no xinput driver of its own. The third-party drivers bind and
+/// handshake like xpad, so use [`OsCompat::Linux`] there.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OsCompat {
+    /// The Windows xinput driver. Behaves like a genuine receiver: the
+    /// controller info is sent once the driver acknowledged the connection.
+    #[default]
+    Windows,
+    /// The Linux xpad driver, which never acknowledges reports. The
+    /// controller info follows the connection announcement unprompted, so
+    /// user space tools reading the raw reports see the capabilities.
+    Linux,
+}
+
+impl OsCompat {
+    /// Whether the controller info is sent without waiting for an ACK.
+    pub const fn unprompted_controller_info(&self) -> bool {
+        matches!(self, OsCompat::Linux)
+    }
+}
+
+impl XInputConfig {
+    /// Offsets the timers of receiver slot `slot` (0 to 3) by a quarter
+    /// polling interval per slot.
+    ///
+    /// Slots started together would otherwise send their idle and battery
+    /// reports in the same frame, delaying input reports of the
+    /// other slots behind bursts of four packets.
+    pub fn staggered(mut self, slot: u8) -> Self {
+        let interval_us = 1000 * u64::from(self.poll_interval.max(1));
+        self.phase_offset = Duration::from_micros(interval_us * u64::from(slot % 4) / 4);
+        self
+    }
 
+    /// Time without input changes after which an idle report is sent, ten
+    /// polling intervals plus one.
+    fn idle_timeout(&self) -> Duration {
+        Duration::from_millis(10 * u64::from(self.poll_interval.max(1)) + 1)
+    }
+}
+
+/// Report to answer a host command with.
+enum Reply {
+    ConnectionStatus(bool),
+    ControllerInfo,
+    PowerOff,
+}
+
+/// Protocol state of a receiver slot, shared by the USB backends.
+struct Session {
+    handshake: Handshake,
+    chatpad_announced: bool,
+    os_compat: OsCompat,
+    // Controller info to send after the connection announcement.
+    info_pending: bool,
+}
+
+impl Session {
+    const fn new(os_compat: OsCompat) -> Self {
+        Self {
+            handshake: Handshake::Disconnected,
+            chatpad_announced: false,
+            os_compat,
+            info_pending: false,
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.handshake.is_connected()
+    }
+
+    /// Starts or ends a connection, returning the report announcing it.
+    fn connection_status(&mut self, available: bool, ep: u8) -> [u8; 2] {
+        self.info_pending = available && self.os_compat.unprompted_controller_info();
+        if available {
+            self.handshake = Handshake::Unknown1;
+            debug!("{}-> Controller connected", ep);
+        } else {
+            self.handshake = Handshake::Disconnected;
+            self.chatpad_announced = false;
+            debug!("{}-> Controller disconnected", ep);
+        }
+        protocol::connection_status_report(available)
+    }
+
+    /// Whether the controller info has to be sent unprompted, see
+    /// [`OsCompat`]. Clears the request.
+    fn take_info_pending(&mut self) -> bool {
+        core::mem::take(&mut self.info_pending)
+    }
+
+    /// Applies a command received on OUT endpoint `ep`.
+    fn handle_out_data<const N: usize>(
+        &mut self,
+        state: &State<N>,
+        out_data: OutData<'_>,
+        ep: u8,
+    ) -> Option<Reply> {
         match out_data {
-            &[0x08, 0x00, 0x0F, 0xC0, ..] => OutData::ConnectionStatus,
-            &[0x00, 0x00, 0x00, 0x40, ..] => OutData::Ack,
-            &[0x00, 0x00, 0x08, led, ..] if led & 0x40 == 0x40 => OutData::Led(led & 0x0F),
-            &[0x00, 0x01, 0x0F, 0xC0, 0x00, strong, weak, ..] => OutData::Rumble(strong, weak),
-            data => OutData::Unknown(data),
+            OutData::ConnectionStatus => {
+                debug!("{}<- Controller connected?", ep);
+                return Some(Reply::ConnectionStatus(self.is_connected()));
+            }
+            OutData::Led(led) => {
+                debug!("{}<- LED data {}", ep, led);
+                state.led.store(led, Ordering::Relaxed);
+            }
+            OutData::Ack => {
+                debug!("{}<- ACK", ep);
+                match self.handshake.ack() {
+                    AckResponse::Unexpected => {
+                        warn!("Unexpected ACK message from host.");
+                    }
+                    AckResponse::ControllerInfo => return Some(Reply::ControllerInfo),
+                    AckResponse::Done => {}
+                }
+            }
+            OutData::Rumble(strong, weak) => {
+                debug!("{}<- Rumble data strong={:#X} weak={:#X}", ep, strong, weak);
+                let rumble16 = u16::from_le_bytes([strong, weak]);
+                state.rumble.store(rumble16, Ordering::Relaxed);
+                state
+                    .rumble_updated
+                    .store(Instant::now().as_millis() as u32, Ordering::Relaxed);
+            }
+            OutData::PowerOff => {
+                debug!("{}<- Power off", ep);
+                state.power_off.signal(());
+                return Some(Reply::PowerOff);
+            }
+            OutData::Unknown(_data) => {
+                info!("{}<- Unhandled out data: {:X}", ep, Bytes(_data))
+            }
         }
+        None
     }
 }
 
-enum ControllerInfoState {
-    Disconnected,
-    None,
-    Unknown1,
-    Unknown2,
+/// Class specific descriptor of the controller interface.
+fn controller_descriptor(ep_in_idx: u8, ep_out_idx: u8) -> [u8; 18] {
+    [
+        // Unknown
+        0x00,
+        0x01,
+        // Endpoint information
+        0x13,             // type = 1, length = 3
+        0x80 | ep_in_idx, // IN endpoint
+        0x1D,             // IN data size
+        0x00,             // ?
+        0x17,             // IN data used
+        // Unknown
+        0x01,
+        0x02,
+        0x08,
+        // Endpoint information
+        0x13,       // type = 1, length 3
+        ep_out_idx, // OUT endpoint
+        0x0C,       // OUT max data size
+        0x00,       // ?
+        0x0C,       // OUT data used
+        // Unknown
+        0x01,
+        0x02,
+        0x08,
+    ]
 }
 
-pub struct XInput<'d, D: Driver<'d>> {
+pub struct XInput<'d, D: Driver<'d>, const N: usize = 1> {
     ep_in: D::EndpointIn,
     ep_out: D::EndpointOut,
-    state: &'d State,
-    controller_info_state: ControllerInfoState,
+    state: &'d State<N>,
+    session: Session,
+    class_descriptors: [Option<ClassDescriptor>; 2],
+    idle_timeout: Duration,
+    phase_offset: Duration,
+    input_timeout: Option<Duration>,
+    disconnect_on_timeout: bool,
+    reports: InputReports,
+    capabilities: Capabilities,
+    // battery level in the last controller info report
+    reported_battery: BatteryLevel,
+    events: Option<&'d XInputEvents>,
 }
 
-impl<'d, D: Driver<'d>> XInput<'d, D> {
+impl<'d, D: Driver<'d>, const N: usize> XInput<'d, D, N> {
+    /// Adds a receiver slot with the default [`XInputConfig`] and the
+    /// headset interface if `headset` is set.
     pub fn new_wireless(
         builder: &mut embassy_usb::Builder<'d, D>,
-        state: &'d State,
+        state: &'d State<N>,
         headset: bool,
     ) -> Self {
-        const CLASS_VENDOR: u8 = 0xFF;
-        const SUBCLASS_XINPUT: u8 = 0x5D;
-        const PROTOCOL_WIRELESS: u8 = 0x81;
-        const PROTOCOL_WIRELESS_UNKNOWN: u8 = 0x82;
+        let config = XInputConfig {
+            headset,
+            ..XInputConfig::default()
+        };
+        Self::new(builder, state, config)
+    }
+
+    /// Adds a receiver slot.
+    pub fn new(
+        builder: &mut embassy_usb::Builder<'d, D>,
+        state: &'d State<N>,
+        config: XInputConfig,
+    ) -> Self {
+        #[cfg(not(feature = "panic-free"))]
+        debug_assert!(
+            builder.control_buf_len() >= CONTROL_BUF_MIN_LEN,
+            "xinput: control buffer shorter than CONTROL_BUF_MIN_LEN"
+        );
+        #[cfg(feature = "panic-free")]
+        if builder.control_buf_len() < CONTROL_BUF_MIN_LEN {
+            warn!("xinput: control buffer shorter than CONTROL_BUF_MIN_LEN");
+        }
         let mut function = builder.function(CLASS_VENDOR, SUBCLASS_XINPUT, PROTOCOL_WIRELESS);
         let mut interface = function.interface();
         let mut alt = interface.alt_setting(CLASS_VENDOR, SUBCLASS_XINPUT, PROTOCOL_WIRELESS, None);
 
-        let ep_in = alt.endpoint_interrupt_in(32, 1);
+        let ep_in = alt.endpoint_interrupt_in(ENDPOINT_SIZE, config.poll_interval.max(1));
         let ep_in_idx = 0x80 | ep_in.info().addr.index() as u8;
-        let ep_out = alt.endpoint_interrupt_out(32, 8);
+        let ep_out = alt.endpoint_interrupt_out(ENDPOINT_SIZE, config.out_poll_interval.max(1));
         let ep_out_idx = ep_out.info().addr.index() as u8;
 
-        // Unknown descriptor
-        alt.descriptor(
-            0x22,
-            &[
-                // Unknown
-                0x00,
-                0x01,
-                // Endpoint information
-                0x13,             // type = 1, length = 3
-                0x80 | ep_in_idx, // IN endpoint
-                0x1D,             // IN data size
-                0x00,             // ?
-                0x17,             // IN data used
-                // Unknown
-                0x01,
-                0x02,
-                0x08,
-                // Endpoint information
-                0x13,       // type = 1, length 3
-                ep_out_idx, // OUT endpoint
-                0x0C,       // OUT max data size
-                0x00,       // ?
-                0x0C,       // OUT data used
-                // Unknown
-                0x01,
-                0x02,
-                0x08,
-            ],
-        );
+        let controller_descriptor = controller_descriptor(ep_in_idx, ep_out_idx);
+        alt.descriptor(CLASS_DESCRIPTOR_TYPE, &controller_descriptor);
+        let mut class_descriptors = [
+            Some(ClassDescriptor::new(
+                interface.interface_number(),
+                &controller_descriptor,
+            )),
+            None,
+        ];
 
         // Headset data interface
         // When enabled hte windows driver polls for controller and headset
         // availability every 2.5 seconds.
-        if headset {
+        if config.headset {
             drop(function);
             let mut function =
                 builder.function(CLASS_VENDOR, SUBCLASS_XINPUT, PROTOCOL_WIRELESS_UNKNOWN);
@@ -160,201 +1126,642 @@ impl<'d, D: Driver<'d>> XInput<'d, D> {
                 None,
             );
 
-            let ep_in = alt.endpoint_interrupt_in(32, 2);
+            let ep_in = alt.endpoint_interrupt_in(ENDPOINT_SIZE, 2);
             let ep_in_idx = 0x80 | ep_in.info().addr.index() as u8;
-            let ep_out = alt.endpoint_interrupt_out(32, 4);
+            let ep_out = alt.endpoint_interrupt_out(ENDPOINT_SIZE, 4);
             let ep_out_idx = ep_out.info().addr.index() as u8;
 
-            alt.descriptor(
-                0x22,
-                &[
-                    0x00,
-                    0x01,
-                    0x01,
-                    0x80 | ep_in_idx,
-                    0x00,
-                    0x40,
-                    0x01,
-                    ep_out_idx,
-                    0x20,
-                    0x00,
-                ],
-            );
+            let headset_descriptor: [u8; HEADSET_DESCRIPTOR_LEN] = [
+                0x00,
+                0x01,
+                0x01,
+                0x80 | ep_in_idx,
+                0x00,
+                0x40,
+                0x01,
+                ep_out_idx,
+                0x20,
+                0x00,
+            ];
+            alt.descriptor(CLASS_DESCRIPTOR_TYPE, &headset_descriptor);
+            class_descriptors[1] = Some(ClassDescriptor::new(
+                interface.interface_number(),
+                &headset_descriptor,
+            ));
         }
 
         Self {
             ep_in,
             ep_out,
             state,
-            controller_info_state: ControllerInfoState::Disconnected,
+            session: Session::new(config.os_compat),
+            class_descriptors,
+            idle_timeout: config.idle_timeout(),
+            phase_offset: config.phase_offset,
+            input_timeout: config.input_timeout,
+            disconnect_on_timeout: config.disconnect_on_timeout,
+            reports: InputReports::new(),
+            capabilities: config.capabilities,
+            reported_battery: BatteryLevel::Full,
+            events: None,
         }
     }
 
-    // this is used by defmt logging
-    #[allow(dead_code)]
+    /// Reports endpoint errors and host OUT activity to `events`.
+    ///
+    /// Without an events handle, endpoint errors panic, or are only logged
+    /// with the `panic-free` feature.
+    pub fn with_events(mut self, events: &'d XInputEvents) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Class specific descriptors of the interfaces, to answer
+    /// GET_DESCRIPTOR requests with an [`XInputControlHandler`].
+    pub fn class_descriptors(&self) -> impl Iterator<Item = ClassDescriptor> + '_ {
+        self.class_descriptors.iter().flatten().copied()
+    }
+
+    // this is used by logging
     fn ep_in_addr(&self) -> u8 {
         self.ep_in.info().addr.index() as u8
     }
 
-    // this is used by defmt logging
-    #[allow(dead_code)]
+    // this is used by logging
     fn ep_out_addr(&self) -> u8 {
         self.ep_out.info().addr.index() as u8
     }
 
-    // TODO: better error handling instead of panicking
+    /// Reports a failed transfer on `ep`, see [`XInput::with_events`].
+    fn endpoint_error(&self, ep: u8, error: EndpointError) {
+        let Some(events) = self.events else {
+            #[cfg(feature = "panic-free")]
+            {
+                warn!("{}-> Endpoint error {:?}", ep, error);
+                return;
+            }
+            #[cfg(not(feature = "panic-free"))]
+            panic!("endpoint {:#X} failed: {:?}", ep, error);
+        };
+        warn!("{}-> Endpoint error {:?}", ep, error);
+        events.send(XInputEvent::EndpointError { ep, error });
+    }
+
     async fn ep_in_try_write(&mut self, data: &[u8]) {
-        #[cfg(feature = "defmt")]
-        unwrap!(self.ep_in.write(data).await);
-        #[cfg(not(feature = "defmt"))]
-        self.ep_in.write(data).await.unwrap()
+        if let Err(error) = self.ep_in.write(data).await {
+            self.endpoint_error(self.ep_in_addr(), error);
+        }
+    }
+
+    fn controller_info(&mut self) -> [u8; protocol::IN_REPORT_LEN] {
+        self.reported_battery = self.state.battery_level();
+        self.capabilities
+            .controller_info_with_battery(self.reported_battery)
     }
 
     async fn send_connection_status(&mut self, available: bool) {
-        if available {
-            self.controller_info_state = ControllerInfoState::Unknown1;
-            #[cfg(feature = "defmt")]
-            debug!("{=u8}-> Controller connected", self.ep_in_addr());
-            self.ep_in_try_write(&[0x08, 0x80]).await;
-        } else {
-            self.controller_info_state = ControllerInfoState::Disconnected;
-            #[cfg(feature = "defmt")]
-            debug!("{=u8}-> Controller disconnected", self.ep_out_addr());
-            self.ep_in_try_write(&[0x08, 0x08]).await;
-        };
+        let report = self.session.connection_status(available, self.ep_in_addr());
+        self.ep_in_try_write(&report).await;
+        if self.session.take_info_pending() {
+            let info = self.controller_info();
+            debug!("{}-> {:X}", self.ep_in_addr(), Bytes(&info));
+            self.ep_in_try_write(&info).await;
+        }
     }
 
     pub async fn run(mut self) -> ! {
-        let mut out_data = [0_u8; 32];
+        let mut out_data = [0_u8; ENDPOINT_SIZE as usize];
 
         // Use this deadline to send an "idle" message when there was no change
-        // in pad data for more than 11ms. Only active after sending pad data.
+        // in pad data for more than 10 polling intervals (11ms by default).
+        // Only active after sending pad data.
         let mut idle_msg_deadline = Instant::MAX;
 
+        // Turns the controller off when reached, set while guide is held.
+        let mut power_off_deadline = Instant::MAX;
+        let mut guide_held = false;
+        let mut powered_off = false;
+
+        // Checks for a changed battery level when reached.
+        let mut battery_deadline = Instant::now() + BATTERY_CHECK_PERIOD + self.phase_offset;
+
+        // Reverts to neutral input when reached, see `XInputConfig::input_timeout`.
+        let mut watchdog_deadline = Instant::MAX;
+
         loop {
-            match select3(
-                self.state.xinput.wait(),
-                Timer::at(idle_msg_deadline),
+            match select4(
+                self.state.xinput.receive(),
+                Timer::at(
+                    idle_msg_deadline
+                        .min(power_off_deadline)
+                        .min(battery_deadline)
+                        .min(watchdog_deadline),
+                ),
                 self.ep_out.read(&mut out_data),
+                select(self.state.presence.wait(), self.state.chatpad.receive()),
             )
             .await
             {
-                Either3::First(xinput_data) => {
-                    if matches!(
-                        self.controller_info_state,
-                        ControllerInfoState::Disconnected
-                    ) {
+                Either4::First(queued) => {
+                    let xinput_data = queued.data;
+                    if !self.state.is_present() {
+                        continue;
+                    }
+
+                    let guide = xinput_data.guide();
+                    let guide_pressed = guide && !guide_held;
+                    guide_held = guide;
+                    if powered_off {
+                        if !guide_pressed {
+                            continue;
+                        }
+                        debug!("{}-> Controller powered on", self.ep_in_addr());
+                        powered_off = false;
+                    }
+                    if guide_pressed && self.state.guide_power_off.load(Ordering::Relaxed) {
+                        power_off_deadline = Instant::now() + GUIDE_POWER_OFF_TIME;
+                    } else if !guide {
+                        power_off_deadline = Instant::MAX;
+                    }
+
+                    if !self.session.is_connected() {
                         self.send_connection_status(true).await;
                     }
 
-                    let mut data = [0_u8; 29];
-                    data[0] = 0x00; // Outer message type?
-                    data[1] = 0x01; // Message contains xinput data
-                    data[3] = 0xF0; // Unused
-                    data[4] = 0x00; // Inner message type
-                    data[5] = 0x13; // Inner message length
-                    data[6..18].copy_from_slice(&xinput_data.0);
-                    self.ep_in_try_write(&data).await;
-                    idle_msg_deadline = Instant::now() + Duration::from_millis(11);
+                    if let Err(error) = self.ep_in.write(self.reports.fill(&xinput_data)).await {
+                        self.endpoint_error(self.ep_in_addr(), error);
+                        continue;
+                    }
+                    let packet = self.state.count_sent();
+                    trace!(
+                        "{}-> Packet {} sampled at {} us",
+                        self.ep_in_addr(),
+                        packet,
+                        queued.timestamp.as_micros()
+                    );
+                    #[cfg(feature = "latency")]
+                    {
+                        let latency = queued.timestamp.elapsed();
+                        trace!("{}-> Latency {} us", self.ep_in_addr(), latency.as_micros());
+                        self.state.latency.lock(|stats| {
+                            let mut updated = stats.get();
+                            updated.record(latency);
+                            stats.set(updated);
+                        });
+                    }
+                    idle_msg_deadline = Instant::now() + self.idle_timeout + self.phase_offset;
+                    if let Some(timeout) = self.input_timeout {
+                        watchdog_deadline = Instant::now() + timeout;
+                    }
+                }
+                Either4::Second(_) if Instant::now() >= watchdog_deadline => {
+                    warn!("{}-> No input, reverting to neutral", self.ep_in_addr());
+                    watchdog_deadline = Instant::MAX;
+                    // The neutral report releases the guide button as well.
+                    guide_held = false;
+                    power_off_deadline = Instant::MAX;
+                    if self.session.is_connected() {
+                        let neutral = ControllerData::from(XboxGamepad::new());
+                        if let Err(error) = self.ep_in.write(self.reports.fill(&neutral)).await {
+                            self.endpoint_error(self.ep_in_addr(), error);
+                        }
+                        idle_msg_deadline = Instant::now() + self.idle_timeout + self.phase_offset;
+                        if self.disconnect_on_timeout {
+                            self.send_connection_status(false).await;
+                            idle_msg_deadline = Instant::MAX;
+                        }
+                    }
+                }
+                Either4::Second(_) if Instant::now() >= power_off_deadline => {
+                    debug!("{}-> Controller powered off", self.ep_in_addr());
+                    power_off_deadline = Instant::MAX;
+                    idle_msg_deadline = Instant::MAX;
+                    powered_off = true;
+                    if self.session.is_connected() {
+                        self.send_connection_status(false).await;
+                    }
+                }
+                Either4::Second(_) if Instant::now() >= battery_deadline => {
+                    // Keep the phase instead of drifting with the handling time.
+                    battery_deadline =
+                        (battery_deadline + BATTERY_CHECK_PERIOD).max(Instant::now());
+                    if self.session.is_connected()
+                        && self.capabilities.battery == BatteryType::Battery
+                        && self.state.battery_level() != self.reported_battery
+                    {
+                        let info = self.controller_info();
+                        debug!("{}-> Battery {:X}", self.ep_in_addr(), Bytes(&info));
+                        self.ep_in_try_write(&info).await;
+                    }
                 }
-                Either3::Second(_) => {
-                    let mut data = [0_u8; 29];
-                    data[3] = 0xF0;
-                    self.ep_in_try_write(&data).await;
+                Either4::Second(_) => {
+                    self.ep_in_try_write(&protocol::idle_report()).await;
                     idle_msg_deadline = Instant::MAX;
                 }
-                Either3::Third(n) => {
-                    #[cfg(feature = "defmt")]
-                    let out_data = OutData::from_raw(&out_data[..unwrap!(n)]);
-                    #[cfg(not(feature = "defmt"))]
-                    let out_data = OutData::from_raw(&out_data[..n.unwrap()]);
-                    self.handle_out_data(out_data).await;
+                Either4::Third(Ok(n)) => {
+                    if let Some(events) = self.events {
+                        events.send(XInputEvent::HostOutActivity {
+                            ep: self.ep_out_addr(),
+                        });
+                    }
+                    let out_data = OutData::from_raw(&out_data[..n]);
+                    if self.handle_out_data(out_data).await {
+                        debug!("{}-> Controller powered off", self.ep_in_addr());
+                        power_off_deadline = Instant::MAX;
+                        idle_msg_deadline = Instant::MAX;
+                        powered_off = true;
+                    }
+                }
+                Either4::Third(Err(error)) => {
+                    self.endpoint_error(self.ep_out_addr(), error);
+                    if error == EndpointError::Disabled {
+                        // Reads fail immediately until the host configures the device again.
+                        self.ep_out.wait_enabled().await;
+                    }
+                }
+                Either4::Fourth(Either::Second(keys)) => {
+                    if !self.session.is_connected() {
+                        continue;
+                    }
+                    if !self.session.chatpad_announced {
+                        self.ep_in_try_write(&chatpad::announcement_report()).await;
+                        self.session.chatpad_announced = true;
+                    }
+                    self.ep_in_try_write(&chatpad::key_report(&keys)).await;
+                }
+                Either4::Fourth(Either::First(present)) => {
+                    powered_off = false;
+                    power_off_deadline = Instant::MAX;
+                    if present != self.session.is_connected() {
+                        self.send_connection_status(present).await;
+                    }
+                    if !present {
+                        while self.state.xinput.try_receive().is_ok() {}
+                        idle_msg_deadline = Instant::MAX;
+                    }
                 }
             }
         }
     }
 
+    // Returns whether the host turned the controller off.
     async fn handle_out_data(&mut self, out_data: OutData<'_>) -> bool {
-        match out_data {
-            OutData::ConnectionStatus => {
-                #[cfg(feature = "defmt")]
-                debug!("{=u8}<- Controller connected?", self.ep_out_addr());
-                self.send_connection_status(!matches!(
-                    self.controller_info_state,
-                    ControllerInfoState::Disconnected
-                ))
-                .await;
+        let reply = self
+            .session
+            .handle_out_data(self.state, out_data, self.ep_out_addr());
+        match reply {
+            Some(Reply::ConnectionStatus(available)) => {
+                self.send_connection_status(available).await;
             }
-            OutData::Led(_led) => {
-                #[cfg(feature = "defmt")]
-                debug!("{=u8}<- LED data {=u8}", self.ep_out_addr(), _led);
+            Some(Reply::ControllerInfo) => {
+                let info = self.controller_info();
+                debug!("{}-> {:X}", self.ep_in_addr(), Bytes(&info));
+                self.ep_in_try_write(&info).await;
             }
-            OutData::Ack => {
-                #[cfg(feature = "defmt")]
-                debug!("{=u8}<- ACK", self.ep_out_addr(),);
-                match self.controller_info_state {
-                    ControllerInfoState::Disconnected | ControllerInfoState::None => {
-                        #[cfg(feature = "defmt")]
-                        warn!("Unexpected ACK message from host.");
-                    }
-                    ControllerInfoState::Unknown1 => {
-                        self.controller_info_state = ControllerInfoState::Unknown2;
-
-                        // This message is required for windows to detect the controller.
-                        // Interestingly Steam detects the controller without that message.
-                        let controller_info = [
-                            0x00, 0x0F, 0x00, 0xF0, // Controller info message
-                            0xF0, // Ignored
-                            0xCC, // Important for windows to detect the pad
-                            0xFF, 0xFF, 0xFF, 0xFF, // Wireless adapter serial number
-                            0x58, 0x91, 0xb3, 0xf0, 0x00, 0x09, // Controller serial number?
-                            0x13, // Important for windows to detect the pad
-                            0xA3, // Battery status?
-                            // The windows driver does not care about the remaining bytes.
-                            0x20, 0x1D, 0x30, 0x03, 0x40, 0x01, 0x50, 0x01, 0xFF, 0xFF, 0xFF,
-                        ];
-                        #[cfg(feature = "defmt")]
-                        debug!("{=u8}-> {=[u8]:#X}", self.ep_in_addr(), controller_info);
-                        self.ep_in_try_write(&controller_info).await;
-                    }
-                    ControllerInfoState::Unknown2 => {
-                        self.controller_info_state = ControllerInfoState::None;
-                        // The original adapter sends 4 additional messages:
-                        // let mut unknown2a = [0_u8; 29];
-                        // unknown2a[3] = 0x13;
-                        // unknown2a[4] = 0xA2;
-                        // let mut unknown2b = [0_u8; 29];
-                        // unknown2b[3] = 0xF0;
-                        // Timer::after(Duration::from_millis(8)).await;
-                        // for buf in [&unknown2a, &unknown2b, &unknown2a, &unknown2b] {
-                        //     Timer::after(Duration::from_millis(8)).await;
-                        //     debug!("{=u8}-> {=[u8]:#X}...", self.ep_in_addr(), buf[..6]);
-                        //     unwrap!(self.ep_in.write(buf).await);
-                        // }
+            Some(Reply::PowerOff) => {
+                if self.session.is_connected() {
+                    self.send_connection_status(false).await;
+                }
+                return true;
+            }
+            None => {}
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::thread;
+    use std::vec::Vec;
+
+    use embassy_usb::driver::{
+        Bus, ControlPipe, Direction, EndpointAddress, EndpointAllocError, EndpointInfo,
+        EndpointType, Event, Unsupported,
+    };
+
+    use super::*;
+
+    const SENDERS: u32 = 4;
+    const UPDATES: u32 = 2000;
+
+    // Update `seq` of `sender`, with the guide button toggling on every
+    // update of sender 0.
+    fn update(sender: u32, seq: u32) -> ControllerData {
+        let mut data = [0_u8; 12];
+        if sender == 0 && seq % 2 == 1 {
+            data[1] = 0x04;
+        }
+        data[4..8].copy_from_slice(&sender.to_le_bytes());
+        data[8..12].copy_from_slice(&seq.to_le_bytes());
+        ControllerData(data)
+    }
+
+    fn sender_and_seq(data: &ControllerData) -> (u32, u32) {
+        let [s0, s1, s2, s3, q0, q1, q2, q3] = data.0[4..12].try_into().unwrap();
+        (
+            u32::from_le_bytes([s0, s1, s2, s3]),
+            u32::from_le_bytes([q0, q1, q2, q3]),
+        )
+    }
+
+    #[test]
+    fn try_send_hands_back_data_when_full() {
+        let state = State::<2>::new();
+        assert_eq!(state.try_send_xinput(update(1, 0)), Ok(()));
+        assert_eq!(state.try_send_xinput(update(1, 1)), Ok(()));
+        assert_eq!(state.try_send_xinput(update(1, 2)), Err(update(1, 2)));
+        assert_eq!(state.counters().queued, 2);
+        assert_eq!(
+            state.xinput.try_receive().map(|timed| timed.data),
+            Ok(update(1, 0))
+        );
+        assert_eq!(state.try_send_xinput(update(1, 2)), Ok(()));
+        assert_eq!(state.counters().queued, 3);
+    }
+
+    #[test]
+    fn concurrent_senders_lose_nothing() {
+        // Threads stand in for interrupt handlers preempting each other and
+        // the task draining the queue.
+        let state = State::<4>::new();
+        let received = thread::scope(|scope| {
+            for sender in 0..SENDERS {
+                let state = &state;
+                scope.spawn(move || {
+                    for seq in 0..UPDATES {
+                        let mut data = update(sender, seq);
+                        while let Err(rejected) = state.try_send_xinput(data) {
+                            assert_eq!(rejected, data);
+                            data = rejected;
+                            thread::yield_now();
+                        }
                     }
+                });
+            }
+            let mut received = Vec::new();
+            while received.len() < (SENDERS * UPDATES) as usize {
+                match state.xinput.try_receive() {
+                    Ok(timed) => received.push(timed.data),
+                    Err(_) => thread::yield_now(),
                 }
             }
-            OutData::Rumble(strong, weak) => {
-                #[cfg(feature = "defmt")]
-                debug!(
-                    "{=u8}<- Rumble data strong={=u8:#X} weak={=u8:#X}",
-                    self.ep_out_addr(),
-                    strong,
-                    weak,
-                );
-                let rumble16 = u16::from_le_bytes([strong, weak]);
-                self.state.rumble.store(rumble16, Ordering::Relaxed);
+            received
+        });
+
+        assert_eq!(state.counters().queued, SENDERS * UPDATES);
+        assert!(state.xinput.try_receive().is_err());
+        // Every update arrives once, in the order of its sender.
+        let mut next = [0; SENDERS as usize];
+        for data in &received {
+            let (sender, seq) = sender_and_seq(data);
+            assert_eq!(seq, next[sender as usize]);
+            next[sender as usize] += 1;
+        }
+        // The guide state follows the last queued update.
+        let last_guide = received.last().map(ControllerData::guide);
+        assert_eq!(last_guide, Some(state.guide.load(Ordering::Relaxed)));
+    }
+
+    // Driver that only allocates endpoints, enough to build descriptors.
+    struct FakeDriver {
+        next_index: usize,
+    }
+
+    struct FakeEndpoint(EndpointInfo);
+
+    enum NoBus {}
+
+    impl Driver<'_> for FakeDriver {
+        type EndpointOut = FakeEndpoint;
+        type EndpointIn = FakeEndpoint;
+        type ControlPipe = NoBus;
+        type Bus = NoBus;
+
+        fn alloc_endpoint_out(
+            &mut self,
+            ep_type: EndpointType,
+            max_packet_size: u16,
+            interval_ms: u8,
+        ) -> Result<FakeEndpoint, EndpointAllocError> {
+            Ok(self.alloc(Direction::Out, ep_type, max_packet_size, interval_ms))
+        }
+
+        fn alloc_endpoint_in(
+            &mut self,
+            ep_type: EndpointType,
+            max_packet_size: u16,
+            interval_ms: u8,
+        ) -> Result<FakeEndpoint, EndpointAllocError> {
+            Ok(self.alloc(Direction::In, ep_type, max_packet_size, interval_ms))
+        }
+
+        fn start(self, _control_max_packet_size: u16) -> (NoBus, NoBus) {
+            unimplemented!("descriptor tests never start the device")
+        }
+    }
+
+    impl FakeDriver {
+        // Endpoints get the same index in both directions, like on a
+        // genuine receiver.
+        fn alloc(
+            &mut self,
+            dir: Direction,
+            ep_type: EndpointType,
+            max_packet_size: u16,
+            interval_ms: u8,
+        ) -> FakeEndpoint {
+            if dir == Direction::In {
+                self.next_index += 1;
             }
-            OutData::Unknown(_data) => {
-                #[cfg(feature = "defmt")]
-                info!(
-                    "{=u8}<- Unhandled out data: {=[u8]:X}",
-                    self.ep_out_addr(),
-                    _data
-                )
+            FakeEndpoint(EndpointInfo {
+                addr: EndpointAddress::from_parts(self.next_index, dir),
+                ep_type,
+                max_packet_size,
+                interval_ms,
+            })
+        }
+    }
+
+    impl Endpoint for FakeEndpoint {
+        fn info(&self) -> &EndpointInfo {
+            &self.0
+        }
+
+        async fn wait_enabled(&mut self) {}
+    }
+
+    impl EndpointIn for FakeEndpoint {
+        async fn write(&mut self, _buf: &[u8]) -> Result<(), EndpointError> {
+            Err(EndpointError::Disabled)
+        }
+    }
+
+    impl EndpointOut for FakeEndpoint {
+        async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, EndpointError> {
+            Err(EndpointError::Disabled)
+        }
+    }
+
+    impl Bus for NoBus {
+        async fn enable(&mut self) {
+            match *self {}
+        }
+
+        async fn disable(&mut self) {
+            match *self {}
+        }
+
+        async fn poll(&mut self) -> Event {
+            match *self {}
+        }
+
+        fn endpoint_set_enabled(&mut self, _ep_addr: EndpointAddress, _enabled: bool) {
+            match *self {}
+        }
+
+        fn endpoint_set_stalled(&mut self, _ep_addr: EndpointAddress, _stalled: bool) {
+            match *self {}
+        }
+
+        fn endpoint_is_stalled(&mut self, _ep_addr: EndpointAddress) -> bool {
+            match *self {}
+        }
+
+        async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+            match *self {}
+        }
+    }
+
+    impl ControlPipe for NoBus {
+        fn max_packet_size(&self) -> usize {
+            match *self {}
+        }
+
+        async fn setup(&mut self) -> [u8; 8] {
+            match *self {}
+        }
+
+        async fn data_out(
+            &mut self,
+            _buf: &mut [u8],
+            _first: bool,
+            _last: bool,
+        ) -> Result<usize, EndpointError> {
+            match *self {}
+        }
+
+        async fn data_in(
+            &mut self,
+            _data: &[u8],
+            _first: bool,
+            _last: bool,
+        ) -> Result<(), EndpointError> {
+            match *self {}
+        }
+
+        async fn accept(&mut self) {
+            match *self {}
+        }
+
+        async fn reject(&mut self) {
+            match *self {}
+        }
+
+        async fn accept_set_address(&mut self, _addr: u8) {
+            match *self {}
+        }
+    }
+
+    const CONFIG_LEN: usize = config_descriptor_len(1, true);
+
+    // Configuration descriptor and class specific descriptors of a slot
+    // added with `config`.
+    fn descriptors(config: XInputConfig) -> ([u8; CONFIG_LEN], Vec<ClassDescriptor>) {
+        let state = State::<1>::new();
+        let mut device_descriptor = [0; 18];
+        let mut config_descriptor = [0; CONFIG_LEN];
+        let mut bos_descriptor = [0; BOS_DESCRIPTOR_LEN];
+        let mut msos_descriptor = [0; 0];
+        let mut control_buf = [0; CONTROL_BUF_MIN_LEN];
+        let mut builder = embassy_usb::Builder::new(
+            FakeDriver { next_index: 0 },
+            embassy_usb::Config::new(0x045E, 0x0719),
+            &mut device_descriptor,
+            &mut config_descriptor,
+            &mut bos_descriptor,
+            &mut msos_descriptor,
+            &mut control_buf,
+        );
+        let class_descriptors = XInput::new(&mut builder, &state, config)
+            .class_descriptors()
+            .collect();
+        drop(builder);
+        (config_descriptor, class_descriptors)
+    }
+
+    // embassy-usb writes the endpoints when they are allocated, so the
+    // class specific descriptor follows them, unlike on a genuine receiver.
+    #[rustfmt::skip]
+    const CONTROLLER_INTERFACE: [u8; 43] = [
+        0x09, 0x04, 0x00, 0x00, 0x02, 0xFF, 0x5D, 0x81, 0x00, // Interface
+        0x07, 0x05, 0x81, 0x03, 0x20, 0x00, 0x01, // Endpoint 1 IN
+        0x07, 0x05, 0x01, 0x03, 0x20, 0x00, 0x08, // Endpoint 1 OUT
+        0x14, 0x22, // Class specific descriptor
+        0x00, 0x01, 0x13, 0x81, 0x1D, 0x00, 0x17, 0x01, 0x02, 0x08,
+        0x13, 0x01, 0x0C, 0x00, 0x0C, 0x01, 0x02, 0x08,
+    ];
+
+    #[rustfmt::skip]
+    const HEADSET_INTERFACE: [u8; 35] = [
+        0x09, 0x04, 0x01, 0x00, 0x02, 0xFF, 0x5D, 0x82, 0x00, // Interface
+        0x07, 0x05, 0x82, 0x03, 0x20, 0x00, 0x02, // Endpoint 2 IN
+        0x07, 0x05, 0x02, 0x03, 0x20, 0x00, 0x04, // Endpoint 2 OUT
+        0x0C, 0x22, // Class specific descriptor
+        0x00, 0x01, 0x01, 0x82, 0x00, 0x40, 0x01, 0x02, 0x20, 0x00,
+    ];
+
+    #[test]
+    fn descriptor_bytes_per_os() {
+        for os_compat in [OsCompat::Windows, OsCompat::Linux] {
+            for headset in [false, true] {
+                let config = XInputConfig {
+                    headset,
+                    os_compat,
+                    ..XInputConfig::default()
+                };
+                let (config_descriptor, class_descriptors) = descriptors(config);
+                let interfaces = &config_descriptor[CONFIGURATION_LEN..];
+                assert_eq!(
+                    interfaces[..CONTROLLER_INTERFACE.len()],
+                    CONTROLLER_INTERFACE,
+                    "{os_compat:?}"
+                );
+                assert_eq!(class_descriptors[0].data(), &CONTROLLER_INTERFACE[25..]);
+                let rest = &interfaces[CONTROLLER_INTERFACE.len()..];
+                if headset {
+                    assert_eq!(rest, HEADSET_INTERFACE, "{os_compat:?}");
+                    assert_eq!(class_descriptors[1].data(), &HEADSET_INTERFACE[25..]);
+                } else {
+                    assert!(rest.iter().all(|byte| *byte == 0), "{os_compat:?}");
+                    assert_eq!(class_descriptors.len(), 1);
+                }
             }
         }
+    }
 
-        false
+    #[test]
+    fn handshake_per_os() {
+        for os_compat in [OsCompat::Windows, OsCompat::Linux] {
+            let mut session = Session::new(os_compat);
+            assert_eq!(session.connection_status(true, 1), [0x08, 0x80]);
+            let unprompted = os_compat != OsCompat::Windows;
+            assert_eq!(session.take_info_pending(), unprompted, "{os_compat:?}");
+            assert!(!session.take_info_pending());
+            session.connection_status(false, 1);
+            assert!(!session.take_info_pending());
+        }
     }
 }
@@ -0,0 +1,207 @@
+//! Captures the descriptors the host reads, to diff them against a capture
+//! of a genuine receiver when Windows refuses to bind.
+//!
+//! [`CaptureDriver`] wraps the USB driver and records the configuration
+//! descriptor exactly as it is sent on the control pipe, so interfaces of
+//! other classes and the interface association descriptors are included:
+//!
+//! ```ignore
+//! static CAPTURE: DescriptorCapture = DescriptorCapture::new(Capabilities::WIRELESS_CONTROLLER);
+//! let driver = CaptureDriver::new(Driver::new(p.USB, Irqs), &CAPTURE);
+//! let mut builder = Builder::new(driver, config, ...);
+//! ```
+//!
+//! Print the result with [`DescriptorCapture::log`] once the host
+//! enumerated the device, or read it with the `descriptor` command of the
+//! serial configuration channel.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_usb::driver::{ControlPipe, Driver, EndpointAllocError, EndpointError, EndpointType};
+
+use crate::fmt::Bytes;
+use crate::protocol::{Capabilities, IN_REPORT_LEN};
+
+/// Bytes of the configuration descriptor kept by a [`DescriptorCapture`],
+/// longer descriptors are truncated.
+pub const CAPTURE_LEN: usize = 512;
+
+/// Bytes per line of [`DescriptorCapture::log`].
+const LOG_LINE_LEN: usize = 32;
+
+const REQUEST_TYPE_DEVICE_TO_HOST: u8 = 0x80;
+const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+const DESCRIPTOR_TYPE_CONFIGURATION: u8 = 0x02;
+
+struct Captured {
+    data: [u8; CAPTURE_LEN],
+    len: usize,
+}
+
+/// Descriptors captured by a [`CaptureDriver`].
+pub struct DescriptorCapture {
+    config: Mutex<CriticalSectionRawMutex, RefCell<Captured>>,
+    controller_info: [u8; IN_REPORT_LEN],
+}
+
+impl DescriptorCapture {
+    /// `capabilities` are those of [`XInputConfig`](super::XInputConfig),
+    /// which determine the controller info report.
+    pub const fn new(capabilities: Capabilities) -> Self {
+        Self {
+            config: Mutex::new(RefCell::new(Captured {
+                data: [0; CAPTURE_LEN],
+                len: 0,
+            })),
+            controller_info: capabilities.controller_info(),
+        }
+    }
+
+    /// Calls `f` with the configuration descriptor sent to the host, empty
+    /// until the host read it.
+    pub fn config_descriptor<R>(&self, f: impl FnOnce(&[u8]) -> R) -> R {
+        self.config.lock(|captured| {
+            let captured = captured.borrow();
+            f(&captured.data[..captured.len])
+        })
+    }
+
+    /// Controller info report sent after the connection handshake.
+    pub fn controller_info(&self) -> [u8; IN_REPORT_LEN] {
+        self.controller_info
+    }
+
+    /// Logs the configuration descriptor and the controller info report at
+    /// info level.
+    pub fn log(&self) {
+        self.config_descriptor(|config| {
+            info!("config descriptor, {} bytes:", config.len());
+            for line in config.chunks(LOG_LINE_LEN) {
+                info!("{:X}", Bytes(line));
+            }
+        });
+        info!("controller info: {:X}", Bytes(&self.controller_info));
+    }
+
+    // Stores `data` sent at `offset` of a configuration descriptor transfer.
+    // Hosts read the descriptor several times with growing lengths, the
+    // bytes are the same every time.
+    fn record(&self, offset: usize, data: &[u8]) {
+        self.config.lock(|captured| {
+            let mut captured = captured.borrow_mut();
+            let end = (offset + data.len()).min(CAPTURE_LEN);
+            if let Some(dest) = captured.data.get_mut(offset..end) {
+                dest.copy_from_slice(&data[..end - offset]);
+                captured.len = captured.len.max(end);
+            }
+        });
+    }
+}
+
+/// USB driver that records the descriptors sent on the control pipe into
+/// a [`DescriptorCapture`] and otherwise passes everything to `D`.
+pub struct CaptureDriver<'c, D> {
+    inner: D,
+    capture: &'c DescriptorCapture,
+}
+
+impl<'c, D> CaptureDriver<'c, D> {
+    pub fn new(inner: D, capture: &'c DescriptorCapture) -> Self {
+        Self { inner, capture }
+    }
+}
+
+impl<'d, D: Driver<'d>> Driver<'d> for CaptureDriver<'d, D> {
+    type EndpointOut = D::EndpointOut;
+    type EndpointIn = D::EndpointIn;
+    type ControlPipe = CapturePipe<'d, D::ControlPipe>;
+    type Bus = D::Bus;
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        self.inner
+            .alloc_endpoint_out(ep_type, max_packet_size, interval_ms)
+    }
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        self.inner
+            .alloc_endpoint_in(ep_type, max_packet_size, interval_ms)
+    }
+
+    fn start(self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        let (bus, pipe) = self.inner.start(control_max_packet_size);
+        let pipe = CapturePipe {
+            inner: pipe,
+            capture: self.capture,
+            offset: None,
+        };
+        (bus, pipe)
+    }
+}
+
+/// Control pipe of a [`CaptureDriver`].
+pub struct CapturePipe<'c, P> {
+    inner: P,
+    capture: &'c DescriptorCapture,
+    // Position in the configuration descriptor being sent, `None` during
+    // other requests.
+    offset: Option<usize>,
+}
+
+impl<P: ControlPipe> ControlPipe for CapturePipe<'_, P> {
+    fn max_packet_size(&self) -> usize {
+        self.inner.max_packet_size()
+    }
+
+    async fn setup(&mut self) -> [u8; 8] {
+        let setup = self.inner.setup().await;
+        // bmRequestType, bRequest, wValue (index, type)
+        self.offset = match setup {
+            [REQUEST_TYPE_DEVICE_TO_HOST, REQUEST_GET_DESCRIPTOR, _, DESCRIPTOR_TYPE_CONFIGURATION, ..] => {
+                Some(0)
+            }
+            _ => None,
+        };
+        setup
+    }
+
+    async fn data_out(
+        &mut self,
+        buf: &mut [u8],
+        first: bool,
+        last: bool,
+    ) -> Result<usize, EndpointError> {
+        self.inner.data_out(buf, first, last).await
+    }
+
+    async fn data_in(&mut self, data: &[u8], first: bool, last: bool) -> Result<(), EndpointError> {
+        if let Some(offset) = self.offset {
+            self.capture.record(offset, data);
+            self.offset = Some(offset + data.len());
+        }
+        self.inner.data_in(data, first, last).await
+    }
+
+    async fn accept(&mut self) {
+        self.inner.accept().await
+    }
+
+    async fn reject(&mut self) {
+        self.inner.reject().await
+    }
+
+    async fn accept_set_address(&mut self, addr: u8) {
+        self.inner.accept_set_address(addr).await
+    }
+}
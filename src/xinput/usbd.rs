@@ -0,0 +1,305 @@
+//! XInput receiver slot for the synchronous `usb-device` stack, for
+//! applications using RTIC or a bare-metal loop instead of embassy-usb.
+//!
+//! [`XInputClass`] is fed through the same [`State`] as [`XInput`](super::XInput).
+//! Nothing runs in the background: reports are written when the class is
+//! polled by [`UsbDevice::poll`](usb_device::device::UsbDevice::poll) or
+//! when [`XInputClass::update`] is called, so call the latter after
+//! [`State::send_xinput`] or from the main loop.
+//!
+//! Idle reports, guide button power off and latency measurement need a
+//! timer and are not supported by this class. Neither is the headset
+//! interface.
+
+use usb_device::class_prelude::*;
+use usb_device::control::{Recipient, RequestType};
+
+use super::{
+    controller_descriptor, BatteryLevel, BatteryType, Capabilities, Reply, Session, State,
+    TimedControllerData, XInputConfig, CLASS_DESCRIPTOR_TYPE, CLASS_VENDOR, ENDPOINT_SIZE,
+    PROTOCOL_WIRELESS, SUBCLASS_XINPUT,
+};
+use crate::chatpad::{self, ChatpadKeys};
+use crate::fmt::Bytes;
+use crate::protocol::{self, OutData, IN_REPORT_LEN};
+
+/// Report waiting for the IN endpoint.
+#[derive(Clone, Copy)]
+struct Report {
+    data: [u8; IN_REPORT_LEN],
+    len: usize,
+}
+
+impl Report {
+    fn new(data: &[u8]) -> Self {
+        let mut report = Self {
+            data: [0; IN_REPORT_LEN],
+            len: data.len(),
+        };
+        report.data[..data.len()].copy_from_slice(data);
+        report
+    }
+}
+
+/// Receiver slot with one controller interface.
+pub struct XInputClass<'a, B: UsbBus, const N: usize = 1> {
+    interface: InterfaceNumber,
+    ep_in: EndpointIn<'a, B>,
+    ep_out: EndpointOut<'a, B>,
+    state: &'a State<N>,
+    session: Session,
+    capabilities: Capabilities,
+    // battery level in the last controller info report
+    reported_battery: BatteryLevel,
+    serial_number: Option<[u8; 7]>,
+    pending: Option<Report>,
+    // Answer to the last host command, sent before any other report.
+    reply: Option<Report>,
+    // Input and chatpad data held back until the reports that have to
+    // precede them are written.
+    staged_input: Option<TimedControllerData>,
+    staged_keys: Option<ChatpadKeys>,
+}
+
+impl<'a, B: UsbBus, const N: usize> XInputClass<'a, B, N> {
+    /// Allocates the interface and endpoints. `config.headset` is ignored.
+    pub fn new(alloc: &'a UsbBusAllocator<B>, state: &'a State<N>, config: XInputConfig) -> Self {
+        Self {
+            interface: alloc.interface(),
+            ep_in: alloc.interrupt(ENDPOINT_SIZE, config.poll_interval.max(1)),
+            ep_out: alloc.interrupt(ENDPOINT_SIZE, config.out_poll_interval.max(1)),
+            state,
+            session: Session::new(config.os_compat),
+            capabilities: config.capabilities,
+            reported_battery: BatteryLevel::Full,
+            serial_number: None,
+            pending: None,
+            reply: None,
+            staged_input: None,
+            staged_keys: None,
+        }
+    }
+
+    /// Answers the vendor device request for the receiver serial number.
+    ///
+    /// Set this on one class of the device only.
+    pub fn with_serial_number(mut self, serial_number: [u8; 7]) -> Self {
+        self.serial_number = Some(serial_number);
+        self
+    }
+
+    // this is used by logging
+    fn ep_in_addr(&self) -> u8 {
+        self.ep_in.address().index() as u8
+    }
+
+    // this is used by logging
+    fn ep_out_addr(&self) -> u8 {
+        self.ep_out.address().index() as u8
+    }
+
+    fn controller_info(&mut self) -> Report {
+        self.reported_battery = self.state.battery_level();
+        let info = self
+            .capabilities
+            .controller_info_with_battery(self.reported_battery);
+        debug!("{}-> {:X}", self.ep_in_addr(), Bytes(&info));
+        Report::new(&info)
+    }
+
+    fn connection_status(&mut self, available: bool) -> Report {
+        let ep = self.ep_in_addr();
+        Report::new(&self.session.connection_status(available, ep))
+    }
+
+    // Picks the next report to send, following the order of the embassy
+    // backend.
+    fn next_report(&mut self) -> Option<Report> {
+        if let Some(reply) = self.reply.take() {
+            return Some(reply);
+        }
+        if self.session.take_info_pending() {
+            return Some(self.controller_info());
+        }
+        if self.session.is_connected()
+            && self.capabilities.battery == BatteryType::Battery
+            && self.state.battery_level() != self.reported_battery
+        {
+            return Some(self.controller_info());
+        }
+        if self.state.presence.signaled() {
+            self.state.presence.reset();
+            let present = self.state.is_present();
+            if !present {
+                while self.state.xinput.try_receive().is_ok() {}
+                self.staged_input = None;
+            }
+            if present != self.session.is_connected() {
+                return Some(self.connection_status(present));
+            }
+        }
+
+        let input = self
+            .staged_input
+            .take()
+            .or_else(|| self.state.xinput.try_receive().ok());
+        if let (Some(data), true) = (input, self.state.is_present()) {
+            if !self.session.is_connected() {
+                self.staged_input = Some(data);
+                return Some(self.connection_status(true));
+            }
+            let packet = self.state.count_sent();
+            trace!(
+                "{}-> Packet {} sampled at {} us",
+                self.ep_in_addr(),
+                packet,
+                data.timestamp.as_micros()
+            );
+            return Some(Report::new(&protocol::input_report(&data.data)));
+        }
+
+        let keys = self
+            .staged_keys
+            .take()
+            .or_else(|| self.state.chatpad.try_receive().ok());
+        if let (Some(keys), true) = (keys, self.session.is_connected()) {
+            if !self.session.chatpad_announced {
+                self.session.chatpad_announced = true;
+                self.staged_keys = Some(keys);
+                return Some(Report::new(&chatpad::announcement_report()));
+            }
+            return Some(Report::new(&chatpad::key_report(&keys)));
+        }
+
+        None
+    }
+
+    /// Writes pending reports until the IN endpoint is busy.
+    pub fn update(&mut self) {
+        loop {
+            if let Some(report) = self.pending {
+                match self.ep_in.write(&report.data[..report.len]) {
+                    Ok(_) => self.pending = None,
+                    Err(UsbError::WouldBlock) => return,
+                    Err(_) => {
+                        warn!("{}-> Dropped report", self.ep_in_addr());
+                        self.pending = None;
+                        return;
+                    }
+                }
+            }
+            match self.next_report() {
+                Some(report) => self.pending = Some(report),
+                None => return,
+            }
+        }
+    }
+}
+
+impl<B: UsbBus, const N: usize> UsbClass<B> for XInputClass<'_, B, N> {
+    fn get_configuration_descriptors(
+        &self,
+        writer: &mut DescriptorWriter,
+    ) -> usb_device::Result<()> {
+        writer.interface(
+            self.interface,
+            CLASS_VENDOR,
+            SUBCLASS_XINPUT,
+            PROTOCOL_WIRELESS,
+        )?;
+        writer.endpoint(&self.ep_in)?;
+        writer.endpoint(&self.ep_out)?;
+        writer.write(
+            CLASS_DESCRIPTOR_TYPE,
+            &controller_descriptor(self.ep_in_addr(), self.ep_out_addr()),
+        )
+    }
+
+    fn reset(&mut self) {
+        self.session = Session::new(self.session.os_compat);
+        self.pending = None;
+        self.reply = None;
+        self.staged_input = None;
+        self.staged_keys = None;
+    }
+
+    fn poll(&mut self) {
+        self.update();
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        let own_interface = req.index == u16::from(u8::from(self.interface));
+        let _ = match (req.request_type, req.recipient, req.request, req.value) {
+            (RequestType::Vendor, Recipient::Device, 0x01, 0x0001) if req.index == 0 => {
+                let Some(serial_number) = self.serial_number else {
+                    return;
+                };
+                xfer.accept_with(&serial_number)
+            }
+            (RequestType::Vendor, Recipient::Interface, 0x01, 0x0100) if own_interface => {
+                xfer.accept_with(&self.capabilities.input_capabilities())
+            }
+            (RequestType::Vendor, Recipient::Interface, 0x01, 0x0000) if own_interface => {
+                xfer.accept_with(&self.capabilities.vibration_capabilities())
+            }
+            (
+                RequestType::Standard,
+                Recipient::Interface,
+                control::Request::GET_DESCRIPTOR,
+                value,
+            ) if own_interface && (value >> 8) as u8 == CLASS_DESCRIPTOR_TYPE => {
+                let data = controller_descriptor(self.ep_in_addr(), self.ep_out_addr());
+                let mut full = [0_u8; 20];
+                full[0] = full.len() as u8;
+                full[1] = CLASS_DESCRIPTOR_TYPE;
+                full[2..].copy_from_slice(&data);
+                xfer.accept_with(&full)
+            }
+            _ => return,
+        };
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if (req.request_type, req.recipient, req.request)
+            == (RequestType::Vendor, Recipient::Interface, 0x00)
+            && req.index == u16::from(u8::from(self.interface))
+        {
+            debug!("<- Control LED data {:#X}", req.value);
+            let _ = xfer.accept();
+        }
+    }
+
+    fn endpoint_out(&mut self, addr: EndpointAddress) {
+        if addr != self.ep_out.address() {
+            return;
+        }
+        let mut out_data = [0_u8; ENDPOINT_SIZE as usize];
+        let Ok(n) = self.ep_out.read(&mut out_data) else {
+            return;
+        };
+        let out_data = OutData::from_raw(&out_data[..n]);
+        let ep = self.ep_out_addr();
+        match self.session.handle_out_data(self.state, out_data, ep) {
+            Some(Reply::ConnectionStatus(available)) => {
+                self.reply = Some(self.connection_status(available));
+            }
+            Some(Reply::ControllerInfo) => {
+                self.reply = Some(self.controller_info());
+            }
+            // Reconnects with the next input, there is no power off state.
+            Some(Reply::PowerOff) if self.session.is_connected() => {
+                self.reply = Some(self.connection_status(false));
+            }
+            Some(Reply::PowerOff) | None => {}
+        }
+        self.update();
+    }
+
+    fn endpoint_in_complete(&mut self, addr: EndpointAddress) {
+        if addr == self.ep_in.address() {
+            self.update();
+        }
+    }
+}